@@ -0,0 +1,90 @@
+use actix_web::{get, post, HttpResponse, Responder};
+
+use super::{auth::AdminAuth, endpoints::error_status, workers};
+use crate::api::utils::env::{get_db_ip, get_table_config, DB_IP_ENV_ERR_MSG};
+
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The order table exists; its schema as reported by HBase", body = TableSchema),
+        (status = 401, description = "The admin token was missing or wrong", body = String),
+        (status = 403, description = "Admin endpoints are disabled", body = String),
+        (status = 500, description = "An error occurred on the server side", body = String),
+        (status = 503, description = "The database is unavailable, retry later", body = String),
+    )
+)]
+#[post("/admin/schema")]
+pub async fn ensure_schema(_auth: AdminAuth) -> impl Responder {
+    let db_ip = match get_db_ip() {
+        Some(v) => v,
+        None => return HttpResponse::InternalServerError().json(DB_IP_ENV_ERR_MSG),
+    };
+    let tables = match get_table_config() {
+        Ok(v) => v,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    match workers::ensure_order_table(&db_ip, &tables) {
+        Ok(schema) => {
+            tracing::info!(table = schema.table_name.as_str(), "order table verified");
+            HttpResponse::Ok().json(schema)
+        }
+        Err(e) => error_status(&e).json(e.to_string()),
+    }
+}
+
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The schema of the order table as reported by HBase", body = TableSchema),
+        (status = 401, description = "The admin token was missing or wrong", body = String),
+        (status = 403, description = "Admin endpoints are disabled", body = String),
+        (status = 500, description = "An error occurred on the server side", body = String),
+        (status = 503, description = "The database is unavailable, retry later", body = String),
+    )
+)]
+#[get("/admin/schema")]
+pub async fn describe_schema(_auth: AdminAuth) -> impl Responder {
+    let db_ip = match get_db_ip() {
+        Some(v) => v,
+        None => return HttpResponse::InternalServerError().json(DB_IP_ENV_ERR_MSG),
+    };
+    let tables = match get_table_config() {
+        Ok(v) => v,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    match workers::describe_order_table(&db_ip, &tables) {
+        Ok(schema) => HttpResponse::Ok().json(schema),
+        Err(e) => error_status(&e).json(e.to_string()),
+    }
+}
+
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Column families expected by the service but missing in HBase, and the other way around", body = SchemaDrift),
+        (status = 401, description = "The admin token was missing or wrong", body = String),
+        (status = 403, description = "Admin endpoints are disabled", body = String),
+        (status = 500, description = "An error occurred on the server side", body = String),
+        (status = 503, description = "The database is unavailable, retry later", body = String),
+    )
+)]
+#[get("/admin/schema/drift")]
+pub async fn schema_drift(_auth: AdminAuth) -> impl Responder {
+    let db_ip = match get_db_ip() {
+        Some(v) => v,
+        None => return HttpResponse::InternalServerError().json(DB_IP_ENV_ERR_MSG),
+    };
+    let tables = match get_table_config() {
+        Ok(v) => v,
+        Err(e) => return HttpResponse::InternalServerError().json(e.to_string()),
+    };
+    match workers::order_table_drift(&db_ip, &tables) {
+        Ok(drift) => {
+            if !drift.in_sync {
+                tracing::warn!(missing = ?drift.missing_families, unexpected = ?drift.unexpected_families, "order table schema drift");
+            }
+            HttpResponse::Ok().json(drift)
+        }
+        Err(e) => error_status(&e).json(e.to_string()),
+    }
+}
@@ -0,0 +1,102 @@
+use std::future::{ready, Ready};
+
+use actix_web::{
+    dev::Payload,
+    error::InternalError,
+    http::header::{AUTHORIZATION, WWW_AUTHENTICATE},
+    Error, FromRequest, HttpRequest, HttpResponse,
+};
+
+use super::utils::env::get_admin_token;
+
+/// Guard for the admin routes. Extracting it succeeds only when the request carries
+/// `Authorization: Bearer <ADMIN_TOKEN>`. Admin routes are disabled while `ADMIN_TOKEN` is unset.
+#[derive(Debug)]
+pub struct AdminAuth;
+
+#[derive(Debug, PartialEq, Eq)]
+enum AuthFailure {
+    Disabled,
+    Unauthorized,
+}
+
+impl FromRequest for AdminAuth {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let header = req.headers().get(AUTHORIZATION).and_then(|v| v.to_str().ok());
+        let result = match check_bearer(header, get_admin_token().as_deref()) {
+            Ok(()) => Ok(AdminAuth),
+            Err(AuthFailure::Disabled) => Err(reject(
+                HttpResponse::Forbidden().json("Admin endpoints are disabled. Set ADMIN_TOKEN to enable them."),
+                "admin endpoints disabled",
+            )),
+            Err(AuthFailure::Unauthorized) => Err(reject(
+                HttpResponse::Unauthorized().insert_header((WWW_AUTHENTICATE, "Bearer")).json("Missing or invalid admin token."),
+                "missing or invalid admin token",
+            )),
+        };
+        if result.is_err() {
+            tracing::warn!(path = req.path(), "rejected admin request");
+        }
+        ready(result)
+    }
+}
+
+fn reject(response: HttpResponse, reason: &'static str) -> Error {
+    InternalError::from_response(reason, response).into()
+}
+
+fn check_bearer(header: Option<&str>, expected: Option<&str>) -> Result<(), AuthFailure> {
+    let expected = match expected {
+        Some(t) if !t.is_empty() => t,
+        _ => return Err(AuthFailure::Disabled),
+    };
+    let token = header
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .ok_or(AuthFailure::Unauthorized)?;
+    if constant_time_eq(token.trim().as_bytes(), expected.as_bytes()) {
+        Ok(())
+    } else {
+        Err(AuthFailure::Unauthorized)
+    }
+}
+
+/// Compares without returning early, so the response time does not leak how much of the token matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_bearer_accepts_matching_token() {
+        assert_eq!(check_bearer(Some("Bearer s3cret"), Some("s3cret")), Ok(()));
+    }
+
+    #[test]
+    fn test_check_bearer_without_configured_token_is_disabled() {
+        assert_eq!(check_bearer(Some("Bearer s3cret"), None), Err(AuthFailure::Disabled));
+        assert_eq!(check_bearer(Some("Bearer "), Some("")), Err(AuthFailure::Disabled));
+    }
+
+    #[test]
+    fn test_check_bearer_rejects_wrong_or_missing_token() {
+        assert_eq!(check_bearer(Some("Bearer nope"), Some("s3cret")), Err(AuthFailure::Unauthorized));
+        assert_eq!(check_bearer(Some("Basic s3cret"), Some("s3cret")), Err(AuthFailure::Unauthorized));
+        assert_eq!(check_bearer(None, Some("s3cret")), Err(AuthFailure::Unauthorized));
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+    }
+}
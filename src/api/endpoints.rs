@@ -1,93 +1,137 @@
-use super::workers;
+use super::{request_tracing::RequestId, workers};
 use crate::{
-    api::utils::env::{get_db_ip, get_kafka_ip, DB_IP_ENV_ERR_MSG, KAFKA_IP_ENV_ERR_MSG},
+    api::utils::env::get_table_config,
     models::orders::CreateOrder, models::errors::OrderServiceError,
 };
-use actix_web::{get, post, web, HttpResponse, HttpResponseBuilder, Responder};
+use actix_web::{error::InternalError, get, post, web, HttpResponse, HttpResponseBuilder, Responder};
 use serde::Serialize;
+use tracing::Span;
 // const DB_IP: &str = "165.22.194.124:9090";
 
+#[utoipa::path(responses((status = 200, description = "The service is running", body = String)))]
 #[get("/")]
 pub async fn index() -> String {
     "Service is running".to_string()
 }
 
+#[utoipa::path(
+    request_body = CreateOrder,
+    responses(
+        (status = 200, description = "The order was created", body = Order),
+        (status = 400, description = "The request body was missing or invalid", body = String),
+        (status = 500, description = "An error occurred on the server side", body = String),
+        (status = 503, description = "The database is unavailable, retry later", body = String),
+    )
+)]
 #[post("/create")]
-pub async fn create(param_obj: web::Json<CreateOrder>) -> impl Responder {
-    let db_ip = match get_db_ip() {
-        Some(v) => v,
-        None => {
-            return generate_response(&mut HttpResponse::InternalServerError(), DB_IP_ENV_ERR_MSG)
-        }
+pub async fn create(param_obj: web::Json<CreateOrder>, request_id: RequestId) -> impl Responder {
+    Span::current().record("c_id", param_obj.c_id.as_str());
+    let tables = match get_table_config() {
+        Ok(v) => v,
+        Err(e) => return generate_response(&mut HttpResponse::InternalServerError(), e.to_string()),
     };
-    let kafka_ip = match get_kafka_ip() {
-        Some(v) => v,
-        None => {
-            return generate_response(
-                &mut HttpResponse::InternalServerError(),
-                KAFKA_IP_ENV_ERR_MSG,
-            )
-        }
+    let repository = match workers::order_repository(&tables) {
+        Ok(v) => v,
+        Err(e) => return generate_response(&mut HttpResponse::InternalServerError(), e.to_string()),
     };
-    let order = match workers::create_order(param_obj, &db_ip, &kafka_ip) {
-        Ok(r) => r,
+    let mut publisher = match workers::event_publisher() {
+        Ok(v) => v,
+        Err(e) => return generate_response(&mut HttpResponse::InternalServerError(), e.to_string()),
+    };
+    let order = match workers::create_order(param_obj, repository.as_ref(), publisher.as_mut(), &request_id.0) {
+        Ok(r) => {
+            Span::current().record("o_id", r.o_id.as_str());
+            r
+        }
         Err(e) => {
-            return generate_response(&mut HttpResponse::InternalServerError(), e.to_string())
+            tracing::error!(error = %e, "failed to create order");
+            return generate_response(&mut error_status(&e), e.to_string())
         }
     };
     generate_response(&mut HttpResponse::Ok(), order)
 }
 
+#[utoipa::path(
+    responses(
+        (status = 200, description = "The tables in the database", body = Vec<TableName>),
+        (status = 500, description = "An error occurred on the server side", body = String),
+        (status = 503, description = "The database is unavailable, retry later", body = String),
+    )
+)]
 #[get("/tables")]
 pub async fn get_tables() -> impl Responder {
-    let db_ip = match get_db_ip() {
-        Some(v) => v,
-        None => {
-            return generate_response(&mut HttpResponse::InternalServerError(), DB_IP_ENV_ERR_MSG)
-        }
+    let tables = match get_table_config() {
+        Ok(v) => v,
+        Err(e) => return generate_response(&mut HttpResponse::InternalServerError(), e.to_string()),
     };
-    match workers::get_tables(&db_ip) {
-        Ok(tables) => return generate_response(&mut HttpResponse::Ok(), tables),
-        Err(e) => {
-            return generate_response(&mut HttpResponse::InternalServerError(), e.to_string())
-        }
+    let repository = match workers::order_repository(&tables) {
+        Ok(v) => v,
+        Err(e) => return generate_response(&mut HttpResponse::InternalServerError(), e.to_string()),
+    };
+    match workers::get_tables(repository.as_ref()) {
+        Ok(tables) => generate_response(&mut HttpResponse::Ok(), tables),
+        Err(e) => generate_response(&mut error_status(&e), e.to_string()),
     }
 }
 
+#[utoipa::path(
+    params(("id" = String, Path, description = "The id of the order")),
+    responses(
+        (status = 200, description = "The order was found", body = Order),
+        (status = 404, description = "The order was not found", body = String),
+        (status = 500, description = "An error occurred on the server side", body = String),
+        (status = 503, description = "The database is unavailable, retry later", body = String),
+    )
+)]
 #[get("/order/{id}")]
 pub async fn get_order(path: web::Path<String>) -> impl Responder {
-    let db_ip = match get_db_ip() {
-        Some(v) => v,
-        None => {
-            return generate_response(&mut HttpResponse::InternalServerError(), DB_IP_ENV_ERR_MSG)
-        }
-    };
     let id = path.into_inner();
-    let order = match workers::get_row(&id, &db_ip) {
+    Span::current().record("o_id", id.as_str());
+    let tables = match get_table_config() {
+        Ok(v) => v,
+        Err(e) => return generate_response(&mut HttpResponse::InternalServerError(), e.to_string()),
+    };
+    let repository = match workers::order_repository(&tables) {
+        Ok(v) => v,
+        Err(e) => return generate_response(&mut HttpResponse::InternalServerError(), e.to_string()),
+    };
+    let order = match workers::get_row(&id, repository.as_ref()) {
         Ok(r) => r,
         Err(e) => {
             match e {
                 OrderServiceError::RowNotFound(r) => return generate_response(&mut HttpResponse::NotFound(), format!("Order by id {} was not found.", r)),
-                _ => return generate_response(&mut HttpResponse::InternalServerError(), e.to_string())
+                _ => return generate_response(&mut error_status(&e), e.to_string())
             }
         }
     };
     generate_response(&mut HttpResponse::Ok(), order)
 }
 
+#[utoipa::path(
+    params(("id" = String, Path, description = "The id of the customer")),
+    responses(
+        (status = 200, description = "The orders of the customer, without orderlines", body = Vec<OrderInfo>),
+        (status = 404, description = "No orders were found for the customer", body = String),
+        (status = 500, description = "An error occurred on the server side", body = String),
+        (status = 503, description = "The database is unavailable, retry later", body = String),
+    )
+)]
 #[get("/cust/{id}")]
 pub async fn get_orders_from_user(path: web::Path<String>) -> impl Responder {
-    let db_ip = match get_db_ip() {
-        Some(v) => v,
-        None => {
-            return generate_response(&mut HttpResponse::InternalServerError(), DB_IP_ENV_ERR_MSG)
-        }
-    };
     let id = path.into_inner();
-    let r = match workers::get_orders_info_by_user(&id, &db_ip) {
+    Span::current().record("c_id", id.as_str());
+    let tables = match get_table_config() {
+        Ok(v) => v,
+        Err(e) => return generate_response(&mut HttpResponse::InternalServerError(), e.to_string()),
+    };
+    let repository = match workers::order_repository(&tables) {
+        Ok(v) => v,
+        Err(e) => return generate_response(&mut HttpResponse::InternalServerError(), e.to_string()),
+    };
+    let r = match workers::get_orders_info_by_user(&id, repository.as_ref()) {
         Ok(r) => r,
         Err(e) => {
-            return generate_response(&mut HttpResponse::InternalServerError(), e.to_string())
+            return generate_response(&mut error_status(&e), e.to_string())
         }
     };
     if r.is_empty() {
@@ -96,9 +140,26 @@ pub async fn get_orders_from_user(path: web::Path<String>) -> impl Responder {
     generate_response(&mut HttpResponse::Ok(), r)
 }
 
+/// 503 while the HBase circuit breaker is open, so clients back off, otherwise 500.
+pub(super) fn error_status(e: &OrderServiceError) -> HttpResponseBuilder {
+    match e {
+        OrderServiceError::CircuitOpen => HttpResponse::ServiceUnavailable(),
+        _ => HttpResponse::InternalServerError(),
+    }
+}
+
 fn generate_response(
     response_builder: &mut HttpResponseBuilder,
     val: impl Serialize,
 ) -> HttpResponse {
     response_builder.content_type("APPLICATION_JSON").json(val)
 }
+
+/// Answers unparsable or missing request bodies with a 400 and a JSON error message,
+/// instead of actix' default plain text error.
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|err, _req| {
+        let response = generate_response(&mut HttpResponse::BadRequest(), format!("Invalid request body: {}", err));
+        InternalError::from_response(err, response).into()
+    })
+}
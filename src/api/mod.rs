@@ -1,4 +1,28 @@
 pub mod workers;
 pub mod endpoints;
 pub mod utils;
+pub mod request_tracing;
+pub mod openapi;
+pub mod auth;
+pub mod admin;
+
+use actix_web::web::ServiceConfig;
+
+/// Registers the REST API. Routes added here should also be listed in `openapi::ApiDoc`,
+/// which the tests in `openapi` check.
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.app_data(endpoints::json_config())
+        .service(endpoints::index)
+        .service(endpoints::get_tables)
+        .service(endpoints::create)
+        .service(endpoints::get_orders_from_user)
+        .service(endpoints::get_order)
+        .service(admin::ensure_schema)
+        .service(admin::describe_schema)
+        .service(admin::schema_drift)
+        .service(openapi::openapi_json);
+    // The UI reads the document `openapi_json` serves, so the route is not registered twice.
+    #[cfg(feature = "swagger-ui")]
+    cfg.service(utoipa_swagger_ui::SwaggerUi::new("/swagger-ui/{_:.*}").config(utoipa_swagger_ui::Config::new(["/openapi.json"])));
+}
 // use crate::models::Order;
\ No newline at end of file
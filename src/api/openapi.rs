@@ -0,0 +1,152 @@
+use actix_web::{get, HttpResponse, Responder};
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use super::{admin, endpoints};
+use crate::models::{
+    orders::{CreateOrder, Order, OrderInfo, OrderState, Orderline},
+    schema::{ColumnFamilySchema, SchemaDrift, TableSchema},
+    tables::TableName,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Customer Facing Order Service", description = "Creation and fetching of orders for the customer."),
+    paths(
+        endpoints::index,
+        endpoints::get_tables,
+        endpoints::create,
+        endpoints::get_orders_from_user,
+        endpoints::get_order,
+        admin::ensure_schema,
+        admin::describe_schema,
+        admin::schema_drift,
+        openapi_json,
+    ),
+    components(schemas(CreateOrder, Order, OrderInfo, Orderline, OrderState, TableName, TableSchema, ColumnFamilySchema, SchemaDrift)),
+    modifiers(&AdminSecurity)
+)]
+pub struct ApiDoc;
+
+/// Declares the bearer token scheme the admin routes refer to.
+struct AdminSecurity;
+
+impl Modify for AdminSecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "admin_token",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+    }
+}
+
+#[utoipa::path(responses((status = 200, description = "The OpenAPI 3 document of this service")))]
+#[get("/openapi.json")]
+pub async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::BTreeSet, rc::Rc};
+
+    use super::*;
+    use crate::api::configure;
+    use actix_web::{dev::Service, http::{Method, StatusCode}, test, App};
+
+    /// Routes declared with actix route macros in the api modules.
+    fn declared_routes() -> BTreeSet<(String, String)> {
+        let sources = [include_str!("endpoints.rs"), include_str!("admin.rs"), include_str!("openapi.rs")];
+        let mut routes = BTreeSet::new();
+        for line in sources.iter().flat_map(|s| s.lines()).map(str::trim) {
+            for method in ["get", "post", "put", "patch", "delete"] {
+                let prefix = format!("#[{}(\"", method);
+                if let Some(rest) = line.strip_prefix(&prefix) {
+                    let path = &rest[..rest.find('"').unwrap()];
+                    routes.insert((method.to_owned(), path.to_owned()));
+                }
+            }
+        }
+        routes
+    }
+
+    fn documented_routes() -> BTreeSet<(String, String)> {
+        let mut routes = BTreeSet::new();
+        for (path, item) in ApiDoc::openapi().paths.paths {
+            for method in item.operations.keys() {
+                let method = serde_json::to_value(method).unwrap().as_str().unwrap().to_owned();
+                routes.insert((method, path.clone()));
+            }
+        }
+        routes
+    }
+
+    #[actix_web::test]
+    async fn test_spec_documents_every_declared_route() {
+        assert_eq!(declared_routes(), documented_routes());
+    }
+
+    #[actix_web::test]
+    async fn test_every_documented_route_is_registered() {
+        let matched: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+        let recorder = matched.clone();
+        let app = test::init_service(App::new()
+            .wrap_fn(move |req, srv| {
+                *recorder.borrow_mut() = req.match_pattern();
+                srv.call(req)
+            })
+            .configure(configure)).await;
+        for (method, path) in documented_routes() {
+            *matched.borrow_mut() = None;
+            let uri = path.replace("{id}", "some-id");
+            let req = test::TestRequest::default()
+                .method(Method::from_bytes(method.to_uppercase().as_bytes()).unwrap())
+                .uri(&uri)
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(matched.borrow().as_deref(), Some(path.as_str()), "{} {} is not registered", method, path);
+            assert_ne!(res.status(), StatusCode::METHOD_NOT_ALLOWED, "{} {} is not registered", method, path);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_openapi_json_is_served() {
+        let app = test::init_service(App::new().configure(configure)).await;
+        let req = test::TestRequest::get().uri("/openapi.json").to_request();
+        let spec: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+        assert!(spec["components"]["schemas"]["CreateOrder"].is_object());
+        assert!(spec["paths"]["/order/{id}"]["get"].is_object());
+    }
+
+    #[cfg(feature = "swagger-ui")]
+    #[actix_web::test]
+    async fn test_swagger_ui_reads_the_served_document() {
+        let app = test::init_service(App::new().configure(configure)).await;
+        let req = test::TestRequest::get().uri("/swagger-ui/swagger-initializer.js").to_request();
+        let initializer = test::call_and_read_body(&app, req).await;
+        assert!(String::from_utf8_lossy(&initializer).contains("\"/openapi.json\""));
+    }
+
+    #[actix_web::test]
+    async fn test_admin_routes_require_a_token() {
+        let app = test::init_service(App::new().configure(configure)).await;
+        let req = test::TestRequest::get().uri("/admin/schema/drift").to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(matches!(res.status(), StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN));
+    }
+
+    #[actix_web::test]
+    async fn test_create_with_bad_body_is_bad_request() {
+        let app = test::init_service(App::new().configure(configure)).await;
+        let req = test::TestRequest::post().uri("/create").set_payload("{not json").insert_header(("content-type", "application/json")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: String = test::read_body_json(res).await;
+        assert!(body.starts_with("Invalid request body"));
+    }
+}
@@ -0,0 +1,194 @@
+use std::{future::{ready, Ready}, time::Instant};
+
+use actix_web::{
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderMap, HeaderName, HeaderValue},
+    Error, FromRequest, HttpMessage, HttpRequest,
+};
+use futures::future::LocalBoxFuture;
+use opentelemetry::{global, propagation::Extractor, Context};
+use tracing::{field::Empty, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The id of the current request, either taken from the `X-Request-Id` header or generated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl FromRequest for RequestId {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let id = req.extensions().get::<RequestId>().cloned();
+        ready(Ok(id.unwrap_or_else(|| RequestId(uuid::Uuid::new_v4().to_string()))))
+    }
+}
+
+/// Middleware that opens a span per request, logs the outcome and echoes the request id in the response.
+pub struct RequestTracing;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTracing
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestTracingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTracingMiddleware { service }))
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTracingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = request_id_from_header(req.headers().get(REQUEST_ID_HEADER));
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+        let route = req.match_pattern().unwrap_or_else(|| req.path().to_owned());
+        let span = tracing::info_span!(
+            "request",
+            otel.name = %format!("{} {}", req.method(), route),
+            otel.kind = "server",
+            otel.status_code = Empty,
+            request_id = %request_id,
+            method = %req.method(),
+            route = %route,
+            status = Empty,
+            o_id = Empty,
+            c_id = Empty,
+        );
+        span.set_parent(extract_trace_context(req.headers()));
+        let start = Instant::now();
+        let fut = self.service.call(req);
+        Box::pin(
+            async move {
+                let res = fut.await;
+                let latency_ms = start.elapsed().as_millis() as u64;
+                match res {
+                    Ok(mut res) => {
+                        if let Ok(value) = HeaderValue::from_str(&request_id) {
+                            res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                        }
+                        let status = res.status();
+                        let span = tracing::Span::current();
+                        span.record("status", status.as_u16());
+                        if status.is_server_error() {
+                            span.record("otel.status_code", "ERROR");
+                        }
+                        tracing::info!(status = status.as_u16(), latency_ms, "request completed");
+                        Ok(res)
+                    }
+                    Err(e) => {
+                        tracing::Span::current().record("otel.status_code", "ERROR");
+                        tracing::error!(latency_ms, error = %e, "request failed");
+                        Err(e)
+                    }
+                }
+            }
+            .instrument(span),
+        )
+    }
+}
+
+/// Reads an inbound W3C `traceparent` so the request span joins the caller's trace.
+fn extract_trace_context(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Uses the inbound id if it is a sane header value, otherwise generates a new one.
+fn request_id_from_header(value: Option<&HeaderValue>) -> String {
+    value
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty() && v.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_owned)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{get, test, App, HttpResponse, Responder};
+
+    #[get("/echo")]
+    async fn echo(request_id: RequestId) -> impl Responder {
+        HttpResponse::Ok().body(request_id.0)
+    }
+
+    #[actix_web::test]
+    async fn test_request_id_is_propagated() {
+        let app = test::init_service(App::new().wrap(RequestTracing).service(echo)).await;
+        let req = test::TestRequest::get().uri("/echo").insert_header(("X-Request-Id", "abc-123")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(REQUEST_ID_HEADER).unwrap(), "abc-123");
+        let body = test::read_body(res).await;
+        assert_eq!(body, "abc-123");
+    }
+
+    #[actix_web::test]
+    async fn test_request_id_is_generated() {
+        let app = test::init_service(App::new().wrap(RequestTracing).service(echo)).await;
+        let req = test::TestRequest::get().uri("/echo").to_request();
+        let res = test::call_service(&app, req).await;
+        let header = res.headers().get(REQUEST_ID_HEADER).unwrap().to_str().unwrap().to_owned();
+        assert!(uuid::Uuid::parse_str(&header).is_ok());
+        let body = test::read_body(res).await;
+        assert_eq!(body, header.as_bytes());
+    }
+
+    #[actix_web::test]
+    async fn test_extract_trace_context_reads_traceparent() {
+        use opentelemetry::trace::TraceContextExt;
+        global::set_text_map_propagator(opentelemetry_sdk::propagation::TraceContextPropagator::new());
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("traceparent"),
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+        let context = extract_trace_context(&headers);
+        let span_context = context.span().span_context().clone();
+        assert!(span_context.is_remote());
+        assert_eq!(span_context.trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
+
+    #[actix_web::test]
+    async fn test_request_id_from_header_rejects_blank() {
+        let value = HeaderValue::from_static("   ");
+        let id = request_id_from_header(Some(&value));
+        assert!(uuid::Uuid::parse_str(&id).is_ok());
+    }
+}
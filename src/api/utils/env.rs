@@ -1,16 +1,101 @@
-use std::env;
+use std::{collections::HashMap, env, str::FromStr, time::Duration};
+
+use crate::{
+    consumers::{consumer::RetryPolicy, saga::{SagaConfig, SagaTopics}},
+    models::{errors::OrderServiceError, orders::DecodeMode, saga::StepTimeouts, schema::ColumnFamilySpec, tables::{Tables, DEFAULT_CUSTOMER_INDEX_TABLE, DEFAULT_LEASES_TABLE, DEFAULT_MIGRATIONS_TABLE, DEFAULT_ORDER_TABLE, DEFAULT_SAGAS_TABLE}},
+    repository::{hbase::{CUSTOMER_INDEX_FAMILIES, ORDER_FAMILIES}, migrations::{DEFAULT_BATCH_SIZE, MIGRATIONS_FAMILY}, leases::LEASE_FAMILY, sagas::SAGA_FAMILY, failover::{DEFAULT_PROBE_INTERVAL, DEFAULT_UNHEALTHY_AFTER}, hbase_connection::{ThriftApi, ThriftConfig, ThriftProtocol, ThriftTransport}, order_repository::OrderStore, resilience::ResiliencePolicy},
+    producers::{event_publisher::EventSink, producer_connection::{Acks, EventCompression, KafkaConfig, TlsConfig, DEFAULT_ACK_TIMEOUT}, producers::{EventRouting, PartitionKey}, cloud_events::{CloudEvents, CloudEventsMode}, spool::{DEFAULT_REPLAY_INTERVAL, DEFAULT_SPOOL_MAX_BYTES}, stale_orders::StaleOrderConfig},
+};
 
 pub const DB_IP_ENV_ERR_MSG: &str = "Error finding database ip environment variable. Contact system administrator";
 pub const HBASE_DB_ENV_VAR: &str = "HBASE_IP";
 
 pub const KAFKA_IP_ENV_ERR_MSG: &str = "Error finding event-broker ip environment variable. Contact system administrator";
 pub const KAFKA_ENV_VAR: &str = "KAFKA_IP";
+pub const KAFKA_CLIENT_ID_ENV_VAR: &str = "KAFKA_CLIENT_ID";
+pub const KAFKA_ACKS_ENV_VAR: &str = "KAFKA_ACKS";
+pub const KAFKA_ACK_TIMEOUT_ENV_VAR: &str = "KAFKA_ACK_TIMEOUT_MS";
+pub const KAFKA_COMPRESSION_ENV_VAR: &str = "KAFKA_COMPRESSION";
+pub const KAFKA_TLS_ENV_VAR: &str = "KAFKA_TLS";
+pub const KAFKA_TLS_CA_FILE_ENV_VAR: &str = "KAFKA_TLS_CA_FILE";
+pub const KAFKA_TLS_CERT_FILE_ENV_VAR: &str = "KAFKA_TLS_CERT_FILE";
+pub const KAFKA_TLS_KEY_FILE_ENV_VAR: &str = "KAFKA_TLS_KEY_FILE";
+pub const KAFKA_TLS_VERIFY_HOSTNAME_ENV_VAR: &str = "KAFKA_TLS_VERIFY_HOSTNAME";
+pub const ORDER_CREATED_TOPIC_ENV_VAR: &str = "KAFKA_TOPIC_ORDER_CREATED";
+pub const ORDER_STATE_CHANGED_TOPIC_ENV_VAR: &str = "KAFKA_TOPIC_ORDER_STATE_CHANGED";
+pub const PARTITION_KEY_ENV_VAR: &str = "KAFKA_PARTITION_KEY";
+pub const CLOUDEVENTS_MODE_ENV_VAR: &str = "CLOUDEVENTS_MODE";
+pub const CLOUDEVENTS_SOURCE_ENV_VAR: &str = "CLOUDEVENTS_SOURCE";
+pub const EVENT_SINK_ENV_VAR: &str = "EVENT_SINK";
+pub const EVENT_SINK_PATH_ENV_VAR: &str = "EVENT_SINK_PATH";
+const DEFAULT_EVENT_SINK_PATH: &str = "events.jsonl";
+pub const EVENT_SPOOL_PATH_ENV_VAR: &str = "EVENT_SPOOL_PATH";
+pub const EVENT_SPOOL_MAX_BYTES_ENV_VAR: &str = "EVENT_SPOOL_MAX_BYTES";
+pub const EVENT_SPOOL_REPLAY_INTERVAL_ENV_VAR: &str = "EVENT_SPOOL_REPLAY_INTERVAL_MS";
+
+pub const CONSUMER_GROUP_ENV_VAR: &str = "KAFKA_CONSUMER_GROUP";
+const DEFAULT_CONSUMER_GROUP: &str = "order_service";
+pub const CONSUMER_MAX_ATTEMPTS_ENV_VAR: &str = "CONSUMER_MAX_ATTEMPTS";
+pub const CONSUMER_RETRY_BACKOFF_ENV_VAR: &str = "CONSUMER_RETRY_BACKOFF_MS";
+
+pub const DECODE_MODE_ENV_VAR: &str = "ORDER_DECODE_MODE";
+
+pub const OTLP_ENDPOINT_ENV_VAR: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+pub const SERVICE_NAME_ENV_VAR: &str = "OTEL_SERVICE_NAME";
+const DEFAULT_SERVICE_NAME: &str = "cust-order-service";
+
+pub const ADMIN_TOKEN_ENV_VAR: &str = "ADMIN_TOKEN";
+
+pub const NAMESPACE_ENV_VAR: &str = "HBASE_NAMESPACE";
+pub const ORDER_TABLE_ENV_VAR: &str = "ORDER_TABLE";
+pub const CUSTOMER_INDEX_TABLE_ENV_VAR: &str = "CUSTOMER_INDEX_TABLE";
+pub const MIGRATIONS_TABLE_ENV_VAR: &str = "MIGRATIONS_TABLE";
+pub const SAGAS_TABLE_ENV_VAR: &str = "SAGAS_TABLE";
+pub const LEASES_TABLE_ENV_VAR: &str = "LEASES_TABLE";
+/// Prefix of the per family settings, e.g. `HBASE_CF_OL=compression=SNAPPY;versions=1`.
+pub const FAMILY_SETTINGS_ENV_PREFIX: &str = "HBASE_CF_";
+pub const ORDER_REGIONS_ENV_VAR: &str = "ORDER_TABLE_REGIONS";
+
+pub const MIGRATE_ON_STARTUP_ENV_VAR: &str = "MIGRATE_ON_STARTUP";
+pub const MIGRATION_BATCH_SIZE_ENV_VAR: &str = "MIGRATION_BATCH_SIZE";
+
+pub const CALL_TIMEOUT_ENV_VAR: &str = "HBASE_CALL_TIMEOUT_MS";
+pub const CALL_DEADLINE_ENV_VAR: &str = "HBASE_CALL_DEADLINE_MS";
+pub const MAX_ATTEMPTS_ENV_VAR: &str = "HBASE_MAX_ATTEMPTS";
+pub const RETRY_BACKOFF_ENV_VAR: &str = "HBASE_RETRY_BACKOFF_MS";
+pub const RETRY_MAX_BACKOFF_ENV_VAR: &str = "HBASE_RETRY_MAX_BACKOFF_MS";
+pub const BREAKER_THRESHOLD_ENV_VAR: &str = "HBASE_BREAKER_FAILURE_THRESHOLD";
+pub const BREAKER_OPEN_ENV_VAR: &str = "HBASE_BREAKER_OPEN_MS";
+
+pub const THRIFT_API_ENV_VAR: &str = "HBASE_THRIFT_API";
+pub const THRIFT_TRANSPORT_ENV_VAR: &str = "HBASE_THRIFT_TRANSPORT";
+pub const THRIFT_PROTOCOL_ENV_VAR: &str = "HBASE_THRIFT_PROTOCOL";
+
+pub const UNHEALTHY_AFTER_ENV_VAR: &str = "HBASE_UNHEALTHY_AFTER";
+pub const PROBE_INTERVAL_ENV_VAR: &str = "HBASE_PROBE_INTERVAL_MS";
+
+pub const ORDER_STORE_ENV_VAR: &str = "ORDER_STORE";
+pub const EMBEDDED_STORE_PATH_ENV_VAR: &str = "EMBEDDED_STORE_PATH";
+const DEFAULT_EMBEDDED_STORE_PATH: &str = "order_service.db";
+
+pub const SAGA_ENABLED_ENV_VAR: &str = "SAGA_ENABLED";
+pub const SAGA_PAYMENT_TOPIC_ENV_VAR: &str = "SAGA_TOPIC_PAYMENT";
+pub const SAGA_RESTAURANT_TOPIC_ENV_VAR: &str = "SAGA_TOPIC_RESTAURANT";
+pub const SAGA_COURIER_TOPIC_ENV_VAR: &str = "SAGA_TOPIC_COURIER";
+pub const SAGA_REPLY_TOPIC_ENV_VAR: &str = "SAGA_TOPIC_REPLIES";
+pub const SAGA_PAYMENT_TIMEOUT_ENV_VAR: &str = "SAGA_PAYMENT_TIMEOUT_MS";
+pub const SAGA_RESTAURANT_TIMEOUT_ENV_VAR: &str = "SAGA_RESTAURANT_TIMEOUT_MS";
+pub const SAGA_COURIER_TIMEOUT_ENV_VAR: &str = "SAGA_COURIER_TIMEOUT_MS";
+pub const SAGA_SWEEP_INTERVAL_ENV_VAR: &str = "SAGA_SWEEP_INTERVAL_MS";
+
+pub const STALE_ORDERS_ENABLED_ENV_VAR: &str = "STALE_ORDERS_ENABLED";
+pub const PENDING_SLA_ENV_VAR: &str = "PENDING_SLA_MS";
+/// SLAs of single restaurants, e.g. `PENDING_SLA_BY_RESTAURANT=r1=600000,r2=1800000`.
+pub const PENDING_SLA_BY_RESTAURANT_ENV_VAR: &str = "PENDING_SLA_BY_RESTAURANT";
+pub const STALE_ORDERS_SWEEP_INTERVAL_ENV_VAR: &str = "STALE_ORDERS_SWEEP_INTERVAL_MS";
 
 pub fn get_env_var(var: &str) -> Option<String> {
-    match env::var(var) {
-        Ok(v) => Some(v),
-        Err(_) => None,
-    }
+    env::var(var).ok()
 }
 
 pub fn get_db_ip() -> Option<String> {
@@ -21,11 +106,338 @@ pub fn get_kafka_ip() -> Option<String> {
     get_env_var(KAFKA_ENV_VAR)
 }
 
+/// Brokers, acknowledgements, compression, client id and TLS of the Kafka connections. `KAFKA_IP` is a comma
+/// separated broker list, and may be unset while events go elsewhere. Unknown values are an error.
+pub fn get_kafka_config() -> Result<KafkaConfig, OrderServiceError> {
+    let set = |var: &str| get_env_var(var).map(|v| v.trim().to_owned()).filter(|v| !v.is_empty());
+    let brokers = get_kafka_ip().map(|v| parse_broker_list(&v)).unwrap_or_default();
+    let acks = match set(KAFKA_ACKS_ENV_VAR) {
+        Some(v) => Acks::from_str(&v)
+            .map_err(|_| OrderServiceError::InvalidConfig(format!("{} must be none, one or all, not '{}'", KAFKA_ACKS_ENV_VAR, v)))?,
+        None => Acks::default(),
+    };
+    let compression = match set(KAFKA_COMPRESSION_ENV_VAR) {
+        Some(v) => EventCompression::from_str(&v)
+            .map_err(|_| OrderServiceError::InvalidConfig(format!("{} must be none, gzip or snappy, not '{}'", KAFKA_COMPRESSION_ENV_VAR, v)))?,
+        None => EventCompression::default(),
+    };
+    let ack_timeout = match set(KAFKA_ACK_TIMEOUT_ENV_VAR) {
+        Some(v) => v.parse::<u64>().ok().filter(|v| *v > 0).map(Duration::from_millis)
+            .ok_or_else(|| OrderServiceError::InvalidConfig(format!("{} must be a positive number of milliseconds, not '{}'", KAFKA_ACK_TIMEOUT_ENV_VAR, v)))?,
+        None => DEFAULT_ACK_TIMEOUT,
+    };
+    let flag = |var: &str, default: bool| set(var).map(|v| matches!(v.as_str(), "true" | "1")).unwrap_or(default);
+    let tls = flag(KAFKA_TLS_ENV_VAR, false).then(|| TlsConfig {
+        ca_file: set(KAFKA_TLS_CA_FILE_ENV_VAR),
+        cert_file: set(KAFKA_TLS_CERT_FILE_ENV_VAR),
+        key_file: set(KAFKA_TLS_KEY_FILE_ENV_VAR),
+        verify_hostname: flag(KAFKA_TLS_VERIFY_HOSTNAME_ENV_VAR, true),
+    });
+    Ok(KafkaConfig {
+        brokers,
+        client_id: set(KAFKA_CLIENT_ID_ENV_VAR),
+        acks,
+        ack_timeout,
+        compression,
+        tls,
+    })
+}
+
+/// `host:port` entries of a comma separated list, without blanks.
+fn parse_broker_list(list: &str) -> Vec<String> {
+    list.split(',').map(str::trim).filter(|b| !b.is_empty()).map(str::to_owned).collect()
+}
+
+/// Topic names of the published events, the order field they are keyed by, and their CloudEvents mode.
+/// Unknown keys and modes are an error.
+pub fn get_event_routing() -> Result<EventRouting, OrderServiceError> {
+    let default = EventRouting::default();
+    let partition_key = match get_env_var(PARTITION_KEY_ENV_VAR).filter(|v| !v.is_empty()) {
+        Some(v) => PartitionKey::from_str(&v)
+            .map_err(|_| OrderServiceError::InvalidConfig(format!("{} must be none, o_id or r_id, not '{}'", PARTITION_KEY_ENV_VAR, v)))?,
+        None => default.partition_key,
+    };
+    let topic = |var: &str, default: String| get_env_var(var).filter(|v| !v.is_empty()).unwrap_or(default);
+    Ok(EventRouting {
+        order_created: topic(ORDER_CREATED_TOPIC_ENV_VAR, default.order_created),
+        order_state_changed: topic(ORDER_STATE_CHANGED_TOPIC_ENV_VAR, default.order_state_changed),
+        partition_key,
+        cloud_events: get_cloud_events()?,
+    })
+}
+
+fn get_cloud_events() -> Result<Option<CloudEvents>, OrderServiceError> {
+    let mode = match get_env_var(CLOUDEVENTS_MODE_ENV_VAR).filter(|v| !v.is_empty() && v.to_lowercase() != "none") {
+        Some(v) => CloudEventsMode::from_str(&v)
+            .map_err(|_| OrderServiceError::InvalidConfig(format!("{} must be none, structured or binary, not '{}'", CLOUDEVENTS_MODE_ENV_VAR, v)))?,
+        None => return Ok(None),
+    };
+    let source = get_env_var(CLOUDEVENTS_SOURCE_ENV_VAR).filter(|v| !v.is_empty()).unwrap_or_else(get_service_name);
+    Ok(Some(CloudEvents { mode, source }))
+}
+
+/// Where events are published, `kafka` when unset. Unknown values are an error.
+pub fn get_event_sink() -> Result<EventSink, OrderServiceError> {
+    match get_env_var(EVENT_SINK_ENV_VAR).filter(|v| !v.is_empty()) {
+        Some(v) => EventSink::from_str(&v)
+            .map_err(|_| OrderServiceError::InvalidConfig(format!("{} must be kafka, memory, jsonl or stdout, not '{}'", EVENT_SINK_ENV_VAR, v))),
+        None => Ok(EventSink::default()),
+    }
+}
+
+/// File the `jsonl` event sink appends to, `events.jsonl` in the working directory when unset.
+pub fn get_event_sink_path() -> String {
+    get_env_var(EVENT_SINK_PATH_ENV_VAR).filter(|v| !v.is_empty()).unwrap_or_else(|| DEFAULT_EVENT_SINK_PATH.to_owned())
+}
+
+/// File undelivered Kafka events are spooled to. Spooling is off when unset.
+pub fn get_event_spool_path() -> Option<String> {
+    get_env_var(EVENT_SPOOL_PATH_ENV_VAR).filter(|v| !v.is_empty())
+}
+
+/// Size the event spool may grow to before events are refused.
+pub fn get_event_spool_max_bytes() -> u64 {
+    get_env_var(EVENT_SPOOL_MAX_BYTES_ENV_VAR)
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_SPOOL_MAX_BYTES)
+}
+
+/// Time between attempts to replay spooled events.
+pub fn get_event_spool_replay_interval() -> Duration {
+    get_env_var(EVENT_SPOOL_REPLAY_INTERVAL_ENV_VAR)
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|v| *v > 0)
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_REPLAY_INTERVAL)
+}
+
+/// Consumer group of the service's Kafka consumers.
+pub fn get_consumer_group() -> String {
+    get_env_var(CONSUMER_GROUP_ENV_VAR).filter(|v| !v.is_empty()).unwrap_or_else(|| DEFAULT_CONSUMER_GROUP.to_owned())
+}
+
+/// Attempts per consumed message before it is dead-lettered, and the pause between them. Unset or invalid values keep their default.
+pub fn get_consumer_retry_policy() -> RetryPolicy {
+    let default = RetryPolicy::default();
+    RetryPolicy {
+        max_attempts: get_env_var(CONSUMER_MAX_ATTEMPTS_ENV_VAR).and_then(|v| v.parse::<u32>().ok()).filter(|v| *v > 0).unwrap_or(default.max_attempts),
+        backoff: get_env_var(CONSUMER_RETRY_BACKOFF_ENV_VAR).and_then(|v| v.parse::<u64>().ok()).map(Duration::from_millis).unwrap_or(default.backoff),
+    }
+}
+
+/// Decode mode for order rows, `strict` when unset. Unknown values are an error.
+pub fn get_decode_mode() -> Result<DecodeMode, OrderServiceError> {
+    match get_env_var(DECODE_MODE_ENV_VAR).filter(|v| !v.is_empty()) {
+        Some(v) => DecodeMode::from_str(&v)
+            .map_err(|_| OrderServiceError::InvalidConfig(format!("{} must be strict or lenient, not '{}'", DECODE_MODE_ENV_VAR, v))),
+        None => Ok(DecodeMode::default()),
+    }
+}
+
+/// Where orders are stored, `hbase` when unset. Unknown values are an error.
+pub fn get_order_store() -> Result<OrderStore, OrderServiceError> {
+    match get_env_var(ORDER_STORE_ENV_VAR).filter(|v| !v.is_empty()) {
+        Some(v) => OrderStore::from_str(&v)
+            .map_err(|_| OrderServiceError::InvalidConfig(format!("{} must be hbase or embedded, not '{}'", ORDER_STORE_ENV_VAR, v))),
+        None => Ok(OrderStore::default()),
+    }
+}
+
+/// Topics and step timeouts of the order creation saga, `None` unless `SAGA_ENABLED` is `true` or `1`. Unset or
+/// invalid timeouts keep their default. The saga state is kept in HBase, so the embedded store cannot run it.
+pub fn get_saga_config() -> Result<Option<SagaConfig>, OrderServiceError> {
+    if !matches!(get_env_var(SAGA_ENABLED_ENV_VAR).as_deref().map(str::trim), Some("true") | Some("1")) {
+        return Ok(None);
+    }
+    if get_order_store()? == OrderStore::Embedded {
+        return Err(OrderServiceError::InvalidConfig(format!("{} needs the hbase order store", SAGA_ENABLED_ENV_VAR)));
+    }
+    let default = SagaConfig::default();
+    let topic = |var: &str, default: String| get_env_var(var).filter(|v| !v.is_empty()).unwrap_or(default);
+    let millis = |var: &str, default: Duration| get_env_var(var).and_then(|v| v.parse::<u64>().ok()).map(Duration::from_millis).unwrap_or(default);
+    Ok(Some(SagaConfig {
+        topics: SagaTopics {
+            payment: topic(SAGA_PAYMENT_TOPIC_ENV_VAR, default.topics.payment),
+            restaurant: topic(SAGA_RESTAURANT_TOPIC_ENV_VAR, default.topics.restaurant),
+            courier: topic(SAGA_COURIER_TOPIC_ENV_VAR, default.topics.courier),
+            replies: topic(SAGA_REPLY_TOPIC_ENV_VAR, default.topics.replies),
+        },
+        timeouts: StepTimeouts {
+            payment: millis(SAGA_PAYMENT_TIMEOUT_ENV_VAR, default.timeouts.payment),
+            restaurant: millis(SAGA_RESTAURANT_TIMEOUT_ENV_VAR, default.timeouts.restaurant),
+            courier: millis(SAGA_COURIER_TIMEOUT_ENV_VAR, default.timeouts.courier),
+        },
+        sweep_interval: millis(SAGA_SWEEP_INTERVAL_ENV_VAR, default.sweep_interval),
+    }))
+}
+
+/// SLAs and sweep interval of the stale order sweep, `None` unless `STALE_ORDERS_ENABLED` is `true` or `1`. Unset or
+/// invalid durations keep their default, a malformed restaurant SLA is an error. Orders with a saga are timed out by
+/// the saga, so the sweep cannot run with it, nor with the embedded store, which has no leases.
+pub fn get_stale_order_config() -> Result<Option<StaleOrderConfig>, OrderServiceError> {
+    if !matches!(get_env_var(STALE_ORDERS_ENABLED_ENV_VAR).as_deref().map(str::trim), Some("true") | Some("1")) {
+        return Ok(None);
+    }
+    if get_order_store()? == OrderStore::Embedded {
+        return Err(OrderServiceError::InvalidConfig(format!("{} needs the hbase order store", STALE_ORDERS_ENABLED_ENV_VAR)));
+    }
+    if get_saga_config()?.is_some() {
+        return Err(OrderServiceError::InvalidConfig(format!("{} cannot be combined with {}, the saga times out the restaurant step", STALE_ORDERS_ENABLED_ENV_VAR, SAGA_ENABLED_ENV_VAR)));
+    }
+    let default = StaleOrderConfig::default();
+    let millis = |var: &str, default: Duration| get_env_var(var).and_then(|v| v.parse::<u64>().ok()).filter(|v| *v > 0).map(Duration::from_millis).unwrap_or(default);
+    Ok(Some(StaleOrderConfig {
+        default_sla: millis(PENDING_SLA_ENV_VAR, default.default_sla),
+        restaurant_slas: get_env_var(PENDING_SLA_BY_RESTAURANT_ENV_VAR).map(|v| parse_restaurant_slas(&v)).transpose()?.unwrap_or_default(),
+        sweep_interval: millis(STALE_ORDERS_SWEEP_INTERVAL_ENV_VAR, default.sweep_interval),
+    }))
+}
+
+/// `r_id=millis` entries of a comma separated list.
+fn parse_restaurant_slas(list: &str) -> Result<HashMap<String, Duration>, OrderServiceError> {
+    list.split(',').map(str::trim).filter(|e| !e.is_empty()).map(|entry| {
+        let invalid = || OrderServiceError::InvalidConfig(format!("{} entries must be r_id=millis, not '{}'", PENDING_SLA_BY_RESTAURANT_ENV_VAR, entry));
+        let (r_id, sla) = entry.split_once('=').ok_or_else(invalid)?;
+        let sla = sla.trim().parse::<u64>().ok().filter(|v| *v > 0).ok_or_else(invalid)?;
+        Ok((r_id.trim().to_owned(), Duration::from_millis(sla)))
+    }).collect()
+}
+
+/// Directory of the embedded order store, `order_service.db` in the working directory when unset.
+pub fn get_embedded_store_path() -> String {
+    get_env_var(EMBEDDED_STORE_PATH_ENV_VAR).filter(|v| !v.is_empty()).unwrap_or_else(|| DEFAULT_EMBEDDED_STORE_PATH.to_owned())
+}
+
+/// Base url of the OTLP/HTTP collector, e.g. `http://otel-collector:4318`. Trace and metric export is off when unset.
+pub fn get_otlp_endpoint() -> Option<String> {
+    get_env_var(OTLP_ENDPOINT_ENV_VAR).filter(|v| !v.is_empty())
+}
+
+pub fn get_service_name() -> String {
+    get_env_var(SERVICE_NAME_ENV_VAR).unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_owned())
+}
+
+/// Bearer token for the `/admin` routes. The routes answer 403 while it is unset.
+pub fn get_admin_token() -> Option<String> {
+    get_env_var(ADMIN_TOKEN_ENV_VAR).filter(|v| !v.is_empty())
+}
+
+/// Table names, qualified with `HBASE_NAMESPACE` when it is set. Each name can be overridden on its own.
+/// Also reads the column family settings and the number of order table regions.
+pub fn get_table_config() -> Result<Tables, OrderServiceError> {
+    let name = |var: &str, default: &str| get_env_var(var).filter(|v| !v.is_empty()).unwrap_or_else(|| default.to_owned());
+    let mut tables = Tables::new(
+        get_env_var(NAMESPACE_ENV_VAR).filter(|v| !v.is_empty()).as_deref(),
+        &name(ORDER_TABLE_ENV_VAR, DEFAULT_ORDER_TABLE),
+        &name(CUSTOMER_INDEX_TABLE_ENV_VAR, DEFAULT_CUSTOMER_INDEX_TABLE),
+        &name(MIGRATIONS_TABLE_ENV_VAR, DEFAULT_MIGRATIONS_TABLE),
+        &name(SAGAS_TABLE_ENV_VAR, DEFAULT_SAGAS_TABLE),
+        &name(LEASES_TABLE_ENV_VAR, DEFAULT_LEASES_TABLE),
+    )?;
+    let families = ORDER_FAMILIES.iter().chain(CUSTOMER_INDEX_FAMILIES.iter()).chain([MIGRATIONS_FAMILY, SAGA_FAMILY, LEASE_FAMILY].iter());
+    for family in families {
+        if let Some(settings) = get_env_var(&format!("{}{}", FAMILY_SETTINGS_ENV_PREFIX, family.to_uppercase())) {
+            tables = tables.with_family(ColumnFamilySpec::new(*family).with_settings(&settings)?);
+        }
+    }
+    if let Some(regions) = get_env_var(ORDER_REGIONS_ENV_VAR) {
+        let regions = regions.parse::<u32>().ok().filter(|r| (1..=256).contains(r)).ok_or_else(|| {
+            OrderServiceError::InvalidConfig(format!("{} must be between 1 and 256, got '{}'", ORDER_REGIONS_ENV_VAR, regions))
+        })?;
+        tables = tables.with_order_regions(regions);
+    }
+    Ok(tables)
+}
+
+/// Whether pending migrations are applied before the server starts. Off unless set to `true` or `1`.
+pub fn get_migrate_on_startup() -> bool {
+    matches!(get_env_var(MIGRATE_ON_STARTUP_ENV_VAR).as_deref().map(str::trim), Some("true") | Some("1"))
+}
+
+/// Rows fetched and written per round trip by backfill migrations.
+pub fn get_migration_batch_size() -> i32 {
+    get_env_var(MIGRATION_BATCH_SIZE_ENV_VAR)
+        .and_then(|v| v.parse::<i32>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_BATCH_SIZE)
+}
+
+/// Retry, timeout and circuit breaker settings for HBase calls. Unset or invalid values keep their default.
+pub fn get_resilience_policy() -> ResiliencePolicy {
+    let default = ResiliencePolicy::default();
+    let millis = |var: &str| get_env_var(var).and_then(|v| v.parse::<u64>().ok()).map(Duration::from_millis);
+    let count = |var: &str| get_env_var(var).and_then(|v| v.parse::<u32>().ok()).filter(|v| *v > 0);
+    ResiliencePolicy {
+        call_timeout: millis(CALL_TIMEOUT_ENV_VAR).filter(|d| !d.is_zero()).unwrap_or(default.call_timeout),
+        deadline: millis(CALL_DEADLINE_ENV_VAR).unwrap_or(default.deadline),
+        max_attempts: count(MAX_ATTEMPTS_ENV_VAR).unwrap_or(default.max_attempts),
+        base_backoff: millis(RETRY_BACKOFF_ENV_VAR).unwrap_or(default.base_backoff),
+        max_backoff: millis(RETRY_MAX_BACKOFF_ENV_VAR).unwrap_or(default.max_backoff),
+        failure_threshold: count(BREAKER_THRESHOLD_ENV_VAR).unwrap_or(default.failure_threshold),
+        open_duration: millis(BREAKER_OPEN_ENV_VAR).unwrap_or(default.open_duration),
+    }
+}
+
+/// API, transport and protocol of the HBase Thrift server, `thrift1`, `buffered` and `binary` when unset.
+/// Unknown values are an error, since the service cannot talk to the server with a wrong guess.
+pub fn get_thrift_config() -> Result<ThriftConfig, OrderServiceError> {
+    let api = match get_env_var(THRIFT_API_ENV_VAR).filter(|v| !v.is_empty()) {
+        Some(v) => ThriftApi::from_str(&v)
+            .map_err(|_| OrderServiceError::InvalidConfig(format!("{} must be thrift1 or thrift2, not '{}'", THRIFT_API_ENV_VAR, v)))?,
+        None => ThriftApi::default(),
+    };
+    let transport = match get_env_var(THRIFT_TRANSPORT_ENV_VAR).filter(|v| !v.is_empty()) {
+        Some(v) => ThriftTransport::from_str(&v)
+            .map_err(|_| OrderServiceError::InvalidConfig(format!("{} must be buffered, framed or http, not '{}'", THRIFT_TRANSPORT_ENV_VAR, v)))?,
+        None => ThriftTransport::default(),
+    };
+    let protocol = match get_env_var(THRIFT_PROTOCOL_ENV_VAR).filter(|v| !v.is_empty()) {
+        Some(v) => ThriftProtocol::from_str(&v)
+            .map_err(|_| OrderServiceError::InvalidConfig(format!("{} must be binary or compact, not '{}'", THRIFT_PROTOCOL_ENV_VAR, v)))?,
+        None => ThriftProtocol::default(),
+    };
+    Ok(ThriftConfig { api, transport, protocol, ..Default::default() })
+}
+
+/// Consecutive transient errors after which an HBase endpoint is taken out of rotation.
+pub fn get_unhealthy_after() -> u32 {
+    get_env_var(UNHEALTHY_AFTER_ENV_VAR)
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_UNHEALTHY_AFTER)
+}
+
+/// Time an unhealthy HBase endpoint is left alone before it is probed again.
+pub fn get_probe_interval() -> Duration {
+    get_env_var(PROBE_INTERVAL_ENV_VAR)
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_PROBE_INTERVAL)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::env::{set_var, remove_var};
     
+    #[test]
+    fn test_parse_broker_list() {
+        assert_eq!(parse_broker_list("kafka-1:9092"), ["kafka-1:9092"]);
+        assert_eq!(parse_broker_list(" kafka-1:9092, kafka-2:9092,,"), ["kafka-1:9092", "kafka-2:9092"]);
+        assert!(parse_broker_list("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_restaurant_slas() {
+        let slas = parse_restaurant_slas("r1=600000, r2 = 60000,").unwrap();
+        assert_eq!(slas, HashMap::from([("r1".to_owned(), Duration::from_secs(600)), ("r2".to_owned(), Duration::from_secs(60))]));
+        assert!(parse_restaurant_slas("").unwrap().is_empty());
+        assert!(parse_restaurant_slas("r1").is_err());
+        assert!(parse_restaurant_slas("r1=soon").is_err());
+        assert!(parse_restaurant_slas("r1=0").is_err());
+    }
+
     #[test]
     #[ignore = "These tests interact in a way that make them fail randomly."]
     fn test_get_env_var_not_set() {
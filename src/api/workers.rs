@@ -1,34 +1,197 @@
+use std::sync::atomic::AtomicBool;
+
 use actix_web::{web};
 
-use crate::{models::{orders::{CreateOrder, Order, OrderInfo}, tables::TableName, errors::OrderServiceError}, repository::{hbase_connection::HbaseConnection, hbase}, producers::{producers, producer_connection::KafkaProdConnection}};
+#[cfg(feature = "embedded")]
+use crate::{api::utils::env::get_embedded_store_path, repository::embedded::EmbeddedRepository};
+use crate::{consumers::{consumer::{ConsumedMessage, MessageConsumer}, consumer_connection::KafkaConsumerConnection, dlq::{dlq_topic, redrive, RedriveReport}, reconcile::{reconcile, ReconcileOptions, ReconcileReport}, saga}, api::utils::env::{get_consumer_group, get_consumer_retry_policy, get_db_ip, get_migration_batch_size, get_saga_config, get_stale_order_config, get_env_var, get_table_config, get_decode_mode, get_event_sink, get_event_sink_path, get_event_spool_path, get_event_routing, get_event_spool_replay_interval, get_kafka_config, get_order_store, DB_IP_ENV_ERR_MSG, SAGA_ENABLED_ENV_VAR}, models::{orders::{CreateOrder, Order, OrderInfo}, schema::{SchemaDrift, TableSchema}, tables::{TableName, Tables}, errors::OrderServiceError}, repository::{resilience::ResilientClient, hbase, migrations::{self, MigrationLease, MigrationReport}, order_repository::{HbaseRepository, OrderRepository, OrderStore}}, producers::{producers, event_publisher::{EventPublisher, EventSink, InMemoryPublisher, JsonlPublisher, StdoutPublisher}, producer_connection::KafkaProdConnection, replay::{replay_orders, ReplayOptions, ReplayReport}, spool::{spawn_replayer, EventSpool, SpoolingPublisher}, stale_orders::{spawn_sweeper, StaleOrderSweeper}}};
+
+/// The order store selected by `ORDER_STORE`.
+pub fn order_repository(tables: &Tables) -> Result<Box<dyn OrderRepository>, OrderServiceError> {
+    match get_order_store()? {
+        OrderStore::Hbase => {
+            let db_ip = get_db_ip().ok_or_else(|| OrderServiceError::InvalidConfig(DB_IP_ENV_ERR_MSG.into()))?;
+            Ok(Box::new(HbaseRepository::new(db_ip, tables.clone())))
+        }
+        #[cfg(feature = "embedded")]
+        OrderStore::Embedded => Ok(Box::new(EmbeddedRepository::open(get_embedded_store_path(), tables.clone())?)),
+        #[cfg(not(feature = "embedded"))]
+        OrderStore::Embedded => Err(OrderServiceError::InvalidConfig("the embedded store needs a build with the `embedded` feature".into())),
+    }
+}
+
+/// The event sink selected by `EVENT_SINK`. Kafka is connected to here, the other sinks cannot fail to open.
+/// With `EVENT_SPOOL_PATH` set, events Kafka does not take are spooled, and a failed connect is not an error.
+pub fn event_publisher() -> Result<Box<dyn EventPublisher>, OrderServiceError> {
+    Ok(match get_event_sink()? {
+        EventSink::Kafka => match get_event_spool_path() {
+            None => Box::new(connect_kafka()?),
+            Some(path) => {
+                let kafka = connect_kafka()
+                    .inspect_err(|e| tracing::warn!(error = %e, "cannot connect to kafka, spooling events"))
+                    .ok();
+                Box::new(SpoolingPublisher::new(kafka, EventSpool::shared(&path)?))
+            }
+        },
+        EventSink::Memory => Box::new(InMemoryPublisher::shared()),
+        EventSink::Jsonl => Box::new(JsonlPublisher::new(get_event_sink_path())),
+        EventSink::Stdout => Box::new(StdoutPublisher),
+    })
+}
+
+fn connect_kafka() -> Result<KafkaProdConnection, OrderServiceError> {
+    KafkaProdConnection::connect_with(&get_kafka_config()?)
+}
+
+/// Opens the event spool, replays what an earlier run left in it, and keeps replaying it in the background.
+/// Does nothing unless events go to Kafka and `EVENT_SPOOL_PATH` is set.
+pub fn start_event_spool() -> Result<(), OrderServiceError> {
+    let (EventSink::Kafka, Some(path)) = (get_event_sink()?, get_event_spool_path()) else { return Ok(()) };
+    let spool = EventSpool::shared(&path)?;
+    if !spool.is_empty() {
+        tracing::info!(spooled = spool.len(), "replaying events spooled by an earlier run");
+        if let Err(e) = connect_kafka().and_then(|mut kafka| spool.replay(&mut kafka)) {
+            tracing::warn!(error = %e, spooled = spool.len(), "spooled events not replayed yet");
+        }
+    }
+    spawn_replayer(spool, get_event_spool_replay_interval(), connect_kafka);
+    Ok(())
+}
+
+/// Starts the stale order sweep on a background thread. Does nothing unless `STALE_ORDERS_ENABLED` is set.
+pub fn start_stale_order_sweeper() -> Result<(), OrderServiceError> {
+    let Some(config) = get_stale_order_config()? else { return Ok(()) };
+    let db_ip = get_db_ip().ok_or_else(|| OrderServiceError::InvalidConfig(DB_IP_ENV_ERR_MSG.into()))?;
+    let holder = lease_holder();
+    tracing::info!(holder = holder.as_str(), sweep_interval_ms = config.sweep_interval.as_millis() as u64, "starting the stale order sweep");
+    let sweeper = StaleOrderSweeper { tables: get_table_config()?, config, routing: get_event_routing()?, holder, batch_size: get_migration_batch_size() };
+    spawn_sweeper(sweeper, move || Ok((ResilientClient::connect(&db_ip)?, event_publisher()?)));
+    Ok(())
+}
+
+/// Names this process as a lease holder. Unique per process, so a restarted pod does not take over the lease of its
+/// previous run.
+fn lease_holder() -> String {
+    format!("{}-{}", get_env_var("HOSTNAME").unwrap_or_else(|| "order_service".to_owned()), uuid::Uuid::new_v4())
+}
+
+/// Publishes the messages of `<topic>.DLQ` back to `topic`, at most `limit` of them.
+pub fn redrive_dlq(topic: &str, limit: Option<usize>) -> Result<RedriveReport, OrderServiceError> {
+    let mut source = KafkaConsumerConnection::connect(&get_kafka_config()?, &dlq_topic(topic), &format!("{}.redrive", get_consumer_group()))?;
+    redrive(&mut source, &mut event_publisher()?, limit)
+}
+
+/// Re-publishes the events of the orders in HBase matching `options`. A dry run does not open the event sink.
+pub fn replay_events(db_ip: &str, tables: &Tables, batch_size: i32, options: &ReplayOptions) -> Result<ReplayReport, OrderServiceError> {
+    let mut publisher: Box<dyn EventPublisher> = if options.dry_run { Box::new(InMemoryPublisher::new()) } else { event_publisher()? };
+    let mut con = ResilientClient::connect(db_ip)?;
+    replay_orders(&mut con, tables, batch_size, &get_event_routing()?, options, publisher.as_mut())
+}
+
+/// Compares the `OrderCreated` topic with the orders in HBase. Reads the topic as `<KAFKA_CONSUMER_GROUP>.reconcile`,
+/// and only opens the event sink when missing events are republished.
+pub fn reconcile_events(db_ip: &str, tables: &Tables, batch_size: i32, options: &ReconcileOptions) -> Result<ReconcileReport, OrderServiceError> {
+    let routing = get_event_routing()?;
+    let topic = options.topic.clone().unwrap_or_else(|| routing.order_created.clone());
+    let mut source = KafkaConsumerConnection::connect(&get_kafka_config()?, &topic, &format!("{}.reconcile", get_consumer_group()))?;
+    let mut publisher: Box<dyn EventPublisher> = if options.republish { event_publisher()? } else { Box::new(InMemoryPublisher::new()) };
+    let mut con = ResilientClient::connect(db_ip)?;
+    reconcile(&mut con, tables, batch_size, &mut source, &routing, options, publisher.as_mut())
+}
 
-pub fn create_order(param_obj: web::Json<CreateOrder>, db_ip: &str, kafka_ip: &str) -> Result<Order, OrderServiceError> {
-    let hbase_con = HbaseConnection::connect(db_ip)?;
+/// Stores the order and publishes its `OrderCreated` event. With `SAGA_ENABLED` set, the order's saga is started
+/// in between.
+pub fn create_order(param_obj: web::Json<CreateOrder>, repository: &dyn OrderRepository, publisher: &mut dyn EventPublisher, request_id: &str) -> Result<Order, OrderServiceError> {
     let order = Order::from(param_obj);
-    let _o_id = hbase::add_order(&order, hbase_con)?;
+    // Resolved before the order is stored, so a request that cannot go through does not leave an order behind.
+    let saga_config = get_saga_config()?;
+    if saga_config.is_some() && !repository.supports_sagas() {
+        return Err(OrderServiceError::InvalidConfig(format!("{} needs the hbase order store", SAGA_ENABLED_ENV_VAR)));
+    }
+    let routing = get_event_routing()?;
+    let _o_id = repository.add_order(&order)?;
 
-    let mut kafka_con = KafkaProdConnection::connect(kafka_ip.into())?;
-    producers::publish_order_created(&order, &mut kafka_con)?;
+    if let Some(config) = saga_config {
+        repository.start_saga(&order, &config, request_id, publisher)?;
+    }
+    producers::publish_order_created(&order, request_id, &routing, publisher)?;
 
     Ok(order)
 }
 
-pub fn get_tables(db_ip: &str) -> Result<Vec<TableName>, OrderServiceError> {
-    let con = HbaseConnection::connect(db_ip)?;
-    hbase::get_tables(con)
+/// Runs the saga orchestrator until `stop` is set: handles the replies on the reply topic as
+/// `<KAFKA_CONSUMER_GROUP>.saga`, and compensates timed out steps every sweep interval while it holds the
+/// `saga-expiry` lease.
+pub fn run_saga_orchestrator(stop: &AtomicBool) -> Result<(), OrderServiceError> {
+    let config = get_saga_config()?.ok_or_else(|| OrderServiceError::InvalidConfig(format!("the saga orchestrator needs {}=true", SAGA_ENABLED_ENV_VAR)))?;
+    let db_ip = get_db_ip().ok_or_else(|| OrderServiceError::InvalidConfig(DB_IP_ENV_ERR_MSG.into()))?;
+    let tables = get_table_config()?;
+    let routing = get_event_routing()?;
+    let source = KafkaConsumerConnection::connect(&get_kafka_config()?, &config.topics.replies, &format!("{}.saga", get_consumer_group()))?;
+    let (mut reply_client, mut reply_publisher) = (ResilientClient::connect(&db_ip)?, event_publisher()?);
+    let handler = |message: &ConsumedMessage| saga::handle_reply(&mut reply_client, &tables, &config, &routing, message, reply_publisher.as_mut());
+    let mut consumer = MessageConsumer::new(source, handler, event_publisher()?, get_consumer_retry_policy());
+    let (mut sweep_client, mut sweep_publisher) = (ResilientClient::connect(&db_ip)?, event_publisher()?);
+    let holder = lease_holder();
+    let sweep = || saga::expire_sagas_with_lease(&mut sweep_client, &tables, &config, &routing, &holder, get_migration_batch_size(), sweep_publisher.as_mut())
+        .map(Option::unwrap_or_default);
+    saga::run_orchestrator(&mut consumer, sweep, config.sweep_interval, stop)
 }
 
-pub fn create_table(db_ip: &str) -> Result<(), OrderServiceError> {
-    let con = HbaseConnection::connect(db_ip)?;
-    hbase::create_order_table(con)
+pub fn get_tables(repository: &dyn OrderRepository) -> Result<Vec<TableName>, OrderServiceError> {
+    repository.get_tables()
 }
 
-pub fn get_row(row_id: &str, db_ip: &str) -> Result<Order, OrderServiceError> {
-    let con = HbaseConnection::connect(db_ip)?;
-    hbase::get_order_row(row_id, con)
+pub fn create_table(db_ip: &str, tables: &Tables) -> Result<(), OrderServiceError> {
+    HbaseRepository::new(db_ip, tables.clone()).create_tables()
+}
+
+pub fn ensure_order_table(db_ip: &str, tables: &Tables) -> Result<TableSchema, OrderServiceError> {
+    let con = ResilientClient::connect(db_ip)?;
+    hbase::ensure_order_table(tables, con)
+}
+
+pub fn describe_order_table(db_ip: &str, tables: &Tables) -> Result<TableSchema, OrderServiceError> {
+    let con = ResilientClient::connect(db_ip)?;
+    hbase::describe_order_table(tables, con)
+}
+
+pub fn order_table_drift(db_ip: &str, tables: &Tables) -> Result<SchemaDrift, OrderServiceError> {
+    let con = ResilientClient::connect(db_ip)?;
+    hbase::order_table_drift(tables, con)
+}
+
+pub fn run_migrations(db_ip: &str, tables: &Tables, batch_size: i32) -> Result<Vec<MigrationReport>, OrderServiceError> {
+    let mut con = ResilientClient::connect(db_ip)?;
+    let lease = MigrationLease::new(lease_holder());
+    migrations::run_pending_with_lease(&mut con, tables, &migrations::migrations(tables), batch_size, &lease)
+}
+
+pub fn rebuild_customer_index(db_ip: &str, tables: &Tables, batch_size: i32) -> Result<MigrationReport, OrderServiceError> {
+    let mut con = ResilientClient::connect(db_ip)?;
+    migrations::rebuild_customer_index(&mut con, tables, batch_size)
+}
+
+/// Versions and names of the migrations that have not been applied yet.
+pub fn pending_migrations(db_ip: &str, tables: &Tables) -> Result<Vec<(u32, &'static str)>, OrderServiceError> {
+    let mut con = ResilientClient::connect(db_ip)?;
+    let all = migrations::migrations(tables);
+    let pending = migrations::pending(&mut con, tables, &all)?;
+    Ok(pending.iter().map(|m| (m.version, m.name)).collect())
+}
+
+pub fn get_row(row_id: &str, repository: &dyn OrderRepository) -> Result<Order, OrderServiceError> {
+    let (order, warnings) = repository.get_order(row_id, get_decode_mode()?)?;
+    for warning in warnings {
+        tracing::warn!(o_id = row_id, warning = %warning, "order row decoded with warnings");
+    }
+    Ok(order)
 }
 
-pub fn get_orders_info_by_user(user_id: &str, db_ip: &str) -> Result<Vec<OrderInfo>, OrderServiceError> {
-    let con = HbaseConnection::connect(db_ip)?;
-    hbase::get_orders_info_by_user(user_id.to_string(), con)
+pub fn get_orders_info_by_user(user_id: &str, repository: &dyn OrderRepository) -> Result<Vec<OrderInfo>, OrderServiceError> {
+    let (orders, skipped) = repository.get_orders_by_customer(user_id, get_decode_mode()?)?;
+    if skipped > 0 {
+        tracing::warn!(c_id = user_id, skipped, "skipped order rows that do not decode");
+    }
+    Ok(orders)
 }
\ No newline at end of file
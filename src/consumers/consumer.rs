@@ -0,0 +1,281 @@
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+use opentelemetry::{global, metrics::Counter, KeyValue};
+
+use crate::{
+    models::errors::OrderServiceError,
+    producers::event_publisher::{EventHeaders, EventPublisher},
+};
+use super::dlq;
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(200);
+/// Pause after a poll that returned nothing.
+const IDLE_WAIT: Duration = Duration::from_millis(500);
+
+/// A message read from a topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumedMessage {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub headers: EventHeaders,
+}
+
+impl ConsumedMessage {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+}
+
+/// Where a consumer reads messages from.
+#[cfg_attr(test, mockall::automock)]
+pub trait MessageSource {
+    /// The next messages, none when there is nothing new.
+    fn poll(&mut self) -> Result<Vec<ConsumedMessage>, OrderServiceError>;
+    /// Records that the message, and those before it in its partition, are done with.
+    fn commit(&mut self, message: &ConsumedMessage) -> Result<(), OrderServiceError>;
+    /// The offset after the last message of each partition that holds messages, as of now. Empty when the source
+    /// cannot tell.
+    fn end_offsets(&mut self) -> Result<BTreeMap<i32, i64>, OrderServiceError> {
+        Ok(BTreeMap::new())
+    }
+    /// The offset of the next message the source hands out, by partition.
+    fn positions(&mut self) -> Result<BTreeMap<i32, i64>, OrderServiceError> {
+        Ok(BTreeMap::new())
+    }
+}
+
+pub trait MessageHandler {
+    fn handle(&mut self, message: &ConsumedMessage) -> Result<(), OrderServiceError>;
+}
+
+impl<F: FnMut(&ConsumedMessage) -> Result<(), OrderServiceError>> MessageHandler for F {
+    fn handle(&mut self, message: &ConsumedMessage) -> Result<(), OrderServiceError> {
+        self(message)
+    }
+}
+
+/// How often a message is handled before it goes to the dead-letter topic, and the pause between attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_attempts: DEFAULT_MAX_ATTEMPTS, backoff: DEFAULT_RETRY_BACKOFF }
+    }
+}
+
+/// Messages handled and dead-lettered by one poll.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConsumeReport {
+    pub handled: usize,
+    pub dead_lettered: usize,
+}
+
+/// Errors that come back on every attempt, such as a message that does not parse. They are not retried.
+fn is_permanent(e: &OrderServiceError) -> bool {
+    matches!(
+        e,
+        OrderServiceError::JSONParseError(_) | OrderServiceError::TimeParseError(_) | OrderServiceError::IntParseError(_) | OrderServiceError::OrderBuildFailed(_)
+    )
+}
+
+/// Reads messages from `source` and hands them to `handler`. A message the handler keeps failing on is published
+/// to `<topic>.DLQ` through `dead_letters`, so a bad message does not hold up its partition. A message is committed
+/// once it is handled or dead-lettered.
+pub struct MessageConsumer<S, H, P> {
+    source: S,
+    handler: H,
+    dead_letters: P,
+    policy: RetryPolicy,
+    messages: Counter<u64>,
+}
+
+impl<S: MessageSource, H: MessageHandler, P: EventPublisher> MessageConsumer<S, H, P> {
+    pub fn new(source: S, handler: H, dead_letters: P, policy: RetryPolicy) -> Self {
+        let messages = global::meter("order_service").u64_counter("events.consumer.messages")
+            .with_description("Consumed messages, by topic and outcome")
+            .build();
+        Self { source, handler, dead_letters, policy, messages }
+    }
+
+    /// Polls once and handles what came back. Fails when the source fails, or when a message cannot be
+    /// dead-lettered. That message is not committed then.
+    pub fn poll_once(&mut self) -> Result<ConsumeReport, OrderServiceError> {
+        let mut report = ConsumeReport::default();
+        for message in self.source.poll()? {
+            let outcome = match self.handle_with_retry(&message) {
+                Ok(()) => {
+                    report.handled += 1;
+                    "handled"
+                }
+                Err((e, attempts)) => {
+                    dlq::dead_letter(&mut self.dead_letters, &message, &e, attempts)?;
+                    report.dead_lettered += 1;
+                    "dead_lettered"
+                }
+            };
+            self.messages.add(1, &[KeyValue::new("topic", message.topic.clone()), KeyValue::new("outcome", outcome)]);
+            self.source.commit(&message)?;
+        }
+        Ok(report)
+    }
+
+    /// Polls until `stop` is set.
+    pub fn run(&mut self, stop: &AtomicBool) -> Result<(), OrderServiceError> {
+        while !stop.load(Ordering::Relaxed) {
+            let report = self.poll_once()?;
+            if report.handled + report.dead_lettered == 0 {
+                thread::sleep(IDLE_WAIT);
+            }
+        }
+        Ok(())
+    }
+
+    /// The last error and the number of attempts when the message could not be handled.
+    fn handle_with_retry(&mut self, message: &ConsumedMessage) -> Result<(), (OrderServiceError, u32)> {
+        let mut attempt = 1;
+        loop {
+            match self.handler.handle(message) {
+                Ok(()) => return Ok(()),
+                Err(e) if is_permanent(&e) || attempt >= self.policy.max_attempts => return Err((e, attempt)),
+                Err(e) => {
+                    tracing::warn!(error = %e, topic = message.topic.as_str(), partition = message.partition, offset = message.offset, attempt, "message handling failed, retrying");
+                    thread::sleep(self.policy.backoff);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use crate::producers::event_publisher::{InMemoryPublisher, MockEventPublisher};
+    use super::dlq::{DLQ_ATTEMPTS_HEADER, DLQ_ERROR_HEADER, DLQ_OFFSET_HEADER, DLQ_PARTITION_HEADER, DLQ_TOPIC_HEADER};
+
+    /// Hands out `batches` one per poll, and records the commits.
+    #[derive(Default)]
+    pub(crate) struct VecSource {
+        pub batches: VecDeque<Vec<ConsumedMessage>>,
+        pub committed: Vec<i64>,
+    }
+
+    impl MessageSource for VecSource {
+        fn poll(&mut self) -> Result<Vec<ConsumedMessage>, OrderServiceError> {
+            Ok(self.batches.pop_front().unwrap_or_default())
+        }
+        fn commit(&mut self, message: &ConsumedMessage) -> Result<(), OrderServiceError> {
+            self.committed.push(message.offset);
+            Ok(())
+        }
+    }
+
+    pub(crate) fn message(topic: &str, offset: i64, value: &str) -> ConsumedMessage {
+        ConsumedMessage { topic: topic.into(), partition: 2, offset, key: vec![], value: value.into(), headers: vec![("X-Request-Id".into(), format!("req-{}", offset))] }
+    }
+
+    fn source(messages: Vec<ConsumedMessage>) -> VecSource {
+        VecSource { batches: VecDeque::from([messages]), committed: vec![] }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy { max_attempts: 3, backoff: Duration::ZERO }
+    }
+
+    fn parse(message: &ConsumedMessage) -> Result<(), OrderServiceError> {
+        serde_json::from_slice::<serde_json::Value>(&message.value)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_handled_messages_are_committed() {
+        let dlq = InMemoryPublisher::new();
+        let mut consumer = MessageConsumer::new(source(vec![message("OrderCreated", 0, "{}"), message("OrderCreated", 1, "{}")]), parse, dlq.clone(), policy());
+        assert_eq!(consumer.poll_once().unwrap(), ConsumeReport { handled: 2, dead_lettered: 0 });
+        assert_eq!(consumer.source.committed, [0, 1]);
+        assert!(dlq.events().is_empty());
+        assert_eq!(consumer.poll_once().unwrap(), ConsumeReport::default());
+    }
+
+    #[test]
+    fn test_failing_message_is_retried_then_dead_lettered() {
+        let dlq = InMemoryPublisher::new();
+        let mut attempts = 0;
+        let handler = |message: &ConsumedMessage| {
+            if message.offset == 5 {
+                attempts += 1;
+                return Err(OrderServiceError::StorageError("disk full".into()));
+            }
+            Ok(())
+        };
+        let mut consumer = MessageConsumer::new(source(vec![message("OrderCreated", 5, "{}"), message("OrderCreated", 6, "{}")]), handler, dlq.clone(), policy());
+        assert_eq!(consumer.poll_once().unwrap(), ConsumeReport { handled: 1, dead_lettered: 1 });
+        assert_eq!(consumer.source.committed, [5, 6]);
+        drop(consumer);
+        assert_eq!(attempts, 3);
+
+        let dead = dlq.events_for("OrderCreated.DLQ");
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].json, "{}");
+        assert_eq!(dead[0].header("X-Request-Id"), Some("req-5"));
+        assert_eq!(dead[0].header(DLQ_ERROR_HEADER), Some("StorageError: disk full"));
+        assert_eq!(dead[0].header(DLQ_TOPIC_HEADER), Some("OrderCreated"));
+        assert_eq!(dead[0].header(DLQ_PARTITION_HEADER), Some("2"));
+        assert_eq!(dead[0].header(DLQ_OFFSET_HEADER), Some("5"));
+        assert_eq!(dead[0].header(DLQ_ATTEMPTS_HEADER), Some("3"));
+    }
+
+    #[test]
+    fn test_message_handled_on_retry_is_not_dead_lettered() {
+        let dlq = InMemoryPublisher::new();
+        let mut failures = 1;
+        let handler = |_: &ConsumedMessage| {
+            if failures > 0 {
+                failures -= 1;
+                return Err(OrderServiceError::StorageError("busy".into()));
+            }
+            Ok(())
+        };
+        let mut consumer = MessageConsumer::new(source(vec![message("OrderCreated", 0, "{}")]), handler, dlq.clone(), policy());
+        assert_eq!(consumer.poll_once().unwrap(), ConsumeReport { handled: 1, dead_lettered: 0 });
+        assert!(dlq.events().is_empty());
+    }
+
+    #[test]
+    fn test_malformed_message_is_dead_lettered_without_retry() {
+        let dlq = InMemoryPublisher::new();
+        let mut attempts = 0;
+        let handler = |message: &ConsumedMessage| {
+            attempts += 1;
+            parse(message)
+        };
+        let mut consumer = MessageConsumer::new(source(vec![message("OrderCreated", 0, "{not json")]), handler, dlq.clone(), policy());
+        assert_eq!(consumer.poll_once().unwrap(), ConsumeReport { handled: 0, dead_lettered: 1 });
+        drop(consumer);
+        assert_eq!(attempts, 1);
+        assert_eq!(dlq.events_for("OrderCreated.DLQ")[0].header(DLQ_ATTEMPTS_HEADER), Some("1"));
+    }
+
+    #[test]
+    fn test_message_is_not_committed_when_dead_lettering_fails() {
+        let mut dlq = MockEventPublisher::new();
+        dlq.expect_publish_bytes().times(1).returning(|_, _, _, _| Err(OrderServiceError::EventBrokerError(rdkafka::error::KafkaError::MessageProduction(rdkafka::error::RDKafkaErrorCode::AllBrokersDown))));
+        let mut consumer = MessageConsumer::new(source(vec![message("OrderCreated", 0, "{not json")]), parse, dlq, policy());
+        assert!(consumer.poll_once().is_err());
+        assert!(consumer.source.committed.is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+use std::{collections::BTreeMap, time::{Duration, Instant}};
+
+use rdkafka::{
+    consumer::{BaseConsumer, CommitMode, Consumer},
+    message::{Headers, Message},
+    Offset, TopicPartitionList,
+};
+
+use crate::{models::errors::OrderServiceError, producers::producer_connection::KafkaConfig};
+use super::consumer::{ConsumedMessage, MessageSource};
+
+/// How long a poll waits for a first message.
+const POLL_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long the first polls wait for the group to assign partitions to the connection.
+const ASSIGN_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_STEP: Duration = Duration::from_millis(100);
+/// Most messages one poll returns.
+const MAX_POLL_MESSAGES: usize = 500;
+/// How long fetching the partitions and their offsets may take.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A consumer group member reading one topic from the earliest uncommitted offset.
+pub struct KafkaConsumerConnection {
+    con: BaseConsumer,
+    topic: String,
+    assigned: bool,
+}
+
+impl KafkaConsumerConnection {
+    pub fn connect(config: &KafkaConfig, topic: &str, group: &str) -> Result<Self, OrderServiceError> {
+        let mut client_config = config.client_config()?;
+        client_config.set("group.id", group)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest");
+        let con: BaseConsumer = client_config.create()?;
+        con.subscribe(&[topic])?;
+        Ok(Self {
+            con,
+            topic: topic.to_owned(),
+            assigned: false,
+        })
+    }
+}
+
+impl MessageSource for KafkaConsumerConnection {
+    /// Waits up to a second for a first message, and then takes the ones that are already fetched. Until the group
+    /// has assigned partitions to the connection, it waits for that instead, so a poll that comes back empty means
+    /// there is nothing new.
+    #[tracing::instrument(name = "kafka.poll", skip_all, fields(otel.kind = "client", messaging.system = "kafka"), err)]
+    fn poll(&mut self) -> Result<Vec<ConsumedMessage>, OrderServiceError> {
+        let mut messages = vec![];
+        let mut deadline = Instant::now() + if self.assigned { POLL_TIMEOUT } else { ASSIGN_TIMEOUT };
+        while messages.len() < MAX_POLL_MESSAGES {
+            let wait = if messages.is_empty() { deadline.saturating_duration_since(Instant::now()).min(POLL_STEP) } else { Duration::ZERO };
+            match self.con.poll(wait) {
+                Some(message) => messages.push(consumed_message(&message?)),
+                None if !messages.is_empty() || Instant::now() >= deadline => break,
+                None => {
+                    if !self.assigned && self.con.assignment()?.count() > 0 {
+                        self.assigned = true;
+                        deadline = Instant::now() + POLL_TIMEOUT;
+                    }
+                }
+            }
+        }
+        self.assigned |= !messages.is_empty();
+        Ok(messages)
+    }
+
+    fn commit(&mut self, message: &ConsumedMessage) -> Result<(), OrderServiceError> {
+        let mut offsets = TopicPartitionList::new();
+        offsets.add_partition_offset(&message.topic, message.partition, Offset::Offset(message.offset + 1))?;
+        Ok(self.con.commit(&offsets, CommitMode::Sync)?)
+    }
+
+    /// The high watermark of every partition of the topic that holds messages.
+    fn end_offsets(&mut self) -> Result<BTreeMap<i32, i64>, OrderServiceError> {
+        let metadata = self.con.fetch_metadata(Some(&self.topic), METADATA_TIMEOUT)?;
+        let mut ends = BTreeMap::new();
+        for partition in metadata.topics().iter().flat_map(|t| t.partitions()) {
+            let (low, high) = self.con.fetch_watermarks(&self.topic, partition.id(), METADATA_TIMEOUT)?;
+            if high > low {
+                ends.insert(partition.id(), high);
+            }
+        }
+        Ok(ends)
+    }
+
+    /// The positions of the assigned partitions that have one, i.e. that were read from.
+    fn positions(&mut self) -> Result<BTreeMap<i32, i64>, OrderServiceError> {
+        Ok(self.con.position()?.elements().iter()
+            .filter_map(|e| match e.offset() {
+                Offset::Offset(offset) => Some((e.partition(), offset)),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+/// The message with its record headers. Header values that are not UTF-8 are read lossily, and null values as empty.
+pub(crate) fn consumed_message<M: Message>(message: &M) -> ConsumedMessage {
+    let headers = message.headers()
+        .map(|headers| headers.iter().map(|h| (h.key.to_owned(), String::from_utf8_lossy(h.value.unwrap_or_default()).into_owned())).collect())
+        .unwrap_or_default();
+    ConsumedMessage {
+        topic: message.topic().to_owned(),
+        partition: message.partition(),
+        offset: message.offset(),
+        key: message.key().unwrap_or_default().to_vec(),
+        value: message.payload().unwrap_or_default().to_vec(),
+        headers,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdkafka::message::{OwnedMessage, Timestamp};
+    use crate::producers::producer_connection::record_headers;
+
+    #[test]
+    fn test_consumed_message_keeps_record_headers() {
+        let headers = vec![("X-Request-Id".to_owned(), "req-1".to_owned()), ("traceparent".to_owned(), "00-abc-def-01".to_owned())];
+        let record = OwnedMessage::new(Some(b"{}".to_vec()), Some(b"r-1".to_vec()), "OrderCreated".into(), Timestamp::NotAvailable, 2, 17, Some(record_headers(&headers)));
+        let message = consumed_message(&record);
+        assert_eq!(message.headers, headers);
+        assert_eq!((message.topic.as_str(), message.partition, message.offset), ("OrderCreated", 2, 17));
+        assert_eq!(message.key, b"r-1");
+        assert_eq!(message.value, b"{}");
+    }
+
+    #[test]
+    fn test_reads_up_to_the_end_offsets() {
+        use crate::producers::{event_publisher::EventPublisher, producer_connection::{tests::mock_kafka, KafkaProdConnection}};
+
+        let (_cluster, config) = mock_kafka(&["OrderCreated"]);
+        let mut kafka = KafkaProdConnection::connect_with(&config).unwrap();
+        let mut source = KafkaConsumerConnection::connect(&config, "OrderCreated", "test").unwrap();
+        assert!(source.end_offsets().unwrap().is_empty());
+        for n in 0..3 {
+            kafka.publish("OrderCreated", None, &vec![], format!("{{\"n\":{}}}", n)).unwrap();
+        }
+        let ends = source.end_offsets().unwrap();
+        assert_eq!(ends, BTreeMap::from([(0, 3)]));
+        let mut read = 0;
+        for _ in 0..30 {
+            if source.positions().unwrap().get(&0) >= Some(&3) {
+                break;
+            }
+            read += source.poll().unwrap().len();
+        }
+        assert_eq!((read, source.positions().unwrap()), (3, ends));
+    }
+
+    #[test]
+    fn test_consumed_message_without_headers() {
+        let record = OwnedMessage::new(None, None, "OrderCreated".into(), Timestamp::NotAvailable, 0, 0, None);
+        let message = consumed_message(&record);
+        assert!(message.headers.is_empty());
+        assert!(message.key.is_empty() && message.value.is_empty());
+    }
+}
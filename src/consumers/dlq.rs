@@ -0,0 +1,207 @@
+use crate::{models::errors::OrderServiceError, producers::event_publisher::EventPublisher};
+use super::consumer::{ConsumedMessage, MessageSource};
+
+/// Suffix of the dead-letter topic of a topic.
+pub const DLQ_SUFFIX: &str = ".DLQ";
+pub const DLQ_ERROR_HEADER: &str = "dlq.error";
+pub const DLQ_TOPIC_HEADER: &str = "dlq.original.topic";
+pub const DLQ_PARTITION_HEADER: &str = "dlq.original.partition";
+pub const DLQ_OFFSET_HEADER: &str = "dlq.original.offset";
+pub const DLQ_ATTEMPTS_HEADER: &str = "dlq.attempts";
+
+pub fn dlq_topic(topic: &str) -> String {
+    format!("{}{}", topic, DLQ_SUFFIX)
+}
+
+/// Publishes the message to `<topic>.DLQ` with its own headers, and headers naming the error and where the message came from.
+pub(super) fn dead_letter(publisher: &mut impl EventPublisher, message: &ConsumedMessage, error: &OrderServiceError, attempts: u32) -> Result<(), OrderServiceError> {
+    let mut headers = message.headers.clone();
+    headers.extend([
+        (DLQ_ERROR_HEADER.to_owned(), error.to_string()),
+        (DLQ_TOPIC_HEADER.to_owned(), message.topic.clone()),
+        (DLQ_PARTITION_HEADER.to_owned(), message.partition.to_string()),
+        (DLQ_OFFSET_HEADER.to_owned(), message.offset.to_string()),
+        (DLQ_ATTEMPTS_HEADER.to_owned(), attempts.to_string()),
+    ]);
+    tracing::error!(error = %error, topic = message.topic.as_str(), partition = message.partition, offset = message.offset, attempts, "message dead-lettered");
+    publisher.publish_bytes(&dlq_topic(&message.topic), message_key(message), &headers, message.value.clone())
+}
+
+pub const REDRIVE_USAGE: &str = "usage: order_service redrive-dlq <topic> [--limit <n>]";
+
+/// Which dead-letter topic to re-drive, and how many of its messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedriveOptions {
+    /// The original topic, its messages are read from `<topic>.DLQ`.
+    pub topic: String,
+    pub limit: Option<usize>,
+}
+
+impl RedriveOptions {
+    /// Parses the arguments of `redrive-dlq`.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut topic = None;
+        let mut limit = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--limit" => {
+                    let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+                    limit = Some(value.parse::<usize>().map_err(|_| format!("--limit must be a number, got '{}'", value))?);
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown argument '{}'", arg)),
+                _ if topic.is_none() => topic = Some(arg.clone()),
+                _ => return Err(format!("unexpected argument '{}'", arg)),
+            }
+        }
+        Ok(Self { topic: topic.ok_or("the topic is missing")?, limit })
+    }
+}
+
+/// Messages re-driven from a dead-letter topic.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RedriveReport {
+    pub redriven: usize,
+}
+
+/// Publishes the messages of a dead-letter topic back to their original topic, without the dead-letter headers,
+/// until the source has nothing new or `limit` messages are re-driven. Each message is committed once published.
+pub fn redrive(source: &mut impl MessageSource, publisher: &mut impl EventPublisher, limit: Option<usize>) -> Result<RedriveReport, OrderServiceError> {
+    let mut report = RedriveReport::default();
+    loop {
+        let messages = source.poll()?;
+        if messages.is_empty() {
+            return Ok(report);
+        }
+        for message in messages {
+            if limit.is_some_and(|limit| report.redriven >= limit) {
+                return Ok(report);
+            }
+            let topic = original_topic(&message)?;
+            let headers = message.headers.iter().filter(|(k, _)| !k.starts_with("dlq.")).cloned().collect();
+            publisher.publish_bytes(&topic, message_key(&message), &headers, message.value.clone())?;
+            source.commit(&message)?;
+            report.redriven += 1;
+            tracing::info!(topic, dlq_offset = message.offset, original_offset = message.header(DLQ_OFFSET_HEADER), "message re-driven");
+        }
+    }
+}
+
+/// The message key, so a dead-lettered or re-driven message lands in the partition of its key again.
+/// Keys that are not UTF-8 are dropped.
+fn message_key(message: &ConsumedMessage) -> Option<&str> {
+    std::str::from_utf8(&message.key).ok().filter(|k| !k.is_empty())
+}
+
+/// The topic named in the dead-letter headers, or the dead-letter topic without its suffix.
+fn original_topic(message: &ConsumedMessage) -> Result<String, OrderServiceError> {
+    message.header(DLQ_TOPIC_HEADER)
+        .or_else(|| message.topic.strip_suffix(DLQ_SUFFIX))
+        .map(str::to_owned)
+        .ok_or_else(|| OrderServiceError::InvalidConfig(format!("{} is not a dead-letter topic", message.topic)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use crate::{consumers::consumer::tests::{message, VecSource}, producers::event_publisher::InMemoryPublisher};
+
+    fn dead(offset: i64) -> ConsumedMessage {
+        let mut m = message("OrderCreated.DLQ", offset, "{\"o_id\":\"1\"}");
+        m.key = b"r-1".to_vec();
+        m.headers.push((DLQ_ERROR_HEADER.into(), "JSONParseError".into()));
+        m.headers.push((DLQ_OFFSET_HEADER.into(), "17".into()));
+        m
+    }
+
+    #[test]
+    fn test_redrive_publishes_to_original_topic() {
+        let mut source = VecSource { batches: VecDeque::from([vec![dead(0), dead(1)], vec![dead(2)]]), committed: vec![] };
+        let published = InMemoryPublisher::new();
+        let report = redrive(&mut source, &mut published.clone(), None).unwrap();
+        assert_eq!(report, RedriveReport { redriven: 3 });
+        assert_eq!(source.committed, [0, 1, 2]);
+        let events = published.events_for("OrderCreated");
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].json, "{\"o_id\":\"1\"}");
+        assert_eq!(events[0].key.as_deref(), Some("r-1"));
+        assert_eq!(events[0].headers, vec![("X-Request-Id".to_owned(), "req-0".to_owned())]);
+    }
+
+    #[test]
+    fn test_dead_letter_headers_round_trip_through_kafka() {
+        use crate::{consumers::consumer_connection::KafkaConsumerConnection, producers::producer_connection::{tests::mock_kafka, KafkaProdConnection}};
+
+        let (_cluster, config) = mock_kafka(&["OrderCreated", "OrderCreated.DLQ"]);
+        let mut kafka = KafkaProdConnection::connect_with(&config).unwrap();
+        let mut failed = message("OrderCreated", 17, "{\"o_id\":\"1\"}");
+        failed.key = b"r-1".to_vec();
+        dead_letter(&mut kafka, &failed, &OrderServiceError::StorageError("disk full".into()), 3).unwrap();
+
+        let mut dlq = KafkaConsumerConnection::connect(&config, "OrderCreated.DLQ", "test").unwrap();
+        let dead = dlq.poll().unwrap().pop().unwrap();
+        assert_eq!(dead.header("X-Request-Id"), Some("req-17"));
+        assert_eq!(dead.header(DLQ_ERROR_HEADER), Some("StorageError: disk full"));
+        assert_eq!(dead.header(DLQ_TOPIC_HEADER), Some("OrderCreated"));
+        assert_eq!(dead.header(DLQ_PARTITION_HEADER), Some("2"));
+        assert_eq!(dead.header(DLQ_OFFSET_HEADER), Some("17"));
+        assert_eq!(dead.header(DLQ_ATTEMPTS_HEADER), Some("3"));
+        assert_eq!((dead.key.as_slice(), dead.value.as_slice()), (b"r-1".as_slice(), failed.value.as_slice()));
+
+        let mut source = VecSource { batches: VecDeque::from([vec![dead]]), committed: vec![] };
+        assert_eq!(redrive(&mut source, &mut kafka, None).unwrap(), RedriveReport { redriven: 1 });
+        let redriven = KafkaConsumerConnection::connect(&config, "OrderCreated", "test.redriven").unwrap().poll().unwrap().pop().unwrap();
+        assert_eq!(redriven.headers, failed.headers);
+    }
+
+    #[test]
+    fn test_payload_that_is_not_utf8_is_kept() {
+        use crate::{consumers::consumer_connection::KafkaConsumerConnection, producers::producer_connection::{tests::mock_kafka, KafkaProdConnection}};
+
+        let (_cluster, config) = mock_kafka(&["OrderCreated", "OrderCreated.DLQ"]);
+        let mut kafka = KafkaProdConnection::connect_with(&config).unwrap();
+        let mut failed = message("OrderCreated", 17, "");
+        failed.value = vec![0x7b, 0xff, 0xfe, 0x00, 0x7d];
+        dead_letter(&mut kafka, &failed, &OrderServiceError::StorageError("disk full".into()), 1).unwrap();
+        let dead = KafkaConsumerConnection::connect(&config, "OrderCreated.DLQ", "test").unwrap().poll().unwrap().pop().unwrap();
+        assert_eq!(dead.value, failed.value);
+
+        let mut source = VecSource { batches: VecDeque::from([vec![dead.clone()]]), committed: vec![] };
+        assert_eq!(redrive(&mut source, &mut kafka, None).unwrap(), RedriveReport { redriven: 1 });
+        let redriven = KafkaConsumerConnection::connect(&config, "OrderCreated", "test.redriven").unwrap().poll().unwrap().pop().unwrap();
+        assert_eq!(redriven.value, failed.value);
+
+        // A sink that stores text refuses it rather than storing something else.
+        let mut source = VecSource { batches: VecDeque::from([vec![dead]]), committed: vec![] };
+        assert!(matches!(redrive(&mut source, &mut InMemoryPublisher::new(), None), Err(OrderServiceError::EventSinkError(_))));
+        assert!(source.committed.is_empty());
+    }
+
+    #[test]
+    fn test_parse_redrive_args() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(RedriveOptions::from_args(&args(&["OrderCreated", "--limit", "5"])), Ok(RedriveOptions { topic: "OrderCreated".into(), limit: Some(5) }));
+        assert_eq!(RedriveOptions::from_args(&args(&["OrderCreated"])), Ok(RedriveOptions { topic: "OrderCreated".into(), limit: None }));
+        assert!(RedriveOptions::from_args(&args(&["OrderCreated", "--limit", "five"])).is_err());
+        assert!(RedriveOptions::from_args(&args(&["OrderCreated", "--limit"])).is_err());
+        assert!(RedriveOptions::from_args(&args(&["--limit", "5"])).is_err());
+        assert!(RedriveOptions::from_args(&args(&["OrderCreated", "--dry-run"])).is_err());
+        assert!(RedriveOptions::from_args(&args(&["OrderCreated", "OrderRejected"])).is_err());
+    }
+
+    #[test]
+    fn test_redrive_stops_at_limit() {
+        let mut source = VecSource { batches: VecDeque::from([vec![dead(0), dead(1), dead(2)]]), committed: vec![] };
+        let report = redrive(&mut source, &mut InMemoryPublisher::new(), Some(2)).unwrap();
+        assert_eq!(report, RedriveReport { redriven: 2 });
+        assert_eq!(source.committed, [0, 1]);
+    }
+
+    #[test]
+    fn test_redrive_rejects_other_topics() {
+        let mut source = VecSource { batches: VecDeque::from([vec![message("OrderCreated", 0, "{}")]]), committed: vec![] };
+        assert!(redrive(&mut source, &mut InMemoryPublisher::new(), None).is_err());
+        assert!(source.committed.is_empty());
+    }
+}
@@ -0,0 +1,5 @@
+pub mod consumer;
+pub mod consumer_connection;
+pub mod dlq;
+pub mod reconcile;
+pub mod saga;
@@ -0,0 +1,306 @@
+//! Compares the orders in HBase with the `OrderCreated` events on Kafka.
+//!
+//! An order is stored before its event is published, so a failed publish leaves an order Kafka never heard of.
+//! The job reads the whole topic into a local index of order ids, scans HBase over a time window, and reports the
+//! orders missing from either side. Orders missing from Kafka can be republished.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::DateTime;
+use rdkafka::{error::KafkaError, types::RDKafkaErrorCode};
+use serde::Serialize;
+
+use crate::{
+    models::{errors::OrderServiceError, orders::{Order, OrderState}, tables::Tables},
+    producers::{cloud_events::event_data, event_publisher::EventPublisher, producers::{publish_order_created, EventRouting}},
+    repository::{hbase::{self, OrderScanFilter}, hbase_connection::HbaseClient},
+};
+use super::consumer::MessageSource;
+
+/// Polls in a row that may come back empty before the end offsets are reached.
+const MAX_EMPTY_POLLS: usize = 30;
+
+pub const RECONCILE_USAGE: &str = "usage: order_service reconcile [--from <rfc3339>] [--until <rfc3339>] [--topic <topic>] [--republish]";
+
+/// The window to compare and what to do about orders missing from Kafka.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReconcileOptions {
+    /// Order time range. The restaurant and customer are not used.
+    pub window: OrderScanFilter,
+    /// Topic of the `OrderCreated` events, the configured one when unset.
+    pub topic: Option<String>,
+    /// Publishes the `OrderCreated` event of every order missing from Kafka.
+    pub republish: bool,
+}
+
+impl ReconcileOptions {
+    /// Parses the arguments of `reconcile`.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut options = ReconcileOptions::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--republish" {
+                options.republish = true;
+                continue;
+            }
+            let value = args.next().filter(|v| !v.starts_with("--")).ok_or_else(|| format!("{} needs a value", arg))?.clone();
+            let time = |v: &str| DateTime::parse_from_rfc3339(v).map(|t| t.timestamp_millis()).map_err(|e| format!("{} must be an RFC 3339 time: {}", arg, e));
+            match arg.as_str() {
+                "--from" => options.window.from = Some(time(&value)?),
+                "--until" => options.window.until = Some(time(&value)?),
+                "--topic" => options.topic = Some(value),
+                _ => return Err(format!("unknown argument '{}'", arg)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Differences between HBase and the topic, in the window.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReconcileReport {
+    pub topic: String,
+    pub hbase_orders: usize,
+    pub topic_events: usize,
+    /// Orders in HBase without an event, by o_id.
+    pub missing_from_kafka: Vec<String>,
+    /// Events of orders that are not in HBase, by o_id.
+    pub missing_from_hbase: Vec<String>,
+    pub republished: usize,
+    /// Messages that are not an order.
+    pub unreadable_messages: usize,
+    /// Rows that do not decode into an order.
+    pub unreadable_rows: usize,
+}
+
+impl ReconcileReport {
+    pub fn in_sync(&self) -> bool {
+        self.missing_from_kafka.is_empty() && self.missing_from_hbase.is_empty()
+    }
+}
+
+/// Order ids of the events on a topic, with their order time.
+#[derive(Debug, Default)]
+struct EventIndex {
+    orders: BTreeMap<String, i64>,
+    unreadable: usize,
+}
+
+/// Reads `source` up to the end offsets its partitions had when the read started, or until it has nothing new when
+/// it cannot tell them. Nothing is committed, so every run reads the topic from the start. Values are read as orders
+/// or as structured CloudEvents of orders.
+fn index_events(source: &mut impl MessageSource) -> Result<EventIndex, OrderServiceError> {
+    let mut index = EventIndex::default();
+    let ends = source.end_offsets()?;
+    let mut empty_polls = 0;
+    loop {
+        let messages = source.poll()?;
+        if messages.is_empty() {
+            if ends.is_empty() {
+                return Ok(index);
+            }
+            empty_polls += 1;
+            if empty_polls >= MAX_EMPTY_POLLS {
+                tracing::error!(?ends, positions = ?source.positions()?, "the topic was not read up to its end offsets");
+                return Err(OrderServiceError::EventBrokerError(KafkaError::MessageConsumption(RDKafkaErrorCode::OperationTimedOut)));
+            }
+        } else {
+            empty_polls = 0;
+        }
+        for message in messages {
+            let order = event_data::<Order>(&message.value).ok()
+                .and_then(|order| Some((DateTime::parse_from_rfc3339(&order.ordertime).ok()?.timestamp_millis(), order.o_id)));
+            match order {
+                Some((ordertime, o_id)) => { index.orders.insert(o_id, ordertime); }
+                None => {
+                    tracing::warn!(topic = message.topic.as_str(), partition = message.partition, offset = message.offset, "message is not an order");
+                    index.unreadable += 1;
+                }
+            }
+        }
+        if !ends.is_empty() {
+            let positions = source.positions()?;
+            if ends.iter().all(|(partition, end)| positions.get(partition).is_some_and(|position| position >= end)) {
+                return Ok(index);
+            }
+        }
+    }
+}
+
+/// Compares the events in `source` with the orders in HBase in the window of `options`, and republishes the
+/// missing events through `publisher` when asked to.
+pub fn reconcile<H: HbaseClient>(
+    client: &mut H,
+    tables: &Tables,
+    batch_size: i32,
+    source: &mut impl MessageSource,
+    routing: &EventRouting,
+    options: &ReconcileOptions,
+    publisher: &mut dyn EventPublisher,
+) -> Result<ReconcileReport, OrderServiceError> {
+    let routing = EventRouting { order_created: options.topic.clone().unwrap_or_else(|| routing.order_created.clone()), ..routing.clone() };
+    let events = index_events(source)?;
+    let window = OrderScanFilter { from: options.window.from, until: options.window.until, ..Default::default() };
+    let mut report = ReconcileReport {
+        topic: routing.order_created.clone(),
+        topic_events: events.orders.values().filter(|t| window.in_range(**t)).count(),
+        unreadable_messages: events.unreadable,
+        ..Default::default()
+    };
+    let mut stored = BTreeSet::new();
+    let mut missing = vec![];
+    report.unreadable_rows = hbase::scan_orders(client, tables, &window, batch_size, |scanned| {
+        report.hbase_orders += 1;
+        stored.insert(scanned.order.o_id.clone());
+        if !events.orders.contains_key(&scanned.order.o_id) {
+            missing.push(scanned.order);
+        }
+        Ok(())
+    })?;
+    report.missing_from_hbase = events.orders.iter()
+        .filter(|(o_id, ordertime)| window.in_range(**ordertime) && !stored.contains(*o_id))
+        .map(|(o_id, _)| o_id.clone())
+        .collect();
+    report.missing_from_kafka = missing.iter().map(|o| o.o_id.clone()).collect();
+    if options.republish {
+        let request_id = format!("reconcile-{}", uuid::Uuid::new_v4());
+        for mut order in missing {
+            // The event is the order as it was created.
+            order.state = OrderState::Pending;
+            publish_order_created(&order, &request_id, &routing, publisher)?;
+            report.republished += 1;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use crate::{
+        consumers::consumer::{tests::VecSource, ConsumedMessage},
+        producers::event_publisher::InMemoryPublisher,
+        repository::{fake_hbase::FakeHbase, hbase_connection::HbaseConnection},
+    };
+
+    fn order(o_id: &str, ordertime: &str) -> Order {
+        let mut order = Order::new(vec![], "Lyngvej 2, 2800 Lyngby".into(), "Rest 1".into(), "c1".into(), "r1".into(), 2800);
+        order.o_id = o_id.into();
+        order.ordertime = ordertime.into();
+        order
+    }
+
+    fn event(offset: i64, order: &Order) -> ConsumedMessage {
+        ConsumedMessage { topic: "OrderCreated".into(), partition: 0, offset, key: vec![], value: order.to_json_string().unwrap().into_bytes(), headers: vec![] }
+    }
+
+    fn store(orders: &[&Order]) -> String {
+        let tables = Tables::default();
+        let url = FakeHbase::serve_with_tables(&[hbase::order_table_spec(&tables), hbase::customer_index_spec(&tables)]);
+        for order in orders {
+            hbase::add_order(order, &tables, HbaseConnection::connect(&url).unwrap()).unwrap();
+        }
+        url
+    }
+
+    #[test]
+    fn test_parse_reconcile_args() {
+        let args: Vec<String> = ["--from", "2026-01-01T00:00:00Z", "--republish", "--topic", "orders"].iter().map(|a| a.to_string()).collect();
+        let options = ReconcileOptions::from_args(&args).unwrap();
+        assert_eq!(options.window.from, Some(1767225600000));
+        assert_eq!(options.window.until, None);
+        assert_eq!(options.topic.as_deref(), Some("orders"));
+        assert!(options.republish);
+        assert!(ReconcileOptions::from_args(&["--until".to_string()]).is_err());
+        assert!(ReconcileOptions::from_args(&["--restaurant".to_string(), "r1".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_reconcile_reports_both_directions_and_republishes() {
+        let published = order("a", "2026-01-01T10:00:00+00:00");
+        let unpublished = order("b", "2026-01-01T11:00:00+00:00");
+        let mut accepted = order("c", "2026-01-01T12:00:00+00:00");
+        accepted.state = OrderState::Accepted;
+        let outside = order("d", "2026-01-03T10:00:00+00:00");
+        let lost = order("e", "2026-01-01T13:00:00+00:00");
+        let url = store(&[&published, &unpublished, &accepted, &outside]);
+        let mut source = VecSource {
+            batches: VecDeque::from([vec![event(0, &published), event(1, &lost)], vec![event(2, &order("f", "2026-01-04T10:00:00+00:00"))]]),
+            committed: vec![],
+        };
+        source.batches[1].push(ConsumedMessage { value: b"{not json".to_vec(), ..event(3, &published) });
+
+        let options = ReconcileOptions {
+            window: OrderScanFilter { until: Some(1767312000000), ..Default::default() },
+            republish: true,
+            ..Default::default()
+        };
+        let recorder = InMemoryPublisher::new();
+        let report = reconcile(&mut HbaseConnection::connect(&url).unwrap(), &Tables::default(), 2, &mut source, &EventRouting::default(), &options, &mut recorder.clone()).unwrap();
+        assert_eq!(report.topic, "OrderCreated");
+        assert_eq!(report.hbase_orders, 3);
+        assert_eq!(report.topic_events, 2);
+        let mut missing = report.missing_from_kafka.clone();
+        missing.sort();
+        assert_eq!(missing, ["b", "c"]);
+        assert_eq!(report.missing_from_hbase, ["e"]);
+        assert_eq!(report.unreadable_messages, 1);
+        assert_eq!(report.republished, 2);
+        assert!(!report.in_sync());
+        assert!(source.committed.is_empty());
+
+        let republished: Vec<Order> = recorder.events_for("OrderCreated").iter().map(|e| serde_json::from_str(&e.json).unwrap()).collect();
+        assert_eq!(republished.len(), 2);
+        assert!(republished.iter().all(|o| o.state == OrderState::Pending));
+    }
+
+    /// Hands out `batches` one per poll, some empty as if the broker were slow, and knows the end of partition 0.
+    struct LaggingSource {
+        batches: VecDeque<Vec<ConsumedMessage>>,
+        end: i64,
+        position: i64,
+    }
+
+    impl MessageSource for LaggingSource {
+        fn poll(&mut self) -> Result<Vec<ConsumedMessage>, OrderServiceError> {
+            let batch = self.batches.pop_front().unwrap_or_default();
+            self.position = batch.iter().map(|m| m.offset + 1).fold(self.position, i64::max);
+            Ok(batch)
+        }
+        fn commit(&mut self, _message: &ConsumedMessage) -> Result<(), OrderServiceError> {
+            Ok(())
+        }
+        fn end_offsets(&mut self) -> Result<BTreeMap<i32, i64>, OrderServiceError> {
+            Ok(BTreeMap::from([(0, self.end)]))
+        }
+        fn positions(&mut self) -> Result<BTreeMap<i32, i64>, OrderServiceError> {
+            Ok(BTreeMap::from([(0, self.position)]))
+        }
+    }
+
+    #[test]
+    fn test_empty_poll_before_the_end_offsets_does_not_end_the_read() {
+        let (a, b) = (order("a", "2026-01-01T10:00:00+00:00"), order("b", "2026-01-01T11:00:00+00:00"));
+        let event = |offset: i64, order: &Order| ConsumedMessage { partition: 0, ..event(offset, order) };
+        let batches = VecDeque::from([vec![event(0, &a)], vec![], vec![], vec![event(1, &b)]]);
+        let index = index_events(&mut LaggingSource { batches: batches.clone(), end: 2, position: 0 }).unwrap();
+        assert_eq!(index.orders.keys().collect::<Vec<_>>(), ["a", "b"]);
+
+        // Gives up when the end offsets are not reached.
+        assert!(index_events(&mut LaggingSource { batches, end: 3, position: 0 }).is_err());
+    }
+
+    #[test]
+    fn test_reconcile_in_sync_publishes_nothing() {
+        let published = order("a", "2026-01-01T10:00:00+00:00");
+        let url = store(&[&published]);
+        let mut source = VecSource { batches: VecDeque::from([vec![event(0, &published)]]), committed: vec![] };
+        let recorder = InMemoryPublisher::new();
+        let options = ReconcileOptions { republish: true, ..Default::default() };
+        let report = reconcile(&mut HbaseConnection::connect(&url).unwrap(), &Tables::default(), 10, &mut source, &EventRouting::default(), &options, &mut recorder.clone()).unwrap();
+        assert!(report.in_sync());
+        assert_eq!(report.republished, 0);
+        assert!(recorder.events().is_empty());
+    }
+}
@@ -0,0 +1,449 @@
+//! Runs the order creation saga of [`crate::models::saga`] over Kafka, with its state in HBase.
+//!
+//! Commands go to one topic per participant, keyed by o_id. Replies are read from one topic by the `saga`
+//! process, which also sweeps the saga table for steps that timed out. A transition stores the saga before it
+//! sends its commands and moves the order, and is dropped when the saga changed since it was read, so a reply and
+//! a timeout racing on one saga do not both act on it. A crash in between loses the commands: a running step then
+//! times out and is compensated.
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    models::{
+        errors::OrderServiceError,
+        orders::{Order, OrderState},
+        saga::{Saga, SagaCommand, SagaReply, SagaStep, StepTimeouts, Transition},
+        tables::Tables,
+    },
+    producers::{
+        event_publisher::{EventHeaders, EventPublisher},
+        producers::{publish_order_state_changed, EventRouting, OrderStateChanged, REQUEST_ID_HEADER},
+        replay::to_rfc3339,
+    },
+    repository::{hbase::{self, get_unix_time}, hbase_connection::HbaseClient, leases, sagas::{expired_sagas, read_saga, save_saga}},
+    telemetry::inject_trace_context,
+};
+use super::consumer::{ConsumedMessage, MessageConsumer, MessageHandler, MessageSource};
+
+pub const DEFAULT_PAYMENT_TOPIC: &str = "PaymentCommands";
+pub const DEFAULT_RESTAURANT_TOPIC: &str = "RestaurantCommands";
+pub const DEFAULT_COURIER_TOPIC: &str = "CourierCommands";
+pub const DEFAULT_REPLY_TOPIC: &str = "OrderSagaReplies";
+pub const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+/// The lease a replica holds while it sweeps for timed out steps.
+pub const SAGA_EXPIRY_LEASE: &str = "saga-expiry";
+/// Pause after a poll that returned nothing.
+const IDLE_WAIT: Duration = Duration::from_millis(200);
+
+/// The command topic of each participant, and the topic they all reply on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SagaTopics {
+    pub payment: String,
+    pub restaurant: String,
+    pub courier: String,
+    pub replies: String,
+}
+
+impl Default for SagaTopics {
+    fn default() -> Self {
+        Self {
+            payment: DEFAULT_PAYMENT_TOPIC.to_owned(),
+            restaurant: DEFAULT_RESTAURANT_TOPIC.to_owned(),
+            courier: DEFAULT_COURIER_TOPIC.to_owned(),
+            replies: DEFAULT_REPLY_TOPIC.to_owned(),
+        }
+    }
+}
+
+impl SagaTopics {
+    pub fn of(&self, step: SagaStep) -> &str {
+        match step {
+            SagaStep::Payment => &self.payment,
+            SagaStep::Restaurant => &self.restaurant,
+            SagaStep::Courier => &self.courier,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SagaConfig {
+    pub topics: SagaTopics,
+    pub timeouts: StepTimeouts,
+    /// Time between two sweeps for timed out steps.
+    pub sweep_interval: Duration,
+}
+
+impl SagaConfig {
+    /// How long a sweep holds the lease for.
+    pub fn lease_duration(&self) -> Duration {
+        self.sweep_interval * 3
+    }
+}
+
+impl Default for SagaConfig {
+    fn default() -> Self {
+        Self { topics: SagaTopics::default(), timeouts: StepTimeouts::default(), sweep_interval: DEFAULT_SWEEP_INTERVAL }
+    }
+}
+
+/// Stores a new saga for `order`, then sends its first command. A command that cannot be sent is not an error:
+/// the step times out and the saga is compensated.
+pub fn start_saga<H: HbaseClient>(
+    client: &mut H,
+    tables: &Tables,
+    config: &SagaConfig,
+    order: &Order,
+    request_id: &str,
+    publisher: &mut dyn EventPublisher,
+) -> Result<Saga, OrderServiceError> {
+    let (saga, transition) = Saga::start(order, get_unix_time(), &config.timeouts);
+    if !save_saga(client, tables, &saga, b"")? {
+        tracing::warn!(o_id = saga.o_id(), "order already has a saga, not starting another");
+        return Ok(saga);
+    }
+    if let Err(e) = send_commands(&transition.commands, request_id, config, publisher) {
+        tracing::warn!(error = %e, o_id = saga.o_id(), "saga command not sent, the step will time out");
+    }
+    Ok(saga)
+}
+
+/// Applies a reply read from the reply topic. Replies the saga is not waiting for, and replies to orders without
+/// a saga, are logged and dropped.
+pub fn handle_reply<H: HbaseClient>(
+    client: &mut H,
+    tables: &Tables,
+    config: &SagaConfig,
+    routing: &EventRouting,
+    message: &ConsumedMessage,
+    publisher: &mut dyn EventPublisher,
+) -> Result<(), OrderServiceError> {
+    let reply: SagaReply = serde_json::from_slice(&message.value)?;
+    let Some((mut saga, read)) = read_saga(client, tables, &reply.o_id)? else {
+        tracing::warn!(o_id = reply.o_id.as_str(), step = %reply.step, "reply to an order without a saga");
+        return Ok(());
+    };
+    let from = saga.order_state();
+    let Some(transition) = saga.on_reply(&reply, get_unix_time(), &config.timeouts) else {
+        tracing::info!(o_id = reply.o_id.as_str(), step = %reply.step, status = %saga.status, current = %saga.step, "ignoring reply the saga is not waiting for");
+        return Ok(());
+    };
+    let request_id = message.header(REQUEST_ID_HEADER).map(str::to_owned).unwrap_or_else(|| format!("saga-{}", uuid::Uuid::new_v4()));
+    apply(client, tables, config, routing, &saga, &read, &from, transition, &request_id, publisher).map(|_| ())
+}
+
+/// Compensates the running sagas whose step timed out, and returns how many there were.
+pub fn expire_sagas<H: HbaseClient>(
+    client: &mut H,
+    tables: &Tables,
+    config: &SagaConfig,
+    routing: &EventRouting,
+    batch_size: i32,
+    publisher: &mut dyn EventPublisher,
+) -> Result<usize, OrderServiceError> {
+    let now = get_unix_time();
+    let request_id = format!("saga-timeout-{}", uuid::Uuid::new_v4());
+    let mut expired = 0;
+    for (mut saga, read) in expired_sagas(client, tables, now, batch_size)? {
+        let from = saga.order_state();
+        if let Some(transition) = saga.on_timeout(now) {
+            tracing::warn!(o_id = saga.o_id(), step = %saga.step, "saga step timed out, compensating");
+            if apply(client, tables, config, routing, &saga, &read, &from, transition, &request_id, publisher)? {
+                expired += 1;
+            }
+        }
+    }
+    Ok(expired)
+}
+
+/// Takes or renews the `saga-expiry` lease, and compensates the timed out steps when it got it. `None` while
+/// another replica has the lease.
+#[allow(clippy::too_many_arguments)]
+pub fn expire_sagas_with_lease<H: HbaseClient>(
+    client: &mut H,
+    tables: &Tables,
+    config: &SagaConfig,
+    routing: &EventRouting,
+    holder: &str,
+    batch_size: i32,
+    publisher: &mut dyn EventPublisher,
+) -> Result<Option<usize>, OrderServiceError> {
+    let now = get_unix_time();
+    let expires_at = now + config.lease_duration().as_millis() as i64;
+    if !leases::try_acquire(client, tables, SAGA_EXPIRY_LEASE, holder, now, expires_at)? {
+        tracing::debug!(holder, "saga expiry lease is held by another replica");
+        return Ok(None);
+    }
+    expire_sagas(client, tables, config, routing, batch_size, publisher).map(Some)
+}
+
+/// Handles replies from `consumer`, and calls `sweep` every `sweep_interval`, until `stop` is set. A failed
+/// sweep is logged and tried again at the next interval.
+pub fn run_orchestrator<S: MessageSource, H: MessageHandler, P: EventPublisher>(
+    consumer: &mut MessageConsumer<S, H, P>,
+    mut sweep: impl FnMut() -> Result<usize, OrderServiceError>,
+    sweep_interval: Duration,
+    stop: &AtomicBool,
+) -> Result<(), OrderServiceError> {
+    let mut next_sweep = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        if Instant::now() >= next_sweep {
+            if let Err(e) = sweep() {
+                tracing::error!(error = %e, "saga timeout sweep failed");
+            }
+            next_sweep = Instant::now() + sweep_interval;
+        }
+        let report = consumer.poll_once()?;
+        if report.handled + report.dead_lettered == 0 {
+            thread::sleep(IDLE_WAIT);
+        }
+    }
+    Ok(())
+}
+
+/// Stores the saga against `read`, the raw value it was read from, then sends the commands, moves the order from
+/// `from` to its new state and publishes the change. Returns `false`, having done nothing, when the saga changed
+/// since it was read. An order that is no longer in `from`, e.g. rejected by the stale order sweep, is left as is.
+#[allow(clippy::too_many_arguments)]
+fn apply<H: HbaseClient>(
+    client: &mut H,
+    tables: &Tables,
+    config: &SagaConfig,
+    routing: &EventRouting,
+    saga: &Saga,
+    read: &[u8],
+    from: &OrderState,
+    transition: Transition,
+    request_id: &str,
+    publisher: &mut dyn EventPublisher,
+) -> Result<bool, OrderServiceError> {
+    if !save_saga(client, tables, saga, read)? {
+        tracing::info!(o_id = saga.o_id(), "saga changed since it was read, dropping the transition");
+        return Ok(false);
+    }
+    send_commands(&transition.commands, request_id, config, publisher)?;
+    if let Some(state) = transition.order_state {
+        if !hbase::move_order_state(client, tables, saga.o_id(), from, &state)? {
+            tracing::warn!(o_id = saga.o_id(), from = %from, to = %state, "order is no longer where the saga left it, not moving it");
+            return Ok(true);
+        }
+        let order = &saga.order;
+        let event = OrderStateChanged { o_id: order.o_id.clone(), c_id: order.c_id.clone(), r_id: order.r_id.clone(), state, changed_at: to_rfc3339(saga.updated_at), reason: saga.reason.clone() };
+        publish_order_state_changed(&event, request_id, routing, publisher)?;
+    }
+    tracing::info!(o_id = saga.o_id(), status = %saga.status, step = %saga.step, reason = saga.reason.as_deref(), "saga moved on");
+    Ok(true)
+}
+
+fn send_commands(commands: &[SagaCommand], request_id: &str, config: &SagaConfig, publisher: &mut dyn EventPublisher) -> Result<(), OrderServiceError> {
+    for command in commands {
+        let mut headers: EventHeaders = vec![(REQUEST_ID_HEADER.to_owned(), request_id.to_owned())];
+        inject_trace_context(&mut headers);
+        publisher.publish(config.topics.of(command.step), Some(&command.o_id), &headers, serde_json::to_string(command)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{orders::{OrderState, Orderline}, saga::{CommandType, ReplyOutcome, SagaStatus}},
+        producers::{event_publisher::{InMemoryPublisher, MockEventPublisher}, producers::DEFAULT_ORDER_STATE_CHANGED_TOPIC},
+        repository::{fake_hbase::FakeHbase, hbase_connection::HbaseConnection, leases::lease_table_spec, sagas::{load_saga, saga_table_spec}},
+    };
+
+    /// A fake HBase with the tables, and a connection to it.
+    fn connect() -> (String, HbaseConnection) {
+        let tables = Tables::default();
+        let url = FakeHbase::serve_with_tables(&[hbase::order_table_spec(&tables), hbase::customer_index_spec(&tables), saga_table_spec(&tables), lease_table_spec(&tables)]);
+        let client = HbaseConnection::connect(&url).unwrap();
+        (url, client)
+    }
+
+    fn stored_order(url: &str) -> Order {
+        let order = Order::new(vec![Orderline { item_num: 1, price: 100 }], "CustAddr".into(), "RestAddr".into(), "custid".into(), "restid".into(), 2860);
+        hbase::add_order(&order, &Tables::default(), HbaseConnection::connect(url).unwrap()).unwrap();
+        order
+    }
+
+    fn order_state(url: &str, o_id: &str) -> OrderState {
+        hbase::get_order_row(o_id, &Tables::default(), HbaseConnection::connect(url).unwrap()).unwrap().state
+    }
+
+    fn reply(o_id: &str, step: SagaStep, outcome: ReplyOutcome) -> ConsumedMessage {
+        let reply = SagaReply { o_id: o_id.into(), step, outcome, reason: None };
+        ConsumedMessage {
+            topic: DEFAULT_REPLY_TOPIC.into(), partition: 0, offset: 0, key: vec![],
+            value: serde_json::to_vec(&reply).unwrap(), headers: vec![(REQUEST_ID_HEADER.into(), "req-2".into())],
+        }
+    }
+
+    fn commands(recorder: &InMemoryPublisher, topic: &str) -> Vec<SagaCommand> {
+        recorder.events_for(topic).iter().map(|e| serde_json::from_str(&e.json).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_saga_runs_to_completion() {
+        let (url, mut client) = connect();
+        let order = stored_order(&url);
+        let (tables, config, routing) = (Tables::default(), SagaConfig::default(), EventRouting::default());
+        let recorder = InMemoryPublisher::new();
+        start_saga(&mut client, &tables, &config, &order, "req-1", &mut recorder.clone()).unwrap();
+        let payment = recorder.events_for(DEFAULT_PAYMENT_TOPIC);
+        assert_eq!(payment.len(), 1);
+        assert_eq!(payment[0].key.as_deref(), Some(order.o_id.as_str()));
+        assert_eq!(payment[0].header(REQUEST_ID_HEADER), Some("req-1"));
+        assert_eq!(commands(&recorder, DEFAULT_PAYMENT_TOPIC)[0].command, CommandType::AuthorizePayment);
+
+        for step in [SagaStep::Payment, SagaStep::Restaurant, SagaStep::Courier] {
+            handle_reply(&mut client, &tables, &config, &routing, &reply(&order.o_id, step, ReplyOutcome::Succeeded), &mut recorder.clone()).unwrap();
+        }
+        assert_eq!(commands(&recorder, DEFAULT_RESTAURANT_TOPIC)[0].command, CommandType::AcceptOrder);
+        assert_eq!(commands(&recorder, DEFAULT_COURIER_TOPIC)[0].command, CommandType::AssignCourier);
+        assert_eq!(load_saga(&mut client, &tables, &order.o_id).unwrap().unwrap().status, SagaStatus::Completed);
+        assert_eq!(order_state(&url, &order.o_id), OrderState::Accepted);
+        let changes = recorder.events_for(DEFAULT_ORDER_STATE_CHANGED_TOPIC);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].header(REQUEST_ID_HEADER), Some("req-2"));
+    }
+
+    #[test]
+    fn test_restaurant_rejection_releases_payment_and_rejects_order() {
+        let (url, mut client) = connect();
+        let order = stored_order(&url);
+        let (tables, config, routing) = (Tables::default(), SagaConfig::default(), EventRouting::default());
+        let recorder = InMemoryPublisher::new();
+        start_saga(&mut client, &tables, &config, &order, "req-1", &mut recorder.clone()).unwrap();
+        handle_reply(&mut client, &tables, &config, &routing, &reply(&order.o_id, SagaStep::Payment, ReplyOutcome::Succeeded), &mut recorder.clone()).unwrap();
+        handle_reply(&mut client, &tables, &config, &routing, &reply(&order.o_id, SagaStep::Restaurant, ReplyOutcome::Failed), &mut recorder.clone()).unwrap();
+
+        let payment = commands(&recorder, DEFAULT_PAYMENT_TOPIC);
+        assert_eq!(payment.iter().map(|c| c.command).collect::<Vec<_>>(), [CommandType::AuthorizePayment, CommandType::ReleasePayment]);
+        assert_eq!(payment[1].amount, 100);
+        assert_eq!(load_saga(&mut client, &tables, &order.o_id).unwrap().unwrap().status, SagaStatus::Compensated);
+        assert_eq!(order_state(&url, &order.o_id), OrderState::Rejected);
+        let change: OrderStateChanged = serde_json::from_str(&recorder.events_for(DEFAULT_ORDER_STATE_CHANGED_TOPIC)[0].json).unwrap();
+        assert_eq!(change.state, OrderState::Rejected);
+
+        // A late reply is dropped.
+        handle_reply(&mut client, &tables, &config, &routing, &reply(&order.o_id, SagaStep::Restaurant, ReplyOutcome::Succeeded), &mut recorder.clone()).unwrap();
+        assert_eq!(recorder.events_for(DEFAULT_COURIER_TOPIC).len(), 0);
+    }
+
+    #[test]
+    fn test_timed_out_step_is_compensated() {
+        let (url, mut client) = connect();
+        let order = stored_order(&url);
+        let tables = Tables::default();
+        let config = SagaConfig { timeouts: StepTimeouts { restaurant: Duration::ZERO, ..Default::default() }, ..Default::default() };
+        let recorder = InMemoryPublisher::new();
+        start_saga(&mut client, &tables, &config, &order, "req-1", &mut recorder.clone()).unwrap();
+        assert_eq!(expire_sagas(&mut client, &tables, &config, &EventRouting::default(), 10, &mut recorder.clone()).unwrap(), 0);
+        handle_reply(&mut client, &tables, &config, &EventRouting::default(), &reply(&order.o_id, SagaStep::Payment, ReplyOutcome::Succeeded), &mut recorder.clone()).unwrap();
+
+        assert_eq!(expire_sagas(&mut client, &tables, &config, &EventRouting::default(), 10, &mut recorder.clone()).unwrap(), 1);
+        let saga = load_saga(&mut client, &tables, &order.o_id).unwrap().unwrap();
+        assert_eq!(saga.reason.as_deref(), Some("restaurant timed out"));
+        assert_eq!(commands(&recorder, DEFAULT_PAYMENT_TOPIC).last().unwrap().command, CommandType::ReleasePayment);
+        assert_eq!(expire_sagas(&mut client, &tables, &config, &EventRouting::default(), 10, &mut recorder.clone()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_transition_on_a_saga_changed_since_it_was_read_is_dropped() {
+        let (url, mut client) = connect();
+        let order = stored_order(&url);
+        let (tables, config, routing) = (Tables::default(), SagaConfig::default(), EventRouting::default());
+        let recorder = InMemoryPublisher::new();
+        start_saga(&mut client, &tables, &config, &order, "req-1", &mut recorder.clone()).unwrap();
+        handle_reply(&mut client, &tables, &config, &routing, &reply(&order.o_id, SagaStep::Payment, ReplyOutcome::Succeeded), &mut recorder.clone()).unwrap();
+
+        // The sweep reads the saga, then the restaurant reply moves it on before the sweep stores its timeout.
+        let (mut swept, read) = read_saga(&mut client, &tables, &order.o_id).unwrap().unwrap();
+        let from = swept.order_state();
+        let timeout = swept.on_timeout(i64::MAX).unwrap();
+        handle_reply(&mut client, &tables, &config, &routing, &reply(&order.o_id, SagaStep::Restaurant, ReplyOutcome::Succeeded), &mut recorder.clone()).unwrap();
+        let sent = recorder.events().len();
+        assert!(!apply(&mut client, &tables, &config, &routing, &swept, &read, &from, timeout, "req-3", &mut recorder.clone()).unwrap());
+
+        assert_eq!(recorder.events().len(), sent);
+        assert_eq!(order_state(&url, &order.o_id), OrderState::Accepted);
+        assert_eq!(load_saga(&mut client, &tables, &order.o_id).unwrap().unwrap().step, SagaStep::Courier);
+    }
+
+    #[test]
+    fn test_order_rejected_outside_the_saga_is_not_accepted() {
+        let (url, mut client) = connect();
+        let order = stored_order(&url);
+        let (tables, config, routing) = (Tables::default(), SagaConfig::default(), EventRouting::default());
+        let recorder = InMemoryPublisher::new();
+        start_saga(&mut client, &tables, &config, &order, "req-1", &mut recorder.clone()).unwrap();
+        handle_reply(&mut client, &tables, &config, &routing, &reply(&order.o_id, SagaStep::Payment, ReplyOutcome::Succeeded), &mut recorder.clone()).unwrap();
+        assert!(hbase::move_order_state(&mut client, &tables, &order.o_id, &OrderState::Pending, &OrderState::Rejected).unwrap());
+
+        handle_reply(&mut client, &tables, &config, &routing, &reply(&order.o_id, SagaStep::Restaurant, ReplyOutcome::Succeeded), &mut recorder.clone()).unwrap();
+        assert_eq!(order_state(&url, &order.o_id), OrderState::Rejected);
+        assert!(recorder.events_for(DEFAULT_ORDER_STATE_CHANGED_TOPIC).is_empty());
+    }
+
+    #[test]
+    fn test_only_the_lease_holder_expires_sagas() {
+        let (url, mut client) = connect();
+        let order = stored_order(&url);
+        let (tables, routing) = (Tables::default(), EventRouting::default());
+        let config = SagaConfig { timeouts: StepTimeouts { payment: Duration::ZERO, ..Default::default() }, ..Default::default() };
+        let recorder = InMemoryPublisher::new();
+        start_saga(&mut client, &tables, &config, &order, "req-1", &mut recorder.clone()).unwrap();
+
+        assert_eq!(expire_sagas_with_lease(&mut client, &tables, &config, &routing, "replica-1", 10, &mut recorder.clone()).unwrap(), Some(1));
+        assert_eq!(expire_sagas_with_lease(&mut client, &tables, &config, &routing, "replica-2", 10, &mut recorder.clone()).unwrap(), None);
+        assert_eq!(expire_sagas_with_lease(&mut client, &tables, &config, &routing, "replica-1", 10, &mut recorder.clone()).unwrap(), Some(0));
+        assert_eq!(load_saga(&mut client, &tables, &order.o_id).unwrap().unwrap().status, SagaStatus::Compensated);
+    }
+
+    #[test]
+    fn test_reply_request_id_arrives_through_kafka() {
+        use crate::{consumers::consumer_connection::KafkaConsumerConnection, producers::producer_connection::{tests::mock_kafka, KafkaProdConnection}};
+
+        let (url, mut client) = connect();
+        let order = stored_order(&url);
+        let (tables, config, routing) = (Tables::default(), SagaConfig::default(), EventRouting::default());
+        let recorder = InMemoryPublisher::new();
+        start_saga(&mut client, &tables, &config, &order, "req-1", &mut recorder.clone()).unwrap();
+
+        let (_cluster, kafka_config) = mock_kafka(&[DEFAULT_REPLY_TOPIC]);
+        let sent = reply(&order.o_id, SagaStep::Payment, ReplyOutcome::Failed);
+        let json = String::from_utf8(sent.value.clone()).unwrap();
+        KafkaProdConnection::connect_with(&kafka_config).unwrap().publish(DEFAULT_REPLY_TOPIC, Some(&order.o_id), &sent.headers, json).unwrap();
+        let received = KafkaConsumerConnection::connect(&kafka_config, DEFAULT_REPLY_TOPIC, "test.saga").unwrap().poll().unwrap().pop().unwrap();
+
+        handle_reply(&mut client, &tables, &config, &routing, &received, &mut recorder.clone()).unwrap();
+        let changes = recorder.events_for(DEFAULT_ORDER_STATE_CHANGED_TOPIC);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].header(REQUEST_ID_HEADER), Some("req-2"));
+    }
+
+    #[test]
+    fn test_start_survives_unsent_command() {
+        let (url, mut client) = connect();
+        let order = stored_order(&url);
+        let mut publisher = MockEventPublisher::new();
+        publisher.expect_publish().times(1).returning(|_, _, _, _| Err(OrderServiceError::EventBrokerError(rdkafka::error::KafkaError::MessageProduction(rdkafka::error::RDKafkaErrorCode::AllBrokersDown))));
+        let saga = start_saga(&mut client, &Tables::default(), &SagaConfig::default(), &order, "req-1", &mut publisher).unwrap();
+        assert_eq!(load_saga(&mut client, &Tables::default(), &order.o_id).unwrap(), Some(saga));
+    }
+
+    #[test]
+    fn test_reply_without_saga_is_dropped_and_malformed_reply_fails() {
+        let (_, mut client) = connect();
+        let (tables, config, routing) = (Tables::default(), SagaConfig::default(), EventRouting::default());
+        let recorder = InMemoryPublisher::new();
+        assert!(handle_reply(&mut client, &tables, &config, &routing, &reply("unknown", SagaStep::Payment, ReplyOutcome::Succeeded), &mut recorder.clone()).is_ok());
+        let malformed = ConsumedMessage { value: b"{not json".to_vec(), ..reply("unknown", SagaStep::Payment, ReplyOutcome::Succeeded) };
+        assert!(matches!(handle_reply(&mut client, &tables, &config, &routing, &malformed, &mut recorder.clone()), Err(OrderServiceError::JSONParseError(_))));
+        assert!(recorder.events().is_empty());
+    }
+}
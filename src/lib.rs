@@ -1,21 +1,229 @@
 pub mod api;
+pub mod consumers;
 pub mod models;
 pub mod repository;
-mod producers;
+pub mod producers;
+pub mod telemetry;
+
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
 
 use actix_web::{App, HttpServer};
+use repository::{failover::parse_endpoints, order_repository::OrderStore};
+
+use api::utils::env::{get_db_ip, get_decode_mode, get_event_routing, get_kafka_config, get_migrate_on_startup, get_saga_config, get_order_store, get_migration_batch_size, get_table_config, get_thrift_config, DB_IP_ENV_ERR_MSG};
 
 pub async fn run_api() -> std::io::Result<()>{
-    HttpServer::new(|| {
+    telemetry::init();
+    if let Err(e) = get_table_config() {
+        tracing::error!(error = %e, "invalid table configuration");
+        telemetry::shutdown();
+        return Err(std::io::Error::other(e.to_string()));
+    }
+    if let Err(e) = get_decode_mode() {
+        tracing::error!(error = %e, "invalid order decode mode");
+        telemetry::shutdown();
+        return Err(std::io::Error::other(e.to_string()));
+    }
+    if let Err(e) = get_db_ip().map(|hosts| parse_endpoints(&hosts)).transpose().and(get_thrift_config()) {
+        tracing::error!(error = %e, "invalid HBase connection settings");
+        telemetry::shutdown();
+        return Err(std::io::Error::other(e.to_string()));
+    }
+    if let Err(e) = get_kafka_config().and(get_event_routing()) {
+        tracing::error!(error = %e, "invalid Kafka settings");
+        telemetry::shutdown();
+        return Err(std::io::Error::other(e.to_string()));
+    }
+    // The saga state is kept in HBase, so SAGA_ENABLED with the embedded store is refused here rather than on each order.
+    if let Err(e) = get_saga_config() {
+        tracing::error!(error = %e, "invalid saga settings");
+        telemetry::shutdown();
+        return Err(std::io::Error::other(e.to_string()));
+    }
+    if let Err(e) = api::workers::start_event_spool() {
+        tracing::error!(error = %e, "invalid event sink settings");
+        telemetry::shutdown();
+        return Err(std::io::Error::other(e.to_string()));
+    }
+    if let Err(e) = prepare_order_store() {
+        tracing::error!(error = %e, "invalid order store settings");
+        telemetry::shutdown();
+        return Err(std::io::Error::other(e.to_string()));
+    }
+    // Migrations rewrite HBase tables, the embedded store has none to run.
+    if get_migrate_on_startup() && matches!(get_order_store(), Ok(OrderStore::Hbase)) {
+        if let Err(e) = migrate() {
+            telemetry::shutdown();
+            return Err(e);
+        }
+    }
+    if let Err(e) = api::workers::start_stale_order_sweeper() {
+        tracing::error!(error = %e, "invalid stale order settings");
+        telemetry::shutdown();
+        return Err(std::io::Error::other(e.to_string()));
+    }
+    let res = HttpServer::new(|| {
         App::new()
+            .wrap(api::request_tracing::RequestTracing)
             // register HTTP requests handlers
-            .service(api::endpoints::index)
-            .service(api::endpoints::get_tables)
-            .service(api::endpoints::create)
-            .service(api::endpoints::get_orders_from_user)
-            .service(api::endpoints::get_order)
+            .configure(api::configure)
     })
     .bind("0.0.0.0:8080")?
     .run()
-    .await
-}
\ No newline at end of file
+    .await;
+    telemetry::shutdown();
+    res
+}
+
+/// Runs `order_service migrate`. Applies pending migrations, or with `status_only` only lists them.
+pub fn run_migrate(status_only: bool) -> std::io::Result<()> {
+    telemetry::init();
+    let res = if status_only { migration_status() } else { migrate() };
+    telemetry::shutdown();
+    res
+}
+
+/// Runs `order_service rebuild-customer-index`, which repopulates the customer index from `orders`.
+pub fn run_rebuild_customer_index() -> std::io::Result<()> {
+    telemetry::init();
+    let res = get_db_ip()
+        .ok_or_else(|| std::io::Error::other(DB_IP_ENV_ERR_MSG))
+        .and_then(|db_ip| {
+            let tables = get_table_config().map_err(|e| std::io::Error::other(e.to_string()))?;
+            api::workers::rebuild_customer_index(&db_ip, &tables, get_migration_batch_size()).map_err(|e| std::io::Error::other(e.to_string()))
+        });
+    match &res {
+        Ok(report) => tracing::info!(rows_scanned = report.rows_scanned, rows_indexed = report.rows_rewritten, "customer index rebuilt"),
+        Err(e) => tracing::error!(error = %e, "customer index rebuild failed"),
+    }
+    telemetry::shutdown();
+    res.map(|_| ())
+}
+
+/// Runs `order_service redrive-dlq <topic> [--limit <n>]`, which publishes the messages of `<topic>.DLQ` back to `topic`.
+pub fn run_redrive_dlq(args: &[String]) -> std::io::Result<()> {
+    telemetry::init();
+    let res = consumers::dlq::RedriveOptions::from_args(args)
+        .map_err(|e| std::io::Error::other(format!("{}\n{}", e, consumers::dlq::REDRIVE_USAGE)))
+        .and_then(|options| api::workers::redrive_dlq(&options.topic, options.limit).map_err(|e| std::io::Error::other(e.to_string())));
+    match &res {
+        Ok(report) => tracing::info!(redriven = report.redriven, "dead-letter messages re-driven"),
+        Err(e) => tracing::error!(error = %e, "re-drive failed"),
+    }
+    telemetry::shutdown();
+    res.map(|_| ())
+}
+
+/// Runs `order_service replay-events [options]`, which re-publishes the events of stored orders, see [`producers::replay`].
+pub fn run_replay_events(args: &[String]) -> std::io::Result<()> {
+    telemetry::init();
+    let res = producers::replay::ReplayOptions::from_args(args)
+        .map_err(|e| std::io::Error::other(format!("{}\n{}", e, producers::replay::REPLAY_USAGE)))
+        .and_then(|options| {
+            let db_ip = get_db_ip().ok_or_else(|| std::io::Error::other(DB_IP_ENV_ERR_MSG))?;
+            let tables = get_table_config().map_err(|e| std::io::Error::other(e.to_string()))?;
+            api::workers::replay_events(&db_ip, &tables, get_migration_batch_size(), &options).map_err(|e| std::io::Error::other(e.to_string()))
+        });
+    match &res {
+        Ok(report) => tracing::info!(orders = report.orders, order_created = report.order_created, state_changed = report.state_changed, skipped_rows = report.skipped_rows, "order events replayed"),
+        Err(e) => tracing::error!(error = %e, "event replay failed"),
+    }
+    telemetry::shutdown();
+    res.map(|_| ())
+}
+
+/// Runs `order_service reconcile [options]`, which prints the orders missing from the `OrderCreated` topic or
+/// from HBase as JSON, see [`consumers::reconcile`].
+pub fn run_reconcile(args: &[String]) -> std::io::Result<()> {
+    telemetry::init();
+    let res = consumers::reconcile::ReconcileOptions::from_args(args)
+        .map_err(|e| std::io::Error::other(format!("{}\n{}", e, consumers::reconcile::RECONCILE_USAGE)))
+        .and_then(|options| {
+            let db_ip = get_db_ip().ok_or_else(|| std::io::Error::other(DB_IP_ENV_ERR_MSG))?;
+            let tables = get_table_config().map_err(|e| std::io::Error::other(e.to_string()))?;
+            api::workers::reconcile_events(&db_ip, &tables, get_migration_batch_size(), &options).map_err(|e| std::io::Error::other(e.to_string()))
+        });
+    match &res {
+        Ok(report) => {
+            println!("{}", serde_json::to_string_pretty(report).unwrap_or_default());
+            tracing::info!(
+                hbase_orders = report.hbase_orders, topic_events = report.topic_events, missing_from_kafka = report.missing_from_kafka.len(),
+                missing_from_hbase = report.missing_from_hbase.len(), republished = report.republished, "reconciliation done"
+            );
+        }
+        Err(e) => tracing::error!(error = %e, "reconciliation failed"),
+    }
+    telemetry::shutdown();
+    res.map(|_| ())
+}
+
+/// Runs `order_service saga`, the orchestrator of the order creation saga, until the process is interrupted.
+/// See [`consumers::saga`].
+pub async fn run_saga() -> std::io::Result<()> {
+    telemetry::init();
+    let stop = Arc::new(AtomicBool::new(false));
+    let on_signal = Arc::clone(&stop);
+    actix_web::rt::spawn(async move {
+        if actix_web::rt::signal::ctrl_c().await.is_ok() {
+            tracing::info!("stopping the saga orchestrator");
+            on_signal.store(true, Ordering::Relaxed);
+        }
+    });
+    let res = actix_web::rt::task::spawn_blocking(move || api::workers::run_saga_orchestrator(&stop))
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|res| res.map_err(|e| std::io::Error::other(e.to_string())));
+    if let Err(e) = &res {
+        tracing::error!(error = %e, "saga orchestrator failed");
+    }
+    telemetry::shutdown();
+    res
+}
+
+/// Runs `order_service table-spec`, which prints HBase shell statements that create the tables
+/// with the configured family settings and pre-split regions.
+pub fn run_table_spec() -> std::io::Result<()> {
+    let tables = get_table_config().map_err(|e| std::io::Error::other(e.to_string()))?;
+    let specs = [
+        repository::hbase::order_table_spec(&tables),
+        repository::hbase::customer_index_spec(&tables),
+        repository::migrations::migrations_table_spec(&tables),
+        repository::sagas::saga_table_spec(&tables),
+        repository::leases::lease_table_spec(&tables),
+    ];
+    for spec in specs {
+        println!("{}", spec.shell_command());
+    }
+    Ok(())
+}
+
+/// Checks the order store settings. The embedded store is opened, and its tables are created.
+fn prepare_order_store() -> Result<(), models::errors::OrderServiceError> {
+    if get_order_store()? == OrderStore::Embedded {
+        let tables = get_table_config()?;
+        api::workers::order_repository(&tables)?.create_tables()?;
+    }
+    Ok(())
+}
+
+fn migrate() -> std::io::Result<()> {
+    let db_ip = get_db_ip().ok_or_else(|| std::io::Error::other(DB_IP_ENV_ERR_MSG))?;
+    let tables = get_table_config().map_err(|e| std::io::Error::other(e.to_string()))?;
+    let reports = api::workers::run_migrations(&db_ip, &tables, get_migration_batch_size()).map_err(|e| {
+        tracing::error!(error = %e, "migrations failed");
+        std::io::Error::other(e.to_string())
+    })?;
+    tracing::info!(applied = reports.len(), "migrations up to date");
+    Ok(())
+}
+
+fn migration_status() -> std::io::Result<()> {
+    let db_ip = get_db_ip().ok_or_else(|| std::io::Error::other(DB_IP_ENV_ERR_MSG))?;
+    let tables = get_table_config().map_err(|e| std::io::Error::other(e.to_string()))?;
+    let pending = api::workers::pending_migrations(&db_ip, &tables).map_err(|e| std::io::Error::other(e.to_string()))?;
+    for (version, name) in &pending {
+        tracing::info!(version, name, "pending migration");
+    }
+    tracing::info!(pending = pending.len(), "migration status");
+    Ok(())
+}
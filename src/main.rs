@@ -1,6 +1,24 @@
-use order_service::run_api;
+use order_service::{run_api, run_migrate, run_rebuild_customer_index, run_reconcile, run_redrive_dlq, run_replay_events, run_saga, run_table_spec};
+
+const USAGE: &str = "usage: order_service [migrate [--status] | rebuild-customer-index | table-spec | redrive-dlq <topic> [--limit <n>] \
+    | replay-events [options] | reconcile [options] | saga]
+Without a command the REST API is served.";
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    run_api().await
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("migrate") => run_migrate(args.iter().any(|a| a == "--status")),
+        Some("rebuild-customer-index") => run_rebuild_customer_index(),
+        Some("table-spec") => run_table_spec(),
+        Some("redrive-dlq") => run_redrive_dlq(&args[1..]),
+        Some("replay-events") => run_replay_events(&args[1..]),
+        Some("reconcile") => run_reconcile(&args[1..]),
+        Some("saga") => run_saga().await,
+        None => run_api().await,
+        Some(command) => {
+            eprintln!("unknown command '{}'\n{}", command, USAGE);
+            std::process::exit(2);
+        }
+    }
 }
\ No newline at end of file
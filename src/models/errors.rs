@@ -7,8 +7,26 @@ pub enum OrderServiceError {
     SplitColumnError(String),
     DBError(thrift::Error),
     RowNotFound(String),
-    OrderBuildFailed(),
-    EventBrokerError(kafka::Error)
+    OrderBuildFailed(Vec<RowIssue>),
+    EventBrokerError(rdkafka::error::KafkaError),
+    MigrationFailed { version: u32, reason: String },
+    InvalidConfig(String),
+    /// HBase calls are short-circuited after repeated transport failures.
+    CircuitOpen,
+    /// The embedded order store failed to read or write.
+    StorageError(String),
+    /// An event sink other than Kafka failed to take an event.
+    EventSinkError(String),
+    /// Another replica took over the named lease while this one was still working under it.
+    LeaseLost(String),
+}
+
+/// A problem found while decoding an HBase row into an order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RowIssue {
+    MissingField(&'static str),
+    InvalidValue { column: String, value: String, reason: String },
+    UnknownColumn(String),
 }
 
 impl Display for OrderServiceError {
@@ -20,12 +38,31 @@ impl Display for OrderServiceError {
             OrderServiceError::IntParseError(e) => write!(f, "IntParseError: {}", e),
             OrderServiceError::EventBrokerError(e) => write!(f, "KafkaError: {}", e),
             OrderServiceError::RowNotFound(row) => write!(f, "Error: Row with id: '{}' was not found.", row),
-            OrderServiceError::OrderBuildFailed() => write!(f, "Error building order from row content."),
+            OrderServiceError::OrderBuildFailed(issues) => {
+                let issues: Vec<String> = issues.iter().map(|i| i.to_string()).collect();
+                write!(f, "Error building order from row content: {}", issues.join("; "))
+            }
+            OrderServiceError::MigrationFailed { version, reason } => write!(f, "Migration {} failed: {}", version, reason),
+            OrderServiceError::InvalidConfig(reason) => write!(f, "Invalid configuration: {}", reason),
+            OrderServiceError::CircuitOpen => write!(f, "Database unavailable: too many failed calls, retry later"),
+            OrderServiceError::StorageError(reason) => write!(f, "StorageError: {}", reason),
+            OrderServiceError::EventSinkError(reason) => write!(f, "EventSinkError: {}", reason),
+            OrderServiceError::LeaseLost(name) => write!(f, "Lease '{}' was taken over by another replica", name),
             OrderServiceError::SplitColumnError(column) => write!(f, "Error splitting column - missing ':' character in string: {}", column),
         }
     }
 }
 
+impl Display for RowIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RowIssue::MissingField(field) => write!(f, "missing field '{}'", field),
+            RowIssue::InvalidValue { column, value, reason } => write!(f, "column '{}' has invalid value '{}': {}", column, value, reason),
+            RowIssue::UnknownColumn(column) => write!(f, "unknown column '{}'", column),
+        }
+    }
+}
+
 impl From<serde_json::Error> for OrderServiceError {
     fn from(err: serde_json::Error) -> Self {
         OrderServiceError::JSONParseError(err)
@@ -50,8 +87,8 @@ impl From<std::num::ParseIntError> for OrderServiceError {
     }
 }
 
-impl From<kafka::Error> for OrderServiceError {
-    fn from(err: kafka::Error) -> Self {
+impl From<rdkafka::error::KafkaError> for OrderServiceError {
+    fn from(err: rdkafka::error::KafkaError) -> Self {
         OrderServiceError::EventBrokerError(err)
     }
-}
\ No newline at end of file
+}
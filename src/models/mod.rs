@@ -1,3 +1,5 @@
 pub mod orders;
-pub(crate) mod tables;
-pub mod errors;
\ No newline at end of file
+pub mod saga;
+pub mod tables;
+pub mod errors;
+pub mod schema;
\ No newline at end of file
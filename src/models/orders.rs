@@ -1,4 +1,4 @@
-use std::{ops::{Deref, DerefMut}, str::FromStr, fmt::Display};
+use std::{ops::{Deref, DerefMut}, str::FromStr};
 
 use actix_web::{web};
 use chrono::{Utc, DateTime, NaiveDateTime};
@@ -7,13 +7,21 @@ use rand_pcg::Pcg64;
 use rand_seeder::Seeder;
 use serde::{Serialize, Deserialize, Serializer, Deserializer};
 use sha2::{Sha256, Digest};
+use utoipa::ToSchema;
 
-use super::errors::OrderServiceError;
+use super::errors::{OrderServiceError, RowIssue};
 
-const SERIALIZE_FORMAT: &'static str = "%Y-%m-%d %H:%M:%S.%f %Z";
+const SERIALIZE_FORMAT: &str = "%Y-%m-%d %H:%M:%S.%f %Z";
+/// Digits of the salt that starts an order id.
+pub const SALT_DIGITS: usize = 3;
+
+/// The salt as it starts an order id, zero padded so that order ids sort by salt.
+pub fn salt_prefix(salt: u8) -> String {
+    format!("{:0width$}", salt, width = SALT_DIGITS)
+}
 
 // Types
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct CreateOrder {
     pub c_id: String,
     pub r_id: String,
@@ -23,7 +31,7 @@ pub struct CreateOrder {
     pub orderlines: Vec<Orderline>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct OrderInfo {
     pub o_id: String,
     pub ordertime: String,
@@ -32,7 +40,7 @@ pub struct OrderInfo {
     pub c_id: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
 pub struct Order {
     pub o_id: String,
     pub c_id: String,
@@ -44,7 +52,7 @@ pub struct Order {
     pub rest_addr: String,
     pub postal_code: u32,
 }
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, ToSchema)]
 pub struct Orderline {
     pub item_num: u32,
     pub price: u32,
@@ -60,8 +68,9 @@ pub struct OrderBuilder {
     pub rest_addr: Option<String>,
     pub postal_code: Option<u32>,
     pub orderlines: Vec<Orderline>,
+    pub issues: Vec<RowIssue>,
 }
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
 pub enum OrderState {
     Processing,
     Pending,
@@ -74,12 +83,25 @@ pub enum OrderState {
 #[derive(Debug, Clone)]
 pub struct FormattedDateTime(DateTime<Utc>);
 
+/// How strictly a row is decoded into an order.
+/// `Strict` rejects rows with any issue, `Lenient` only rejects rows missing required fields
+/// and hands the remaining issues back as warnings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecodeMode {
+    #[default]
+    Strict,
+    Lenient,
+}
+
 // Impls
 impl Order {
     pub fn new (orderlines: Vec<Orderline>, cust_addr: String, rest_addr: String, c_id: String, r_id: String, postal_code: u32) -> Self {
         let ordertime = FormattedDateTime::new().to_rfc3339();
+        // The order time only has second precision, so a random nonce keeps identical orders placed within the same
+        // second from sharing an o_id.
+        let nonce = rand::thread_rng().gen::<u64>();
         Self {
-            o_id: Order::generate_o_id(&c_id, &r_id, &ordertime.to_string(), &orderlines),
+            o_id: Order::generate_o_id(&c_id, &r_id, &format!("{}#{}", ordertime, nonce), &orderlines),
             c_id,
             r_id,
             ordertime,
@@ -103,33 +125,47 @@ impl Order {
     }
 
     fn generate_o_id(c_id: &str, r_id: &str, ordertime: &str, orderlines: &Vec<Orderline>) -> String {
-        let hash = to_u32(&Order::hash(&c_id, &r_id, &ordertime.to_string(), &orderlines));
-        let mut res = String::from(Order::generate_salt(r_id));
+        let hash = to_u32(&Order::hash(c_id, r_id, ordertime, orderlines));
+        let mut res = Order::generate_salt(r_id);
         res.push_str(&hash.to_string());
         res
     }
     
     fn generate_salt(seed: &str) -> String {
         let mut rng: Pcg64 = Seeder::from(seed).make_rng();
-        rng.gen::<u8>().to_string()
+        salt_prefix(rng.gen::<u8>())
     }
 
-    pub fn build(builder: OrderBuilder) -> Option<Self> {
-        let orderstate = match OrderState::from_str(&builder.state?) {
-            Ok(s)=> s,
-            Err(_) => return None,
-        };
-        Some(Self {
-            o_id: builder.o_id?,
-            c_id: builder.c_id?,
-            r_id: builder.r_id?,
-            cust_addr: builder.cust_addr?,
-            rest_addr: builder.rest_addr?,
-            state: orderstate,
-            ordertime: builder.ordertime?,
-            postal_code: builder.postal_code?,
-            orderlines: builder.orderlines,
-        })
+    pub fn build(builder: OrderBuilder) -> Result<Self, OrderServiceError> {
+        Order::decode(builder, DecodeMode::Strict).map(|(order, _)| order)
+    }
+
+    /// Decodes the builder into an order, returning the issues that were tolerated under `mode`.
+    pub fn decode(mut builder: OrderBuilder, mode: DecodeMode) -> Result<(Self, Vec<RowIssue>), OrderServiceError> {
+        let mut missing = Vec::new();
+        let state = take_state(&mut builder, &mut missing);
+        let o_id = take_required(builder.o_id.take(), "o_id", &mut missing);
+        let c_id = take_required(builder.c_id.take(), "c_id", &mut missing);
+        let r_id = take_required(builder.r_id.take(), "r_id", &mut missing);
+        let cust_addr = take_required(builder.cust_addr.take(), "cust_addr", &mut missing);
+        let rest_addr = take_required(builder.rest_addr.take(), "rest_addr", &mut missing);
+        let ordertime = take_required(builder.ordertime.take(), "ordertime", &mut missing);
+        let postal_code = take_required(builder.postal_code.take(), "postal_code", &mut missing);
+        let warnings = check_issues(missing, builder.issues, mode)?;
+        match (o_id, c_id, r_id, cust_addr, rest_addr, state, ordertime, postal_code) {
+            (Some(o_id), Some(c_id), Some(r_id), Some(cust_addr), Some(rest_addr), Some(state), Some(ordertime), Some(postal_code)) => Ok((Self {
+                o_id,
+                c_id,
+                r_id,
+                cust_addr,
+                rest_addr,
+                state,
+                ordertime,
+                postal_code,
+                orderlines: builder.orderlines,
+            }, warnings)),
+            _ => Err(OrderServiceError::OrderBuildFailed(warnings)),
+        }
     }
 
     pub fn to_json_string(&self) -> Result<String, OrderServiceError> {
@@ -148,7 +184,7 @@ impl From<web::Json<CreateOrder>> for Order {
             params.rest_addr.clone(),
             params.c_id.clone(),
             params.r_id.clone(),
-            params.postal_code.clone(),
+            params.postal_code,
         )
     }
 }
@@ -180,7 +216,7 @@ impl<'de> Deserialize<'de> for FormattedDateTime {
             .map_err(serde::de::Error::custom)
             .map(|x| {
                 let now = Utc::now();
-                let date: DateTime<Utc> = DateTime::from_utc(x, now.offset().clone());
+                let date: DateTime<Utc> = DateTime::from_utc(x, *now.offset());
                 Self(date)
                 // or
                 // date.into()
@@ -210,14 +246,14 @@ impl From<DateTime<Utc>> for FormattedDateTime {
 
 impl FormattedDateTime {
     pub fn parse_from_str(str: &str) -> Result<Self, OrderServiceError> {
-        let s = DateTime::parse_from_str(&str, SERIALIZE_FORMAT)?;
+        let s = DateTime::parse_from_str(str, SERIALIZE_FORMAT)?;
         Ok(Self(s.into()))
     }
 }
 
-impl Into<DateTime<Utc>> for FormattedDateTime {
-    fn into(self) -> DateTime<Utc> {
-        self.0
+impl From<FormattedDateTime> for DateTime<Utc> {
+    fn from(date: FormattedDateTime) -> Self {
+        date.0
     }
 }
 
@@ -275,18 +311,69 @@ impl FromStr for Orderline {
 }
 
 impl OrderInfo {
-    pub fn build(builder: OrderBuilder) -> Option<Self> {
-        let orderstate = match OrderState::from_str(&builder.state?) {
-            Ok(s)=> s,
-            Err(_) => return None,
-        };
-        Some(Self {
-            o_id: builder.o_id?,
-            r_id: builder.r_id?,
-            state: orderstate,
-            ordertime: builder.ordertime?,
-            c_id: builder.c_id?,
-        })
+    pub fn build(builder: OrderBuilder) -> Result<Self, OrderServiceError> {
+        OrderInfo::decode(builder, DecodeMode::Strict).map(|(info, _)| info)
+    }
+
+    /// Decodes the builder into order info, returning the issues that were tolerated under `mode`.
+    pub fn decode(mut builder: OrderBuilder, mode: DecodeMode) -> Result<(Self, Vec<RowIssue>), OrderServiceError> {
+        let mut missing = Vec::new();
+        let state = take_state(&mut builder, &mut missing);
+        let o_id = take_required(builder.o_id.take(), "o_id", &mut missing);
+        let r_id = take_required(builder.r_id.take(), "r_id", &mut missing);
+        let c_id = take_required(builder.c_id.take(), "c_id", &mut missing);
+        let ordertime = take_required(builder.ordertime.take(), "ordertime", &mut missing);
+        let warnings = check_issues(missing, builder.issues, mode)?;
+        match (o_id, r_id, c_id, state, ordertime) {
+            (Some(o_id), Some(r_id), Some(c_id), Some(state), Some(ordertime)) => Ok((Self {
+                o_id,
+                r_id,
+                state,
+                ordertime,
+                c_id,
+            }, warnings)),
+            _ => Err(OrderServiceError::OrderBuildFailed(warnings)),
+        }
+    }
+}
+
+fn take_required<T>(value: Option<T>, field: &'static str, missing: &mut Vec<RowIssue>) -> Option<T> {
+    if value.is_none() {
+        missing.push(RowIssue::MissingField(field));
+    }
+    value
+}
+
+fn take_state(builder: &mut OrderBuilder, missing: &mut Vec<RowIssue>) -> Option<OrderState> {
+    let state = take_required(builder.state.take(), "state", missing)?;
+    match OrderState::from_str(&state) {
+        Ok(s) => Some(s),
+        Err(_) => {
+            missing.push(RowIssue::InvalidValue { column: "info:state".into(), value: state, reason: "unknown order state".into() });
+            None
+        }
+    }
+}
+
+/// Missing or invalid required fields always fail the build. Other issues fail it only in strict mode,
+/// and are otherwise returned as warnings.
+fn check_issues(missing: Vec<RowIssue>, issues: Vec<RowIssue>, mode: DecodeMode) -> Result<Vec<RowIssue>, OrderServiceError> {
+    if !missing.is_empty() || (mode == DecodeMode::Strict && !issues.is_empty()) {
+        let mut all = missing;
+        all.extend(issues);
+        return Err(OrderServiceError::OrderBuildFailed(all));
+    }
+    Ok(issues)
+}
+
+impl FromStr for DecodeMode {
+    type Err = ();
+    fn from_str(input: &str) -> Result<DecodeMode, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "strict" => Ok(DecodeMode::Strict),
+            "lenient" => Ok(DecodeMode::Lenient),
+            _ => Err(()),
+        }
     }
 }
 
@@ -337,6 +424,14 @@ mod tests{
         assert_eq!(rkey1, rkey2, "Row key was generated differently with same input");
     }
 
+    #[test]
+    fn test_identical_orders_get_different_o_ids() {
+        let orderlines = vec![Orderline { item_num: 1, price: 5 }];
+        let order1 = Order::new(orderlines.clone(), "addr".into(), "addr2".into(), "custid".into(), "restid".into(), 2860);
+        let order2 = Order::new(orderlines, "addr".into(), "addr2".into(), "custid".into(), "restid".into(), 2860);
+        assert_ne!(order1.o_id, order2.o_id, "Identical orders within the same second got the same o_id");
+    }
+
     #[test]
     fn test_generate_row_key_front_same() {
         let restid = "restid".to_string();
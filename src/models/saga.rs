@@ -0,0 +1,357 @@
+//! The order creation saga: payment authorization, restaurant acceptance and courier assignment, in that order.
+//!
+//! Each step sends a command and waits for a reply. A failed or timed out step ends the saga, and the steps that
+//! succeeded before it are undone in reverse order by compensating commands. The saga is only the state machine,
+//! storing it and sending its commands is left to the caller.
+
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use super::orders::{Order, OrderState};
+
+pub const DEFAULT_PAYMENT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_RESTAURANT_TIMEOUT: Duration = Duration::from_secs(300);
+pub const DEFAULT_COURIER_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SagaStep {
+    Payment,
+    Restaurant,
+    Courier,
+}
+
+impl SagaStep {
+    const ALL: [SagaStep; 3] = [SagaStep::Payment, SagaStep::Restaurant, SagaStep::Courier];
+
+    fn next(self) -> Option<SagaStep> {
+        Self::ALL.iter().skip_while(|s| **s != self).nth(1).copied()
+    }
+
+    /// The steps done before this one, latest first.
+    fn done_before(self) -> impl Iterator<Item = SagaStep> {
+        Self::ALL.into_iter().take_while(move |s| *s != self).collect::<Vec<_>>().into_iter().rev()
+    }
+
+    /// The command that runs the step.
+    fn command(self) -> CommandType {
+        match self {
+            SagaStep::Payment => CommandType::AuthorizePayment,
+            SagaStep::Restaurant => CommandType::AcceptOrder,
+            SagaStep::Courier => CommandType::AssignCourier,
+        }
+    }
+
+    /// The command that undoes the step.
+    fn compensation(self) -> CommandType {
+        match self {
+            SagaStep::Payment => CommandType::ReleasePayment,
+            SagaStep::Restaurant => CommandType::CancelOrder,
+            SagaStep::Courier => CommandType::ReleaseCourier,
+        }
+    }
+}
+
+impl fmt::Display for SagaStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SagaStep::Payment => write!(f, "payment"),
+            SagaStep::Restaurant => write!(f, "restaurant"),
+            SagaStep::Courier => write!(f, "courier"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SagaStatus {
+    /// Waiting for the reply of the current step.
+    Running,
+    /// Every step succeeded.
+    Completed,
+    /// A step failed or timed out, and the steps before it were compensated.
+    Compensated,
+}
+
+impl fmt::Display for SagaStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SagaStatus::Running => write!(f, "running"),
+            SagaStatus::Completed => write!(f, "completed"),
+            SagaStatus::Compensated => write!(f, "compensated"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommandType {
+    AuthorizePayment,
+    ReleasePayment,
+    AcceptOrder,
+    CancelOrder,
+    AssignCourier,
+    ReleaseCourier,
+}
+
+/// How long each step may take before the saga gives up on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepTimeouts {
+    pub payment: Duration,
+    pub restaurant: Duration,
+    pub courier: Duration,
+}
+
+impl Default for StepTimeouts {
+    fn default() -> Self {
+        Self { payment: DEFAULT_PAYMENT_TIMEOUT, restaurant: DEFAULT_RESTAURANT_TIMEOUT, courier: DEFAULT_COURIER_TIMEOUT }
+    }
+}
+
+impl StepTimeouts {
+    pub fn of(&self, step: SagaStep) -> Duration {
+        match step {
+            SagaStep::Payment => self.payment,
+            SagaStep::Restaurant => self.restaurant,
+            SagaStep::Courier => self.courier,
+        }
+    }
+}
+
+/// A command to a participant. The participant replies to the step of the command, and must handle a command it
+/// has already seen again, since commands are sent at least once.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SagaCommand {
+    pub command: CommandType,
+    pub step: SagaStep,
+    pub o_id: String,
+    /// Sum of the orderline prices, in cents/ører.
+    pub amount: u64,
+    pub order: Order,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplyOutcome {
+    Succeeded,
+    Failed,
+}
+
+/// A participant's answer to the command of a step.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SagaReply {
+    pub o_id: String,
+    pub step: SagaStep,
+    pub outcome: ReplyOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// What a transition asks of the caller: commands to send, and the state the order moves to, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    pub commands: Vec<SagaCommand>,
+    pub order_state: Option<OrderState>,
+}
+
+/// The saga of one order, identified by its o_id.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Saga {
+    pub order: Order,
+    pub status: SagaStatus,
+    /// The step waited for while running, the last step tried otherwise.
+    pub step: SagaStep,
+    /// When the current step times out, in Unix millis.
+    pub deadline: i64,
+    pub started_at: i64,
+    pub updated_at: i64,
+    /// Why the saga was compensated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Saga {
+    /// A running saga for `order`, and the command of its first step.
+    pub fn start(order: &Order, now: i64, timeouts: &StepTimeouts) -> (Saga, Transition) {
+        let step = SagaStep::Payment;
+        let saga = Saga {
+            order: order.clone(),
+            status: SagaStatus::Running,
+            step,
+            deadline: now + timeouts.of(step).as_millis() as i64,
+            started_at: now,
+            updated_at: now,
+            reason: None,
+        };
+        let transition = Transition { commands: vec![saga.command(step.command())], order_state: None };
+        (saga, transition)
+    }
+
+    pub fn o_id(&self) -> &str {
+        &self.order.o_id
+    }
+
+    /// The state the saga has moved its order to so far.
+    pub fn order_state(&self) -> OrderState {
+        match self.status {
+            SagaStatus::Running if self.step == SagaStep::Courier => OrderState::Accepted,
+            SagaStatus::Running => OrderState::Pending,
+            SagaStatus::Completed => OrderState::Accepted,
+            SagaStatus::Compensated => OrderState::Rejected,
+        }
+    }
+
+    /// Applies a reply. `None` when the saga is not waiting for it, e.g. a duplicate or a reply after a timeout.
+    pub fn on_reply(&mut self, reply: &SagaReply, now: i64, timeouts: &StepTimeouts) -> Option<Transition> {
+        if self.status != SagaStatus::Running || reply.step != self.step || reply.o_id != self.order.o_id {
+            return None;
+        }
+        if reply.outcome == ReplyOutcome::Failed {
+            let reason = format!("{} failed: {}", self.step, reply.reason.as_deref().unwrap_or("no reason given"));
+            return Some(self.compensate(reason, now));
+        }
+        self.updated_at = now;
+        let order_state = (self.step == SagaStep::Restaurant).then_some(OrderState::Accepted);
+        let commands = match self.step.next() {
+            Some(next) => {
+                self.step = next;
+                self.deadline = now + timeouts.of(next).as_millis() as i64;
+                vec![self.command(next.command())]
+            }
+            None => {
+                self.status = SagaStatus::Completed;
+                vec![]
+            }
+        };
+        Some(Transition { commands, order_state })
+    }
+
+    /// Gives up on the current step once its deadline has passed. `None` before that, or when not running.
+    pub fn on_timeout(&mut self, now: i64) -> Option<Transition> {
+        if self.status != SagaStatus::Running || now < self.deadline {
+            return None;
+        }
+        Some(self.compensate(format!("{} timed out", self.step), now))
+    }
+
+    /// Ends the saga and undoes the steps done before the current one. The current step is not undone: it
+    /// failed, or its participant never answered and gives up on it by itself.
+    fn compensate(&mut self, reason: String, now: i64) -> Transition {
+        self.status = SagaStatus::Compensated;
+        self.reason = Some(reason);
+        self.updated_at = now;
+        let commands = self.step.done_before().map(|step| SagaCommand { step, ..self.command(step.compensation()) }).collect();
+        Transition { commands, order_state: Some(OrderState::Rejected) }
+    }
+
+    fn command(&self, command: CommandType) -> SagaCommand {
+        SagaCommand {
+            command,
+            step: self.step,
+            o_id: self.order.o_id.clone(),
+            amount: self.order.orderlines.iter().map(|l| l.price as u64).sum(),
+            order: self.order.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::orders::Orderline;
+
+    const NOW: i64 = 1_000_000;
+
+    fn order() -> Order {
+        let lines = vec![Orderline { item_num: 1, price: 100 }, Orderline { item_num: 2, price: 250 }];
+        Order::new(lines, "CustAddr".into(), "RestAddr".into(), "custid".into(), "restid".into(), 2860)
+    }
+
+    fn reply(saga: &Saga, step: SagaStep, outcome: ReplyOutcome) -> SagaReply {
+        SagaReply { o_id: saga.o_id().into(), step, outcome, reason: (outcome == ReplyOutcome::Failed).then(|| "declined".into()) }
+    }
+
+    fn commands(transition: &Transition) -> Vec<(CommandType, SagaStep)> {
+        transition.commands.iter().map(|c| (c.command, c.step)).collect()
+    }
+
+    #[test]
+    fn test_start_authorizes_payment() {
+        let (saga, transition) = Saga::start(&order(), NOW, &StepTimeouts::default());
+        assert_eq!(saga.status, SagaStatus::Running);
+        assert_eq!(saga.step, SagaStep::Payment);
+        assert_eq!(saga.deadline, NOW + 30_000);
+        assert_eq!(commands(&transition), [(CommandType::AuthorizePayment, SagaStep::Payment)]);
+        assert_eq!(transition.commands[0].amount, 350);
+        assert_eq!(transition.order_state, None);
+    }
+
+    #[test]
+    fn test_successful_saga_runs_every_step() {
+        let timeouts = StepTimeouts::default();
+        let (mut saga, _) = Saga::start(&order(), NOW, &timeouts);
+        assert_eq!(saga.order_state(), OrderState::Pending);
+
+        let transition = saga.on_reply(&reply(&saga, SagaStep::Payment, ReplyOutcome::Succeeded), NOW + 1, &timeouts).unwrap();
+        assert_eq!(commands(&transition), [(CommandType::AcceptOrder, SagaStep::Restaurant)]);
+        assert_eq!(saga.deadline, NOW + 1 + 300_000);
+
+        let transition = saga.on_reply(&reply(&saga, SagaStep::Restaurant, ReplyOutcome::Succeeded), NOW + 2, &timeouts).unwrap();
+        assert_eq!(commands(&transition), [(CommandType::AssignCourier, SagaStep::Courier)]);
+        assert_eq!(transition.order_state, Some(OrderState::Accepted));
+        assert_eq!(saga.order_state(), OrderState::Accepted);
+
+        let transition = saga.on_reply(&reply(&saga, SagaStep::Courier, ReplyOutcome::Succeeded), NOW + 3, &timeouts).unwrap();
+        assert!(transition.commands.is_empty());
+        assert_eq!(transition.order_state, None);
+        assert_eq!(saga.status, SagaStatus::Completed);
+        assert_eq!(saga.on_timeout(i64::MAX), None);
+    }
+
+    #[test]
+    fn test_restaurant_rejection_releases_payment() {
+        let timeouts = StepTimeouts::default();
+        let (mut saga, _) = Saga::start(&order(), NOW, &timeouts);
+        saga.on_reply(&reply(&saga, SagaStep::Payment, ReplyOutcome::Succeeded), NOW, &timeouts).unwrap();
+        let transition = saga.on_reply(&reply(&saga, SagaStep::Restaurant, ReplyOutcome::Failed), NOW, &timeouts).unwrap();
+        assert_eq!(commands(&transition), [(CommandType::ReleasePayment, SagaStep::Payment)]);
+        assert_eq!(transition.order_state, Some(OrderState::Rejected));
+        assert_eq!(saga.status, SagaStatus::Compensated);
+        assert_eq!(saga.reason.as_deref(), Some("restaurant failed: declined"));
+    }
+
+    #[test]
+    fn test_courier_timeout_compensates_in_reverse_order() {
+        let timeouts = StepTimeouts::default();
+        let (mut saga, _) = Saga::start(&order(), NOW, &timeouts);
+        saga.on_reply(&reply(&saga, SagaStep::Payment, ReplyOutcome::Succeeded), NOW, &timeouts).unwrap();
+        saga.on_reply(&reply(&saga, SagaStep::Restaurant, ReplyOutcome::Succeeded), NOW, &timeouts).unwrap();
+        assert_eq!(saga.on_timeout(saga.deadline - 1), None);
+        let transition = saga.on_timeout(saga.deadline).unwrap();
+        assert_eq!(commands(&transition), [(CommandType::CancelOrder, SagaStep::Restaurant), (CommandType::ReleasePayment, SagaStep::Payment)]);
+        assert_eq!(saga.reason.as_deref(), Some("courier timed out"));
+        assert_eq!(saga.order_state(), OrderState::Rejected);
+    }
+
+    #[test]
+    fn test_failed_payment_has_nothing_to_compensate() {
+        let timeouts = StepTimeouts::default();
+        let (mut saga, _) = Saga::start(&order(), NOW, &timeouts);
+        let transition = saga.on_reply(&reply(&saga, SagaStep::Payment, ReplyOutcome::Failed), NOW, &timeouts).unwrap();
+        assert!(transition.commands.is_empty());
+        assert_eq!(transition.order_state, Some(OrderState::Rejected));
+    }
+
+    #[test]
+    fn test_stale_and_duplicate_replies_are_ignored() {
+        let timeouts = StepTimeouts::default();
+        let (mut saga, _) = Saga::start(&order(), NOW, &timeouts);
+        let paid = reply(&saga, SagaStep::Payment, ReplyOutcome::Succeeded);
+        assert!(saga.on_reply(&reply(&saga, SagaStep::Restaurant, ReplyOutcome::Succeeded), NOW, &timeouts).is_none());
+        assert!(saga.on_reply(&SagaReply { o_id: "other".into(), ..paid.clone() }, NOW, &timeouts).is_none());
+        assert!(saga.on_reply(&paid, NOW, &timeouts).is_some());
+        assert!(saga.on_reply(&paid, NOW, &timeouts).is_none());
+        saga.on_timeout(saga.deadline).unwrap();
+        assert!(saga.on_reply(&reply(&saga, SagaStep::Restaurant, ReplyOutcome::Succeeded), NOW, &timeouts).is_none());
+    }
+}
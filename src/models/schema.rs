@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{errors::OrderServiceError, orders::salt_prefix};
+
+const COMPRESSIONS: [&str; 7] = ["NONE", "GZ", "SNAPPY", "LZO", "LZ4", "ZSTD", "BZIP2"];
+const BLOOM_FILTERS: [&str; 3] = ["NONE", "ROW", "ROWCOL"];
+/// HBase treats this TTL as "keep forever".
+pub const FOREVER: i32 = i32::MAX;
+
+/// Settings of a single column family, as reported by HBase.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct ColumnFamilySchema {
+    pub name: String,
+    pub max_versions: Option<i32>,
+    pub compression: Option<String>,
+    pub in_memory: Option<bool>,
+    pub bloom_filter_type: Option<String>,
+    pub time_to_live: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct TableSchema {
+    pub table_name: String,
+    pub exists: bool,
+    pub column_families: Vec<ColumnFamilySchema>,
+}
+
+/// Difference between the column families the service expects and those HBase has.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct SchemaDrift {
+    pub table_name: String,
+    pub table_exists: bool,
+    pub missing_families: Vec<String>,
+    pub unexpected_families: Vec<String>,
+    pub in_sync: bool,
+}
+
+impl SchemaDrift {
+    pub fn new(table_name: String, table_exists: bool, missing_families: Vec<String>, unexpected_families: Vec<String>) -> Self {
+        let in_sync = table_exists && missing_families.is_empty() && unexpected_families.is_empty();
+        Self { table_name, table_exists, missing_families, unexpected_families, in_sync }
+    }
+}
+
+/// Settings used when creating a column family.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnFamilySpec {
+    pub name: String,
+    pub compression: String,
+    pub bloom_filter_type: String,
+    pub max_versions: i32,
+    /// Seconds a cell is kept.
+    pub time_to_live: i32,
+    pub in_memory: bool,
+}
+
+impl ColumnFamilySpec {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            compression: "NONE".into(),
+            bloom_filter_type: "NONE".into(),
+            max_versions: 3,
+            time_to_live: FOREVER,
+            in_memory: false,
+        }
+    }
+
+    /// Applies settings written as `key=value` pairs separated by `;`, e.g.
+    /// `compression=SNAPPY;bloom=ROW;versions=1;ttl=2592000;in_memory=true`.
+    pub fn with_settings(mut self, settings: &str) -> Result<Self, OrderServiceError> {
+        let invalid = |reason: String| OrderServiceError::InvalidConfig(format!("column family '{}': {}", self.name, reason));
+        for setting in settings.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+            let (key, value) = setting.split_once('=').ok_or_else(|| invalid(format!("expected key=value but got '{}'", setting)))?;
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "compression" => self.compression = one_of(value, &COMPRESSIONS).ok_or_else(|| invalid(format!("unknown compression '{}'", value)))?,
+                "bloom" => self.bloom_filter_type = one_of(value, &BLOOM_FILTERS).ok_or_else(|| invalid(format!("unknown bloom filter '{}'", value)))?,
+                "versions" => self.max_versions = positive(value).ok_or_else(|| invalid(format!("versions must be a positive number, got '{}'", value)))?,
+                "ttl" => self.time_to_live = positive(value).ok_or_else(|| invalid(format!("ttl must be a positive number of seconds, got '{}'", value)))?,
+                "in_memory" => self.in_memory = value.parse().map_err(|_| invalid(format!("in_memory must be true or false, got '{}'", value)))?,
+                _ => return Err(invalid(format!("unknown setting '{}'", key))),
+            }
+        }
+        Ok(self)
+    }
+}
+
+fn one_of(value: &str, allowed: &[&str]) -> Option<String> {
+    let value = value.to_ascii_uppercase();
+    allowed.contains(&value.as_str()).then_some(value)
+}
+
+fn positive(value: &str) -> Option<i32> {
+    value.parse::<i32>().ok().filter(|v| *v > 0)
+}
+
+/// Everything needed to create a table: its families and the row keys its first regions are split at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableSpec {
+    pub name: String,
+    pub families: Vec<ColumnFamilySpec>,
+    pub split_keys: Vec<String>,
+}
+
+impl TableSpec {
+    /// The equivalent `create` statement for the HBase shell.
+    pub fn shell_command(&self) -> String {
+        let mut parts = vec![format!("'{}'", self.name)];
+        for f in &self.families {
+            let ttl = if f.time_to_live == FOREVER { "'FOREVER'".to_owned() } else { f.time_to_live.to_string() };
+            parts.push(format!(
+                "{{NAME => '{}', COMPRESSION => '{}', BLOOMFILTER => '{}', VERSIONS => {}, TTL => {}, IN_MEMORY => '{}'}}",
+                f.name, f.compression, f.bloom_filter_type, f.max_versions, ttl, f.in_memory
+            ));
+        }
+        if !self.split_keys.is_empty() {
+            let keys: Vec<String> = self.split_keys.iter().map(|k| format!("'{}'", k)).collect();
+            parts.push(format!("SPLITS => [{}]", keys.join(", ")));
+        }
+        format!("create {}", parts.join(", "))
+    }
+}
+
+/// Split keys that divide the 0–255 order id salt into `regions` ranges of equal size. Each key is a salt as it
+/// starts an order id, so a region holds the orders of the salts from its start key up to the next one.
+pub fn salt_split_keys(regions: u32) -> Vec<String> {
+    let regions = regions.clamp(1, 256) as usize;
+    (1..regions).map(|i| salt_prefix((i * 256 / regions) as u8)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::orders::{Order, SALT_DIGITS};
+
+    #[test]
+    fn test_family_defaults() {
+        let f = ColumnFamilySpec::new("info");
+        assert_eq!((f.compression.as_str(), f.bloom_filter_type.as_str(), f.max_versions, f.time_to_live, f.in_memory), ("NONE", "NONE", 3, FOREVER, false));
+    }
+
+    #[test]
+    fn test_family_with_settings() {
+        let f = ColumnFamilySpec::new("info").with_settings("compression=snappy; bloom=ROW;versions=1;ttl=86400;in_memory=true").unwrap();
+        assert_eq!(f.compression, "SNAPPY");
+        assert_eq!(f.bloom_filter_type, "ROW");
+        assert_eq!(f.max_versions, 1);
+        assert_eq!(f.time_to_live, 86400);
+        assert!(f.in_memory);
+    }
+
+    #[test]
+    fn test_family_with_bad_settings() {
+        for bad in ["compression=FAST", "bloom=yes", "versions=0", "ttl=-1", "in_memory=maybe", "colour=blue", "versions"] {
+            assert!(ColumnFamilySpec::new("info").with_settings(bad).is_err(), "{} was accepted", bad);
+        }
+    }
+
+    #[test]
+    fn test_salt_split_keys() {
+        assert!(salt_split_keys(1).is_empty());
+        let keys = salt_split_keys(16);
+        assert_eq!(keys.len(), 15);
+        let mut sorted = keys.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted, keys);
+        assert_eq!(salt_split_keys(1000).len(), 255);
+    }
+
+    #[test]
+    fn test_salt_split_keys_follow_order_ids() {
+        let keys = salt_split_keys(16);
+        assert_eq!(keys[..3], ["016", "032", "048"]);
+        let mut per_region = [0; 16];
+        for i in 0..2000 {
+            let order = Order::new(vec![], "addr".into(), "addr2".into(), "custid".into(), format!("rest-{}", i), 2860);
+            let salt: usize = order.o_id[..SALT_DIGITS].parse().unwrap();
+            let region = keys.iter().filter(|k| k.as_str() <= order.o_id.as_str()).count();
+            assert_eq!(region, salt / 16, "order {} is outside the region of its salt", order.o_id);
+            per_region[region] += 1;
+        }
+        assert!(per_region.iter().all(|n| *n > 0), "{:?}", per_region);
+    }
+
+    #[test]
+    fn test_shell_command() {
+        let spec = TableSpec {
+            name: "staging:orders".into(),
+            families: vec![ColumnFamilySpec::new("info"), ColumnFamilySpec { time_to_live: 60, ..ColumnFamilySpec::new("ol") }],
+            split_keys: vec!["1".into(), "5".into()],
+        };
+        assert_eq!(
+            spec.shell_command(),
+            "create 'staging:orders', {NAME => 'info', COMPRESSION => 'NONE', BLOOMFILTER => 'NONE', VERSIONS => 3, TTL => 'FOREVER', IN_MEMORY => 'false'}, \
+{NAME => 'ol', COMPRESSION => 'NONE', BLOOMFILTER => 'NONE', VERSIONS => 3, TTL => 60, IN_MEMORY => 'false'}, SPLITS => ['1', '5']"
+        );
+    }
+}
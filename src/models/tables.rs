@@ -1,5 +1,11 @@
 use serde::{Deserialize, Serialize};
-#[derive(Debug, Serialize, Deserialize)]
+use utoipa::ToSchema;
+
+use std::collections::BTreeMap;
+
+use super::{errors::OrderServiceError, schema::ColumnFamilySpec};
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct TableName {
     pub table_name: String,
 }
@@ -10,8 +16,131 @@ impl TableName {
     }
 }
 
-impl Into<String> for TableName {
-    fn into(self) -> String {
-        self.table_name
+impl From<TableName> for String {
+    fn from(table: TableName) -> Self {
+        table.table_name
+    }
+}
+
+
+pub const DEFAULT_ORDER_TABLE: &str = "orders";
+pub const DEFAULT_CUSTOMER_INDEX_TABLE: &str = "orders_by_customer";
+pub const DEFAULT_MIGRATIONS_TABLE: &str = "schema_migrations";
+pub const DEFAULT_SAGAS_TABLE: &str = "order_sagas";
+pub const DEFAULT_LEASES_TABLE: &str = "service_leases";
+pub const DEFAULT_ORDER_REGIONS: u32 = 16;
+
+/// Fully qualified names of the tables the service reads and writes, e.g. `staging:orders`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tables {
+    pub orders: String,
+    /// Index of orders by customer, keyed by c_id, reversed order time and o_id.
+    pub customer_index: String,
+    pub migrations: String,
+    /// State of the order creation sagas, keyed by o_id.
+    pub sagas: String,
+    /// Leases of the background jobs only one replica may run, keyed by job name.
+    pub leases: String,
+    /// Column family settings that differ from `ColumnFamilySpec::new`, by family name.
+    pub families: BTreeMap<String, ColumnFamilySpec>,
+    /// Number of regions the order table is pre-split into.
+    pub order_regions: u32,
+}
+
+impl Default for Tables {
+    fn default() -> Self {
+        Self {
+            orders: DEFAULT_ORDER_TABLE.to_owned(),
+            customer_index: DEFAULT_CUSTOMER_INDEX_TABLE.to_owned(),
+            migrations: DEFAULT_MIGRATIONS_TABLE.to_owned(),
+            sagas: DEFAULT_SAGAS_TABLE.to_owned(),
+            leases: DEFAULT_LEASES_TABLE.to_owned(),
+            families: BTreeMap::new(),
+            order_regions: DEFAULT_ORDER_REGIONS,
+        }
+    }
+}
+
+impl Tables {
+    /// Qualifies each table name with `namespace` when one is given. The default namespace is used otherwise.
+    pub fn new(namespace: Option<&str>, orders: &str, customer_index: &str, migrations: &str, sagas: &str, leases: &str) -> Result<Self, OrderServiceError> {
+        if let Some(ns) = namespace {
+            if ns.is_empty() || !ns.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(OrderServiceError::InvalidConfig(format!("'{}' is not a valid HBase namespace", ns)));
+            }
+        }
+        let qualify = |name: &str| -> Result<String, OrderServiceError> {
+            let valid_start = name.chars().next().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid_start || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+                return Err(OrderServiceError::InvalidConfig(format!("'{}' is not a valid HBase table name", name)));
+            }
+            Ok(match namespace {
+                Some(ns) => format!("{}:{}", ns, name),
+                None => name.to_owned(),
+            })
+        };
+        Ok(Self {
+            orders: qualify(orders)?,
+            customer_index: qualify(customer_index)?,
+            migrations: qualify(migrations)?,
+            sagas: qualify(sagas)?,
+            leases: qualify(leases)?,
+            ..Default::default()
+        })
+    }
+
+    pub fn with_family(mut self, family: ColumnFamilySpec) -> Self {
+        self.families.insert(family.name.clone(), family);
+        self
+    }
+
+    pub fn with_order_regions(mut self, regions: u32) -> Self {
+        self.order_regions = regions;
+        self
+    }
+
+    /// The settings to create `name` with.
+    pub fn family(&self, name: &str) -> ColumnFamilySpec {
+        self.families.get(name).cloned().unwrap_or_else(|| ColumnFamilySpec::new(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tables_without_namespace() {
+        let tables = Tables::new(None, DEFAULT_ORDER_TABLE, DEFAULT_CUSTOMER_INDEX_TABLE, DEFAULT_MIGRATIONS_TABLE, DEFAULT_SAGAS_TABLE, DEFAULT_LEASES_TABLE).unwrap();
+        assert_eq!(tables, Tables::default());
+    }
+
+    #[test]
+    fn test_tables_with_namespace() {
+        let tables = Tables::new(Some("staging"), "orders", "by_cust", "migrations", "sagas", "leases").unwrap();
+        assert_eq!(tables.orders, "staging:orders");
+        assert_eq!(tables.customer_index, "staging:by_cust");
+        assert_eq!(tables.migrations, "staging:migrations");
+        assert_eq!(tables.sagas, "staging:sagas");
+        assert_eq!(tables.leases, "staging:leases");
+    }
+
+    #[test]
+    fn test_tables_family_overrides() {
+        let ol = ColumnFamilySpec { max_versions: 1, ..ColumnFamilySpec::new("ol") };
+        let tables = Tables::default().with_family(ol.clone());
+        assert_eq!(tables.family("ol"), ol);
+        assert_eq!(tables.family("info"), ColumnFamilySpec::new("info"));
+    }
+
+    #[test]
+    fn test_tables_rejects_invalid_names() {
+        assert!(Tables::new(Some("stag:ing"), "orders", "i", "m", "s", "l").is_err());
+        assert!(Tables::new(Some(""), "orders", "i", "m", "s", "l").is_err());
+        assert!(Tables::new(None, "ns:orders", "i", "m", "s", "l").is_err());
+        assert!(Tables::new(None, "orders", "", "m", "s", "l").is_err());
+        assert!(Tables::new(None, "orders", "i", ".m", "s", "l").is_err());
+        assert!(Tables::new(None, "orders", "i", "m", "s:agas", "l").is_err());
+        assert!(Tables::new(None, "orders", "i", "m", "s", "").is_err());
     }
 }
@@ -0,0 +1,168 @@
+//! CloudEvents 1.0 Kafka messages, in the structured or the binary content mode of the Kafka protocol binding.
+//!
+//! Structured messages carry the attributes and the event body in one JSON envelope. Binary messages keep the
+//! event body as the value and carry the attributes as `ce_*` headers.
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::models::errors::OrderServiceError;
+use super::event_publisher::EventHeaders;
+
+pub const SPEC_VERSION: &str = "1.0";
+pub const ORDER_CREATED_TYPE: &str = "dk.f2js.order.created";
+pub const ORDER_STATE_CHANGED_TYPE: &str = "dk.f2js.order.state_changed";
+pub const CONTENT_TYPE_HEADER: &str = "content-type";
+const JSON_CONTENT_TYPE: &str = "application/json";
+const STRUCTURED_CONTENT_TYPE: &str = "application/cloudevents+json; charset=UTF-8";
+
+/// How the attributes travel: in a JSON envelope (`structured`) or in `ce_*` headers (`binary`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloudEventsMode {
+    Structured,
+    Binary,
+}
+
+impl FromStr for CloudEventsMode {
+    type Err = ();
+    fn from_str(input: &str) -> Result<CloudEventsMode, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "structured" => Ok(CloudEventsMode::Structured),
+            "binary" => Ok(CloudEventsMode::Binary),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Publishes events as CloudEvents from `source`, the name of the service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloudEvents {
+    pub mode: CloudEventsMode,
+    pub source: String,
+}
+
+/// The attributes of one event. The id is the same each time the event is published, so consumers can drop
+/// replayed and respooled copies on `source` and `id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attributes<'a> {
+    pub id: String,
+    pub event_type: &'a str,
+    /// The order id.
+    pub subject: &'a str,
+    /// RFC 3339.
+    pub time: &'a str,
+}
+
+/// A structured message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub specversion: String,
+    pub id: String,
+    pub source: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub subject: String,
+    pub time: String,
+    pub datacontenttype: String,
+    pub data: T,
+}
+
+impl CloudEvents {
+    /// Turns the body `json` into the value of the message, and adds the headers of the mode to `headers`.
+    pub fn wrap(&self, attributes: &Attributes, headers: &mut EventHeaders, json: String) -> Result<String, OrderServiceError> {
+        match self.mode {
+            CloudEventsMode::Structured => {
+                headers.push((CONTENT_TYPE_HEADER.to_owned(), STRUCTURED_CONTENT_TYPE.to_owned()));
+                let envelope = Envelope {
+                    specversion: SPEC_VERSION.to_owned(),
+                    id: attributes.id.clone(),
+                    source: self.source.clone(),
+                    event_type: attributes.event_type.to_owned(),
+                    subject: attributes.subject.to_owned(),
+                    time: attributes.time.to_owned(),
+                    datacontenttype: JSON_CONTENT_TYPE.to_owned(),
+                    data: serde_json::from_str::<serde_json::Value>(&json)?,
+                };
+                Ok(serde_json::to_string(&envelope)?)
+            }
+            CloudEventsMode::Binary => {
+                headers.extend([
+                    ("ce_specversion", SPEC_VERSION),
+                    ("ce_id", attributes.id.as_str()),
+                    ("ce_source", self.source.as_str()),
+                    ("ce_type", attributes.event_type),
+                    ("ce_subject", attributes.subject),
+                    ("ce_time", attributes.time),
+                    (CONTENT_TYPE_HEADER, JSON_CONTENT_TYPE),
+                ].map(|(name, value)| (name.to_owned(), value.to_owned())));
+                Ok(json)
+            }
+        }
+    }
+}
+
+/// Reads an event body from a message value that is either the body itself or a structured CloudEvent.
+pub fn event_data<T: DeserializeOwned>(value: &[u8]) -> Result<T, serde_json::Error> {
+    serde_json::from_slice::<T>(value).or_else(|e| serde_json::from_slice::<Envelope<T>>(value).map(|envelope| envelope.data).map_err(|_| e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn attributes() -> Attributes<'static> {
+        Attributes { id: "o-1:created".into(), event_type: ORDER_CREATED_TYPE, subject: "o-1", time: "2026-01-01T10:00:00+00:00" }
+    }
+
+    fn header<'a>(headers: &'a EventHeaders, name: &str) -> Option<&'a str> {
+        headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(CloudEventsMode::from_str("structured"), Ok(CloudEventsMode::Structured));
+        assert_eq!(CloudEventsMode::from_str("BINARY"), Ok(CloudEventsMode::Binary));
+        assert_eq!(CloudEventsMode::from_str("batch"), Err(()));
+    }
+
+    #[test]
+    fn test_structured_mode_wraps_the_body() {
+        let cloud_events = CloudEvents { mode: CloudEventsMode::Structured, source: "cust-order-service".into() };
+        let mut headers = vec![];
+        let value = cloud_events.wrap(&attributes(), &mut headers, r#"{"o_id":"o-1"}"#.into()).unwrap();
+        let envelope: Value = serde_json::from_str(&value).unwrap();
+        assert_eq!(envelope, serde_json::json!({
+            "specversion": "1.0", "id": "o-1:created", "source": "cust-order-service", "type": "dk.f2js.order.created",
+            "subject": "o-1", "time": "2026-01-01T10:00:00+00:00", "datacontenttype": "application/json", "data": {"o_id": "o-1"},
+        }));
+        assert_eq!(header(&headers, CONTENT_TYPE_HEADER), Some(STRUCTURED_CONTENT_TYPE));
+        assert!(headers.iter().all(|(name, _)| !name.starts_with("ce_")));
+    }
+
+    #[test]
+    fn test_binary_mode_moves_attributes_to_headers() {
+        let cloud_events = CloudEvents { mode: CloudEventsMode::Binary, source: "cust-order-service".into() };
+        let mut headers = vec![("X-Request-Id".to_owned(), "req-1".to_owned())];
+        let value = cloud_events.wrap(&attributes(), &mut headers, r#"{"o_id":"o-1"}"#.into()).unwrap();
+        assert_eq!(value, r#"{"o_id":"o-1"}"#);
+        assert_eq!(header(&headers, "X-Request-Id"), Some("req-1"));
+        assert_eq!(header(&headers, "ce_specversion"), Some("1.0"));
+        assert_eq!(header(&headers, "ce_id"), Some("o-1:created"));
+        assert_eq!(header(&headers, "ce_source"), Some("cust-order-service"));
+        assert_eq!(header(&headers, "ce_type"), Some(ORDER_CREATED_TYPE));
+        assert_eq!(header(&headers, "ce_subject"), Some("o-1"));
+        assert_eq!(header(&headers, "ce_time"), Some("2026-01-01T10:00:00+00:00"));
+        assert_eq!(header(&headers, CONTENT_TYPE_HEADER), Some(JSON_CONTENT_TYPE));
+    }
+
+    #[test]
+    fn test_event_data_reads_plain_and_structured_values() {
+        let cloud_events = CloudEvents { mode: CloudEventsMode::Structured, source: "cust-order-service".into() };
+        let value = cloud_events.wrap(&attributes(), &mut vec![], r#"{"o_id":"o-1"}"#.into()).unwrap();
+        assert_eq!(event_data::<Value>(br#"{"o_id":"o-1"}"#).unwrap(), serde_json::json!({"o_id": "o-1"}));
+        assert_eq!(event_data::<std::collections::HashMap<String, String>>(value.as_bytes()).unwrap()["o_id"], "o-1");
+        assert!(event_data::<Value>(b"{not json").is_err());
+    }
+}
@@ -0,0 +1,269 @@
+//! JSON Schemas of the published events, generated from the Rust types of their bodies.
+//!
+//! The schemas are checked in under `contracts/events`, one `<event>.schema.json` per event. The tests fail when
+//! a type no longer matches its checked-in schema, and name the changes consumers would break on. Compatible
+//! changes are written back with `UPDATE_CONTRACTS=1 cargo test contracts`. A breaking change needs a new event, or
+//! the old schema file deleted on purpose.
+
+use std::fmt;
+
+use serde_json::{Map, Value};
+use utoipa::OpenApi;
+
+use crate::models::orders::{Order, OrderState, Orderline};
+use super::producers::OrderStateChanged;
+
+pub const CONTRACTS_DIR: &str = "contracts/events";
+const JSON_SCHEMA_DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+const COMPONENTS_PREFIX: &str = "#/components/schemas/";
+const DEFS_PREFIX: &str = "#/$defs/";
+
+/// The published events and the types of their bodies.
+pub const EVENTS: [(&str, &str); 2] = [("OrderCreated", "Order"), ("OrderStateChanged", "OrderStateChanged")];
+
+#[derive(OpenApi)]
+#[openapi(components(schemas(Order, Orderline, OrderState, OrderStateChanged)))]
+struct EventTypes;
+
+/// The JSON Schema of an event body, with the types it refers to under `$defs`. `None` for unknown events.
+pub fn event_schema(event: &str) -> Option<Value> {
+    let (_, type_name) = EVENTS.iter().find(|(name, _)| *name == event)?;
+    let components = serde_json::to_value(EventTypes::openapi().components?.schemas).ok()?;
+    let Value::Object(body) = to_defs(components.get(*type_name)?.clone()) else { return None };
+    let mut defs = Map::new();
+    let mut todo = refs(&Value::Object(body.clone()));
+    while let Some(name) = todo.pop() {
+        if defs.contains_key(&name) {
+            continue;
+        }
+        let def = to_defs(components.get(&name)?.clone());
+        todo.extend(refs(&def));
+        defs.insert(name, def);
+    }
+    let mut schema = Map::new();
+    schema.insert("$schema".into(), JSON_SCHEMA_DRAFT.into());
+    schema.insert("title".into(), event.into());
+    schema.extend(body);
+    if !defs.is_empty() {
+        schema.insert("$defs".into(), Value::Object(defs));
+    }
+    Some(Value::Object(schema))
+}
+
+/// Points the OpenAPI component references at `$defs`.
+fn to_defs(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(map.into_iter().map(|(k, v)| match (k.as_str(), v) {
+            ("$ref", Value::String(r)) => (k, Value::String(r.replace(COMPONENTS_PREFIX, DEFS_PREFIX))),
+            (_, v) => (k, to_defs(v)),
+        }).collect()),
+        Value::Array(items) => Value::Array(items.into_iter().map(to_defs).collect()),
+        other => other,
+    }
+}
+
+/// Names of the `$defs` referred to in `value`.
+fn refs(value: &Value) -> Vec<String> {
+    match value {
+        Value::Object(map) => map.iter().flat_map(|(k, v)| match (k.as_str(), v) {
+            ("$ref", Value::String(r)) => r.strip_prefix(DEFS_PREFIX).map(str::to_owned).into_iter().collect(),
+            _ => refs(v),
+        }).collect(),
+        Value::Array(items) => items.iter().flat_map(refs).collect(),
+        _ => vec![],
+    }
+}
+
+/// A change to a schema that breaks consumers of the old one. A renamed field shows up as removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Incompatibility {
+    RemovedField(String),
+    NoLongerRequired(String),
+    TypeChanged { path: String, was: String, now: String },
+    RemovedEnumValue { path: String, value: String },
+}
+
+impl fmt::Display for Incompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Incompatibility::RemovedField(path) => write!(f, "field '{}' was removed or renamed", path),
+            Incompatibility::NoLongerRequired(path) => write!(f, "field '{}' is no longer required", path),
+            Incompatibility::TypeChanged { path, was, now } => write!(f, "field '{}' changed type from {} to {}", path, was, now),
+            Incompatibility::RemovedEnumValue { path, value } => write!(f, "value '{}' of '{}' was removed", value, path),
+        }
+    }
+}
+
+/// The changes from `old` to `new` that break consumers of `old`. Added optional or required fields, and added
+/// enum values, are compatible.
+pub fn check_compatibility(old: &Value, new: &Value) -> Vec<Incompatibility> {
+    let mut found = vec![];
+    compare(&Schemas { old, new }, old, new, "", &mut found);
+    found
+}
+
+/// The whole schemas, to resolve `$ref`s in.
+struct Schemas<'a> {
+    old: &'a Value,
+    new: &'a Value,
+}
+
+fn resolve<'a>(root: &'a Value, node: &'a Value) -> &'a Value {
+    match node.get("$ref").and_then(Value::as_str).and_then(|r| r.strip_prefix(DEFS_PREFIX)) {
+        Some(name) => root.get("$defs").and_then(|defs| defs.get(name)).unwrap_or(node),
+        None => node,
+    }
+}
+
+/// `type`, with the `format` when there is one, e.g. `integer (int32)`.
+fn type_name(node: &Value) -> String {
+    let kind = node.get("type").map(|t| t.to_string().trim_matches('"').to_owned()).unwrap_or_else(|| "any".into());
+    match node.get("format").and_then(Value::as_str) {
+        Some(format) => format!("{} ({})", kind, format),
+        None => kind,
+    }
+}
+
+fn compare(schemas: &Schemas, old: &Value, new: &Value, path: &str, found: &mut Vec<Incompatibility>) {
+    let (old, new) = (resolve(schemas.old, old), resolve(schemas.new, new));
+    let (was, now) = (type_name(old), type_name(new));
+    if was != now {
+        found.push(Incompatibility::TypeChanged { path: path.to_owned(), was, now });
+        return;
+    }
+    if let (Some(old_values), Some(new_values)) = (old.get("enum").and_then(Value::as_array), new.get("enum").and_then(Value::as_array)) {
+        for value in old_values.iter().filter(|v| !new_values.contains(v)) {
+            found.push(Incompatibility::RemovedEnumValue { path: path.to_owned(), value: value.as_str().map(str::to_owned).unwrap_or_else(|| value.to_string()) });
+        }
+    }
+    if let (Some(old_items), Some(new_items)) = (old.get("items"), new.get("items")) {
+        compare(schemas, old_items, new_items, &format!("{}[]", path), found);
+    }
+    let required = |node: &Value| -> Vec<String> {
+        node.get("required").and_then(Value::as_array).map(|r| r.iter().filter_map(|v| v.as_str().map(str::to_owned)).collect()).unwrap_or_default()
+    };
+    let (old_required, new_required) = (required(old), required(new));
+    let empty = Map::new();
+    let old_properties = old.get("properties").and_then(Value::as_object).unwrap_or(&empty);
+    let new_properties = new.get("properties").and_then(Value::as_object).unwrap_or(&empty);
+    for (name, old_property) in old_properties {
+        let field = if path.is_empty() { name.clone() } else { format!("{}.{}", path, name) };
+        let Some(new_property) = new_properties.get(name) else {
+            found.push(Incompatibility::RemovedField(field));
+            continue;
+        };
+        if old_required.contains(name) && !new_required.contains(name) {
+            found.push(Incompatibility::NoLongerRequired(field.clone()));
+        }
+        compare(schemas, old_property, new_property, &field, found);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::models::orders::Orderline;
+
+    fn contract_path(event: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(CONTRACTS_DIR).join(format!("{}.schema.json", event))
+    }
+
+    fn order_created() -> Value {
+        event_schema("OrderCreated").unwrap()
+    }
+
+    /// Fails on breaking changes first, then on compatible changes that are not checked in yet.
+    #[test]
+    fn test_checked_in_contracts_match_the_types() {
+        let update = std::env::var("UPDATE_CONTRACTS").is_ok_and(|v| v == "1");
+        for (event, _) in EVENTS {
+            let generated = event_schema(event).unwrap();
+            let path = contract_path(event);
+            let checked_in: Option<Value> = std::fs::read_to_string(&path).ok().map(|s| serde_json::from_str(&s).unwrap());
+            if let Some(checked_in) = &checked_in {
+                let breaking: Vec<String> = check_compatibility(checked_in, &generated).iter().map(ToString::to_string).collect();
+                assert!(breaking.is_empty(), "{} is no longer compatible with {}: {}", event, path.display(), breaking.join(", "));
+            }
+            if update {
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(&path, serde_json::to_string_pretty(&generated).unwrap() + "\n").unwrap();
+            } else {
+                assert_eq!(checked_in.as_ref(), Some(&generated), "{} changed, run `UPDATE_CONTRACTS=1 cargo test contracts` to update {}", event, path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn test_schemas_match_the_published_json() {
+        let order = Order::new(vec![Orderline { item_num: 1, price: 100 }], "CustAddr".into(), "RestAddr".into(), "custid".into(), "restid".into(), 2860);
+        let event = OrderStateChanged { o_id: "o".into(), c_id: "c".into(), r_id: "r".into(), state: OrderState::Rejected, changed_at: "2026-01-01T10:00:00+00:00".into(), reason: Some("no answer".into()) };
+        let bodies = [("OrderCreated", serde_json::to_value(&order).unwrap()), ("OrderStateChanged", serde_json::to_value(&event).unwrap())];
+        for (event, body) in bodies {
+            let schema = event_schema(event).unwrap();
+            let fields: Vec<&String> = body.as_object().unwrap().keys().collect();
+            let properties: Vec<&String> = schema["properties"].as_object().unwrap().keys().collect();
+            assert_eq!(fields, properties, "{}", event);
+        }
+    }
+
+    #[test]
+    fn test_event_schema_resolves_referenced_types() {
+        let schema = order_created();
+        assert_eq!(schema["title"], "OrderCreated");
+        assert_eq!(schema["properties"]["state"]["$ref"], "#/$defs/OrderState");
+        assert_eq!(schema["properties"]["orderlines"]["items"]["$ref"], "#/$defs/Orderline");
+        let defs: Vec<&String> = schema["$defs"].as_object().unwrap().keys().collect();
+        assert_eq!(defs, ["OrderState", "Orderline"]);
+        assert!(!schema.to_string().contains(COMPONENTS_PREFIX));
+        assert!(event_schema("OrderShipped").is_none());
+    }
+
+    #[test]
+    fn test_added_fields_are_compatible() {
+        let old = order_created();
+        let mut new = old.clone();
+        new["properties"]["tip"] = serde_json::json!({"type": "integer"});
+        new["required"].as_array_mut().unwrap().push("tip".into());
+        new["$defs"]["OrderState"]["enum"].as_array_mut().unwrap().push("Cancelled".into());
+        assert!(check_compatibility(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn test_removed_and_renamed_fields_are_incompatible() {
+        let old = order_created();
+        let mut new = old.clone();
+        let properties = new["properties"].as_object_mut().unwrap();
+        properties.remove("postal_code");
+        let c_id = properties.remove("c_id").unwrap();
+        properties.insert("customer_id".into(), c_id);
+        assert_eq!(check_compatibility(&old, &new), [
+            Incompatibility::RemovedField("c_id".into()),
+            Incompatibility::RemovedField("postal_code".into()),
+        ]);
+    }
+
+    #[test]
+    fn test_type_changes_are_incompatible() {
+        let old = order_created();
+        let mut new = old.clone();
+        new["properties"]["postal_code"] = serde_json::json!({"type": "string"});
+        new["$defs"]["Orderline"]["properties"]["price"]["format"] = "int64".into();
+        assert_eq!(check_compatibility(&old, &new), [
+            Incompatibility::TypeChanged { path: "orderlines[].price".into(), was: "integer (int32)".into(), now: "integer (int64)".into() },
+            Incompatibility::TypeChanged { path: "postal_code".into(), was: "integer (int32)".into(), now: "string".into() },
+        ]);
+    }
+
+    #[test]
+    fn test_optional_fields_and_removed_enum_values_are_incompatible() {
+        let old = order_created();
+        let mut new = old.clone();
+        new["required"].as_array_mut().unwrap().retain(|f| f != "ordertime");
+        new["$defs"]["OrderState"]["enum"].as_array_mut().unwrap().retain(|v| v != "Processing");
+        assert_eq!(check_compatibility(&old, &new), [
+            Incompatibility::NoLongerRequired("ordertime".into()),
+            Incompatibility::RemovedEnumValue { path: "state".into(), value: "Processing".into() },
+        ]);
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    collections::VecDeque,
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard, OnceLock},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::models::errors::OrderServiceError;
+
+/// Key/value headers attached to a published event.
+pub type EventHeaders = Vec<(String, String)>;
+
+/// Where events go. Publishing returns once the sink has accepted the event.
+#[cfg_attr(test, mockall::automock)]
+pub trait EventPublisher {
+    /// Events with the same `key` keep their order. Kafka sends them to the same partition.
+    // mockall cannot mock an elided lifetime inside `Option`.
+    #[allow(clippy::needless_lifetimes)]
+    fn publish<'a>(&mut self, topic: &str, key: Option<&'a str>, headers: &EventHeaders, json: String) -> Result<(), OrderServiceError>;
+
+    /// Publishes a payload as it was consumed, which need not be UTF-8, e.g. a dead-lettered message. Sinks that
+    /// store text refuse payloads that are not UTF-8.
+    #[allow(clippy::needless_lifetimes)]
+    fn publish_bytes<'a>(&mut self, topic: &str, key: Option<&'a str>, headers: &EventHeaders, payload: Vec<u8>) -> Result<(), OrderServiceError> {
+        let json = String::from_utf8(payload)
+            .map_err(|_| OrderServiceError::EventSinkError(format!("the event sink cannot take a payload for {} that is not UTF-8", topic)))?;
+        self.publish(topic, key, headers, json)
+    }
+}
+
+impl<P: EventPublisher + ?Sized> EventPublisher for Box<P> {
+    fn publish(&mut self, topic: &str, key: Option<&str>, headers: &EventHeaders, json: String) -> Result<(), OrderServiceError> {
+        (**self).publish(topic, key, headers, json)
+    }
+
+    fn publish_bytes(&mut self, topic: &str, key: Option<&str>, headers: &EventHeaders, payload: Vec<u8>) -> Result<(), OrderServiceError> {
+        (**self).publish_bytes(topic, key, headers, payload)
+    }
+}
+
+/// The event sink: `kafka` (default), `memory`, `jsonl` or `stdout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EventSink {
+    #[default]
+    Kafka,
+    /// Kept in the process, see [`InMemoryPublisher::shared`].
+    Memory,
+    /// Appended to a file, one JSON object per line.
+    Jsonl,
+    /// Written to stdout, one JSON object per line.
+    Stdout,
+}
+
+impl FromStr for EventSink {
+    type Err = ();
+    fn from_str(input: &str) -> Result<EventSink, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "kafka" => Ok(EventSink::Kafka),
+            "memory" | "in-memory" => Ok(EventSink::Memory),
+            "jsonl" => Ok(EventSink::Jsonl),
+            "stdout" => Ok(EventSink::Stdout),
+            _ => Err(()),
+        }
+    }
+}
+
+/// An event as it was handed to a publisher.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublishedEvent {
+    pub topic: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub headers: EventHeaders,
+    pub json: String,
+}
+
+impl PublishedEvent {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+
+    /// `{"topic": .., "key": .., "headers": {..}, "payload": ..}`, with the payload embedded as JSON when it parses.
+    /// The key is left out when the event has none.
+    pub fn to_json_line(&self) -> String {
+        let headers: Map<String, Value> = self.headers.iter().map(|(k, v)| (k.clone(), Value::String(v.clone()))).collect();
+        let payload = serde_json::from_str(&self.json).unwrap_or_else(|_| Value::String(self.json.clone()));
+        let mut line = serde_json::json!({ "topic": self.topic, "headers": headers, "payload": payload });
+        if let Some(key) = &self.key {
+            line["key"] = Value::String(key.clone());
+        }
+        line.to_string()
+    }
+}
+
+/// Most events the recorder of the `memory` sink keeps.
+pub const SHARED_CAPACITY: usize = 10_000;
+
+/// Records events in memory, for tests to assert against. Clones share the recorded events.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryPublisher {
+    events: Arc<Mutex<VecDeque<PublishedEvent>>>,
+    capacity: Option<usize>,
+}
+
+impl InMemoryPublisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps the last `capacity` events, dropping the oldest ones.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { capacity: Some(capacity), ..Self::default() }
+    }
+
+    /// The recorder the service publishes to when the sink is `memory`. It keeps the last [`SHARED_CAPACITY`]
+    /// events, so a long running service does not run out of memory.
+    pub fn shared() -> Self {
+        static SHARED: OnceLock<InMemoryPublisher> = OnceLock::new();
+        SHARED.get_or_init(|| InMemoryPublisher::with_capacity(SHARED_CAPACITY)).clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<PublishedEvent>> {
+        self.events.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn events(&self) -> Vec<PublishedEvent> {
+        self.lock().iter().cloned().collect()
+    }
+
+    pub fn events_for(&self, topic: &str) -> Vec<PublishedEvent> {
+        self.lock().iter().filter(|e| e.topic == topic).cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+}
+
+impl EventPublisher for InMemoryPublisher {
+    fn publish(&mut self, topic: &str, key: Option<&str>, headers: &EventHeaders, json: String) -> Result<(), OrderServiceError> {
+        let mut events = self.lock();
+        if self.capacity.is_some_and(|capacity| events.len() >= capacity) {
+            events.pop_front();
+        }
+        events.push_back(PublishedEvent { topic: topic.to_owned(), key: key.map(str::to_owned), headers: headers.clone(), json });
+        Ok(())
+    }
+}
+
+/// Appends events to a file, one JSON object per line.
+pub struct JsonlPublisher {
+    path: PathBuf,
+}
+
+impl JsonlPublisher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl EventPublisher for JsonlPublisher {
+    #[tracing::instrument(name = "jsonl.publish", skip_all, fields(messaging.destination.name = topic), err)]
+    fn publish(&mut self, topic: &str, key: Option<&str>, headers: &EventHeaders, json: String) -> Result<(), OrderServiceError> {
+        let event = PublishedEvent { topic: topic.to_owned(), key: key.map(str::to_owned), headers: headers.clone(), json };
+        // One write per line, so lines of concurrent requests do not interleave.
+        let line = format!("{}\n", event.to_json_line());
+        let res = OpenOptions::new().create(true).append(true).open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+        res.map_err(|e| OrderServiceError::EventSinkError(format!("cannot write to {}: {}", self.path.display(), e)))
+    }
+}
+
+/// Prints events to stdout, one JSON object per line.
+pub struct StdoutPublisher;
+
+impl EventPublisher for StdoutPublisher {
+    fn publish(&mut self, topic: &str, key: Option<&str>, headers: &EventHeaders, json: String) -> Result<(), OrderServiceError> {
+        let event = PublishedEvent { topic: topic.to_owned(), key: key.map(str::to_owned), headers: headers.clone(), json };
+        writeln!(std::io::stdout().lock(), "{}", event.to_json_line())
+            .map_err(|e| OrderServiceError::EventSinkError(format!("cannot write to stdout: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> EventHeaders {
+        vec![("X-Request-Id".to_owned(), "req-1".to_owned())]
+    }
+
+    #[test]
+    fn test_parse_event_sink() {
+        assert_eq!(EventSink::from_str("Kafka"), Ok(EventSink::Kafka));
+        assert_eq!(EventSink::from_str("in-memory"), Ok(EventSink::Memory));
+        assert_eq!(EventSink::from_str("JSONL"), Ok(EventSink::Jsonl));
+        assert_eq!(EventSink::from_str("stdout"), Ok(EventSink::Stdout));
+        assert_eq!(EventSink::from_str("rabbitmq"), Err(()));
+    }
+
+    #[test]
+    fn test_in_memory_publisher_records_events() {
+        let recorder = InMemoryPublisher::new();
+        let mut publisher = recorder.clone();
+        publisher.publish("OrderCreated", Some("r-1"), &headers(), "{\"o_id\":\"1\"}".into()).unwrap();
+        publisher.publish("OrderRejected", None, &vec![], "{}".into()).unwrap();
+        let created = recorder.events_for("OrderCreated");
+        assert_eq!(created.len(), 1);
+        assert_eq!(created[0].header("X-Request-Id"), Some("req-1"));
+        assert_eq!(created[0].key.as_deref(), Some("r-1"));
+        assert_eq!(recorder.events().len(), 2);
+        recorder.clear();
+        assert!(recorder.events().is_empty());
+    }
+
+    #[test]
+    fn test_in_memory_publisher_drops_oldest_events_beyond_capacity() {
+        let recorder = InMemoryPublisher::with_capacity(2);
+        for o_id in 1..=3 {
+            recorder.clone().publish("OrderCreated", None, &vec![], format!("{{\"o_id\":\"{}\"}}", o_id)).unwrap();
+        }
+        let kept: Vec<String> = recorder.events().into_iter().map(|e| e.json).collect();
+        assert_eq!(kept, ["{\"o_id\":\"2\"}", "{\"o_id\":\"3\"}"]);
+    }
+
+    #[test]
+    fn test_jsonl_publisher_appends_lines() {
+        let path = std::env::temp_dir().join(format!("events-{}.jsonl", uuid::Uuid::new_v4()));
+        let mut publisher = JsonlPublisher::new(&path);
+        publisher.publish("OrderCreated", Some("r-1"), &headers(), "{\"o_id\":\"1\"}".into()).unwrap();
+        publisher.publish("OrderCreated", None, &vec![], "not json".into()).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<Value> = content.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], serde_json::json!({"topic": "OrderCreated", "key": "r-1", "headers": {"X-Request-Id": "req-1"}, "payload": {"o_id": "1"}}));
+        assert_eq!(lines[1]["payload"], "not json");
+        assert!(lines[1].get("key").is_none());
+    }
+
+    #[test]
+    fn test_jsonl_publisher_fails_on_unwritable_path() {
+        let mut publisher = JsonlPublisher::new(std::env::temp_dir().join("missing-dir").join("nested").join("events.jsonl"));
+        assert!(matches!(publisher.publish("OrderCreated", None, &vec![], "{}".into()), Err(OrderServiceError::EventSinkError(_))));
+    }
+}
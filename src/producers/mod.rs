@@ -1,2 +1,9 @@
+#[allow(clippy::module_inception)]
 pub mod producers;
-pub(crate) mod producer_connection;
\ No newline at end of file
+pub mod cloud_events;
+pub mod contracts;
+pub mod event_publisher;
+pub mod replay;
+pub mod stale_orders;
+pub mod spool;
+pub mod producer_connection;
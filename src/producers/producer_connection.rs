@@ -1,32 +1,292 @@
-use kafka::{producer::{Producer, Record, RequiredAcks, AsBytes}};
-use crate::models::{orders::Order, errors::OrderServiceError};
+use std::{path::Path, str::FromStr, time::Duration};
 
-#[cfg_attr(test, mockall::automock)]
-pub trait KafkaProducer {
-    fn send(&mut self, topic: &str, json: String) -> Result<(), OrderServiceError>;
+use rdkafka::{
+    error::KafkaError,
+    message::{Header, OwnedHeaders},
+    producer::{FutureProducer, FutureRecord, Producer},
+    ClientConfig,
+};
+
+use crate::{api::utils::env::KAFKA_IP_ENV_ERR_MSG, models::errors::OrderServiceError};
+use super::event_publisher::{EventHeaders, EventPublisher};
+
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Brokers that must acknowledge a sent event: `none`, `one` (default) or `all` in-sync replicas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Acks {
+    None,
+    #[default]
+    One,
+    All,
 }
 
-pub struct KafkaProdConnection {
-    con: Producer
+impl FromStr for Acks {
+    type Err = ();
+    fn from_str(input: &str) -> Result<Acks, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "none" | "0" => Ok(Acks::None),
+            "one" | "1" => Ok(Acks::One),
+            "all" | "-1" => Ok(Acks::All),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Acks {
+    /// The `acks` producer setting.
+    fn config_value(self) -> &'static str {
+        match self {
+            Acks::None => "0",
+            Acks::One => "1",
+            Acks::All => "all",
+        }
+    }
+}
+
+/// Compression of the message sets sent to Kafka: `none` (default), `gzip` or `snappy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EventCompression {
+    #[default]
+    None,
+    Gzip,
+    Snappy,
+}
+
+impl FromStr for EventCompression {
+    type Err = ();
+    fn from_str(input: &str) -> Result<EventCompression, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "none" => Ok(EventCompression::None),
+            "gzip" => Ok(EventCompression::Gzip),
+            "snappy" => Ok(EventCompression::Snappy),
+            _ => Err(()),
+        }
+    }
+}
+
+impl EventCompression {
+    /// The `compression.type` producer setting.
+    fn config_value(self) -> &'static str {
+        match self {
+            EventCompression::None => "none",
+            EventCompression::Gzip => "gzip",
+            EventCompression::Snappy => "snappy",
+        }
+    }
+}
+
+/// TLS to the brokers. Without a CA file the system trust store is used, and the client certificate is
+/// only sent when both its file and the key file are set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    pub ca_file: Option<String>,
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+    pub verify_hostname: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self { ca_file: None, cert_file: None, key_file: None, verify_hostname: true }
+    }
 }
 
-impl KafkaProducer for KafkaProdConnection {
-    fn send(&mut self, topic: &str, json: String) -> Result<(), OrderServiceError> {
-        match self.con.send(&Record::from_value(topic, json.as_bytes())) {
-            Ok(r) => Ok(r),
-            Err(e) => Err(OrderServiceError::EventBrokerError(e)),
+impl TlsConfig {
+    /// Sets the `ssl.*` client settings. Fails on a file that does not exist, so a typo is reported at startup
+    /// rather than on the first connect.
+    fn apply(&self, config: &mut ClientConfig) -> Result<(), OrderServiceError> {
+        let file = |path: &str| match Path::new(path).is_file() {
+            true => Ok(path.to_owned()),
+            false => Err(OrderServiceError::InvalidConfig(format!("cannot set up Kafka TLS: {} is not a file", path))),
+        };
+        config.set("security.protocol", "ssl");
+        config.set("ssl.endpoint.identification.algorithm", if self.verify_hostname { "https" } else { "none" });
+        if let Some(ca_file) = &self.ca_file {
+            config.set("ssl.ca.location", file(ca_file)?);
+        }
+        match (&self.cert_file, &self.key_file) {
+            (Some(cert_file), Some(key_file)) => {
+                config.set("ssl.certificate.location", file(cert_file)?);
+                config.set("ssl.key.location", file(key_file)?);
+            }
+            (None, None) => {}
+            _ => return Err(OrderServiceError::InvalidConfig("a Kafka client certificate needs both a certificate and a key file".into())),
         }
+        Ok(())
+    }
+}
+
+/// How the service connects to Kafka. The consumers use the brokers, client id and TLS settings.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct KafkaConfig {
+    /// Bootstrap brokers, `host:port` each.
+    pub brokers: Vec<String>,
+    pub client_id: Option<String>,
+    pub acks: Acks,
+    pub ack_timeout: Duration,
+    pub compression: EventCompression,
+    pub tls: Option<TlsConfig>,
+}
+
+impl KafkaConfig {
+    pub fn new(brokers: Vec<String>) -> Self {
+        Self { brokers, ack_timeout: DEFAULT_ACK_TIMEOUT, ..Default::default() }
+    }
+
+    /// The settings producers and consumers share: brokers, client id and TLS.
+    pub(crate) fn client_config(&self) -> Result<ClientConfig, OrderServiceError> {
+        if self.brokers.is_empty() {
+            return Err(OrderServiceError::InvalidConfig(KAFKA_IP_ENV_ERR_MSG.into()));
+        }
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", self.brokers.join(","));
+        if let Some(client_id) = &self.client_id {
+            config.set("client.id", client_id);
+        }
+        if let Some(tls) = &self.tls {
+            tls.apply(&mut config)?;
+        }
+        Ok(config)
+    }
+
+    fn producer_config(&self) -> Result<ClientConfig, OrderServiceError> {
+        let mut config = self.client_config()?;
+        let timeout_ms = self.ack_timeout.as_millis().to_string();
+        config.set("acks", self.acks.config_value())
+            .set("compression.type", self.compression.config_value())
+            // A send fails when the event is not acknowledged within the ack timeout, retries included.
+            .set("request.timeout.ms", &timeout_ms)
+            .set("message.timeout.ms", &timeout_ms);
+        Ok(config)
+    }
+}
+
+/// The record headers of an event.
+pub(crate) fn record_headers(headers: &EventHeaders) -> OwnedHeaders {
+    headers.iter().fold(OwnedHeaders::new_with_capacity(headers.len()), |record, (key, value)| {
+        record.insert(Header { key, value: Some(value) })
+    })
+}
+
+pub struct KafkaProdConnection {
+    con: FutureProducer,
+}
+
+impl EventPublisher for KafkaProdConnection {
+    #[tracing::instrument(name = "kafka.send", skip_all, fields(otel.kind = "client", messaging.system = "kafka", messaging.destination.name = topic), err)]
+    fn publish(&mut self, topic: &str, key: Option<&str>, headers: &EventHeaders, json: String) -> Result<(), OrderServiceError> {
+        self.send(topic, key, headers, json.as_bytes())
+    }
+
+    #[tracing::instrument(name = "kafka.send", skip_all, fields(otel.kind = "client", messaging.system = "kafka", messaging.destination.name = topic), err)]
+    fn publish_bytes(&mut self, topic: &str, key: Option<&str>, headers: &EventHeaders, payload: Vec<u8>) -> Result<(), OrderServiceError> {
+        self.send(topic, key, headers, &payload)
     }
 }
 
 impl KafkaProdConnection {
-    pub fn connect(kafka_ip: String) -> Result<Self, OrderServiceError> { 
-        let con = Producer::from_hosts(vec!(kafka_ip))
-            .with_ack_timeout(std::time::Duration::from_secs(1))
-            .with_required_acks(RequiredAcks::One)
-            .create()?;
+    fn send(&self, topic: &str, key: Option<&str>, headers: &EventHeaders, payload: &[u8]) -> Result<(), OrderServiceError> {
+        let mut record = FutureRecord::<str, [u8]>::to(topic).payload(payload).headers(record_headers(headers));
+        // The default partitioner hashes the key, and spreads events without one over the partitions.
+        if let Some(key) = key {
+            record = record.key(key);
+        }
+        let delivery = self.con.send_result(record).map_err(|(e, _)| OrderServiceError::EventBrokerError(e))?;
+        match futures::executor::block_on(delivery) {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err((e, _))) => Err(OrderServiceError::EventBrokerError(e)),
+            Err(_) => Err(OrderServiceError::EventBrokerError(KafkaError::Canceled)),
+        }
+    }
+
+    pub fn connect(kafka_ip: String) -> Result<Self, OrderServiceError> {
+        Self::connect_with(&KafkaConfig::new(vec!(kafka_ip)))
+    }
+
+    /// Creates the producer and fetches the cluster metadata, so unreachable brokers fail here rather than on
+    /// the first send.
+    pub fn connect_with(config: &KafkaConfig) -> Result<Self, OrderServiceError> {
+        let con: FutureProducer = config.producer_config()?.create()?;
+        con.client().fetch_metadata(None, config.ack_timeout)?;
         Ok(Self {
             con
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use rdkafka::{message::Headers, mocking::MockCluster, producer::DefaultProducerContext};
+
+    /// A Kafka cluster in the process with `topics` created, and the config to connect to it.
+    pub(crate) fn mock_kafka(topics: &[&str]) -> (MockCluster<'static, DefaultProducerContext>, KafkaConfig) {
+        let cluster = MockCluster::new(1).unwrap();
+        for topic in topics {
+            cluster.create_topic(topic, 1, 1).unwrap();
+        }
+        let config = KafkaConfig { ack_timeout: Duration::from_secs(5), ..KafkaConfig::new(vec![cluster.bootstrap_servers()]) };
+        (cluster, config)
+    }
+
+    #[test]
+    fn test_parse_acks() {
+        assert_eq!(Acks::from_str("none"), Ok(Acks::None));
+        assert_eq!(Acks::from_str("1"), Ok(Acks::One));
+        assert_eq!(Acks::from_str("ALL"), Ok(Acks::All));
+        assert_eq!(Acks::from_str("two"), Err(()));
+    }
+
+    #[test]
+    fn test_parse_compression() {
+        assert_eq!(EventCompression::from_str("none"), Ok(EventCompression::None));
+        assert_eq!(EventCompression::from_str("GZIP"), Ok(EventCompression::Gzip));
+        assert_eq!(EventCompression::from_str("snappy"), Ok(EventCompression::Snappy));
+        assert_eq!(EventCompression::from_str("lz4"), Err(()));
+    }
+
+    #[test]
+    fn test_connect_needs_a_broker() {
+        assert!(matches!(KafkaProdConnection::connect_with(&KafkaConfig::new(vec![])), Err(OrderServiceError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_tls_needs_cert_and_key_together() {
+        let tls = TlsConfig { cert_file: Some("client.crt".into()), ..Default::default() };
+        assert!(matches!(tls.apply(&mut ClientConfig::new()), Err(OrderServiceError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_tls_fails_on_missing_ca_file() {
+        let tls = TlsConfig { ca_file: Some("/missing/ca.pem".into()), ..Default::default() };
+        assert!(matches!(tls.apply(&mut ClientConfig::new()), Err(OrderServiceError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_tls_without_files_uses_defaults() {
+        let mut config = ClientConfig::new();
+        TlsConfig::default().apply(&mut config).unwrap();
+        assert_eq!(config.get("security.protocol"), Some("ssl"));
+        assert_eq!(config.get("ssl.endpoint.identification.algorithm"), Some("https"));
+        assert_eq!(config.get("ssl.ca.location"), None);
+    }
+
+    #[test]
+    fn test_producer_config() {
+        let config = KafkaConfig { acks: Acks::All, compression: EventCompression::Gzip, client_id: Some("orders".into()), ..KafkaConfig::new(vec!["k1:9092".into(), "k2:9092".into()]) };
+        let config = config.producer_config().unwrap();
+        assert_eq!(config.get("bootstrap.servers"), Some("k1:9092,k2:9092"));
+        assert_eq!(config.get("client.id"), Some("orders"));
+        assert_eq!(config.get("acks"), Some("all"));
+        assert_eq!(config.get("compression.type"), Some("gzip"));
+        assert_eq!(config.get("message.timeout.ms"), Some("1000"));
+    }
+
+    #[test]
+    fn test_record_headers_keep_order_and_values() {
+        let headers = record_headers(&vec![("X-Request-Id".into(), "req-1".into()), ("traceparent".into(), "00-abc-def-01".into())]);
+        let read: Vec<(&str, Option<&[u8]>)> = headers.iter().map(|h| (h.key, h.value)).collect();
+        assert_eq!(read, [("X-Request-Id", Some(&b"req-1"[..])), ("traceparent", Some(&b"00-abc-def-01"[..]))]);
+    }
+}
@@ -1,15 +1,119 @@
-use crate::models::{errors::OrderServiceError, orders::Order};
+use std::str::FromStr;
 
-use super::producer_connection::{KafkaProducer};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-pub fn publish_order_created(order: &Order, producer: &mut impl KafkaProducer) -> Result<(), OrderServiceError> {
+use crate::{models::{errors::OrderServiceError, orders::{Order, OrderState}}, telemetry::inject_trace_context};
+
+use super::{cloud_events::{Attributes, CloudEvents, ORDER_CREATED_TYPE, ORDER_STATE_CHANGED_TYPE}, event_publisher::{EventHeaders, EventPublisher}};
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+pub const DEFAULT_ORDER_CREATED_TOPIC: &str = "OrderCreated";
+pub const DEFAULT_ORDER_STATE_CHANGED_TOPIC: &str = "OrderStateChanged";
+
+/// The order field events are keyed by: `none` (default, events are spread over the partitions), `o_id` or `r_id`.
+/// Events with the same key go to the same partition, so a consumer sees them in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PartitionKey {
+    #[default]
+    None,
+    OrderId,
+    RestaurantId,
+}
+
+impl FromStr for PartitionKey {
+    type Err = ();
+    fn from_str(input: &str) -> Result<PartitionKey, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "none" => Ok(PartitionKey::None),
+            "o_id" => Ok(PartitionKey::OrderId),
+            "r_id" => Ok(PartitionKey::RestaurantId),
+            _ => Err(()),
+        }
+    }
+}
+
+impl PartitionKey {
+    pub fn key_of<'a>(&self, o_id: &'a str, r_id: &'a str) -> Option<&'a str> {
+        match self {
+            PartitionKey::None => None,
+            PartitionKey::OrderId => Some(o_id),
+            PartitionKey::RestaurantId => Some(r_id),
+        }
+    }
+}
+
+/// Topic of each event, what its events are keyed by, and whether they are sent as CloudEvents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventRouting {
+    pub order_created: String,
+    pub order_state_changed: String,
+    pub partition_key: PartitionKey,
+    pub cloud_events: Option<CloudEvents>,
+}
+
+impl Default for EventRouting {
+    fn default() -> Self {
+        Self {
+            order_created: DEFAULT_ORDER_CREATED_TOPIC.to_owned(),
+            order_state_changed: DEFAULT_ORDER_STATE_CHANGED_TOPIC.to_owned(),
+            partition_key: PartitionKey::default(),
+            cloud_events: None,
+        }
+    }
+}
+
+/// Published when an order moves to another state.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct OrderStateChanged {
+    pub o_id: String,
+    pub c_id: String,
+    pub r_id: String,
+    pub state: OrderState,
+    /// RFC 3339.
+    pub changed_at: String,
+    /// Why the order moved, e.g. why it was rejected. Left out when there is nothing to tell.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[tracing::instrument(name = "OrderCreated publish", skip_all, fields(otel.kind = "producer", messaging.system = "kafka", messaging.destination.name = routing.order_created.as_str(), o_id = %order.o_id))]
+pub fn publish_order_created(order: &Order, request_id: &str, routing: &EventRouting, publisher: &mut dyn EventPublisher) -> Result<(), OrderServiceError> {
     let json = order.to_json_string()?;
-    producer.send("OrderCreated", json)
+    let mut headers = event_headers(request_id);
+    inject_trace_context(&mut headers);
+    let attributes = Attributes { id: format!("{}:created", order.o_id), event_type: ORDER_CREATED_TYPE, subject: &order.o_id, time: &order.ordertime };
+    let json = as_cloud_event(routing, &attributes, &mut headers, json)?;
+    tracing::info!(c_id = %order.c_id, "publishing event");
+    publisher.publish(&routing.order_created, routing.partition_key.key_of(&order.o_id, &order.r_id), &headers, json)
+}
+
+#[tracing::instrument(name = "OrderStateChanged publish", skip_all, fields(otel.kind = "producer", messaging.system = "kafka", messaging.destination.name = routing.order_state_changed.as_str(), o_id = %event.o_id, state = %event.state))]
+pub fn publish_order_state_changed(event: &OrderStateChanged, request_id: &str, routing: &EventRouting, publisher: &mut dyn EventPublisher) -> Result<(), OrderServiceError> {
+    let json = serde_json::to_string(event)?;
+    let mut headers = event_headers(request_id);
+    inject_trace_context(&mut headers);
+    // An order enters each state once.
+    let attributes = Attributes { id: format!("{}:{}", event.o_id, event.state), event_type: ORDER_STATE_CHANGED_TYPE, subject: &event.o_id, time: &event.changed_at };
+    let json = as_cloud_event(routing, &attributes, &mut headers, json)?;
+    tracing::info!(r_id = %event.r_id, "publishing event");
+    publisher.publish(&routing.order_state_changed, routing.partition_key.key_of(&event.o_id, &event.r_id), &headers, json)
+}
+
+fn event_headers(request_id: &str) -> EventHeaders {
+    vec![(REQUEST_ID_HEADER.to_owned(), request_id.to_owned())]
+}
+
+fn as_cloud_event(routing: &EventRouting, attributes: &Attributes, headers: &mut EventHeaders, json: String) -> Result<String, OrderServiceError> {
+    match &routing.cloud_events {
+        Some(cloud_events) => cloud_events.wrap(attributes, headers, json),
+        None => Ok(json),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::producers::producer_connection::MockKafkaProducer;
+    use crate::producers::{cloud_events::{event_data, CloudEventsMode, Envelope}, event_publisher::{InMemoryPublisher, MockEventPublisher}};
 
     use super::*;
 
@@ -19,16 +123,17 @@ mod tests {
         let exp_json  = format!(
             "{{\"o_id\":\"{}\",\"c_id\":\"{}\",\"r_id\":\"{}\",\"ordertime\":\"{}\",\"orderlines\":[],\"state\":\"{}\",\"cust_addr\":\"{}\",\"rest_addr\":\"{}\",\"postal_code\":{}}}", 
             order.o_id, order.c_id, order.r_id, order.ordertime, order.state, order.cust_addr, order.rest_addr, order.postal_code);
-        let mut mock_prod = MockKafkaProducer::new();
-        mock_prod.expect_send()
-            .withf(move |x, y| {
-                x.eq("OrderCreated") && y.eq(&exp_json)
+        let mut mock_prod = MockEventPublisher::new();
+        mock_prod.expect_publish()
+            .withf(move |x, k, h, y| {
+                x.eq("OrderCreated") && k.is_none() && y.eq(&exp_json)
+                    && h.contains(&(REQUEST_ID_HEADER.to_owned(), "req-1".to_owned()))
             })
             .times(1)
-            .returning(|_x, _y| {
+            .returning(|_x, _k, _h, _y| {
                 Ok(())
             });
-        let res = publish_order_created(&order, &mut mock_prod);
+        let res = publish_order_created(&order, "req-1", &EventRouting::default(), &mut mock_prod);
         assert!(res.is_ok());
     }
 
@@ -38,16 +143,147 @@ mod tests {
         let exp_json  = format!(
             "{{\"o_id\":\"{}\",\"c_id\":\"{}\",\"r_id\":\"{}\",\"ordertime\":\"{}\",\"orderlines\":[],\"state\":\"{}\",\"cust_addr\":\"{}\",\"rest_addr\":\"{}\",\"postal_code\":{}}}", 
             order.o_id, order.c_id, order.r_id, order.ordertime, order.state, order.cust_addr, order.rest_addr, order.postal_code);
-        let mut mock_prod = MockKafkaProducer::new();
-        mock_prod.expect_send()
-            .withf(move |x, y| {
+        let mut mock_prod = MockEventPublisher::new();
+        mock_prod.expect_publish()
+            .withf(move |x, _k, _h, y| {
                 x.eq("OrderCreated") && y.eq(&exp_json)
             })
             .times(1)
-            .returning(|_x, _y| {
-                Err(OrderServiceError::EventBrokerError(kafka::Error::CodecError))
+            .returning(|_x, _k, _h, _y| {
+                Err(OrderServiceError::EventBrokerError(rdkafka::error::KafkaError::MessageProduction(rdkafka::error::RDKafkaErrorCode::InvalidMessage)))
             });
-        let res = publish_order_created(&order, &mut mock_prod);
+        let res = publish_order_created(&order, "req-1", &EventRouting::default(), &mut mock_prod);
         assert!(res.is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_raise_event_is_recorded() {
+        let order = Order::new(vec![], "CustAddr".into(), "RestAddr".into(), "custid".into(), "restid".into(), 2860);
+        let recorder = InMemoryPublisher::new();
+        publish_order_created(&order, "req-1", &EventRouting::default(), &mut recorder.clone()).unwrap();
+        let events = recorder.events_for("OrderCreated");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].json, order.to_json_string().unwrap());
+        assert_eq!(events[0].header(REQUEST_ID_HEADER), Some("req-1"));
+    }
+
+    #[test]
+    fn test_parse_partition_key() {
+        assert_eq!(PartitionKey::from_str("none"), Ok(PartitionKey::None));
+        assert_eq!(PartitionKey::from_str("O_ID"), Ok(PartitionKey::OrderId));
+        assert_eq!(PartitionKey::from_str("r_id"), Ok(PartitionKey::RestaurantId));
+        assert_eq!(PartitionKey::from_str("c_id"), Err(()));
+    }
+
+    #[test]
+    fn test_raise_event_uses_configured_topic_and_key() {
+        let order = Order::new(vec![], "CustAddr".into(), "RestAddr".into(), "custid".into(), "restid".into(), 2860);
+        let recorder = InMemoryPublisher::new();
+        let routing = EventRouting { order_created: "orders.created.v1".into(), partition_key: PartitionKey::RestaurantId, ..Default::default() };
+        publish_order_created(&order, "req-1", &routing, &mut recorder.clone()).unwrap();
+        assert!(recorder.events_for("OrderCreated").is_empty());
+        let events = recorder.events_for("orders.created.v1");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].key.as_deref(), Some("restid"));
+
+        let routing = EventRouting { partition_key: PartitionKey::OrderId, ..Default::default() };
+        publish_order_created(&order, "req-2", &routing, &mut recorder.clone()).unwrap();
+        assert_eq!(recorder.events_for("OrderCreated")[0].key.as_deref(), Some(order.o_id.as_str()));
+    }
+
+    #[test]
+    fn test_state_changed_event_is_keyed_like_its_order() {
+        let event = OrderStateChanged { o_id: "o-1".into(), c_id: "custid".into(), r_id: "restid".into(), state: OrderState::Rejected, changed_at: "2026-01-01T10:00:00+00:00".into(), reason: None };
+        let recorder = InMemoryPublisher::new();
+        let routing = EventRouting { partition_key: PartitionKey::RestaurantId, ..Default::default() };
+        publish_order_state_changed(&event, "req-1", &routing, &mut recorder.clone()).unwrap();
+        let events = recorder.events_for(DEFAULT_ORDER_STATE_CHANGED_TOPIC);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].key.as_deref(), Some("restid"));
+        assert_eq!(serde_json::from_str::<OrderStateChanged>(&events[0].json).unwrap(), event);
+    }
+
+    fn cloud_events(mode: CloudEventsMode) -> EventRouting {
+        EventRouting { cloud_events: Some(CloudEvents { mode, source: "cust-order-service".into() }), ..Default::default() }
+    }
+
+    #[test]
+    fn test_order_created_as_structured_cloud_event() {
+        let order = Order::new(vec![], "CustAddr".into(), "RestAddr".into(), "custid".into(), "restid".into(), 2860);
+        let recorder = InMemoryPublisher::new();
+        publish_order_created(&order, "req-1", &cloud_events(CloudEventsMode::Structured), &mut recorder.clone()).unwrap();
+        let events = recorder.events_for("OrderCreated");
+        let envelope: Envelope<Order> = serde_json::from_str(&events[0].json).unwrap();
+        assert_eq!(envelope.specversion, "1.0");
+        assert_eq!(envelope.id, format!("{}:created", order.o_id));
+        assert_eq!(envelope.source, "cust-order-service");
+        assert_eq!(envelope.event_type, "dk.f2js.order.created");
+        assert_eq!(envelope.subject, order.o_id);
+        assert_eq!(envelope.time, order.ordertime);
+        assert_eq!(envelope.data, order);
+        assert_eq!(events[0].header(REQUEST_ID_HEADER), Some("req-1"));
+        assert_eq!(event_data::<Order>(events[0].json.as_bytes()).unwrap(), order);
+    }
+
+    #[test]
+    fn test_state_changed_as_binary_cloud_event() {
+        let event = OrderStateChanged { o_id: "o-1".into(), c_id: "custid".into(), r_id: "restid".into(), state: OrderState::Accepted, changed_at: "2026-01-01T10:00:00+00:00".into(), reason: None };
+        let recorder = InMemoryPublisher::new();
+        publish_order_state_changed(&event, "req-1", &cloud_events(CloudEventsMode::Binary), &mut recorder.clone()).unwrap();
+        let events = recorder.events_for(DEFAULT_ORDER_STATE_CHANGED_TOPIC);
+        assert_eq!(serde_json::from_str::<OrderStateChanged>(&events[0].json).unwrap(), event);
+        assert_eq!(events[0].header("ce_id"), Some("o-1:Accepted"));
+        assert_eq!(events[0].header("ce_type"), Some("dk.f2js.order.state_changed"));
+        assert_eq!(events[0].header("ce_subject"), Some("o-1"));
+        assert_eq!(events[0].header("ce_time"), Some("2026-01-01T10:00:00+00:00"));
+        assert_eq!(events[0].header("content-type"), Some("application/json"));
+    }
+
+    #[test]
+    fn test_kafka_record_carries_trace_context() {
+        use opentelemetry::{global, trace::{TraceContextExt, TracerProvider as _}};
+        use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider};
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+        use tracing_subscriber::{layer::SubscriberExt, Registry};
+        use crate::{consumers::{consumer::MessageSource, consumer_connection::KafkaConsumerConnection}, producers::producer_connection::{tests::mock_kafka, KafkaProdConnection}};
+
+        let (_cluster, config) = mock_kafka(&["OrderCreated"]);
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = SdkTracerProvider::builder().build();
+        let subscriber = Registry::default().with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let order = Order::new(vec![], "CustAddr".into(), "RestAddr".into(), "custid".into(), "restid".into(), 2860);
+        let mut kafka = KafkaProdConnection::connect_with(&config).unwrap();
+        let trace_id = tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("POST /create");
+            let _entered = span.enter();
+            publish_order_created(&order, "req-1", &EventRouting::default(), &mut kafka).unwrap();
+            span.context().span().span_context().trace_id().to_string()
+        });
+
+        let mut source = KafkaConsumerConnection::connect(&config, "OrderCreated", "test").unwrap();
+        let record = source.poll().unwrap().pop().unwrap();
+        let traceparent = record.header("traceparent").unwrap();
+        assert_eq!(traceparent.split('-').nth(1), Some(trace_id.as_str()));
+        assert_eq!(record.header(REQUEST_ID_HEADER), Some("req-1"));
+        assert_eq!(record.value, order.to_json_string().unwrap().into_bytes());
+    }
+
+    #[test]
+    fn test_binary_cloud_event_attributes_are_kafka_record_headers() {
+        use crate::{consumers::{consumer::MessageSource, consumer_connection::KafkaConsumerConnection}, producers::producer_connection::{tests::mock_kafka, KafkaProdConnection}};
+
+        let (_cluster, config) = mock_kafka(&[DEFAULT_ORDER_STATE_CHANGED_TOPIC]);
+        let event = OrderStateChanged { o_id: "o-1".into(), c_id: "custid".into(), r_id: "restid".into(), state: OrderState::Accepted, changed_at: "2026-01-01T10:00:00+00:00".into(), reason: None };
+        let mut kafka = KafkaProdConnection::connect_with(&config).unwrap();
+        publish_order_state_changed(&event, "req-1", &cloud_events(CloudEventsMode::Binary), &mut kafka).unwrap();
+
+        let mut source = KafkaConsumerConnection::connect(&config, DEFAULT_ORDER_STATE_CHANGED_TOPIC, "test").unwrap();
+        let record = source.poll().unwrap().pop().unwrap();
+        assert_eq!(record.header("ce_specversion"), Some("1.0"));
+        assert_eq!(record.header("ce_id"), Some("o-1:Accepted"));
+        assert_eq!(record.header("ce_type"), Some("dk.f2js.order.state_changed"));
+        assert_eq!(record.header("ce_source"), Some("cust-order-service"));
+        assert_eq!(record.header("content-type"), Some("application/json"));
+        assert_eq!(event_data::<OrderStateChanged>(&record.value).unwrap(), event);
+    }
+}
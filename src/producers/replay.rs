@@ -0,0 +1,304 @@
+//! Re-publishes the events of stored orders, for consumers that lost events or come online later.
+//!
+//! Each order gets its `OrderCreated` event again, as it was published when the order was created. An order that
+//! has moved on from `Pending` also gets an `OrderStateChanged` event for its current state, stamped with the time
+//! its state was written. Only the latest state is stored, so earlier states cannot be replayed.
+
+use std::{thread, time::{Duration, Instant}};
+
+use chrono::{DateTime, TimeZone, Utc};
+use opentelemetry::{global, KeyValue};
+use serde::Serialize;
+
+use crate::{
+    models::{errors::OrderServiceError, orders::OrderState, tables::Tables},
+    repository::{hbase::{self, OrderScanFilter, ScannedOrder}, hbase_connection::HbaseClient},
+};
+use super::{event_publisher::EventPublisher, producers::{publish_order_created, publish_order_state_changed, EventRouting, OrderStateChanged}};
+
+pub const REPLAY_USAGE: &str = "usage: order_service replay-events [--restaurant <r_id>] [--customer <c_id>] [--from <rfc3339>] [--until <rfc3339>] \
+    [--topic <topic>] [--state-topic <topic>] [--rate <events per second>] [--dry-run]";
+
+/// Which orders to replay, and where to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayOptions {
+    pub filter: OrderScanFilter,
+    /// Topic of the `OrderCreated` events, the configured one when unset.
+    pub topic: Option<String>,
+    /// Topic of the `OrderStateChanged` events, the configured one when unset.
+    pub state_topic: Option<String>,
+    /// Events published per second at most. No limit when unset.
+    pub rate: Option<u32>,
+    /// Logs the events instead of publishing them.
+    pub dry_run: bool,
+}
+
+impl ReplayOptions {
+    /// Parses the arguments of `replay-events`.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut options = ReplayOptions::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--dry-run" {
+                options.dry_run = true;
+                continue;
+            }
+            let value = args.next().filter(|v| !v.starts_with("--")).ok_or_else(|| format!("{} needs a value", arg))?.clone();
+            let time = |v: &str| DateTime::parse_from_rfc3339(v).map(|t| t.timestamp_millis()).map_err(|e| format!("{} must be an RFC 3339 time: {}", arg, e));
+            match arg.as_str() {
+                "--restaurant" => options.filter.r_id = Some(value),
+                "--customer" => options.filter.c_id = Some(value),
+                "--from" => options.filter.from = Some(time(&value)?),
+                "--until" => options.filter.until = Some(time(&value)?),
+                "--topic" => options.topic = Some(value),
+                "--state-topic" => options.state_topic = Some(value),
+                "--rate" => options.rate = Some(value.parse::<u32>().ok().filter(|r| *r > 0).ok_or_else(|| format!("--rate must be a positive number, got '{}'", value))?),
+                _ => return Err(format!("unknown argument '{}'", arg)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Orders found and events replayed, or only counted in a dry run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ReplayReport {
+    pub orders: usize,
+    pub order_created: usize,
+    pub state_changed: usize,
+    /// Rows that did not decode into an order.
+    pub skipped_rows: usize,
+}
+
+/// Spaces calls `1/rate` seconds apart.
+struct RateLimiter {
+    interval: Duration,
+    next: Option<Instant>,
+}
+
+impl RateLimiter {
+    fn new(rate: Option<u32>) -> Self {
+        Self { interval: rate.map(|r| Duration::from_secs(1) / r).unwrap_or_default(), next: None }
+    }
+
+    fn wait(&mut self) {
+        if self.interval.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let at = self.next.map_or(now, |next| next.max(now));
+        thread::sleep(at - now);
+        self.next = Some(at + self.interval);
+    }
+}
+
+/// Scans the order table and publishes the events of the orders matching `options`. Stops at the first event
+/// `publisher` does not take. Replayed events carry a request id of their own, shared by the run.
+pub fn replay_orders<H: HbaseClient>(
+    client: &mut H,
+    tables: &Tables,
+    batch_size: i32,
+    routing: &EventRouting,
+    options: &ReplayOptions,
+    publisher: &mut dyn EventPublisher,
+) -> Result<ReplayReport, OrderServiceError> {
+    let routing = EventRouting {
+        order_created: options.topic.clone().unwrap_or_else(|| routing.order_created.clone()),
+        order_state_changed: options.state_topic.clone().unwrap_or_else(|| routing.order_state_changed.clone()),
+        ..routing.clone()
+    };
+    let request_id = format!("replay-{}", uuid::Uuid::new_v4());
+    let published = global::meter("order_service").u64_counter("events.replay.published")
+        .with_description("Events re-published by replay-events, by topic")
+        .build();
+    let mut limiter = RateLimiter::new(options.rate);
+    let mut report = ReplayReport::default();
+    tracing::info!(request_id, dry_run = options.dry_run, ?options.filter, "replaying order events");
+    report.skipped_rows = hbase::scan_orders(client, tables, &options.filter, batch_size, |scanned| {
+        report.orders += 1;
+        let ScannedOrder { mut order, ordertime, state_written_at } = scanned;
+        let state = std::mem::replace(&mut order.state, OrderState::Pending);
+        let state_changed = (state != OrderState::Pending).then(|| OrderStateChanged {
+            o_id: order.o_id.clone(),
+            c_id: order.c_id.clone(),
+            r_id: order.r_id.clone(),
+            state,
+            changed_at: to_rfc3339(state_written_at.unwrap_or(ordertime)),
+            // Reasons are not stored with the order, only the original event has one.
+            reason: None,
+        });
+        if options.dry_run {
+            tracing::info!(topic = routing.order_created.as_str(), o_id = order.o_id.as_str(), "dry run, event not published");
+        } else {
+            limiter.wait();
+            publish_order_created(&order, &request_id, &routing, publisher)?;
+            published.add(1, &[KeyValue::new("topic", routing.order_created.clone())]);
+        }
+        report.order_created += 1;
+        if let Some(event) = state_changed {
+            if options.dry_run {
+                tracing::info!(topic = routing.order_state_changed.as_str(), o_id = event.o_id.as_str(), state = %event.state, "dry run, event not published");
+            } else {
+                limiter.wait();
+                publish_order_state_changed(&event, &request_id, &routing, publisher)?;
+                published.add(1, &[KeyValue::new("topic", routing.order_state_changed.clone())]);
+            }
+            report.state_changed += 1;
+        }
+        Ok(())
+    })?;
+    Ok(report)
+}
+
+/// Unix millis as RFC 3339, in UTC.
+pub(crate) fn to_rfc3339(millis: i64) -> String {
+    Utc.timestamp_millis_opt(millis).single().unwrap_or_default().to_rfc3339()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::orders::Order,
+        producers::{event_publisher::{EventHeaders, InMemoryPublisher}, producers::{PartitionKey, DEFAULT_ORDER_CREATED_TOPIC, DEFAULT_ORDER_STATE_CHANGED_TOPIC}},
+        repository::{fake_hbase::FakeHbase, hbase_connection::{HbaseConnection, ThriftProtocol, ThriftTransport}},
+    };
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    /// A fake HBase with an order table holding `orders`.
+    fn store(orders: &[Order]) -> String {
+        let tables = Tables::default();
+        let url = FakeHbase::serve_with_tables(&[hbase::order_table_spec(&tables), hbase::customer_index_spec(&tables)]);
+        for order in orders {
+            hbase::add_order(order, &tables, HbaseConnection::connect(&url).unwrap()).unwrap();
+        }
+        url
+    }
+
+    fn order(c_id: &str, r_id: &str, ordertime: &str, state: OrderState) -> Order {
+        let mut order = Order::new(vec![], "Lyngvej 2, 2800 Lyngby".into(), "Rest 1".into(), c_id.into(), r_id.into(), 2800);
+        order.o_id = format!("{}-{}-{}", c_id, r_id, ordertime);
+        order.ordertime = ordertime.into();
+        order.state = state;
+        order
+    }
+
+    #[test]
+    fn test_parse_replay_args() {
+        let options = ReplayOptions::from_args(&args(&["--restaurant", "r1", "--from", "2026-01-01T00:00:00Z", "--topic", "orders.replay", "--rate", "50", "--dry-run"])).unwrap();
+        assert_eq!(options.filter.r_id.as_deref(), Some("r1"));
+        assert_eq!(options.filter.from, Some(1767225600000));
+        assert_eq!(options.topic.as_deref(), Some("orders.replay"));
+        assert_eq!(options.rate, Some(50));
+        assert!(options.dry_run);
+        assert_eq!(ReplayOptions::from_args(&[]).unwrap(), ReplayOptions::default());
+        assert!(ReplayOptions::from_args(&args(&["--from", "yesterday"])).is_err());
+        assert!(ReplayOptions::from_args(&args(&["--rate", "0"])).is_err());
+        assert!(ReplayOptions::from_args(&args(&["--customer", "--dry-run"])).is_err());
+        assert!(ReplayOptions::from_args(&args(&["--table", "orders"])).is_err());
+    }
+
+    #[test]
+    fn test_replay_rebuilds_created_and_state_events() {
+        let pending = order("c1", "r1", "2026-01-01T10:00:00+00:00", OrderState::Pending);
+        let rejected = order("c2", "r1", "2026-01-01T11:00:00+00:00", OrderState::Rejected);
+        let url = store(&[pending.clone(), rejected.clone()]);
+        let recorder = InMemoryPublisher::new();
+        let routing = EventRouting { partition_key: PartitionKey::OrderId, ..Default::default() };
+        let report = replay_orders(&mut HbaseConnection::connect(&url).unwrap(), &Tables::default(), 10, &routing, &ReplayOptions::default(), &mut recorder.clone()).unwrap();
+        assert_eq!(report, ReplayReport { orders: 2, order_created: 2, state_changed: 1, skipped_rows: 0 });
+
+        let created = recorder.events_for(DEFAULT_ORDER_CREATED_TOPIC);
+        let mut orders: Vec<Order> = created.iter().map(|e| serde_json::from_str(&e.json).unwrap()).collect();
+        orders.sort_by(|a, b| a.o_id.cmp(&b.o_id));
+        assert_eq!(orders, [pending, Order { state: OrderState::Pending, ..rejected.clone() }]);
+        assert!(created.iter().all(|e| e.header("X-Request-Id").is_some_and(|id| id.starts_with("replay-"))));
+
+        let changed = recorder.events_for(DEFAULT_ORDER_STATE_CHANGED_TOPIC);
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].key.as_deref(), Some(rejected.o_id.as_str()));
+        let event: OrderStateChanged = serde_json::from_str(&changed[0].json).unwrap();
+        assert_eq!((event.o_id, event.state), (rejected.o_id, OrderState::Rejected));
+    }
+
+    #[test]
+    fn test_replay_filters_and_topics() {
+        let url = store(&[
+            order("c1", "r1", "2026-01-01T10:00:00+00:00", OrderState::Pending),
+            order("c1", "r2", "2026-01-01T10:00:00+00:00", OrderState::Pending),
+            order("c2", "r1", "2026-01-01T10:00:00+00:00", OrderState::Pending),
+            order("c1", "r1", "2026-01-02T10:00:00+00:00", OrderState::Accepted),
+        ]);
+        let recorder = InMemoryPublisher::new();
+        let options = ReplayOptions {
+            filter: OrderScanFilter { r_id: Some("r1".into()), c_id: Some("c1".into()), state: None, from: None, until: Some(1767312000000) },
+            topic: Some("orders.replay".into()),
+            ..Default::default()
+        };
+        let report = replay_orders(&mut HbaseConnection::connect(&url).unwrap(), &Tables::default(), 2, &EventRouting::default(), &options, &mut recorder.clone()).unwrap();
+        assert_eq!(report.orders, 1);
+        let events = recorder.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].topic, "orders.replay");
+        assert_eq!(serde_json::from_str::<Order>(&events[0].json).unwrap().o_id, "c1-r1-2026-01-01T10:00:00+00:00");
+    }
+
+    #[test]
+    fn test_dry_run_publishes_nothing() {
+        let url = store(&[order("c1", "r1", "2026-01-01T10:00:00+00:00", OrderState::Delivered)]);
+        let recorder = InMemoryPublisher::new();
+        let options = ReplayOptions { dry_run: true, ..Default::default() };
+        let report = replay_orders(&mut HbaseConnection::connect(&url).unwrap(), &Tables::default(), 10, &EventRouting::default(), &options, &mut recorder.clone()).unwrap();
+        assert_eq!(report, ReplayReport { orders: 1, order_created: 1, state_changed: 1, skipped_rows: 0 });
+        assert!(recorder.events().is_empty());
+    }
+
+    #[test]
+    fn test_no_scanner_is_open_while_publishing() {
+        /// Records events, and the scanners the fake HBase had open at each of them.
+        struct ScannersAtPublish {
+            fake: FakeHbase,
+            recorder: InMemoryPublisher,
+            open: Vec<usize>,
+        }
+        impl EventPublisher for ScannersAtPublish {
+            fn publish(&mut self, topic: &str, key: Option<&str>, headers: &EventHeaders, json: String) -> Result<(), OrderServiceError> {
+                self.open.push(self.fake.open_scanners());
+                self.recorder.publish(topic, key, headers, json)
+            }
+        }
+        let tables = Tables::default();
+        let fake = FakeHbase::with_tables(&[hbase::order_table_spec(&tables), hbase::customer_index_spec(&tables)]);
+        let url = fake.serve_thrift1(ThriftTransport::Buffered, ThriftProtocol::Binary);
+        for n in 0..5 {
+            let order = order("c1", "r1", &format!("2026-01-01T10:00:0{}+00:00", n), OrderState::Pending);
+            hbase::add_order(&order, &tables, HbaseConnection::connect(&url).unwrap()).unwrap();
+        }
+        let mut publisher = ScannersAtPublish { fake: fake.clone(), recorder: InMemoryPublisher::new(), open: vec![] };
+        let report = replay_orders(&mut HbaseConnection::connect(&url).unwrap(), &tables, 2, &EventRouting::default(), &ReplayOptions::default(), &mut publisher).unwrap();
+        assert_eq!(report.order_created, 5);
+        assert_eq!(publisher.recorder.events().len(), 5);
+        assert_eq!(publisher.open, [0; 5]);
+        assert_eq!(fake.open_scanners(), 0);
+    }
+
+    #[test]
+    fn test_rate_limiter_spaces_calls() {
+        let mut limiter = RateLimiter::new(Some(100));
+        let started = Instant::now();
+        for _ in 0..5 {
+            limiter.wait();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(40));
+
+        let mut unlimited = RateLimiter::new(None);
+        let started = Instant::now();
+        for _ in 0..1000 {
+            unlimited.wait();
+        }
+        assert!(started.elapsed() < Duration::from_millis(40));
+    }
+}
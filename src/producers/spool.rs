@@ -0,0 +1,457 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, OnceLock},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use opentelemetry::{global, metrics::{Counter, Gauge, Meter}, KeyValue};
+
+use crate::{api::utils::env::get_event_spool_max_bytes, models::errors::OrderServiceError};
+use super::event_publisher::{EventHeaders, EventPublisher, PublishedEvent};
+
+pub const DEFAULT_SPOOL_MAX_BYTES: u64 = 64 * 1024 * 1024;
+pub const DEFAULT_REPLAY_INTERVAL: Duration = Duration::from_secs(5);
+
+struct SpoolMetrics {
+    events: Gauge<u64>,
+    appends: Counter<u64>,
+    replayed: Counter<u64>,
+}
+
+impl SpoolMetrics {
+    fn new(meter: &Meter) -> Self {
+        Self {
+            events: meter.u64_gauge("events.spool.events")
+                .with_description("Events waiting in the spool for the broker to come back")
+                .build(),
+            appends: meter.u64_counter("events.spool.appends")
+                .with_description("Events handed to the spool, by outcome")
+                .build(),
+            replayed: meter.u64_counter("events.spool.replayed")
+                .with_description("Spooled events delivered to the broker")
+                .build(),
+        }
+    }
+}
+
+/// The append handle of the spool file and what it holds.
+struct SpoolFile {
+    file: File,
+    events: u64,
+    bytes: u64,
+}
+
+/// What a replay delivered and what is left in the spool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub delivered: u64,
+    pub remaining: u64,
+}
+
+/// An append-only file of events that could not be delivered, one JSON object per line. Every append is
+/// fsynced before it returns. Events are replayed in the order they were spooled, and delivered events are
+/// removed by rewriting the file, so an event can be delivered twice if the service dies during a replay.
+pub struct EventSpool {
+    path: PathBuf,
+    max_bytes: u64,
+    state: Mutex<SpoolFile>,
+    /// Held for a whole replay, so two replays do not deliver the same events.
+    replaying: Mutex<()>,
+    metrics: SpoolMetrics,
+}
+
+static SPOOLS: OnceLock<Mutex<HashMap<PathBuf, Arc<EventSpool>>>> = OnceLock::new();
+
+impl EventSpool {
+    /// Opens the spool at `path`, creating it when missing. A last line cut short by a crash, and lines that do
+    /// not parse, are dropped.
+    pub fn open(path: impl AsRef<Path>, max_bytes: u64, meter: &Meter) -> Result<Self, OrderServiceError> {
+        let path = path.as_ref().to_path_buf();
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(spool_error(&path, e)),
+        };
+        let mut lines: Vec<&[u8]> = content.split(|b| *b == b'\n').collect();
+        // The part after the last newline is empty, or a record whose write did not complete.
+        let torn = lines.pop().is_some_and(|tail| !tail.is_empty());
+        let events: Vec<PublishedEvent> = lines.iter().filter_map(|line| serde_json::from_slice(line).ok()).collect();
+        let dropped = lines.len() - events.len() + usize::from(torn);
+        if dropped > 0 {
+            tracing::warn!(path = %path.display(), dropped, "dropped unreadable records from the event spool");
+            write_events(&path, &events)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path).map_err(|e| spool_error(&path, e))?;
+        let bytes = file.metadata().map_err(|e| spool_error(&path, e))?.len();
+        let spool = Self {
+            path,
+            max_bytes,
+            state: Mutex::new(SpoolFile { file, events: events.len() as u64, bytes }),
+            replaying: Mutex::new(()),
+            metrics: SpoolMetrics::new(meter),
+        };
+        spool.metrics.events.record(events.len() as u64, &[]);
+        Ok(spool)
+    }
+
+    /// The spool at `path`, opened on first use, so all requests append to the same file.
+    pub fn shared(path: &str) -> Result<Arc<EventSpool>, OrderServiceError> {
+        let mut spools = SPOOLS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+        let path = PathBuf::from(path);
+        if let Some(spool) = spools.get(&path) {
+            return Ok(Arc::clone(spool));
+        }
+        let spool = Arc::new(EventSpool::open(&path, get_event_spool_max_bytes(), &global::meter("order_service"))?);
+        spools.insert(path, Arc::clone(&spool));
+        Ok(spool)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SpoolFile> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Events waiting in the spool.
+    pub fn len(&self) -> u64 {
+        self.lock().events
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds the event to the end of the spool. Fails when the spool would grow beyond its size cap.
+    pub fn append(&self, event: &PublishedEvent) -> Result<(), OrderServiceError> {
+        let line = format!("{}\n", serde_json::to_string(event)?);
+        let mut state = self.lock();
+        if state.bytes + line.len() as u64 > self.max_bytes {
+            self.metrics.appends.add(1, &[KeyValue::new("outcome", "full")]);
+            tracing::error!(path = %self.path.display(), events = state.events, max_bytes = self.max_bytes, topic = event.topic.as_str(), "event spool is full");
+            return Err(OrderServiceError::EventSinkError(format!("the event spool {} is full", self.path.display())));
+        }
+        let res = state.file.write_all(line.as_bytes()).and_then(|_| state.file.sync_data());
+        if let Err(e) = res {
+            self.metrics.appends.add(1, &[KeyValue::new("outcome", "error")]);
+            return Err(spool_error(&self.path, e));
+        }
+        state.events += 1;
+        state.bytes += line.len() as u64;
+        self.metrics.appends.add(1, &[KeyValue::new("outcome", "spooled")]);
+        self.metrics.events.record(state.events, &[]);
+        tracing::warn!(topic = event.topic.as_str(), spooled = state.events, "event spooled");
+        Ok(())
+    }
+
+    /// Publishes the spooled events in order, and stops at the first one `publisher` does not take. The spool is
+    /// only locked to read the events and to remove the delivered ones, so requests keep appending meanwhile.
+    pub fn replay(&self, publisher: &mut dyn EventPublisher) -> Result<ReplayReport, OrderServiceError> {
+        let _replaying = self.replaying.lock().unwrap_or_else(|e| e.into_inner());
+        let events = {
+            let state = self.lock();
+            if state.events == 0 {
+                return Ok(ReplayReport { delivered: 0, remaining: 0 });
+            }
+            self.read_events()?
+        };
+        let mut delivered = 0;
+        for event in &events {
+            if let Err(e) = publisher.publish(&event.topic, event.key.as_deref(), &event.headers, event.json.clone()) {
+                tracing::warn!(error = %e, remaining = events.len() - delivered, "replay of spooled events stopped");
+                break;
+            }
+            delivered += 1;
+        }
+        let mut state = self.lock();
+        if delivered > 0 {
+            // Only appends happened since the read, so the delivered events still start the file.
+            let rest = self.read_events()?.split_off(delivered);
+            write_events(&self.path, &rest)?;
+            state.file = OpenOptions::new().append(true).open(&self.path).map_err(|e| spool_error(&self.path, e))?;
+            state.bytes = state.file.metadata().map_err(|e| spool_error(&self.path, e))?.len();
+            state.events = rest.len() as u64;
+            self.metrics.replayed.add(delivered as u64, &[]);
+            self.metrics.events.record(state.events, &[]);
+            tracing::info!(delivered, remaining = state.events, "replayed spooled events");
+        }
+        Ok(ReplayReport { delivered: delivered as u64, remaining: state.events })
+    }
+
+    fn read_events(&self) -> Result<Vec<PublishedEvent>, OrderServiceError> {
+        let content = fs::read(&self.path).map_err(|e| spool_error(&self.path, e))?;
+        Ok(content.split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| serde_json::from_slice(line).ok())
+            .collect())
+    }
+}
+
+/// Replaces the spool file with `events`, through a synced temporary file, so a crash leaves the old or the new content.
+fn write_events(path: &Path, events: &[PublishedEvent]) -> Result<(), OrderServiceError> {
+    let mut tmp = OsString::from(path.as_os_str());
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut content = String::new();
+    for event in events {
+        content.push_str(&serde_json::to_string(event)?);
+        content.push('\n');
+    }
+    let res = File::create(&tmp)
+        .and_then(|mut file| file.write_all(content.as_bytes()).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&tmp, path));
+    res.map_err(|e| spool_error(path, e))
+}
+
+fn spool_error(path: &Path, e: std::io::Error) -> OrderServiceError {
+    OrderServiceError::EventSinkError(format!("event spool {}: {}", path.display(), e))
+}
+
+/// Publishes through `inner` and spools the events it does not take, or all events while `inner` is `None`
+/// because the broker could not be reached. While the spool holds events, new ones are appended behind them for
+/// [`spawn_replayer`] to deliver, so events keep their order and requests never wait for a replay.
+pub struct SpoolingPublisher<P> {
+    inner: Option<P>,
+    spool: Arc<EventSpool>,
+}
+
+impl<P: EventPublisher> SpoolingPublisher<P> {
+    pub fn new(inner: Option<P>, spool: Arc<EventSpool>) -> Self {
+        Self { inner, spool }
+    }
+}
+
+impl<P: EventPublisher> EventPublisher for SpoolingPublisher<P> {
+    fn publish(&mut self, topic: &str, key: Option<&str>, headers: &EventHeaders, json: String) -> Result<(), OrderServiceError> {
+        let event = PublishedEvent { topic: topic.to_owned(), key: key.map(str::to_owned), headers: headers.clone(), json };
+        let Some(inner) = self.inner.as_mut() else { return self.spool.append(&event) };
+        if !self.spool.is_empty() {
+            return self.spool.append(&event);
+        }
+        match inner.publish(topic, key, headers, event.json.clone()) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                tracing::warn!(error = %e, topic, "event not delivered, spooling it");
+                self.spool.append(&event)
+            }
+        }
+    }
+
+    fn publish_bytes(&mut self, topic: &str, key: Option<&str>, headers: &EventHeaders, payload: Vec<u8>) -> Result<(), OrderServiceError> {
+        match String::from_utf8(payload) {
+            Ok(json) => self.publish(topic, key, headers, json),
+            // The spool stores text, so such a payload can only go straight to the broker.
+            Err(e) => match self.inner.as_mut() {
+                Some(inner) if self.spool.is_empty() => inner.publish_bytes(topic, key, headers, e.into_bytes()),
+                _ => Err(OrderServiceError::EventSinkError(format!("a payload for {} that is not UTF-8 cannot be spooled", topic))),
+            },
+        }
+    }
+}
+
+/// Replays the spool every `interval` on a background thread, connecting with `connect` when it holds events.
+pub fn spawn_replayer<P, F>(spool: Arc<EventSpool>, interval: Duration, connect: F) -> JoinHandle<()>
+where
+    P: EventPublisher,
+    F: Fn() -> Result<P, OrderServiceError> + Send + 'static,
+{
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if spool.is_empty() {
+            continue;
+        }
+        let res = connect().and_then(|mut publisher| spool.replay(&mut publisher));
+        if let Err(e) = res {
+            tracing::debug!(error = %e, spooled = spool.len(), "spooled events not replayed yet");
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use crate::producers::event_publisher::InMemoryPublisher;
+
+    fn spool_path() -> PathBuf {
+        std::env::temp_dir().join(format!("spool-{}.jsonl", uuid::Uuid::new_v4()))
+    }
+
+    fn open(path: &Path, max_bytes: u64) -> Arc<EventSpool> {
+        Arc::new(EventSpool::open(path, max_bytes, &global::meter("test")).unwrap())
+    }
+
+    fn event(n: u32) -> PublishedEvent {
+        PublishedEvent { topic: "OrderCreated".into(), key: Some(format!("r-{}", n % 2)), headers: vec![("X-Request-Id".into(), format!("req-{}", n))], json: format!("{{\"n\":{}}}", n) }
+    }
+
+    /// Takes events into `recorder` while up, fails while down.
+    #[derive(Clone)]
+    struct Broker {
+        recorder: InMemoryPublisher,
+        up: Arc<AtomicBool>,
+    }
+
+    impl Broker {
+        fn new(up: bool) -> Self {
+            Self { recorder: InMemoryPublisher::new(), up: Arc::new(AtomicBool::new(up)) }
+        }
+    }
+
+    impl EventPublisher for Broker {
+        fn publish(&mut self, topic: &str, key: Option<&str>, headers: &EventHeaders, json: String) -> Result<(), OrderServiceError> {
+            if !self.up.load(Ordering::SeqCst) {
+                return Err(OrderServiceError::EventBrokerError(rdkafka::error::KafkaError::MessageProduction(rdkafka::error::RDKafkaErrorCode::AllBrokersDown)));
+            }
+            self.recorder.publish(topic, key, headers, json)
+        }
+    }
+
+    #[test]
+    fn test_replay_delivers_in_order_and_empties_the_spool() {
+        let path = spool_path();
+        let spool = open(&path, DEFAULT_SPOOL_MAX_BYTES);
+        for n in 0..3 {
+            spool.append(&event(n)).unwrap();
+        }
+        let broker = Broker::new(true);
+        assert_eq!(spool.replay(&mut broker.clone()).unwrap(), ReplayReport { delivered: 3, remaining: 0 });
+        assert_eq!(broker.recorder.events(), (0..3).map(event).collect::<Vec<_>>());
+        assert!(spool.is_empty());
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_replay_stops_at_first_failure() {
+        struct TakesOne(InMemoryPublisher);
+        impl EventPublisher for TakesOne {
+            fn publish(&mut self, topic: &str, key: Option<&str>, headers: &EventHeaders, json: String) -> Result<(), OrderServiceError> {
+                if !self.0.events().is_empty() {
+                    return Err(OrderServiceError::EventBrokerError(rdkafka::error::KafkaError::MessageProduction(rdkafka::error::RDKafkaErrorCode::AllBrokersDown)));
+                }
+                self.0.publish(topic, key, headers, json)
+            }
+        }
+        let path = spool_path();
+        let spool = open(&path, DEFAULT_SPOOL_MAX_BYTES);
+        for n in 0..3 {
+            spool.append(&event(n)).unwrap();
+        }
+        assert_eq!(spool.replay(&mut TakesOne(InMemoryPublisher::new())).unwrap(), ReplayReport { delivered: 1, remaining: 2 });
+        spool.append(&event(3)).unwrap();
+        let broker = Broker::new(true);
+        spool.replay(&mut broker.clone()).unwrap();
+        assert_eq!(broker.recorder.events(), (1..4).map(event).collect::<Vec<_>>());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_events_appended_during_a_replay_are_kept() {
+        /// Takes events into `broker`, and spools a new event while it publishes the first one.
+        struct AppendsWhilePublishing {
+            broker: Broker,
+            spool: Arc<EventSpool>,
+        }
+        impl EventPublisher for AppendsWhilePublishing {
+            fn publish(&mut self, topic: &str, key: Option<&str>, headers: &EventHeaders, json: String) -> Result<(), OrderServiceError> {
+                if self.broker.recorder.events().is_empty() {
+                    self.spool.append(&event(3))?;
+                }
+                self.broker.publish(topic, key, headers, json)
+            }
+        }
+        let path = spool_path();
+        let spool = open(&path, DEFAULT_SPOOL_MAX_BYTES);
+        for n in 0..3 {
+            spool.append(&event(n)).unwrap();
+        }
+        let broker = Broker::new(true);
+        let mut publisher = AppendsWhilePublishing { broker: broker.clone(), spool: Arc::clone(&spool) };
+        assert_eq!(spool.replay(&mut publisher).unwrap(), ReplayReport { delivered: 3, remaining: 1 });
+        assert_eq!(spool.replay(&mut broker.clone()).unwrap(), ReplayReport { delivered: 1, remaining: 0 });
+        assert_eq!(broker.recorder.events(), (0..4).map(event).collect::<Vec<_>>());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_recovery_keeps_spooled_events_and_drops_torn_record() {
+        let path = spool_path();
+        {
+            let spool = open(&path, DEFAULT_SPOOL_MAX_BYTES);
+            spool.append(&event(0)).unwrap();
+            spool.append(&event(1)).unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"topic\":\"OrderCrea").unwrap();
+        drop(file);
+
+        let spool = open(&path, DEFAULT_SPOOL_MAX_BYTES);
+        assert_eq!(spool.len(), 2);
+        spool.append(&event(2)).unwrap();
+        let broker = Broker::new(true);
+        spool.replay(&mut broker.clone()).unwrap();
+        assert_eq!(broker.recorder.events(), (0..3).map(event).collect::<Vec<_>>());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_full_spool_rejects_events() {
+        let path = spool_path();
+        let line = serde_json::to_string(&event(0)).unwrap().len() as u64 + 1;
+        let spool = open(&path, 2 * line);
+        spool.append(&event(0)).unwrap();
+        spool.append(&event(1)).unwrap();
+        assert!(matches!(spool.append(&event(2)), Err(OrderServiceError::EventSinkError(_))));
+        assert_eq!(spool.len(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_spooling_publisher_spools_while_broker_is_down() {
+        let path = spool_path();
+        let spool = open(&path, DEFAULT_SPOOL_MAX_BYTES);
+        let broker = Broker::new(false);
+        let mut publisher = SpoolingPublisher::new(Some(broker.clone()), Arc::clone(&spool));
+        let publish = |publisher: &mut SpoolingPublisher<Broker>, n: u32| {
+            let e = event(n);
+            publisher.publish(&e.topic, e.key.as_deref(), &e.headers, e.json)
+        };
+        publish(&mut publisher, 0).unwrap();
+        publish(&mut publisher, 1).unwrap();
+        assert_eq!(spool.len(), 2);
+
+        // Queued behind the spooled events until the replayer has delivered them.
+        broker.up.store(true, Ordering::SeqCst);
+        publish(&mut publisher, 2).unwrap();
+        assert_eq!(spool.len(), 3);
+        assert!(broker.recorder.events().is_empty());
+        spool.replay(&mut broker.clone()).unwrap();
+        publish(&mut publisher, 3).unwrap();
+        assert!(spool.is_empty());
+        assert_eq!(broker.recorder.events(), (0..4).map(event).collect::<Vec<_>>());
+
+        let mut unreachable = SpoolingPublisher::<Broker>::new(None, Arc::clone(&spool));
+        publish(&mut unreachable, 4).unwrap();
+        assert_eq!(spool.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_replayer_delivers_once_broker_is_back() {
+        let path = spool_path();
+        let spool = open(&path, DEFAULT_SPOOL_MAX_BYTES);
+        spool.append(&event(0)).unwrap();
+        let broker = Broker::new(true);
+        let connect = broker.clone();
+        spawn_replayer(Arc::clone(&spool), Duration::from_millis(10), move || Ok(connect.clone()));
+        let start = std::time::Instant::now();
+        while !spool.is_empty() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(broker.recorder.events(), vec![event(0)]);
+        fs::remove_file(&path).unwrap();
+    }
+}
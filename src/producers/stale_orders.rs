@@ -0,0 +1,316 @@
+//! Rejects orders the restaurant left `Pending` for longer than its SLA.
+//!
+//! Every replica of the API runs the sweep on a background thread, but only the one holding the `stale-orders`
+//! lease in HBase rejects orders, see [`crate::repository::leases`]. The lease is renewed on each sweep and runs
+//! out after three sweep intervals, so another replica takes over when its holder stops. An order is only rejected
+//! while it is still `Pending`, so a restaurant that answers during a sweep wins. Orders with a running saga are
+//! left to the saga, which times out its steps and compensates them, see [`crate::consumers::saga`].
+
+use std::{collections::HashMap, thread, time::Duration};
+
+use opentelemetry::global;
+use serde::Serialize;
+
+use crate::{
+    models::{errors::OrderServiceError, orders::{Order, OrderState}, saga::SagaStatus, tables::Tables},
+    repository::{hbase::{self, get_unix_time, OrderScanFilter}, hbase_connection::HbaseClient, leases, sagas},
+};
+use super::{event_publisher::EventPublisher, producers::{publish_order_state_changed, EventRouting, OrderStateChanged}, replay::to_rfc3339};
+
+pub const DEFAULT_PENDING_SLA: Duration = Duration::from_secs(15 * 60);
+pub const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+pub const STALE_ORDERS_LEASE: &str = "stale-orders";
+
+/// How long orders may stay `Pending`, and how often to look for the ones that stayed longer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleOrderConfig {
+    /// SLA of the restaurants without one of their own.
+    pub default_sla: Duration,
+    /// SLAs by r_id.
+    pub restaurant_slas: HashMap<String, Duration>,
+    pub sweep_interval: Duration,
+}
+
+impl Default for StaleOrderConfig {
+    fn default() -> Self {
+        Self { default_sla: DEFAULT_PENDING_SLA, restaurant_slas: HashMap::new(), sweep_interval: DEFAULT_SWEEP_INTERVAL }
+    }
+}
+
+impl StaleOrderConfig {
+    pub fn sla_of(&self, r_id: &str) -> Duration {
+        self.restaurant_slas.get(r_id).copied().unwrap_or(self.default_sla)
+    }
+
+    /// How long a sweep holds the lease for.
+    pub fn lease_duration(&self) -> Duration {
+        self.sweep_interval * 3
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct StaleOrderReport {
+    /// Pending orders past their SLA.
+    pub stale: usize,
+    pub rejected: usize,
+    /// Stale orders left to their running saga.
+    pub in_saga: usize,
+    pub skipped_rows: usize,
+}
+
+/// Runs the sweep for `holder`, one of the replicas.
+#[derive(Debug, Clone)]
+pub struct StaleOrderSweeper {
+    pub tables: Tables,
+    pub config: StaleOrderConfig,
+    pub routing: EventRouting,
+    pub holder: String,
+    pub batch_size: i32,
+}
+
+impl StaleOrderSweeper {
+    /// Takes or renews the lease, and rejects the stale orders when it got it. `None` while another replica has it.
+    pub fn sweep_once<H: HbaseClient>(&self, client: &mut H, now: i64, publisher: &mut dyn EventPublisher) -> Result<Option<StaleOrderReport>, OrderServiceError> {
+        let expires_at = now + self.config.lease_duration().as_millis() as i64;
+        if !leases::try_acquire(client, &self.tables, STALE_ORDERS_LEASE, &self.holder, now, expires_at)? {
+            tracing::debug!(holder = self.holder.as_str(), "stale order lease is held by another replica");
+            return Ok(None);
+        }
+        reject_stale_orders(client, &self.tables, &self.config, &self.routing, now, self.batch_size, publisher).map(Some)
+    }
+}
+
+/// Rejects the `Pending` orders whose SLA ran out at `now`, and publishes an `OrderStateChanged` event with the
+/// reason for each. The state is written before the event is published, so an event that cannot be published is
+/// lost, unless the publisher spools it.
+pub fn reject_stale_orders<H: HbaseClient>(
+    client: &mut H,
+    tables: &Tables,
+    config: &StaleOrderConfig,
+    routing: &EventRouting,
+    now: i64,
+    batch_size: i32,
+    publisher: &mut dyn EventPublisher,
+) -> Result<StaleOrderReport, OrderServiceError> {
+    // No order placed after the shortest SLA can be stale yet.
+    let shortest = config.restaurant_slas.values().copied().chain([config.default_sla]).min().unwrap_or(config.default_sla);
+    let filter = OrderScanFilter { state: Some(OrderState::Pending), until: Some(now - shortest.as_millis() as i64 + 1), ..Default::default() };
+    let mut stale: Vec<(Order, Duration)> = vec![];
+    let skipped_rows = hbase::scan_orders(client, tables, &filter, batch_size, |scanned| {
+        let sla = config.sla_of(&scanned.order.r_id);
+        if scanned.order.state == OrderState::Pending && scanned.ordertime + sla.as_millis() as i64 <= now {
+            stale.push((scanned.order, sla));
+        }
+        Ok(())
+    })?;
+    let mut report = StaleOrderReport { stale: stale.len(), skipped_rows, ..Default::default() };
+    let rejected = global::meter("order_service").u64_counter("orders.stale.rejected")
+        .with_description("Pending orders rejected after their restaurant SLA ran out")
+        .build();
+    let request_id = format!("stale-orders-{}", uuid::Uuid::new_v4());
+    for (order, sla) in stale {
+        if sagas::load_saga(client, tables, &order.o_id)?.is_some_and(|saga| saga.status == SagaStatus::Running) {
+            tracing::info!(o_id = order.o_id.as_str(), "order has a running saga, leaving it to the saga");
+            report.in_saga += 1;
+            continue;
+        }
+        if !hbase::move_order_state(client, tables, &order.o_id, &OrderState::Pending, &OrderState::Rejected)? {
+            tracing::info!(o_id = order.o_id.as_str(), "order moved on during the sweep, not rejected");
+            continue;
+        }
+        let reason = format!("the restaurant did not answer within {}s", sla.as_secs());
+        tracing::info!(o_id = order.o_id.as_str(), r_id = order.r_id.as_str(), reason = reason.as_str(), "rejecting stale order");
+        let event = OrderStateChanged {
+            o_id: order.o_id,
+            c_id: order.c_id,
+            r_id: order.r_id,
+            state: OrderState::Rejected,
+            changed_at: to_rfc3339(now),
+            reason: Some(reason),
+        };
+        publish_order_state_changed(&event, &request_id, routing, publisher)?;
+        rejected.add(1, &[]);
+        report.rejected += 1;
+    }
+    Ok(report)
+}
+
+/// Sweeps every `sweep_interval` on a background thread. Connects with `connect` when it has no connections,
+/// and drops them after a failed sweep.
+pub fn spawn_sweeper<H, F>(sweeper: StaleOrderSweeper, connect: F) -> thread::JoinHandle<()>
+where
+    H: HbaseClient,
+    F: Fn() -> Result<(H, Box<dyn EventPublisher>), OrderServiceError> + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut connections: Option<(H, Box<dyn EventPublisher>)> = None;
+        loop {
+            thread::sleep(sweeper.config.sweep_interval);
+            let swept = match &mut connections {
+                Some((client, publisher)) => sweeper.sweep_once(client, get_unix_time(), publisher.as_mut()),
+                None => connect().and_then(|(mut client, mut publisher)| {
+                    let swept = sweeper.sweep_once(&mut client, get_unix_time(), publisher.as_mut());
+                    connections = Some((client, publisher));
+                    swept
+                }),
+            };
+            match swept {
+                Ok(Some(report)) if report.stale > 0 => tracing::info!(stale = report.stale, rejected = report.rejected, "stale orders swept"),
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::warn!(error = %e, "stale order sweep failed");
+                    connections = None;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        producers::{event_publisher::{EventHeaders, InMemoryPublisher}, producers::DEFAULT_ORDER_STATE_CHANGED_TOPIC},
+        repository::{fake_hbase::{FakeHbase, FakeServer}, leases::lease_table_spec, sagas::saga_table_spec},
+    };
+
+    const MINUTE: i64 = 60_000;
+    /// 2026-01-01T10:00:00+00:00, the time of every test order.
+    const ORDERTIME: i64 = 1767261600000;
+
+    /// A fake HBase per Thrift API with the order, saga and lease tables, holding `orders`.
+    fn stores(orders: &[Order]) -> Vec<FakeServer> {
+        let tables = Tables::default();
+        let specs = [hbase::order_table_spec(&tables), hbase::customer_index_spec(&tables), saga_table_spec(&tables), lease_table_spec(&tables)];
+        let servers = FakeHbase::each_api_with_tables(&specs);
+        for server in &servers {
+            for order in orders {
+                hbase::add_order(order, &tables, server.connect()).unwrap();
+            }
+        }
+        servers
+    }
+
+    fn state_of(server: &FakeServer, o_id: &str) -> OrderState {
+        hbase::get_order_row(o_id, &Tables::default(), server.connect()).unwrap().state
+    }
+
+    fn order(o_id: &str, r_id: &str, state: OrderState) -> Order {
+        let mut order = Order::new(vec![], "Lyngvej 2, 2800 Lyngby".into(), "Rest 1".into(), "c1".into(), r_id.into(), 2800);
+        order.o_id = o_id.into();
+        order.ordertime = "2026-01-01T10:00:00+00:00".into();
+        order.state = state;
+        order
+    }
+
+    fn config() -> StaleOrderConfig {
+        StaleOrderConfig {
+            default_sla: Duration::from_secs(15 * 60),
+            restaurant_slas: HashMap::from([("slow".to_owned(), Duration::from_secs(60 * 60))]),
+            sweep_interval: Duration::from_secs(60),
+        }
+    }
+
+    fn sweeper(holder: &str) -> StaleOrderSweeper {
+        StaleOrderSweeper { tables: Tables::default(), config: config(), routing: EventRouting::default(), holder: holder.into(), batch_size: 2 }
+    }
+
+    #[test]
+    fn test_rejects_pending_orders_past_their_restaurant_sla() {
+        for server in stores(&[
+            order("late", "r1", OrderState::Pending),
+            order("slow", "slow", OrderState::Pending),
+            order("accepted", "r1", OrderState::Accepted),
+        ]) {
+            let mut client = server.connect();
+            let recorder = InMemoryPublisher::new();
+            let now = ORDERTIME + 30 * MINUTE;
+            let report = reject_stale_orders(&mut client, &Tables::default(), &config(), &EventRouting::default(), now, 2, &mut recorder.clone()).unwrap();
+            assert_eq!(report, StaleOrderReport { stale: 1, rejected: 1, skipped_rows: 0, in_saga: 0 });
+
+            let events = recorder.events_for(DEFAULT_ORDER_STATE_CHANGED_TOPIC);
+            assert_eq!(events.len(), 1);
+            let event: OrderStateChanged = serde_json::from_str(&events[0].json).unwrap();
+            assert_eq!((event.o_id.as_str(), event.state, event.reason.as_deref()), ("late", OrderState::Rejected, Some("the restaurant did not answer within 900s")));
+            assert_eq!(event.changed_at, to_rfc3339(now));
+            assert_eq!(state_of(&server, "late"), OrderState::Rejected);
+            assert_eq!(state_of(&server, "slow"), OrderState::Pending);
+
+            let later = ORDERTIME + 60 * MINUTE;
+            let report = reject_stale_orders(&mut client, &Tables::default(), &config(), &EventRouting::default(), later, 2, &mut recorder.clone()).unwrap();
+            assert_eq!(report, StaleOrderReport { stale: 1, rejected: 1, skipped_rows: 0, in_saga: 0 });
+            assert_eq!(state_of(&server, "slow"), OrderState::Rejected);
+        }
+    }
+
+    #[test]
+    fn test_order_accepted_since_the_scan_is_left_alone() {
+        /// Accepts the other stale orders while the first rejection is published, after they were scanned.
+        struct RestaurantAnswers {
+            client: Box<dyn HbaseClient + Send>,
+            pending: Vec<&'static str>,
+            recorder: InMemoryPublisher,
+        }
+        impl EventPublisher for RestaurantAnswers {
+            fn publish(&mut self, topic: &str, key: Option<&str>, headers: &EventHeaders, json: String) -> Result<(), OrderServiceError> {
+                let event: OrderStateChanged = serde_json::from_str(&json)?;
+                for o_id in self.pending.drain(..).filter(|o_id| *o_id != event.o_id) {
+                    hbase::set_order_state(&mut self.client, &Tables::default(), o_id, &OrderState::Accepted)?;
+                }
+                self.recorder.publish(topic, key, headers, json)
+            }
+        }
+        for server in stores(&[order("o1", "r1", OrderState::Pending), order("o2", "r1", OrderState::Pending)]) {
+            let mut publisher = RestaurantAnswers { client: server.connect(), pending: vec!["o1", "o2"], recorder: InMemoryPublisher::new() };
+            let now = ORDERTIME + 30 * MINUTE;
+            let report = reject_stale_orders(&mut server.connect(), &Tables::default(), &config(), &EventRouting::default(), now, 2, &mut publisher).unwrap();
+            assert_eq!(report, StaleOrderReport { stale: 2, rejected: 1, skipped_rows: 0, in_saga: 0 });
+
+            let events = publisher.recorder.events_for(DEFAULT_ORDER_STATE_CHANGED_TOPIC);
+            assert_eq!(events.len(), 1);
+            let rejected = serde_json::from_str::<OrderStateChanged>(&events[0].json).unwrap().o_id;
+            let accepted = if rejected == "o1" { "o2" } else { "o1" };
+            assert_eq!(state_of(&server, &rejected), OrderState::Rejected);
+            assert_eq!(state_of(&server, accepted), OrderState::Accepted);
+        }
+    }
+
+    #[test]
+    fn test_order_with_a_running_saga_is_left_to_the_saga() {
+        use crate::models::saga::{Saga, StepTimeouts};
+
+        let running = order("running", "r1", OrderState::Pending);
+        let ended = order("ended", "r1", OrderState::Pending);
+        for server in stores(&[running.clone(), ended.clone()]) {
+            let mut client = server.connect();
+            let tables = Tables::default();
+            sagas::save_saga(&mut client, &tables, &Saga::start(&running, ORDERTIME, &StepTimeouts::default()).0, b"").unwrap();
+            let mut compensated = Saga::start(&ended, ORDERTIME, &StepTimeouts::default()).0;
+            compensated.on_timeout(i64::MAX).unwrap();
+            sagas::save_saga(&mut client, &tables, &compensated, b"").unwrap();
+
+            let recorder = InMemoryPublisher::new();
+            let report = reject_stale_orders(&mut client, &tables, &config(), &EventRouting::default(), ORDERTIME + 30 * MINUTE, 2, &mut recorder.clone()).unwrap();
+            assert_eq!(report, StaleOrderReport { stale: 2, rejected: 1, skipped_rows: 0, in_saga: 1 });
+            assert_eq!(state_of(&server, "running"), OrderState::Pending);
+            assert_eq!(state_of(&server, "ended"), OrderState::Rejected);
+        }
+    }
+
+    #[test]
+    fn test_only_the_lease_holder_sweeps() {
+        for server in stores(&[order("late", "r1", OrderState::Pending)]) {
+            let mut client = server.connect();
+            let recorder = InMemoryPublisher::new();
+            let now = ORDERTIME + 30 * MINUTE;
+            let (a, b) = (sweeper("a"), sweeper("b"));
+            assert_eq!(a.sweep_once(&mut client, now, &mut recorder.clone()).unwrap(), Some(StaleOrderReport { stale: 1, rejected: 1, skipped_rows: 0, in_saga: 0 }));
+            assert_eq!(b.sweep_once(&mut client, now + MINUTE, &mut recorder.clone()).unwrap(), None);
+            assert!(a.sweep_once(&mut client, now + MINUTE, &mut recorder.clone()).unwrap().is_some());
+            // a stopped renewing, b takes over once the lease ran out.
+            let expired = now + MINUTE + a.config.lease_duration().as_millis() as i64;
+            assert_eq!(b.sweep_once(&mut client, expired, &mut recorder.clone()).unwrap(), Some(StaleOrderReport::default()));
+            assert_eq!(recorder.events().len(), 1);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use sled::{transaction::{ConflictableTransactionError, TransactionError}, Transactional};
+
+use crate::models::{
+    errors::{OrderServiceError, RowIssue},
+    orders::{DecodeMode, Order, OrderInfo},
+    tables::{TableName, Tables},
+};
+use super::{
+    hbase::{get_unix_time, CUSTOMER_HISTORY_LIMIT},
+    hbase_utils::{customer_index_key, customer_index_prefix, ordertime_millis},
+    order_repository::OrderRepository,
+};
+
+/// Orders in a sled database on local disk, for development and tests without HBase.
+/// Each table is a tree named like the HBase table. Orders are stored as JSON under their o_id,
+/// and the customer index uses the same keys as in HBase, so a prefix scan returns the newest orders first.
+#[derive(Clone)]
+pub struct EmbeddedRepository {
+    db: sled::Db,
+    tables: Tables,
+}
+
+impl EmbeddedRepository {
+    /// Opens the database at `path`. A process holds the database lock, so opening the same path twice
+    /// returns the same database.
+    pub fn open(path: impl AsRef<Path>, tables: Tables) -> Result<Self, OrderServiceError> {
+        static OPEN: OnceLock<Mutex<HashMap<PathBuf, sled::Db>>> = OnceLock::new();
+        let mut open = OPEN.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+        let path = path.as_ref().to_path_buf();
+        let db = match open.get(&path) {
+            Some(db) => db.clone(),
+            None => {
+                let db = sled::open(&path).map_err(storage_error)?;
+                open.insert(path, db.clone());
+                db
+            }
+        };
+        Ok(Self { db, tables })
+    }
+
+    /// A database that is deleted when the last handle to it is dropped.
+    pub fn temporary(tables: Tables) -> Result<Self, OrderServiceError> {
+        let db = sled::Config::new().temporary(true).open().map_err(storage_error)?;
+        Ok(Self { db, tables })
+    }
+
+    fn tree(&self, name: &str) -> Result<sled::Tree, OrderServiceError> {
+        self.db.open_tree(name).map_err(storage_error)
+    }
+
+    /// Moves index entries whose key is not the one their order gets now, such as keys written before the c_id in
+    /// the key was length prefixed.
+    fn rebuild_customer_index(&self) -> Result<(), OrderServiceError> {
+        let orders = self.tree(&self.tables.orders)?;
+        let index = self.tree(&self.tables.customer_index)?;
+        for entry in index.iter() {
+            let (key, o_id) = entry.map_err(storage_error)?;
+            let Some(value) = orders.get(&o_id).map_err(storage_error)? else { continue };
+            let expected = index_key(&serde_json::from_slice(&value)?);
+            if key.as_ref() != expected.as_bytes() {
+                index.insert(expected.as_bytes(), o_id).map_err(storage_error)?;
+                index.remove(key).map_err(storage_error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn index_key(order: &Order) -> String {
+    let ordertime = ordertime_millis(&order.ordertime).unwrap_or_else(get_unix_time);
+    customer_index_key(&order.c_id, ordertime, &order.o_id)
+}
+
+fn storage_error(e: impl std::fmt::Display) -> OrderServiceError {
+    OrderServiceError::StorageError(e.to_string())
+}
+
+impl OrderRepository for EmbeddedRepository {
+    fn add_order(&self, order: &Order) -> Result<String, OrderServiceError> {
+        let orders = self.tree(&self.tables.orders)?;
+        let index = self.tree(&self.tables.customer_index)?;
+        let value = serde_json::to_vec(order)?;
+        let index_key = index_key(order);
+        (&orders, &index).transaction(|(orders, index)| {
+            orders.insert(order.o_id.as_bytes(), value.as_slice())?;
+            index.insert(index_key.as_bytes(), order.o_id.as_bytes())?;
+            Ok::<_, ConflictableTransactionError<OrderServiceError>>(())
+        }).map_err(|e| match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => storage_error(e),
+        })?;
+        self.db.flush().map_err(storage_error)?;
+        Ok(order.o_id.clone())
+    }
+
+    fn get_order(&self, o_id: &str, _mode: DecodeMode) -> Result<(Order, Vec<RowIssue>), OrderServiceError> {
+        // Orders are stored whole, so there is nothing to tolerate in either mode.
+        match self.tree(&self.tables.orders)?.get(o_id).map_err(storage_error)? {
+            Some(value) => Ok((serde_json::from_slice(&value)?, vec![])),
+            None => Err(OrderServiceError::RowNotFound(o_id.to_owned())),
+        }
+    }
+
+    fn get_orders_by_customer(&self, c_id: &str, _mode: DecodeMode) -> Result<(Vec<OrderInfo>, usize), OrderServiceError> {
+        let orders = self.tree(&self.tables.orders)?;
+        let mut infos = vec![];
+        for entry in self.tree(&self.tables.customer_index)?.scan_prefix(customer_index_prefix(c_id)).take(CUSTOMER_HISTORY_LIMIT as usize) {
+            let (_, o_id) = entry.map_err(storage_error)?;
+            // Index entries whose order is gone are skipped, like in HBase.
+            let Some(value) = orders.get(&o_id).map_err(storage_error)? else { continue };
+            let order: Order = serde_json::from_slice(&value)?;
+            if order.c_id != c_id {
+                continue;
+            }
+            infos.push(OrderInfo { o_id: order.o_id, ordertime: order.ordertime, state: order.state, r_id: order.r_id, c_id: order.c_id });
+        }
+        Ok((infos, 0))
+    }
+
+    fn get_tables(&self) -> Result<Vec<TableName>, OrderServiceError> {
+        let default = sled::Tree::name(&self.db);
+        Ok(self.db.tree_names().into_iter()
+            .filter(|name| *name != default)
+            .map(|name| TableName::new(String::from_utf8_lossy(&name).into_owned()))
+            .collect())
+    }
+
+    fn create_tables(&self) -> Result<(), OrderServiceError> {
+        self.tree(&self.tables.orders)?;
+        self.tree(&self.tables.customer_index)?;
+        self.rebuild_customer_index()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::orders::{OrderState, Orderline};
+
+    fn order(c_id: &str, ordertime: &str) -> Order {
+        let mut order = Order::new(vec![Orderline { item_num: 1, price: 250 }], "Lyngvej 2".into(), "Rest 1".into(), c_id.into(), "rest".into(), 2800);
+        order.ordertime = ordertime.into();
+        order.o_id = format!("{}-{}", c_id, ordertime);
+        order
+    }
+
+    #[test]
+    fn test_add_and_get_order() {
+        let repo = EmbeddedRepository::temporary(Tables::default()).unwrap();
+        let order = order("cust", "2024-01-01T10:00:00+00:00");
+        assert_eq!(repo.add_order(&order).unwrap(), order.o_id);
+        let (read, issues) = repo.get_order(&order.o_id, DecodeMode::Strict).unwrap();
+        assert_eq!(read, order);
+        assert!(issues.is_empty());
+    }
+
+    #[test]
+    fn test_embedded_store_keeps_no_sagas() {
+        let repo = EmbeddedRepository::temporary(Tables::default()).unwrap();
+        assert!(!repo.supports_sagas());
+        let mut publisher = crate::producers::event_publisher::InMemoryPublisher::new();
+        let started = repo.start_saga(&order("cust", "2024-01-01T10:00:00+00:00"), &Default::default(), "req-1", &mut publisher);
+        assert!(matches!(started, Err(OrderServiceError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_missing_order_is_not_found() {
+        let repo = EmbeddedRepository::temporary(Tables::default()).unwrap();
+        assert!(matches!(repo.get_order("nope", DecodeMode::Strict), Err(OrderServiceError::RowNotFound(_))));
+    }
+
+    #[test]
+    fn test_orders_by_customer_newest_first() {
+        let repo = EmbeddedRepository::temporary(Tables::default()).unwrap();
+        let older = order("cust", "2024-01-01T10:00:00+00:00");
+        let newer = order("cust", "2024-02-01T10:00:00+00:00");
+        repo.add_order(&older).unwrap();
+        repo.add_order(&newer).unwrap();
+        repo.add_order(&order("cust2", "2024-03-01T10:00:00+00:00")).unwrap();
+
+        let infos = repo.get_orders_by_customer("cust", DecodeMode::Strict).unwrap().0;
+        let ids: Vec<&str> = infos.iter().map(|i| i.o_id.as_str()).collect();
+        assert_eq!(ids, [newer.o_id.as_str(), older.o_id.as_str()]);
+        assert_eq!(infos[0].state, OrderState::Pending);
+        assert!(repo.get_orders_by_customer("other", DecodeMode::Strict).unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn test_orders_by_customer_with_a_shared_prefix() {
+        let repo = EmbeddedRepository::temporary(Tables::default()).unwrap();
+        let a = order("a", "2024-01-01T10:00:00+00:00");
+        let a_b = order("a|b", "2024-01-02T10:00:00+00:00");
+        repo.add_order(&a).unwrap();
+        repo.add_order(&a_b).unwrap();
+        let ids = |c_id: &str| repo.get_orders_by_customer(c_id, DecodeMode::Strict).unwrap().0.into_iter().map(|i| i.o_id).collect::<Vec<_>>();
+        assert_eq!(ids("a"), [a.o_id.as_str()]);
+        assert_eq!(ids("a|b"), [a_b.o_id.as_str()]);
+
+        // An entry of `a|b` in the key format before the length prefix is moved when the tables are opened.
+        let index = repo.tree(&Tables::default().customer_index).unwrap();
+        index.clear().unwrap();
+        index.insert(format!("a|b|{:019}|{}", 0, a_b.o_id).as_bytes(), a_b.o_id.as_bytes()).unwrap();
+        assert!(ids("a").is_empty());
+        repo.create_tables().unwrap();
+        assert_eq!(ids("a|b"), [a_b.o_id.as_str()]);
+        assert!(ids("a").is_empty());
+    }
+
+    #[test]
+    fn test_orders_by_customer_is_limited() {
+        let repo = EmbeddedRepository::temporary(Tables::default()).unwrap();
+        for day in 1..=20 {
+            repo.add_order(&order("cust", &format!("2024-01-{:02}T10:00:00+00:00", day))).unwrap();
+        }
+        assert_eq!(repo.get_orders_by_customer("cust", DecodeMode::Strict).unwrap().0.len(), CUSTOMER_HISTORY_LIMIT as usize);
+    }
+
+    #[test]
+    fn test_tables() {
+        let tables = Tables::default();
+        let repo = EmbeddedRepository::temporary(tables.clone()).unwrap();
+        assert!(repo.get_tables().unwrap().is_empty());
+        repo.create_tables().unwrap();
+        let mut names: Vec<String> = repo.get_tables().unwrap().into_iter().map(|t| t.table_name).collect();
+        names.sort();
+        let mut expected = vec![tables.orders, tables.customer_index];
+        expected.sort();
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn test_open_same_path_twice() {
+        let path = std::env::temp_dir().join(format!("order_service-{}", uuid::Uuid::new_v4()));
+        let first = EmbeddedRepository::open(&path, Tables::default()).unwrap();
+        let order = order("cust", "2024-01-01T10:00:00+00:00");
+        first.add_order(&order).unwrap();
+        let second = EmbeddedRepository::open(&path, Tables::default()).unwrap();
+        assert_eq!(second.get_order(&order.o_id, DecodeMode::Strict).unwrap().0, order);
+        drop((first, second));
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
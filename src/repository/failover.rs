@@ -0,0 +1,394 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use hbase_thrift::{hbase::{BatchMutation, ColumnDescriptor, Mutation, ScannerID, TRowResult, TScan, Text}, Attributes};
+use opentelemetry::{global, metrics::{Counter, Meter}, KeyValue};
+
+use crate::{
+    api::utils::env::{get_probe_interval, get_unhealthy_after},
+    models::{errors::OrderServiceError, schema::TableSpec},
+    repository::{hbase_connection::{connect_client, HbaseClient, ThriftConfig}, resilience::is_transient},
+};
+
+/// Consecutive transient errors after which an endpoint is taken out of rotation.
+pub const DEFAULT_UNHEALTHY_AFTER: u32 = 2;
+/// Time an unhealthy endpoint is left alone before it is probed again.
+pub const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Splits a comma separated list of `host:port` Thrift endpoints.
+pub fn parse_endpoints(hosts: &str) -> Result<Vec<String>, OrderServiceError> {
+    let endpoints: Vec<String> = hosts.split(',').map(str::trim).filter(|h| !h.is_empty()).map(str::to_owned).collect();
+    if endpoints.is_empty() {
+        return Err(OrderServiceError::InvalidConfig(format!("no HBase endpoints in '{}'", hosts)));
+    }
+    if let Some(bad) = endpoints.iter().find(|e| !e.contains(':')) {
+        return Err(OrderServiceError::InvalidConfig(format!("HBase endpoint '{}' is not host:port", bad)));
+    }
+    Ok(endpoints)
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    failures: u32,
+    unhealthy_until: Option<Instant>,
+}
+
+struct Endpoint {
+    addr: String,
+    health: Mutex<Health>,
+}
+
+/// The Thrift endpoints of one HBase cluster and what is known about their health, shared by all connections.
+pub struct EndpointPool {
+    endpoints: Vec<Endpoint>,
+    next: AtomicUsize,
+    unhealthy_after: u32,
+    probe_interval: Duration,
+    transitions: Counter<u64>,
+}
+
+static POOLS: OnceLock<Mutex<HashMap<String, Arc<EndpointPool>>>> = OnceLock::new();
+
+impl EndpointPool {
+    pub fn new(endpoints: Vec<String>, unhealthy_after: u32, probe_interval: Duration, meter: &Meter) -> Self {
+        Self {
+            endpoints: endpoints.into_iter().map(|addr| Endpoint { addr, health: Mutex::new(Health::default()) }).collect(),
+            next: AtomicUsize::new(0),
+            unhealthy_after: unhealthy_after.max(1),
+            probe_interval,
+            transitions: meter.u64_counter("hbase.client.endpoint.transitions")
+                .with_description("HBase Thrift endpoints taken out of or put back into rotation, by the state entered")
+                .build(),
+        }
+    }
+
+    /// The pool for `hosts`, created on first use, so endpoint health is kept across requests.
+    pub fn shared(hosts: &str) -> Result<Arc<EndpointPool>, OrderServiceError> {
+        let mut pools = POOLS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(pool) = pools.get(hosts) {
+            return Ok(Arc::clone(pool));
+        }
+        let pool = Arc::new(EndpointPool::new(parse_endpoints(hosts)?, get_unhealthy_after(), get_probe_interval(), &global::meter("order_service")));
+        pools.insert(hosts.to_owned(), Arc::clone(&pool));
+        Ok(pool)
+    }
+
+    pub fn is_healthy(&self, addr: &str) -> bool {
+        self.endpoints.iter()
+            .filter(|e| e.addr == addr)
+            .all(|e| self.health(e).unhealthy_until.is_none())
+    }
+
+    /// Endpoints to try, in order, with whether each has to be probed first.
+    /// Unhealthy endpoints that are due for a probe come first, so they return to rotation as soon
+    /// as they answer. Each is claimed for one probe interval, so concurrent connections do not all probe it.
+    /// Then come healthy endpoints, rotating the start for every connection so load is spread.
+    /// When nothing else is left, the remaining endpoints are tried anyway, soonest due first,
+    /// rather than failing without trying.
+    fn candidates(&self) -> Vec<(usize, bool)> {
+        let n = self.endpoints.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut candidates = vec![];
+        let mut healthy = vec![];
+        let mut cooling = vec![];
+        for i in (0..n).map(|k| (start + k) % n) {
+            let mut health = self.health(&self.endpoints[i]);
+            match health.unhealthy_until {
+                None => healthy.push((i, false)),
+                Some(until) if until <= now => {
+                    health.unhealthy_until = Some(now + self.probe_interval);
+                    candidates.push((i, true));
+                }
+                Some(until) => cooling.push((until, i)),
+            }
+        }
+        candidates.extend(healthy);
+        if candidates.is_empty() {
+            cooling.sort();
+            candidates.extend(cooling.into_iter().map(|(_, i)| (i, true)));
+        }
+        candidates
+    }
+
+    fn report_success(&self, i: usize) {
+        let endpoint = &self.endpoints[i];
+        let mut health = self.health(endpoint);
+        if health.unhealthy_until.take().is_some() {
+            tracing::info!(endpoint = endpoint.addr.as_str(), "HBase endpoint is healthy again");
+            self.transitions.add(1, &[KeyValue::new("endpoint", endpoint.addr.clone()), KeyValue::new("state", "healthy")]);
+        }
+        health.failures = 0;
+    }
+
+    fn report_failure(&self, i: usize) {
+        let endpoint = &self.endpoints[i];
+        let mut health = self.health(endpoint);
+        health.failures += 1;
+        if health.failures < self.unhealthy_after {
+            return;
+        }
+        if health.unhealthy_until.is_none() {
+            tracing::warn!(endpoint = endpoint.addr.as_str(), failures = health.failures, "HBase endpoint marked unhealthy");
+            self.transitions.add(1, &[KeyValue::new("endpoint", endpoint.addr.clone()), KeyValue::new("state", "unhealthy")]);
+        }
+        health.unhealthy_until = Some(Instant::now() + self.probe_interval);
+    }
+
+    fn health<'a>(&self, endpoint: &'a Endpoint) -> std::sync::MutexGuard<'a, Health> {
+        endpoint.health.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub type Connector<H> = Arc<dyn Fn(&str) -> Result<H, OrderServiceError> + Send + Sync>;
+
+/// An `HbaseClient` over one endpoint of an `EndpointPool`. Transient errors count against the endpoint,
+/// and `reconnect` moves to another endpoint, so retries fail over without the caller noticing.
+pub struct FailoverClient<H: HbaseClient> {
+    pool: Arc<EndpointPool>,
+    connector: Connector<H>,
+    index: usize,
+    inner: H,
+}
+
+impl FailoverClient<Box<dyn HbaseClient + Send>> {
+    /// Connects to one of the comma separated `hosts`, with the client for the API in `config`.
+    pub fn connect(hosts: &str, config: ThriftConfig) -> Result<Self, OrderServiceError> {
+        let pool = EndpointPool::shared(hosts)?;
+        Self::connect_with(pool, Arc::new(move |addr: &str| connect_client(addr, config)))
+    }
+}
+
+impl<H: HbaseClient> FailoverClient<H> {
+    pub fn connect_with(pool: Arc<EndpointPool>, connector: Connector<H>) -> Result<Self, OrderServiceError> {
+        let (index, inner) = open(&pool, &connector, None)?;
+        Ok(Self { pool, connector, index, inner })
+    }
+
+    /// Address of the endpoint currently in use.
+    pub fn endpoint(&self) -> &str {
+        &self.pool.endpoints[self.index].addr
+    }
+
+    fn observe<T>(&self, result: Result<T, OrderServiceError>) -> Result<T, OrderServiceError> {
+        match &result {
+            Err(e) if is_transient(e) => self.pool.report_failure(self.index),
+            _ => self.pool.report_success(self.index),
+        }
+        result
+    }
+}
+
+/// Connects to the first candidate that answers. Unhealthy endpoints must also answer a cheap call
+/// before they are used. `avoid` is only tried when no other endpoint works.
+fn open<H: HbaseClient>(pool: &EndpointPool, connector: &Connector<H>, avoid: Option<usize>) -> Result<(usize, H), OrderServiceError> {
+    let mut candidates = pool.candidates();
+    if let Some(avoid) = avoid {
+        candidates.sort_by_key(|(i, _)| *i == avoid);
+    }
+    let mut last_error = None;
+    for (i, probe) in candidates {
+        let addr = pool.endpoints[i].addr.as_str();
+        let connected = connector(addr).and_then(|mut client| {
+            if probe {
+                tracing::info!(endpoint = addr, "probing HBase endpoint");
+                client.get_table_names()?;
+            }
+            Ok(client)
+        });
+        match connected {
+            Ok(client) => {
+                pool.report_success(i);
+                return Ok((i, client));
+            }
+            Err(e) => {
+                tracing::warn!(endpoint = addr, error = %e, "could not connect to HBase endpoint");
+                pool.report_failure(i);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| OrderServiceError::InvalidConfig("no HBase endpoints configured".to_owned())))
+}
+
+impl<H: HbaseClient> HbaseClient for FailoverClient<H> {
+    fn get_table_names(&mut self) -> Result<Vec<Text>, OrderServiceError> {
+        let result = self.inner.get_table_names();
+        self.observe(result)
+    }
+    fn put(
+        &mut self,
+        table_name: &str,
+        row_batches: Vec<BatchMutation>,
+        timestamp: Option<i64>,
+        attributes: Option<Attributes>,
+    ) -> Result<(), OrderServiceError> {
+        let result = self.inner.put(table_name, row_batches, timestamp, attributes);
+        self.observe(result)
+    }
+    fn create_table(&mut self, spec: &TableSpec) -> Result<(), OrderServiceError> {
+        let result = self.inner.create_table(spec);
+        self.observe(result)
+    }
+    fn table_exists(&mut self, table_name: &str) -> Result<bool, OrderServiceError> {
+        let result = self.inner.table_exists(table_name);
+        self.observe(result)
+    }
+    fn get_column_descriptors(&mut self, table_name: &str) -> Result<BTreeMap<Text, ColumnDescriptor>, OrderServiceError> {
+        let result = self.inner.get_column_descriptors(table_name);
+        self.observe(result)
+    }
+    fn get_row(&mut self, table_name: &str, row_id: &str) -> Result<Vec<TRowResult>, OrderServiceError> {
+        let result = self.inner.get_row(table_name, row_id);
+        self.observe(result)
+    }
+    fn get_rows_with_columns(&mut self, table_name: &str, rows: Vec<Text>, columns: Vec<Text>) -> Result<Vec<TRowResult>, OrderServiceError> {
+        let result = self.inner.get_rows_with_columns(table_name, rows, columns);
+        self.observe(result)
+    }
+    fn scanner_open_with_scan(&mut self, table_name: Text, scan: TScan, attributes: BTreeMap<Text, Text>) -> Result<ScannerID, OrderServiceError> {
+        let result = self.inner.scanner_open_with_scan(table_name, scan, attributes);
+        self.observe(result)
+    }
+    fn scanner_get_list(&mut self, id: ScannerID, nb_rows: i32) -> Result<Vec<TRowResult>, OrderServiceError> {
+        let result = self.inner.scanner_get_list(id, nb_rows);
+        self.observe(result)
+    }
+    fn scanner_close(&mut self, id: ScannerID) -> Result<(), OrderServiceError> {
+        let result = self.inner.scanner_close(id);
+        self.observe(result)
+    }
+    fn check_and_put(&mut self, table_name: &str, row: &str, column: &str, value: &[u8], mutation: Mutation) -> Result<bool, OrderServiceError> {
+        let result = self.inner.check_and_put(table_name, row, column, value, mutation);
+        self.observe(result)
+    }
+    /// Moves to another endpoint, falling back to the current one only when no other endpoint answers.
+    fn reconnect(&mut self) -> Result<(), OrderServiceError> {
+        let (index, inner) = open(&self.pool, &self.connector, Some(self.index))?;
+        if index != self.index {
+            tracing::info!(from = self.endpoint(), to = self.pool.endpoints[index].addr.as_str(), "failing over to another HBase endpoint");
+        }
+        self.index = index;
+        self.inner = inner;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::{hbase_connection::MockHbaseClient, resilience::{Resilience, ResiliencePolicy, ResilientClient}};
+    use std::{collections::HashSet, thread};
+    use thrift::{TransportError, TransportErrorKind};
+
+    fn transport_error() -> OrderServiceError {
+        OrderServiceError::DBError(thrift::Error::Transport(TransportError::new(TransportErrorKind::NotOpen, "connection refused")))
+    }
+
+    fn pool(endpoints: &[&str], probe_interval: Duration) -> Arc<EndpointPool> {
+        Arc::new(EndpointPool::new(endpoints.iter().map(|e| e.to_string()).collect(), 2, probe_interval, &global::meter("test")))
+    }
+
+    /// Endpoints in `down` refuse connections and fail every call; the others answer every call.
+    /// Every connection attempt is logged.
+    fn connector(down: Arc<Mutex<HashSet<String>>>, log: Arc<Mutex<Vec<String>>>) -> Connector<MockHbaseClient> {
+        Arc::new(move |addr: &str| {
+            log.lock().unwrap().push(addr.to_owned());
+            if down.lock().unwrap().contains(addr) {
+                return Err(transport_error());
+            }
+            let down = Arc::clone(&down);
+            let addr = addr.to_owned();
+            let mut mock = MockHbaseClient::new();
+            mock.expect_get_table_names().returning(|| Ok(vec![]));
+            mock.expect_get_row().returning(move |_, _| {
+                if down.lock().unwrap().contains(&addr) { Err(transport_error()) } else { Ok(vec![]) }
+            });
+            Ok(mock)
+        })
+    }
+
+    fn down(endpoints: &[&str]) -> Arc<Mutex<HashSet<String>>> {
+        Arc::new(Mutex::new(endpoints.iter().map(|e| e.to_string()).collect()))
+    }
+
+    #[test]
+    fn test_parse_endpoints() {
+        assert_eq!(parse_endpoints("a:9090, b:9090,").unwrap(), vec!["a:9090", "b:9090"]);
+        assert_eq!(parse_endpoints("a:9090").unwrap(), vec!["a:9090"]);
+        assert!(matches!(parse_endpoints(" , "), Err(OrderServiceError::InvalidConfig(_))));
+        assert!(matches!(parse_endpoints("a:9090,b"), Err(OrderServiceError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_connections_are_balanced() {
+        let pool = pool(&["a:1", "b:1", "c:1"], DEFAULT_PROBE_INTERVAL);
+        let log = Arc::new(Mutex::new(vec![]));
+        let connector = connector(down(&[]), Arc::clone(&log));
+        for _ in 0..6 {
+            FailoverClient::connect_with(Arc::clone(&pool), Arc::clone(&connector)).unwrap();
+        }
+        let log = log.lock().unwrap();
+        for endpoint in ["a:1", "b:1", "c:1"] {
+            assert_eq!(log.iter().filter(|e| *e == endpoint).count(), 2);
+        }
+    }
+
+    #[test]
+    fn test_dead_endpoint_is_skipped_and_taken_out_of_rotation() {
+        let pool = pool(&["a:1", "b:1"], Duration::from_secs(60));
+        let log = Arc::new(Mutex::new(vec![]));
+        let connector = connector(down(&["a:1"]), Arc::clone(&log));
+        for _ in 0..4 {
+            let client = FailoverClient::connect_with(Arc::clone(&pool), Arc::clone(&connector)).unwrap();
+            assert_eq!(client.endpoint(), "b:1");
+        }
+        assert!(!pool.is_healthy("a:1"));
+        // Tried until it reached the threshold of 2 failures, then left alone.
+        assert_eq!(log.lock().unwrap().iter().filter(|e| *e == "a:1").count(), 2);
+    }
+
+    #[test]
+    fn test_unhealthy_endpoint_is_probed_after_interval() {
+        let pool = pool(&["a:1", "b:1"], Duration::from_millis(20));
+        let down = down(&["a:1"]);
+        let connector = connector(Arc::clone(&down), Arc::new(Mutex::new(vec![])));
+        for _ in 0..4 {
+            FailoverClient::connect_with(Arc::clone(&pool), Arc::clone(&connector)).unwrap();
+        }
+        assert!(!pool.is_healthy("a:1"));
+
+        down.lock().unwrap().clear();
+        thread::sleep(Duration::from_millis(30));
+        let clients: Vec<String> = (0..2)
+            .map(|_| FailoverClient::connect_with(Arc::clone(&pool), Arc::clone(&connector)).unwrap().endpoint().to_owned())
+            .collect();
+        assert!(pool.is_healthy("a:1"));
+        assert!(clients.contains(&"a:1".to_owned()));
+    }
+
+    #[test]
+    fn test_all_endpoints_down_fails() {
+        let pool = pool(&["a:1", "b:1"], DEFAULT_PROBE_INTERVAL);
+        let connector = connector(down(&["a:1", "b:1"]), Arc::new(Mutex::new(vec![])));
+        assert!(matches!(FailoverClient::connect_with(pool, connector), Err(OrderServiceError::DBError(_))));
+    }
+
+    #[test]
+    fn test_failover_is_invisible_to_callers() {
+        let pool = pool(&["a:1", "b:1"], DEFAULT_PROBE_INTERVAL);
+        let down = down(&[]);
+        let connector = connector(Arc::clone(&down), Arc::new(Mutex::new(vec![])));
+        let inner = FailoverClient::connect_with(Arc::clone(&pool), connector).unwrap();
+        let first = inner.endpoint().to_owned();
+        let policy = ResiliencePolicy { base_backoff: Duration::from_millis(1), max_backoff: Duration::from_millis(1), ..Default::default() };
+        let mut client = ResilientClient::new(inner, Arc::new(Resilience::new(policy, &global::meter("test"))));
+
+        // The endpoint dies after the connection was made.
+        down.lock().unwrap().insert(first);
+        assert!(client.get_row("orders", "row").is_ok());
+    }
+}
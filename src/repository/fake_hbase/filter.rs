@@ -0,0 +1,222 @@
+//! The part of the HBase filter language the fake understands: `SingleColumnValueFilter`s joined by `AND`, e.g.
+//! `SingleColumnValueFilter('info', 'state', =, 'binary:Pending', true, true)`.
+
+use super::{Bytes, Cell};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompareOp {
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+    GreaterOrEqual,
+    Greater,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Comparator {
+    Binary(Bytes),
+    BinaryPrefix(Bytes),
+    Substring(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SingleColumnValueFilter {
+    family: Bytes,
+    qualifier: Bytes,
+    op: CompareOp,
+    comparator: Comparator,
+    /// Drops rows without the column, which pass otherwise.
+    filter_if_missing: bool,
+}
+
+/// Rows must match every filter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RowFilter(Vec<SingleColumnValueFilter>);
+
+impl RowFilter {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut tokens = tokenize(input)?.into_iter();
+        let mut filters = vec![parse_filter(&mut tokens)?];
+        while let Some(token) = tokens.next() {
+            match token {
+                Token::Word(w) if w.eq_ignore_ascii_case("AND") => filters.push(parse_filter(&mut tokens)?),
+                other => return Err(format!("unsupported filter syntax at {:?}", other)),
+            }
+        }
+        Ok(RowFilter(filters))
+    }
+
+    /// Whether the row with `cells` passes.
+    pub fn matches(&self, cells: &[Cell]) -> bool {
+        self.0.iter().all(|f| {
+            match cells.iter().find(|c| c.family == f.family && c.qualifier == f.qualifier) {
+                Some(cell) => f.comparator.compare(&cell.value, f.op),
+                None => !f.filter_if_missing,
+            }
+        })
+    }
+}
+
+impl Comparator {
+    /// Whether `value op operand` holds.
+    fn compare(&self, value: &[u8], op: CompareOp) -> bool {
+        let ordering = match self {
+            Comparator::Binary(operand) => value.cmp(operand.as_slice()),
+            Comparator::BinaryPrefix(operand) => value[..value.len().min(operand.len())].cmp(operand.as_slice()),
+            Comparator::Substring(operand) => {
+                let found = String::from_utf8_lossy(value).to_lowercase().contains(operand.as_str());
+                return match op {
+                    CompareOp::Equal => found,
+                    CompareOp::NotEqual => !found,
+                    _ => false,
+                };
+            }
+        };
+        match op {
+            CompareOp::Less => ordering.is_lt(),
+            CompareOp::LessOrEqual => ordering.is_le(),
+            CompareOp::Equal => ordering.is_eq(),
+            CompareOp::NotEqual => ordering.is_ne(),
+            CompareOp::GreaterOrEqual => ordering.is_ge(),
+            CompareOp::Greater => ordering.is_gt(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Quoted(Bytes),
+    Op(CompareOp),
+    Open,
+    Close,
+    Comma,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let bytes = input.as_bytes();
+    let mut tokens = vec![];
+    let mut at = 0;
+    while at < bytes.len() {
+        let rest = &input[at..];
+        let c = bytes[at];
+        if c.is_ascii_whitespace() {
+            at += 1;
+        } else if c == b'(' {
+            tokens.push(Token::Open);
+            at += 1;
+        } else if c == b')' {
+            tokens.push(Token::Close);
+            at += 1;
+        } else if c == b',' {
+            tokens.push(Token::Comma);
+            at += 1;
+        } else if c == b'\'' {
+            // A quote inside a string is written twice.
+            let mut value = vec![];
+            at += 1;
+            loop {
+                match (bytes.get(at), bytes.get(at + 1)) {
+                    (Some(b'\''), Some(b'\'')) => { value.push(b'\''); at += 2; }
+                    (Some(b'\''), _) => { at += 1; break; }
+                    (Some(b), _) => { value.push(*b); at += 1; }
+                    (None, _) => return Err("unterminated string".into()),
+                }
+            }
+            tokens.push(Token::Quoted(value));
+        } else if let Some((op, len)) = [("<=", CompareOp::LessOrEqual), (">=", CompareOp::GreaterOrEqual), ("!=", CompareOp::NotEqual), ("<", CompareOp::Less), (">", CompareOp::Greater), ("=", CompareOp::Equal)]
+            .iter().find(|(symbol, _)| rest.starts_with(symbol)).map(|(symbol, op)| (*op, symbol.len()))
+        {
+            tokens.push(Token::Op(op));
+            at += len;
+        } else if c.is_ascii_alphanumeric() {
+            let len = rest.bytes().take_while(|b| b.is_ascii_alphanumeric()).count();
+            tokens.push(Token::Word(rest[..len].to_owned()));
+            at += len;
+        } else {
+            return Err(format!("unexpected '{}'", c as char));
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_filter(tokens: &mut impl Iterator<Item = Token>) -> Result<SingleColumnValueFilter, String> {
+    match tokens.next() {
+        Some(Token::Word(name)) if name == "SingleColumnValueFilter" => {}
+        other => return Err(format!("only SingleColumnValueFilter is supported, got {:?}", other)),
+    }
+    if tokens.next() != Some(Token::Open) {
+        return Err("expected '('".into());
+    }
+    let mut args = vec![];
+    loop {
+        args.push(tokens.next().ok_or("unterminated filter")?);
+        match tokens.next() {
+            Some(Token::Comma) => {}
+            Some(Token::Close) => break,
+            other => return Err(format!("expected ',' or ')', got {:?}", other)),
+        }
+    }
+    let (family, qualifier, op, comparator, flags) = match args.as_slice() {
+        [Token::Quoted(f), Token::Quoted(q), Token::Op(op), Token::Quoted(c), flags @ ..] if flags.is_empty() || flags.len() == 2 => (f, q, op, c, flags),
+        _ => return Err("expected ('family', 'qualifier', op, 'comparator'[, filterIfMissing, latestVersionOnly])".into()),
+    };
+    let filter_if_missing = match flags.first() {
+        Some(Token::Word(w)) => w.parse::<bool>().map_err(|_| format!("expected true or false, got '{}'", w))?,
+        Some(other) => return Err(format!("expected true or false, got {:?}", other)),
+        None => false,
+    };
+    let comparator = String::from_utf8_lossy(comparator).into_owned();
+    let comparator = match comparator.split_once(':') {
+        Some(("binary", value)) => Comparator::Binary(value.as_bytes().to_vec()),
+        Some(("binaryprefix", value)) => Comparator::BinaryPrefix(value.as_bytes().to_vec()),
+        Some(("substring", value)) => Comparator::Substring(value.to_lowercase()),
+        _ => return Err(format!("unsupported comparator '{}'", comparator)),
+    };
+    Ok(SingleColumnValueFilter { family: family.clone(), qualifier: qualifier.clone(), op: *op, comparator, filter_if_missing })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(column: &str, value: &str) -> Cell {
+        let (family, qualifier) = column.split_once(':').unwrap();
+        Cell { family: family.into(), qualifier: qualifier.into(), timestamp: 1, value: value.into() }
+    }
+
+    #[test]
+    fn test_single_column_value_filter() {
+        let filter = RowFilter::parse("SingleColumnValueFilter('info', 'state', =, 'binary:Pending', true, true)").unwrap();
+        assert!(filter.matches(&[cell("info:state", "Pending")]));
+        assert!(!filter.matches(&[cell("info:state", "Rejected")]));
+        assert!(!filter.matches(&[cell("info:other", "Pending")]));
+    }
+
+    #[test]
+    fn test_missing_column_passes_by_default() {
+        let filter = RowFilter::parse("SingleColumnValueFilter('info','state',!=,'binary:Pending')").unwrap();
+        assert!(filter.matches(&[]));
+        assert!(!filter.matches(&[cell("info:state", "Pending")]));
+    }
+
+    #[test]
+    fn test_comparators_and_conjunction() {
+        let filter = RowFilter::parse(
+            "SingleColumnValueFilter('ids','c_id',=,'binaryprefix:cu',true,true) AND SingleColumnValueFilter('info','o_time',<,'binary:2024-02',true,true)",
+        ).unwrap();
+        assert!(filter.matches(&[cell("ids:c_id", "cust"), cell("info:o_time", "2024-01-05")]));
+        assert!(!filter.matches(&[cell("ids:c_id", "cust"), cell("info:o_time", "2024-03-05")]));
+        let substring = RowFilter::parse("SingleColumnValueFilter('addr','c_addr',=,'substring:LYNGBY')").unwrap();
+        assert!(substring.matches(&[cell("addr:c_addr", "Lyngvej 2, 2800 Lyngby")]));
+    }
+
+    #[test]
+    fn test_unsupported_filters_are_rejected() {
+        assert!(RowFilter::parse("PrefixFilter('abc')").is_err());
+        assert!(RowFilter::parse("SingleColumnValueFilter('a','b',=,'regexstring:.*')").is_err());
+        assert!(RowFilter::parse("SingleColumnValueFilter('a','b',=").is_err());
+        assert!(RowFilter::parse("SingleColumnValueFilter('a','b',=,'binary:x') OR SingleColumnValueFilter('a','b',=,'binary:y')").is_err());
+    }
+}
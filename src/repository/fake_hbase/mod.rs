@@ -0,0 +1,359 @@
+//! An in-memory HBase for the tests. The same tables can be served over the Thrift1 API (`thrift1`)
+//! and the Thrift2 API (`thrift2`), each on a random local port, so `HbaseConnection` and
+//! `Thrift2Connection` are tested end-to-end, with the real encoding, without Docker.
+//! Integration tests and other crates get it with the `test-support` feature.
+//!
+//! Only the calls the service makes are implemented. Each cell keeps its newest version only.
+
+mod filter;
+mod thrift1;
+mod thrift2;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+};
+
+use super::hbase_connection::{connect_client, protocols, HbaseClient, ThriftApi, ThriftConfig, ThriftProtocol, ThriftTransport};
+use crate::models::schema::TableSpec;
+use filter::RowFilter;
+
+pub(crate) type Bytes = Vec<u8>;
+
+/// Settings of a column family, as given when the table was created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Family {
+    pub name: Bytes,
+    pub compression: String,
+    pub bloom_filter_type: String,
+    pub max_versions: i32,
+    pub time_to_live: i32,
+    pub in_memory: bool,
+    pub block_cache_enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Cell {
+    pub family: Bytes,
+    pub qualifier: Bytes,
+    pub timestamp: i64,
+    pub value: Bytes,
+}
+
+/// A whole family when `qualifier` is `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Column {
+    pub family: Bytes,
+    pub qualifier: Option<Bytes>,
+}
+
+impl Column {
+    /// Parses the Thrift1 form, `family:qualifier`, or `family` / `family:` for a whole family.
+    pub fn parse(column: &[u8]) -> Self {
+        match column.iter().position(|b| *b == b':') {
+            Some(at) if at + 1 < column.len() => Column { family: column[..at].to_vec(), qualifier: Some(column[at + 1..].to_vec()) },
+            Some(at) => Column { family: column[..at].to_vec(), qualifier: None },
+            None => Column { family: column.to_vec(), qualifier: None },
+        }
+    }
+
+    fn selects(&self, family: &[u8], qualifier: &[u8]) -> bool {
+        self.family == family && self.qualifier.as_deref().is_none_or(|q| q == qualifier)
+    }
+}
+
+/// Which cells of a row to read. No columns means all of them.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Selection {
+    pub columns: Vec<Column>,
+    /// Only cells written before this timestamp.
+    pub before: Option<i64>,
+}
+
+impl Selection {
+    fn selects(&self, family: &[u8], qualifier: &[u8], timestamp: i64) -> bool {
+        (self.columns.is_empty() || self.columns.iter().any(|c| c.selects(family, qualifier)))
+            && self.before.is_none_or(|before| timestamp < before)
+    }
+}
+
+/// Rows from `start` (inclusive) to `stop` (exclusive), matching `filter`.
+#[derive(Debug, Default)]
+pub(crate) struct Scan {
+    pub start: Option<Bytes>,
+    pub stop: Option<Bytes>,
+    pub selection: Selection,
+    pub filter: Option<RowFilter>,
+    pub reversed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum StoreError {
+    TableNotFound(Bytes),
+    TableExists(Bytes),
+    NoSuchFamily(Bytes),
+    InvalidScanner(i32),
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::TableNotFound(name) => write!(f, "TableNotFoundException: {}", String::from_utf8_lossy(name)),
+            StoreError::TableExists(name) => write!(f, "TableExistsException: {}", String::from_utf8_lossy(name)),
+            StoreError::NoSuchFamily(name) => write!(f, "NoSuchColumnFamilyException: {}", String::from_utf8_lossy(name)),
+            StoreError::InvalidScanner(id) => write!(f, "invalid scanner id {}", id),
+        }
+    }
+}
+
+/// `(family, qualifier) -> (timestamp, value)`.
+type Row = BTreeMap<(Bytes, Bytes), (i64, Bytes)>;
+
+struct Table {
+    families: Vec<Family>,
+    split_keys: Vec<Bytes>,
+    rows: BTreeMap<Bytes, Row>,
+}
+
+impl Table {
+    fn put(&mut self, row: &[u8], cell: Cell) -> Result<(), StoreError> {
+        if !self.families.iter().any(|f| f.name == cell.family) {
+            return Err(StoreError::NoSuchFamily(cell.family));
+        }
+        let cells = self.rows.entry(row.to_vec()).or_default();
+        let key = (cell.family, cell.qualifier);
+        if cells.get(&key).is_none_or(|(existing, _)| *existing <= cell.timestamp) {
+            cells.insert(key, (cell.timestamp, cell.value));
+        }
+        Ok(())
+    }
+
+    fn cells(&self, row: &[u8], selection: &Selection) -> Vec<Cell> {
+        self.rows.get(row).into_iter().flatten()
+            .filter(|((family, qualifier), (timestamp, _))| selection.selects(family, qualifier, *timestamp))
+            .map(|((family, qualifier), (timestamp, value))| Cell {
+                family: family.clone(),
+                qualifier: qualifier.clone(),
+                timestamp: *timestamp,
+                value: value.clone(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Store {
+    tables: BTreeMap<Bytes, Table>,
+    scanners: HashMap<i32, Vec<(Bytes, Vec<Cell>)>>,
+    next_scanner: i32,
+}
+
+impl Store {
+    fn table(&self, name: &[u8]) -> Result<&Table, StoreError> {
+        self.tables.get(name).ok_or_else(|| StoreError::TableNotFound(name.to_vec()))
+    }
+
+    fn table_mut(&mut self, name: &[u8]) -> Result<&mut Table, StoreError> {
+        self.tables.get_mut(name).ok_or_else(|| StoreError::TableNotFound(name.to_vec()))
+    }
+}
+
+/// A fake HBase served over one Thrift API.
+#[derive(Debug, Clone)]
+pub struct FakeServer {
+    pub api: ThriftApi,
+    pub url: String,
+}
+
+impl FakeServer {
+    /// A new connection with the client for the API.
+    pub fn connect(&self) -> Box<dyn HbaseClient + Send> {
+        connect_client(&self.url, ThriftConfig { api: self.api, ..ThriftConfig::default() }).unwrap()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct FakeHbase {
+    store: Arc<Mutex<Store>>,
+}
+
+impl FakeHbase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A fake HBase holding the empty tables of `specs`, pre-split as they say, whichever API serves it.
+    pub fn with_tables(specs: &[TableSpec]) -> Self {
+        let fake = Self::new();
+        for spec in specs {
+            let families = spec.families.iter().map(|f| Family {
+                name: f.name.clone().into_bytes(),
+                compression: f.compression.clone(),
+                bloom_filter_type: f.bloom_filter_type.clone(),
+                max_versions: f.max_versions,
+                time_to_live: f.time_to_live,
+                in_memory: f.in_memory,
+                block_cache_enabled: true,
+            }).collect();
+            let split_keys = spec.split_keys.iter().map(|k| k.clone().into_bytes()).collect();
+            fake.create_table(spec.name.as_bytes(), families, split_keys).unwrap();
+        }
+        fake
+    }
+
+    /// Serves a fake HBase holding the tables of `specs` over Thrift1, buffered and binary, and returns its `host:port`.
+    pub fn serve_with_tables(specs: &[TableSpec]) -> String {
+        Self::with_tables(specs).serve_thrift1(ThriftTransport::Buffered, ThriftProtocol::Binary)
+    }
+
+    /// One fake HBase holding the tables of `specs` per Thrift API, so a test suite runs against both clients.
+    pub fn each_api_with_tables(specs: &[TableSpec]) -> Vec<FakeServer> {
+        [ThriftApi::Thrift1, ThriftApi::Thrift2].into_iter().map(|api| Self::with_tables(specs).serve(api)).collect()
+    }
+
+    /// Serves `api` over the buffered transport and binary protocol.
+    pub fn serve(&self, api: ThriftApi) -> FakeServer {
+        let url = match api {
+            ThriftApi::Thrift1 => self.serve_thrift1(ThriftTransport::Buffered, ThriftProtocol::Binary),
+            ThriftApi::Thrift2 => self.serve_thrift2(),
+        };
+        FakeServer { api, url }
+    }
+
+    /// Serves the Thrift1 API with `transport` and `protocol`, and returns its `host:port`. HTTP is not supported.
+    pub fn serve_thrift1(&self, transport: ThriftTransport, protocol: ThriftProtocol) -> String {
+        assert_ne!(transport, ThriftTransport::Http, "the fake HBase does not serve HTTP");
+        let fake = self.clone();
+        serve(move |stream| thrift1::serve(&fake, stream, transport, protocol))
+    }
+
+    /// Serves the Thrift2 API over the buffered transport and binary protocol, and returns its `host:port`.
+    pub fn serve_thrift2(&self) -> String {
+        let fake = self.clone();
+        serve(move |stream| thrift2::serve(&fake, stream))
+    }
+
+    fn store(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn table_names(&self) -> Vec<Vec<u8>> {
+        self.store().tables.keys().cloned().collect()
+    }
+
+    pub(crate) fn families(&self, table: &[u8]) -> Result<Vec<Family>, StoreError> {
+        Ok(self.store().table(table)?.families.clone())
+    }
+
+    /// Split keys `table` was created with.
+    pub fn split_keys(&self, table: &str) -> Vec<Vec<u8>> {
+        self.store().tables.get(table.as_bytes()).map(|t| t.split_keys.clone()).unwrap_or_default()
+    }
+
+    pub(crate) fn create_table(&self, name: &[u8], families: Vec<Family>, split_keys: Vec<Bytes>) -> Result<(), StoreError> {
+        let mut store = self.store();
+        if store.tables.contains_key(name) {
+            return Err(StoreError::TableExists(name.to_vec()));
+        }
+        store.tables.insert(name.to_vec(), Table { families, split_keys, rows: BTreeMap::new() });
+        Ok(())
+    }
+
+    /// Writes a cell, unless it already has a newer version.
+    pub(crate) fn put(&self, table: &[u8], row: &[u8], family: &[u8], qualifier: &[u8], timestamp: i64, value: Bytes) -> Result<(), StoreError> {
+        self.store().table_mut(table)?.put(row, Cell { family: family.to_vec(), qualifier: qualifier.to_vec(), timestamp, value })
+    }
+
+    /// Writes `cell` only if the `family:qualifier` of `checked` holds `expected`, or is absent for an empty `expected`.
+    pub(crate) fn check_and_put(&self, table: &[u8], row: &[u8], checked: (&[u8], &[u8]), expected: &[u8], cell: Cell) -> Result<bool, StoreError> {
+        let mut store = self.store();
+        let table = store.table_mut(table)?;
+        let current = table.rows.get(row).and_then(|cells| cells.get(&(checked.0.to_vec(), checked.1.to_vec()))).map(|(_, value)| value.as_slice());
+        let matches = match current {
+            Some(value) => !expected.is_empty() && value == expected,
+            None => expected.is_empty(),
+        };
+        if matches {
+            table.put(row, cell)?;
+        }
+        Ok(matches)
+    }
+
+    /// Deletes the cells of `column` written at or before `up_to`, or the whole row without a column.
+    pub(crate) fn delete(&self, table: &[u8], row: &[u8], column: Option<&Column>, up_to: Option<i64>) -> Result<(), StoreError> {
+        let mut store = self.store();
+        let table = store.table_mut(table)?;
+        if let Some(cells) = table.rows.get_mut(row) {
+            cells.retain(|(family, qualifier), (timestamp, _)| {
+                let selected = column.is_none_or(|c| c.selects(family, qualifier));
+                !(selected && up_to.is_none_or(|up_to| *timestamp <= up_to))
+            });
+            if cells.is_empty() {
+                table.rows.remove(row);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, table: &[u8], row: &[u8], selection: &Selection) -> Result<Vec<Cell>, StoreError> {
+        Ok(self.store().table(table)?.cells(row, selection))
+    }
+
+    /// Runs the scan up front and keeps the rows for `scanner_next`.
+    pub(crate) fn open_scanner(&self, table: &[u8], scan: Scan) -> Result<i32, StoreError> {
+        let mut store = self.store();
+        let table = store.table(table)?;
+        let mut rows: Vec<(Bytes, Vec<Cell>)> = table.rows.iter()
+            .filter(|(row, _)| scan.start.as_ref().is_none_or(|start| start.is_empty() || *row >= start))
+            .filter(|(row, _)| scan.stop.as_ref().is_none_or(|stop| stop.is_empty() || *row < stop))
+            .filter(|(row, _)| scan.filter.as_ref().is_none_or(|f| f.matches(&table.cells(row, &Selection::default()))))
+            .map(|(row, _)| (row.clone(), table.cells(row, &scan.selection)))
+            .filter(|(_, cells)| !cells.is_empty())
+            .collect();
+        if scan.reversed {
+            rows.reverse();
+        }
+        store.next_scanner += 1;
+        let id = store.next_scanner;
+        store.scanners.insert(id, rows);
+        Ok(id)
+    }
+
+    /// The next `count` rows of the scanner, none when it is exhausted.
+    pub(crate) fn scanner_next(&self, id: i32, count: i32) -> Result<Vec<(Bytes, Vec<Cell>)>, StoreError> {
+        let mut store = self.store();
+        let rows = store.scanners.get_mut(&id).ok_or(StoreError::InvalidScanner(id))?;
+        let count = rows.len().min(count.max(0) as usize);
+        Ok(rows.drain(..count).collect())
+    }
+
+    pub(crate) fn close_scanner(&self, id: i32) -> Result<(), StoreError> {
+        self.store().scanners.remove(&id).map(|_| ()).ok_or(StoreError::InvalidScanner(id))
+    }
+
+    /// Scanners opened and not closed yet.
+    pub fn open_scanners(&self) -> usize {
+        self.store().scanners.len()
+    }
+}
+
+/// Accepts connections on a random local port, each served on its own thread by `serve`.
+fn serve(serve: impl Fn(TcpStream) + Send + Sync + Clone + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let serve = serve.clone();
+            thread::spawn(move || serve(stream));
+        }
+    });
+    url
+}
+
+/// The input and output protocol of a server connection.
+fn server_protocols(stream: TcpStream, transport: ThriftTransport, protocol: ThriftProtocol) -> Option<(super::hbase_connection::InputProtocol, super::hbase_connection::OutputProtocol)> {
+    let read_half = stream.try_clone().ok()?;
+    Some(protocols(Box::new(read_half), Box::new(stream), transport, protocol))
+}
@@ -0,0 +1,272 @@
+//! The Thrift1 API of the fake, through the processor generated in `hbase-thrift`.
+
+use std::{collections::BTreeMap, net::TcpStream};
+
+use hbase_thrift::hbase::{
+    AlreadyExists, BatchMutation, ColumnDescriptor, HbaseSyncHandler, HbaseSyncProcessor, IOError, IllegalArgument, Mutation,
+    ScannerID, TAccessControlEntity, TAppend, TCell, TIncrement, TRegionInfo, TRowResult, TScan, TThriftServerType, Text,
+};
+use thrift::server::TProcessor;
+
+use crate::repository::hbase_connection::{ThriftProtocol, ThriftTransport};
+use super::{filter::RowFilter, server_protocols, Cell, Column, Family, FakeHbase, Scan, Selection, StoreError};
+
+pub(super) fn serve(fake: &FakeHbase, stream: TcpStream, transport: ThriftTransport, protocol: ThriftProtocol) {
+    let Some((mut i_prot, mut o_prot)) = server_protocols(stream, transport, protocol) else { return };
+    let processor = HbaseSyncProcessor::new(Thrift1Handler { fake: fake.clone() });
+    while processor.process(&mut *i_prot, &mut *o_prot).is_ok() {}
+}
+
+struct Thrift1Handler {
+    fake: FakeHbase,
+}
+
+fn io_error(message: impl ToString) -> thrift::Error {
+    thrift::Error::User(Box::new(IOError { message: Some(message.to_string()), can_retry: Some(false) }))
+}
+
+fn illegal_argument(message: impl ToString) -> thrift::Error {
+    thrift::Error::User(Box::new(IllegalArgument { message: Some(message.to_string()) }))
+}
+
+fn unsupported<T>(name: &str) -> thrift::Result<T> {
+    Err(io_error(format!("{} is not supported by the fake HBase", name)))
+}
+
+impl From<StoreError> for thrift::Error {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::TableExists(_) => thrift::Error::User(Box::new(AlreadyExists { message: Some(e.to_string()) })),
+            StoreError::InvalidScanner(_) => illegal_argument(e),
+            StoreError::TableNotFound(_) | StoreError::NoSuchFamily(_) => io_error(e),
+        }
+    }
+}
+
+/// Families are named `family:` over Thrift1.
+fn family_name(name: &[u8]) -> Text {
+    name.strip_suffix(b":").unwrap_or(name).to_vec()
+}
+
+fn row_results(row: Text, cells: Vec<Cell>) -> Vec<TRowResult> {
+    if cells.is_empty() {
+        return vec![];
+    }
+    let columns = cells.into_iter()
+        .map(|c| ([c.family.as_slice(), b":", c.qualifier.as_slice()].concat(), TCell { value: Some(c.value), timestamp: Some(c.timestamp) }))
+        .collect();
+    vec![TRowResult { row: Some(row), columns: Some(columns), sorted_columns: None }]
+}
+
+fn selection(columns: &[Text], before: Option<i64>) -> Selection {
+    Selection { columns: columns.iter().map(|c| Column::parse(c)).collect(), before }
+}
+
+impl Thrift1Handler {
+    fn get_rows(&self, table_name: &[u8], rows: Vec<Text>, selection: &Selection) -> thrift::Result<Vec<TRowResult>> {
+        let mut results = vec![];
+        for row in rows {
+            let cells = self.fake.get(table_name, &row, selection)?;
+            results.extend(row_results(row, cells));
+        }
+        Ok(results)
+    }
+
+    /// Puts and deletes at `timestamp`, or now. Deletes remove the versions up to the timestamp.
+    fn mutate(&self, table_name: &[u8], row: &[u8], mutations: Vec<Mutation>, timestamp: Option<i64>) -> thrift::Result<()> {
+        let now = crate::repository::hbase::get_unix_time();
+        for mutation in mutations {
+            let column = Column::parse(&mutation.column.unwrap_or_default());
+            if mutation.is_delete == Some(true) {
+                self.fake.delete(table_name, row, Some(&column), timestamp)?;
+            } else {
+                let qualifier = column.qualifier.unwrap_or_default();
+                self.fake.put(table_name, row, &column.family, &qualifier, timestamp.unwrap_or(now), mutation.value.unwrap_or_default())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn mutate_batches(&self, table_name: &[u8], row_batches: Vec<BatchMutation>, timestamp: Option<i64>) -> thrift::Result<()> {
+        for batch in row_batches {
+            self.mutate(table_name, &batch.row.unwrap_or_default(), batch.mutations.unwrap_or_default(), timestamp)?;
+        }
+        Ok(())
+    }
+
+    fn open_scanner(&self, table_name: &[u8], scan: Scan) -> thrift::Result<ScannerID> {
+        Ok(self.fake.open_scanner(table_name, scan)?)
+    }
+}
+
+impl HbaseSyncHandler for Thrift1Handler {
+    fn handle_enable_table(&self, _table_name: Vec<u8>) -> thrift::Result<()> { unsupported("enableTable") }
+    fn handle_disable_table(&self, _table_name: Vec<u8>) -> thrift::Result<()> { unsupported("disableTable") }
+    fn handle_is_table_enabled(&self, table_name: Vec<u8>) -> thrift::Result<bool> {
+        Ok(self.fake.table_names().contains(&table_name))
+    }
+    fn handle_compact(&self, _table_name_or_region_name: Vec<u8>) -> thrift::Result<()> { Ok(()) }
+    fn handle_major_compact(&self, _table_name_or_region_name: Vec<u8>) -> thrift::Result<()> { Ok(()) }
+
+    fn handle_get_table_names(&self) -> thrift::Result<Vec<Text>> {
+        Ok(self.fake.table_names())
+    }
+
+    fn handle_get_table_names_with_is_table_enabled(&self) -> thrift::Result<BTreeMap<Text, bool>> {
+        Ok(self.fake.table_names().into_iter().map(|name| (name, true)).collect())
+    }
+
+    fn handle_get_column_descriptors(&self, table_name: Text) -> thrift::Result<BTreeMap<Text, ColumnDescriptor>> {
+        Ok(self.fake.families(&table_name)?.into_iter().map(|family| {
+            let name: Text = [family.name.as_slice(), b":"].concat();
+            let descriptor = ColumnDescriptor {
+                name: Some(name.clone()),
+                max_versions: Some(family.max_versions),
+                compression: Some(family.compression),
+                in_memory: Some(family.in_memory),
+                bloom_filter_type: Some(family.bloom_filter_type),
+                bloom_filter_vector_size: Some(0),
+                bloom_filter_nb_hashes: Some(0),
+                block_cache_enabled: Some(family.block_cache_enabled),
+                time_to_live: Some(family.time_to_live),
+            };
+            (name, descriptor)
+        }).collect())
+    }
+
+    fn handle_get_table_regions(&self, _table_name: Text) -> thrift::Result<Vec<TRegionInfo>> { unsupported("getTableRegions") }
+
+    fn handle_create_table(&self, table_name: Text, column_families: Vec<ColumnDescriptor>) -> thrift::Result<()> {
+        if column_families.is_empty() {
+            return Err(illegal_argument("a table needs at least one column family"));
+        }
+        let families = column_families.into_iter().map(|d| Family {
+            name: family_name(&d.name.unwrap_or_default()),
+            compression: d.compression.unwrap_or_else(|| "NONE".into()),
+            bloom_filter_type: d.bloom_filter_type.unwrap_or_else(|| "NONE".into()),
+            max_versions: d.max_versions.unwrap_or(3),
+            time_to_live: d.time_to_live.unwrap_or(i32::MAX),
+            in_memory: d.in_memory.unwrap_or(false),
+            block_cache_enabled: d.block_cache_enabled.unwrap_or(true),
+        }).collect();
+        Ok(self.fake.create_table(&table_name, families, vec![])?)
+    }
+
+    fn handle_delete_table(&self, _table_name: Text) -> thrift::Result<()> { unsupported("deleteTable") }
+
+    fn handle_get(&self, table_name: Text, row: Text, column: Text, _attributes: BTreeMap<Text, Text>) -> thrift::Result<Vec<TCell>> {
+        let cells = self.fake.get(&table_name, &row, &selection(&[column], None))?;
+        Ok(cells.into_iter().map(|c| TCell { value: Some(c.value), timestamp: Some(c.timestamp) }).collect())
+    }
+    fn handle_get_ver(&self, _table_name: Text, _row: Text, _column: Text, _num_versions: i32, _attributes: BTreeMap<Text, Text>) -> thrift::Result<Vec<TCell>> { unsupported("getVer") }
+    fn handle_get_ver_ts(&self, _table_name: Text, _row: Text, _column: Text, _timestamp: i64, _num_versions: i32, _attributes: BTreeMap<Text, Text>) -> thrift::Result<Vec<TCell>> { unsupported("getVerTs") }
+
+    fn handle_get_row(&self, table_name: Text, row: Text, _attributes: BTreeMap<Text, Text>) -> thrift::Result<Vec<TRowResult>> {
+        self.get_rows(&table_name, vec![row], &Selection::default())
+    }
+    fn handle_get_row_with_columns(&self, table_name: Text, row: Text, columns: Vec<Text>, _attributes: BTreeMap<Text, Text>) -> thrift::Result<Vec<TRowResult>> {
+        self.get_rows(&table_name, vec![row], &selection(&columns, None))
+    }
+    fn handle_get_row_ts(&self, table_name: Text, row: Text, timestamp: i64, _attributes: BTreeMap<Text, Text>) -> thrift::Result<Vec<TRowResult>> {
+        self.get_rows(&table_name, vec![row], &selection(&[], Some(timestamp)))
+    }
+    fn handle_get_row_with_columns_ts(&self, table_name: Text, row: Text, columns: Vec<Text>, timestamp: i64, _attributes: BTreeMap<Text, Text>) -> thrift::Result<Vec<TRowResult>> {
+        self.get_rows(&table_name, vec![row], &selection(&columns, Some(timestamp)))
+    }
+    fn handle_get_rows(&self, table_name: Text, rows: Vec<Text>, _attributes: BTreeMap<Text, Text>) -> thrift::Result<Vec<TRowResult>> {
+        self.get_rows(&table_name, rows, &Selection::default())
+    }
+    fn handle_get_rows_with_columns(&self, table_name: Text, rows: Vec<Text>, columns: Vec<Text>, _attributes: BTreeMap<Text, Text>) -> thrift::Result<Vec<TRowResult>> {
+        self.get_rows(&table_name, rows, &selection(&columns, None))
+    }
+    fn handle_get_rows_ts(&self, table_name: Text, rows: Vec<Text>, timestamp: i64, _attributes: BTreeMap<Text, Text>) -> thrift::Result<Vec<TRowResult>> {
+        self.get_rows(&table_name, rows, &selection(&[], Some(timestamp)))
+    }
+    fn handle_get_rows_with_columns_ts(&self, table_name: Text, rows: Vec<Text>, columns: Vec<Text>, timestamp: i64, _attributes: BTreeMap<Text, Text>) -> thrift::Result<Vec<TRowResult>> {
+        self.get_rows(&table_name, rows, &selection(&columns, Some(timestamp)))
+    }
+
+    fn handle_mutate_row(&self, table_name: Text, row: Text, mutations: Vec<Mutation>, _attributes: BTreeMap<Text, Text>) -> thrift::Result<()> {
+        self.mutate(&table_name, &row, mutations, None)
+    }
+    fn handle_mutate_row_ts(&self, table_name: Text, row: Text, mutations: Vec<Mutation>, timestamp: i64, _attributes: BTreeMap<Text, Text>) -> thrift::Result<()> {
+        self.mutate(&table_name, &row, mutations, Some(timestamp))
+    }
+    fn handle_mutate_rows(&self, table_name: Text, row_batches: Vec<BatchMutation>, _attributes: BTreeMap<Text, Text>) -> thrift::Result<()> {
+        self.mutate_batches(&table_name, row_batches, None)
+    }
+    fn handle_mutate_rows_ts(&self, table_name: Text, row_batches: Vec<BatchMutation>, timestamp: i64, _attributes: BTreeMap<Text, Text>) -> thrift::Result<()> {
+        self.mutate_batches(&table_name, row_batches, Some(timestamp))
+    }
+
+    fn handle_atomic_increment(&self, _table_name: Text, _row: Text, _column: Text, _value: i64) -> thrift::Result<i64> { unsupported("atomicIncrement") }
+    fn handle_delete_all(&self, table_name: Text, row: Text, column: Text, _attributes: BTreeMap<Text, Text>) -> thrift::Result<()> {
+        Ok(self.fake.delete(&table_name, &row, Some(&Column::parse(&column)), None)?)
+    }
+    fn handle_delete_all_ts(&self, table_name: Text, row: Text, column: Text, timestamp: i64, _attributes: BTreeMap<Text, Text>) -> thrift::Result<()> {
+        Ok(self.fake.delete(&table_name, &row, Some(&Column::parse(&column)), Some(timestamp))?)
+    }
+    fn handle_delete_all_row(&self, table_name: Text, row: Text, _attributes: BTreeMap<Text, Text>) -> thrift::Result<()> {
+        Ok(self.fake.delete(&table_name, &row, None, None)?)
+    }
+    fn handle_increment(&self, _increment: TIncrement) -> thrift::Result<()> { unsupported("increment") }
+    fn handle_increment_rows(&self, _increments: Vec<TIncrement>) -> thrift::Result<()> { unsupported("incrementRows") }
+    fn handle_delete_all_row_ts(&self, table_name: Text, row: Text, timestamp: i64, _attributes: BTreeMap<Text, Text>) -> thrift::Result<()> {
+        Ok(self.fake.delete(&table_name, &row, None, Some(timestamp))?)
+    }
+
+    fn handle_scanner_open_with_scan(&self, table_name: Text, scan: TScan, _attributes: BTreeMap<Text, Text>) -> thrift::Result<ScannerID> {
+        let filter = match scan.filter_string.filter(|f| !f.is_empty()) {
+            Some(filter) => Some(RowFilter::parse(&String::from_utf8_lossy(&filter)).map_err(io_error)?),
+            None => None,
+        };
+        self.open_scanner(&table_name, Scan {
+            start: scan.start_row,
+            stop: scan.stop_row,
+            selection: selection(&scan.columns.unwrap_or_default(), scan.timestamp),
+            filter,
+            reversed: scan.reversed.unwrap_or(false),
+        })
+    }
+    fn handle_scanner_open(&self, table_name: Text, start_row: Text, columns: Vec<Text>, _attributes: BTreeMap<Text, Text>) -> thrift::Result<ScannerID> {
+        self.open_scanner(&table_name, Scan { start: Some(start_row), selection: selection(&columns, None), ..Default::default() })
+    }
+    fn handle_scanner_open_with_stop(&self, table_name: Text, start_row: Text, stop_row: Text, columns: Vec<Text>, _attributes: BTreeMap<Text, Text>) -> thrift::Result<ScannerID> {
+        self.open_scanner(&table_name, Scan { start: Some(start_row), stop: Some(stop_row), selection: selection(&columns, None), ..Default::default() })
+    }
+    fn handle_scanner_open_with_prefix(&self, _table_name: Text, _start_and_prefix: Text, _columns: Vec<Text>, _attributes: BTreeMap<Text, Text>) -> thrift::Result<ScannerID> { unsupported("scannerOpenWithPrefix") }
+    fn handle_scanner_open_ts(&self, table_name: Text, start_row: Text, columns: Vec<Text>, timestamp: i64, _attributes: BTreeMap<Text, Text>) -> thrift::Result<ScannerID> {
+        self.open_scanner(&table_name, Scan { start: Some(start_row), selection: selection(&columns, Some(timestamp)), ..Default::default() })
+    }
+    fn handle_scanner_open_with_stop_ts(&self, table_name: Text, start_row: Text, stop_row: Text, columns: Vec<Text>, timestamp: i64, _attributes: BTreeMap<Text, Text>) -> thrift::Result<ScannerID> {
+        self.open_scanner(&table_name, Scan { start: Some(start_row), stop: Some(stop_row), selection: selection(&columns, Some(timestamp)), ..Default::default() })
+    }
+
+    fn handle_scanner_get(&self, id: ScannerID) -> thrift::Result<Vec<TRowResult>> {
+        self.handle_scanner_get_list(id, 1)
+    }
+    fn handle_scanner_get_list(&self, id: ScannerID, nb_rows: i32) -> thrift::Result<Vec<TRowResult>> {
+        Ok(self.fake.scanner_next(id, nb_rows)?.into_iter().flat_map(|(row, cells)| row_results(row, cells)).collect())
+    }
+    fn handle_scanner_close(&self, id: ScannerID) -> thrift::Result<()> {
+        Ok(self.fake.close_scanner(id)?)
+    }
+
+    fn handle_get_region_info(&self, _row: Text) -> thrift::Result<TRegionInfo> { unsupported("getRegionInfo") }
+    fn handle_append(&self, _append: TAppend) -> thrift::Result<Vec<TCell>> { unsupported("append") }
+    fn handle_check_and_put(&self, table_name: Text, row: Text, column: Text, value: Text, mput: Mutation, _attributes: BTreeMap<Text, Text>) -> thrift::Result<bool> {
+        let checked = Column::parse(&column);
+        let written = Column::parse(&mput.column.unwrap_or_default());
+        let cell = Cell {
+            family: written.family,
+            qualifier: written.qualifier.unwrap_or_default(),
+            timestamp: crate::repository::hbase::get_unix_time(),
+            value: mput.value.unwrap_or_default(),
+        };
+        Ok(self.fake.check_and_put(&table_name, &row, (&checked.family, &checked.qualifier.unwrap_or_default()), &value, cell)?)
+    }
+    fn handle_get_thrift_server_type(&self) -> thrift::Result<TThriftServerType> { Ok(TThriftServerType::ONE) }
+    fn handle_get_cluster_id(&self) -> thrift::Result<String> { Ok("fake-hbase".into()) }
+    fn handle_grant(&self, _info: TAccessControlEntity) -> thrift::Result<bool> { unsupported("grant") }
+    fn handle_revoke(&self, _info: TAccessControlEntity) -> thrift::Result<bool> { unsupported("revoke") }
+}
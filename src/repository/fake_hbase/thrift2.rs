@@ -0,0 +1,232 @@
+//! The Thrift2 API of the fake, over the buffered transport and binary protocol.
+
+use std::net::TcpStream;
+
+use thrift::protocol::{TInputProtocol, TMessageIdentifier, TMessageType, TOutputProtocol, TStructIdentifier, TType};
+
+use crate::repository::{
+    hbase_connection::{ThriftProtocol, ThriftTransport},
+    thrift2_types::{
+        enum_name, enum_value, read_bytes_list, read_fields, read_struct_list, write_bool_field, write_i32_field,
+        write_struct_field, write_struct_list_field, Bytes, TColumn, TColumnFamilyDescriptor, TColumnValue, TDelete, TGet,
+        TPut, TResult, TScan, TServiceError, TTableDescriptor, TTableName, ThriftStruct, BLOOM_FILTER_TYPES,
+        COMPRESSION_ALGORITHMS,
+    },
+};
+use super::{filter::RowFilter, server_protocols, Cell, Column, Family, FakeHbase, Scan, Selection, StoreError};
+
+/// Arguments of the calls the fake answers, by field id.
+#[derive(Default)]
+struct Args {
+    table: Option<Bytes>,
+    table_name: Option<TTableName>,
+    descriptor: Option<TTableDescriptor>,
+    split_keys: Vec<Bytes>,
+    get: Option<TGet>,
+    gets: Vec<TGet>,
+    puts: Vec<TPut>,
+    deletes: Vec<TDelete>,
+    scan: Option<TScan>,
+    ids: Vec<i32>,
+    /// `row`, `family`, `qualifier` and `value` of a `checkAndPut`.
+    checked: [Bytes; 4],
+    put: Option<TPut>,
+}
+
+enum Reply {
+    Void,
+    Bool(bool),
+    I32(i32),
+    Result(TResult),
+    Results(Vec<TResult>),
+    Names(Vec<TTableName>),
+    Descriptor(TTableDescriptor),
+    Deletes(Vec<TDelete>),
+    IoError(String),
+    IllegalArgument(String),
+}
+
+impl From<StoreError> for Reply {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::InvalidScanner(_) => Reply::IllegalArgument(e.to_string()),
+            _ => Reply::IoError(e.to_string()),
+        }
+    }
+}
+
+pub(super) fn serve(fake: &FakeHbase, stream: TcpStream) {
+    let Some((mut i_prot, mut o_prot)) = server_protocols(stream, ThriftTransport::Buffered, ThriftProtocol::Binary) else { return };
+    while let Ok(call) = i_prot.read_message_begin() {
+        let Ok(args) = read_args(&call.name, &mut *i_prot).and_then(|args| i_prot.read_message_end().map(|_| args)) else { return };
+        let reply = answer(fake, &call.name, args).unwrap_or_else(Reply::from);
+        if write_reply(&mut *o_prot, &call, reply).is_err() {
+            return;
+        }
+    }
+}
+
+fn answer(fake: &FakeHbase, name: &str, args: Args) -> Result<Reply, StoreError> {
+    let table = match (&args.table_name, &args.table) {
+        (Some(name), _) => name.to_name(),
+        (None, Some(table)) => TTableName::parse(&String::from_utf8_lossy(table)).to_name(),
+        (None, None) => Bytes::new(),
+    };
+    Ok(match name {
+        "getTableNamesByPattern" => Reply::Names(fake.table_names().iter().map(|n| TTableName::parse(&String::from_utf8_lossy(n))).collect()),
+        "tableExists" => Reply::Bool(fake.table_names().contains(&table)),
+        "getTableDescriptor" => Reply::Descriptor(TTableDescriptor {
+            table_name: args.table_name.unwrap_or_default(),
+            columns: Some(fake.families(&table)?.iter().map(to_family_descriptor).collect()),
+        }),
+        "createTable" => {
+            let Some(descriptor) = args.descriptor else { return Ok(Reply::IllegalArgument("no descriptor".into())) };
+            let families = descriptor.columns.unwrap_or_default().iter().map(to_family).collect();
+            fake.create_table(&descriptor.table_name.to_name(), families, args.split_keys)?;
+            Reply::Void
+        }
+        "putMultiple" => {
+            let now = crate::repository::hbase::get_unix_time();
+            for put in args.puts {
+                for value in put.column_values {
+                    let timestamp = value.timestamp.or(put.timestamp).unwrap_or(now);
+                    fake.put(&table, &put.row, &value.family, &value.qualifier, timestamp, value.value)?;
+                }
+            }
+            Reply::Void
+        }
+        "deleteMultiple" => {
+            for delete in args.deletes {
+                match delete.columns.as_deref() {
+                    None | Some([]) => fake.delete(&table, &delete.row, None, delete.timestamp)?,
+                    Some(columns) => for column in columns {
+                        fake.delete(&table, &delete.row, Some(&to_column(column)), delete.timestamp)?;
+                    },
+                }
+            }
+            Reply::Deletes(vec![])
+        }
+        "get" => {
+            let get = args.get.unwrap_or_default();
+            Reply::Result(to_result(&get.row, fake.get(&table, &get.row, &selection(get.columns.as_deref()))?))
+        }
+        "getMultiple" => Reply::Results(args.gets.iter()
+            .map(|get| Ok(to_result(&get.row, fake.get(&table, &get.row, &selection(get.columns.as_deref()))?)))
+            .collect::<Result<_, StoreError>>()?),
+        "openScanner" => {
+            let scan = args.scan.unwrap_or_default();
+            let filter = match scan.filter_string.filter(|f| !f.is_empty()) {
+                Some(filter) => match RowFilter::parse(&String::from_utf8_lossy(&filter)) {
+                    Ok(filter) => Some(filter),
+                    Err(e) => return Ok(Reply::IllegalArgument(e)),
+                },
+                None => None,
+            };
+            let mut selection = selection(scan.columns.as_deref());
+            selection.before = scan.time_range.map(|range| range.max_stamp);
+            Reply::I32(fake.open_scanner(&table, Scan {
+                start: scan.start_row,
+                stop: scan.stop_row,
+                selection,
+                filter,
+                reversed: scan.reversed.unwrap_or(false),
+            })?)
+        }
+        "getScannerRows" => {
+            let (id, count) = (args.ids.first().copied().unwrap_or_default(), args.ids.get(1).copied().unwrap_or_default());
+            Reply::Results(fake.scanner_next(id, count)?.into_iter().map(|(row, cells)| to_result(&row, cells)).collect())
+        }
+        "checkAndPut" => {
+            let [row, family, qualifier, expected] = args.checked;
+            let Some(value) = args.put.and_then(|put| put.column_values.into_iter().next()) else { return Ok(Reply::IllegalArgument("no put".into())) };
+            let timestamp = value.timestamp.unwrap_or_else(crate::repository::hbase::get_unix_time);
+            let cell = Cell { family: value.family, qualifier: value.qualifier, timestamp, value: value.value };
+            Reply::Bool(fake.check_and_put(&table, &row, (&family, &qualifier), &expected, cell)?)
+        }
+        "closeScanner" => {
+            fake.close_scanner(args.ids.first().copied().unwrap_or_default())?;
+            Reply::Void
+        }
+        _ => Reply::IllegalArgument(format!("unknown method {}", name)),
+    })
+}
+
+fn to_column(column: &TColumn) -> Column {
+    Column { family: column.family.clone(), qualifier: column.qualifier.clone() }
+}
+
+fn selection(columns: Option<&[TColumn]>) -> Selection {
+    Selection { columns: columns.unwrap_or_default().iter().map(to_column).collect(), before: None }
+}
+
+fn to_result(row: &[u8], cells: Vec<Cell>) -> TResult {
+    let column_values = cells.into_iter()
+        .map(|c| TColumnValue { family: c.family, qualifier: c.qualifier, value: c.value, timestamp: Some(c.timestamp) })
+        .collect();
+    TResult { row: Some(row.to_vec()), column_values }
+}
+
+fn to_family(descriptor: &TColumnFamilyDescriptor) -> Family {
+    Family {
+        name: descriptor.name.clone(),
+        compression: enum_name(&COMPRESSION_ALGORITHMS, descriptor.compression_type.unwrap_or(2)),
+        bloom_filter_type: enum_name(&BLOOM_FILTER_TYPES, descriptor.bloom_filter_type.unwrap_or(1)),
+        max_versions: descriptor.max_versions.unwrap_or(1),
+        time_to_live: descriptor.time_to_live.unwrap_or(i32::MAX),
+        in_memory: descriptor.in_memory.unwrap_or(false),
+        block_cache_enabled: descriptor.block_cache_enabled.unwrap_or(true),
+    }
+}
+
+fn to_family_descriptor(family: &Family) -> TColumnFamilyDescriptor {
+    TColumnFamilyDescriptor {
+        name: family.name.clone(),
+        bloom_filter_type: enum_value(&BLOOM_FILTER_TYPES, &family.bloom_filter_type),
+        compression_type: enum_value(&COMPRESSION_ALGORITHMS, &family.compression),
+        max_versions: Some(family.max_versions),
+        time_to_live: Some(family.time_to_live),
+        block_cache_enabled: Some(family.block_cache_enabled),
+        in_memory: Some(family.in_memory),
+    }
+}
+
+fn read_args(name: &str, i: &mut dyn TInputProtocol) -> thrift::Result<Args> {
+    let mut args = Args::default();
+    read_fields(i, |i, id, t| Ok(match (name, id, t) {
+        ("tableExists" | "getTableDescriptor", 1, TType::Struct) => { args.table_name = Some(TTableName::read(i)?); true }
+        ("createTable", 1, TType::Struct) => { args.descriptor = Some(TTableDescriptor::read(i)?); true }
+        ("createTable", 2, TType::List) => { args.split_keys = read_bytes_list(i)?; true }
+        (_, 1, TType::String) => { args.table = Some(i.read_bytes()?); true }
+        ("putMultiple", 2, TType::List) => { args.puts = read_struct_list(i)?; true }
+        ("deleteMultiple", 2, TType::List) => { args.deletes = read_struct_list(i)?; true }
+        ("get", 2, TType::Struct) => { args.get = Some(TGet::read(i)?); true }
+        ("getMultiple", 2, TType::List) => { args.gets = read_struct_list(i)?; true }
+        ("openScanner", 2, TType::Struct) => { args.scan = Some(TScan::read(i)?); true }
+        ("checkAndPut", 2..=5, TType::String) => { args.checked[id as usize - 2] = i.read_bytes()?; true }
+        ("checkAndPut", 6, TType::Struct) => { args.put = Some(TPut::read(i)?); true }
+        (_, 1 | 2, TType::I32) => { args.ids.push(i.read_i32()?); true }
+        _ => false,
+    }))?;
+    Ok(args)
+}
+
+fn write_reply(o: &mut dyn TOutputProtocol, call: &TMessageIdentifier, reply: Reply) -> thrift::Result<()> {
+    o.write_message_begin(&TMessageIdentifier::new(call.name.clone(), TMessageType::Reply, call.sequence_number))?;
+    o.write_struct_begin(&TStructIdentifier::new(format!("{}_result", call.name)))?;
+    match reply {
+        Reply::Void => {}
+        Reply::Bool(value) => write_bool_field(o, 0, value)?,
+        Reply::I32(value) => write_i32_field(o, 0, value)?,
+        Reply::Result(value) => write_struct_field(o, 0, &value)?,
+        Reply::Results(values) => write_struct_list_field(o, 0, &values)?,
+        Reply::Names(values) => write_struct_list_field(o, 0, &values)?,
+        Reply::Descriptor(value) => write_struct_field(o, 0, &value)?,
+        Reply::Deletes(values) => write_struct_list_field(o, 0, &values)?,
+        Reply::IoError(message) => write_struct_field(o, 1, &TServiceError { kind: "TIOError", message: Some(message) })?,
+        Reply::IllegalArgument(message) => write_struct_field(o, 2, &TServiceError { kind: "TIllegalArgument", message: Some(message) })?,
+    }
+    o.write_field_stop()?;
+    o.write_struct_end()?;
+    o.write_message_end()?;
+    o.flush()
+}
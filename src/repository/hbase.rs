@@ -1,13 +1,15 @@
 use std::collections::BTreeMap;
 
-use crate::models::errors::OrderServiceError;
-use crate::models::orders::OrderInfo;
-use crate::models::{orders::Order, tables::TableName};
+use crate::models::errors::{OrderServiceError, RowIssue};
+use crate::models::orders::{DecodeMode, OrderInfo, OrderState};
+use crate::models::schema::{salt_split_keys, ColumnFamilySchema, SchemaDrift, TableSchema, TableSpec};
+use crate::models::{orders::Order, tables::{TableName, Tables}};
 use crate::repository::hbase_connection::HbaseClient;
-use crate::repository::hbase_utils::{create_mutation_from_order, create_order_builder_from_hbase_row, build_single_column_filter};
-use hbase_thrift::hbase::TScan;
+use crate::repository::hbase_utils::{create_mutation_from_order, create_order_builder_from_hbase_row};
 
-use super::hbase_utils::create_scan;
+use hbase_thrift::{hbase::{TRowResult, TScan}, BatchMutationBuilder};
+
+use super::hbase_utils::{create_cell_mutation, create_customer_index_mutation, create_full_scan, create_prefix_scan, customer_index_prefix, ordertime_millis};
 
 pub fn get_tables(mut client: impl HbaseClient) -> Result<Vec<TableName>, OrderServiceError> {
     let tables = client.get_table_names()?;
@@ -18,63 +20,311 @@ pub fn get_tables(mut client: impl HbaseClient) -> Result<Vec<TableName>, OrderS
     Ok(tables_names)
 }
 
-pub fn add_order(order: &Order, mut client: impl HbaseClient) -> Result<String, OrderServiceError> {
+/// Writes the order row, then its entry in the customer index. If the index write fails the order
+/// is still stored, and is only missing from the customer history until the index is rebuilt.
+pub fn add_order(order: &Order, tables: &Tables, mut client: impl HbaseClient) -> Result<String, OrderServiceError> {
     let (batch, rowkey) = create_mutation_from_order(order);
-    match client.put("orders", vec![batch], Some(get_unix_time()), None) {
-        Ok(_) => Ok(rowkey),
-        Err(e) => Err(OrderServiceError::from(e)),
+    let now = get_unix_time();
+    client.put(&tables.orders, vec![batch], Some(now), None)?;
+    let ordertime = ordertime_millis(&order.ordertime).unwrap_or(now);
+    let index = create_customer_index_mutation(&order.c_id, &rowkey, ordertime);
+    client.put(&tables.customer_index, vec![index], Some(now), None)?;
+    Ok(rowkey)
+}
+
+/// Overwrites the state of a stored order. The customer index holds no state, so it is left as is.
+pub fn set_order_state<H: HbaseClient>(client: &mut H, tables: &Tables, o_id: &str, state: &OrderState) -> Result<(), OrderServiceError> {
+    let mutation = create_cell_mutation("info", "state", state.to_string());
+    let batch = <BatchMutationBuilder>::default().row(o_id).mutations(vec![mutation]).build();
+    client.put(&tables.orders, vec![batch], Some(get_unix_time()), None)
+}
+
+/// Moves a stored order from `from` to `to`, unless its state changed since it was read. Returns whether it moved.
+pub fn move_order_state<H: HbaseClient>(client: &mut H, tables: &Tables, o_id: &str, from: &OrderState, to: &OrderState) -> Result<bool, OrderServiceError> {
+    let mutation = create_cell_mutation("info", "state", to.to_string()).build();
+    client.check_and_put(&tables.orders, o_id, "info:state", from.to_string().as_bytes(), mutation)
+}
+
+pub const ORDER_FAMILIES: [&str; 4] = ["info", "ids", "addr", "ol"];
+pub const CUSTOMER_INDEX_FAMILIES: [&str; 1] = ["o"];
+pub(crate) const CUSTOMER_HISTORY_LIMIT: i32 = 15;
+
+/// The order table, pre-split on the salt in front of every o_id.
+pub fn order_table_spec(tables: &Tables) -> TableSpec {
+    TableSpec {
+        name: tables.orders.clone(),
+        families: ORDER_FAMILIES.iter().map(|f| tables.family(f)).collect(),
+        split_keys: salt_split_keys(tables.order_regions),
     }
 }
 
-pub fn create_order_table(mut client: impl HbaseClient) -> Result<(), OrderServiceError> {
-    match client.create_table(
-        "orders",
-        vec!["info".into(), "ids".into(), "addr".into(), "ol".into()],
-    ) {
-        Ok(_) => Ok(()),
-        Err(e) => Err(OrderServiceError::from(e)),
+/// The customer index is not pre-split. Its keys start with the c_id, which is not uniformly distributed.
+pub fn customer_index_spec(tables: &Tables) -> TableSpec {
+    TableSpec {
+        name: tables.customer_index.clone(),
+        families: CUSTOMER_INDEX_FAMILIES.iter().map(|f| tables.family(f)).collect(),
+        split_keys: vec![],
     }
 }
 
-pub fn get_order_row(row_id: &str, mut client: impl HbaseClient) -> Result<Order, OrderServiceError> {
-    let r = client.get_row(row_id)?;
-    let row = match r.get(0) {
+pub fn create_order_table(tables: &Tables, mut client: impl HbaseClient) -> Result<(), OrderServiceError> {
+    client.create_table(&order_table_spec(tables))
+}
+
+pub fn create_customer_index_table(tables: &Tables, mut client: impl HbaseClient) -> Result<(), OrderServiceError> {
+    client.create_table(&customer_index_spec(tables))
+}
+
+/// Creates the order table when it is missing and returns the schema HBase reports afterwards.
+pub fn ensure_order_table<H: HbaseClient>(tables: &Tables, mut client: H) -> Result<TableSchema, OrderServiceError> {
+    if !client.table_exists(&tables.orders)? {
+        client.create_table(&order_table_spec(tables))?;
+    }
+    describe_order_table(tables, client)
+}
+
+pub fn describe_order_table<H: HbaseClient>(tables: &Tables, mut client: H) -> Result<TableSchema, OrderServiceError> {
+    if !client.table_exists(&tables.orders)? {
+        return Ok(TableSchema { table_name: tables.orders.clone(), exists: false, column_families: vec![] });
+    }
+    let column_families = client.get_column_descriptors(&tables.orders)?
+        .into_values()
+        .map(|d| ColumnFamilySchema {
+            name: family_name(d.name.unwrap_or_default()),
+            max_versions: d.max_versions,
+            compression: d.compression,
+            in_memory: d.in_memory,
+            bloom_filter_type: d.bloom_filter_type,
+            time_to_live: d.time_to_live,
+        })
+        .collect();
+    Ok(TableSchema { table_name: tables.orders.clone(), exists: true, column_families })
+}
+
+/// Compares the families of the order table against `ORDER_FAMILIES`.
+pub fn order_table_drift<H: HbaseClient>(tables: &Tables, client: H) -> Result<SchemaDrift, OrderServiceError> {
+    let schema = describe_order_table(tables, client)?;
+    let actual: Vec<&str> = schema.column_families.iter().map(|f| f.name.as_str()).collect();
+    let missing = ORDER_FAMILIES.iter()
+        .filter(|f| !actual.contains(f))
+        .map(|f| f.to_string())
+        .collect();
+    let unexpected = actual.iter()
+        .filter(|f| !ORDER_FAMILIES.contains(f))
+        .map(|f| f.to_string())
+        .collect();
+    Ok(SchemaDrift::new(schema.table_name, schema.exists, missing, unexpected))
+}
+
+/// HBase reports family names with a trailing ':'.
+fn family_name(raw: Vec<u8>) -> String {
+    let name = String::from_utf8_lossy(&raw).into_owned();
+    name.strip_suffix(':').map(str::to_owned).unwrap_or(name)
+}
+
+pub fn get_order_row(row_id: &str, tables: &Tables, client: impl HbaseClient) -> Result<Order, OrderServiceError> {
+    get_order_row_with_mode(row_id, DecodeMode::Strict, tables, client).map(|(order, _)| order)
+}
+
+/// Fetches and decodes an order row. In lenient mode the issues found in the row are returned alongside the order.
+pub fn get_order_row_with_mode(row_id: &str, mode: DecodeMode, tables: &Tables, mut client: impl HbaseClient) -> Result<(Order, Vec<RowIssue>), OrderServiceError> {
+    let r = client.get_row(&tables.orders, row_id)?;
+    let row = match r.first() {
         Some(v) => v,
         None => return Err(OrderServiceError::RowNotFound(row_id.to_owned())),
     };
-    match Order::build(create_order_builder_from_hbase_row(row)) {
-        Some(v) => Ok(v),
-        None => return Err(OrderServiceError::OrderBuildFailed()),
-    }
+    Order::decode(create_order_builder_from_hbase_row(row), mode)
 }
 
-pub fn get_orders_info_by_user<H: HbaseClient>(user_id: String, mut client: H) -> Result<Vec<OrderInfo>, OrderServiceError> {
-    let scan = create_scan(
-        vec!["info:o_id".into(), "info:o_time".into(), "info:state".into(), "ids:r_id".into(), "ids:c_id".into()],
-        "ids", "c_id", &user_id
-    );
-    let scanid = client.scanner_open_with_scan("orders".into(), scan, BTreeMap::default())?;
-    let res = client.scanner_get_list(scanid, 15)?;
-    let orders: Vec<OrderInfo> =  res.iter()
-        .filter_map(|row| OrderInfo::build(create_order_builder_from_hbase_row(row)))
+/// The newest orders of a customer, read through the customer index, and the number of rows skipped because they
+/// do not decode in `mode`.
+pub fn get_orders_info_by_user<H: HbaseClient>(user_id: String, mode: DecodeMode, tables: &Tables, mut client: H) -> Result<(Vec<OrderInfo>, usize), OrderServiceError> {
+    let scan = create_prefix_scan(vec!["o:o_id".into()], &customer_index_prefix(&user_id));
+    let scanid = client.scanner_open_with_scan(tables.customer_index.as_str().into(), scan, BTreeMap::default())?;
+    let entries = client.scanner_get_list(scanid, CUSTOMER_HISTORY_LIMIT);
+    client.scanner_close(scanid)?;
+    let o_ids: Vec<Vec<u8>> = entries?.into_iter()
+        .filter_map(|entry| entry.columns?.remove(b"o:o_id".as_slice())?.value)
+        .collect();
+    if o_ids.is_empty() {
+        return Ok((vec![], 0));
+    }
+    let columns = vec!["info:o_time".into(), "info:state".into(), "ids:r_id".into(), "ids:c_id".into()];
+    let mut rows: BTreeMap<Vec<u8>, _> = client.get_rows_with_columns(&tables.orders, o_ids.clone(), columns)?
+        .into_iter()
+        .filter_map(|row| Some((row.row.clone()?, row)))
+        .collect();
+    // Index entries whose order row is gone are skipped, the rest keep the index order.
+    let mut skipped = 0;
+    let orders: Vec<OrderInfo> = o_ids.iter()
+        .filter_map(|o_id| rows.remove(o_id))
+        .filter_map(|row| match OrderInfo::decode(create_order_builder_from_hbase_row(&row), mode) {
+            // Entries written before migration 8 rebuilt the index can belong to a customer sharing the prefix.
+            Ok((info, _)) if info.c_id != user_id => None,
+            Ok((info, issues)) => {
+                if !issues.is_empty() {
+                    tracing::debug!(o_id = info.o_id.as_str(), ?issues, "order row decoded with issues");
+                }
+                Some(info)
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, row = %String::from_utf8_lossy(row.row.as_deref().unwrap_or_default()), "skipping order row that does not decode");
+                skipped += 1;
+                None
+            }
+        })
         .collect();
-    Ok(orders)
+    Ok((orders, skipped))
+}
+
+/// Which orders a scan of the order table returns. HBase matches the restaurant, the customer and the state, the
+/// scan checks the order time, `from` inclusive and `until` exclusive, in Unix millis.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrderScanFilter {
+    pub r_id: Option<String>,
+    pub c_id: Option<String>,
+    pub state: Option<OrderState>,
+    pub from: Option<i64>,
+    pub until: Option<i64>,
+}
+
+impl OrderScanFilter {
+    /// `SingleColumnValueFilter`s for the restaurant, customer and state, `None` when none is set.
+    fn filter_string(&self) -> Option<String> {
+        let equals = |family: &str, qualifier: &str, value: &str| {
+            format!("SingleColumnValueFilter('{}', '{}', =, 'binary:{}', true, true)", family, qualifier, value.replace('\'', "''"))
+        };
+        let state = self.state.as_ref().map(ToString::to_string);
+        let filters: Vec<String> = [("ids", "r_id", &self.r_id), ("ids", "c_id", &self.c_id), ("info", "state", &state)].into_iter()
+            .filter_map(|(family, qualifier, value)| value.as_deref().map(|v| equals(family, qualifier, v)))
+            .collect();
+        (!filters.is_empty()).then(|| filters.join(" AND "))
+    }
+
+    pub fn in_range(&self, ordertime: i64) -> bool {
+        self.from.is_none_or(|from| ordertime >= from) && self.until.is_none_or(|until| ordertime < until)
+    }
+}
+
+/// An order read by a scan, with the time its state was written in Unix millis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScannedOrder {
+    pub order: Order,
+    pub ordertime: i64,
+    pub state_written_at: Option<i64>,
+}
+
+/// Scans the whole order table, `batch_size` rows at a time, and hands the orders matching `filter` to `on_order`.
+/// No scanner is open while `on_order` runs, so it may take its time. Rows that do not decode are logged and
+/// skipped. Returns the number of skipped rows.
+pub fn scan_orders<H: HbaseClient>(
+    client: &mut H,
+    tables: &Tables,
+    filter: &OrderScanFilter,
+    batch_size: i32,
+    mut on_order: impl FnMut(ScannedOrder) -> Result<(), OrderServiceError>,
+) -> Result<usize, OrderServiceError> {
+    // No columns fetches every column.
+    let scan = TScan { columns: None, filter_string: filter.filter_string().map(String::into_bytes), ..create_full_scan(vec![], batch_size) };
+    let mut skipped = 0;
+    scan_rows_paged(client, &tables.orders, scan, batch_size, |row| {
+        let cell_time = |column: &[u8]| row.columns.as_ref()?.get(column)?.timestamp;
+        let (order, issues) = match Order::decode(create_order_builder_from_hbase_row(row), DecodeMode::Lenient) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                tracing::warn!(error = %e, row = %String::from_utf8_lossy(row.row.as_deref().unwrap_or_default()), "skipping order row that does not decode");
+                skipped += 1;
+                return Ok(());
+            }
+        };
+        if !issues.is_empty() {
+            tracing::debug!(o_id = order.o_id.as_str(), ?issues, "order row decoded with issues");
+        }
+        let Some(ordertime) = ordertime_millis(&order.ordertime).or_else(|| cell_time(b"info:o_time")) else {
+            skipped += 1;
+            return Ok(());
+        };
+        if !filter.in_range(ordertime) {
+            return Ok(());
+        }
+        let state_written_at = cell_time(b"info:state");
+        on_order(ScannedOrder { order, ordertime, state_written_at })
+    })?;
+    Ok(skipped)
+}
+
+/// Runs `scan` over `table`, `batch_size` rows per round trip, and always closes the scanner.
+pub(crate) fn scan_rows<H: HbaseClient>(
+    client: &mut H,
+    table: &str,
+    scan: TScan,
+    batch_size: i32,
+    mut on_row: impl FnMut(&TRowResult) -> Result<(), OrderServiceError>,
+) -> Result<(), OrderServiceError> {
+    scan_pages(client, table, scan, batch_size, |_, rows| rows.iter().try_for_each(&mut on_row))
+}
+
+/// Like [`scan_rows`], but reads each page with a scanner of its own and closes it before `on_row` sees the rows, so
+/// slow handling cannot let the scanner time out. A page starts right after the last row of the page before.
+pub(crate) fn scan_rows_paged<H: HbaseClient>(
+    client: &mut H,
+    table: &str,
+    mut scan: TScan,
+    batch_size: i32,
+    mut on_row: impl FnMut(&TRowResult) -> Result<(), OrderServiceError>,
+) -> Result<(), OrderServiceError> {
+    loop {
+        let id = client.scanner_open_with_scan(table.into(), scan.clone(), BTreeMap::default())?;
+        let rows = client.scanner_get_list(id, batch_size);
+        let closed = client.scanner_close(id);
+        let rows = rows.and_then(|rows| closed.map(|_| rows))?;
+        let Some(last) = rows.last().and_then(|r| r.row.clone()) else {
+            return Ok(());
+        };
+        rows.iter().try_for_each(&mut on_row)?;
+        // The smallest row key after `last`.
+        scan.start_row = Some([last, vec![0]].concat());
+    }
+}
+
+/// Like [`scan_rows`], but hands over the rows of each round trip together with the client, so they can be written
+/// back while the scanner is still open.
+pub(crate) fn scan_pages<H: HbaseClient>(
+    client: &mut H,
+    table: &str,
+    scan: TScan,
+    batch_size: i32,
+    mut on_page: impl FnMut(&mut H, &[TRowResult]) -> Result<(), OrderServiceError>,
+) -> Result<(), OrderServiceError> {
+    let id = client.scanner_open_with_scan(table.into(), scan, BTreeMap::default())?;
+    let result = (|| {
+        loop {
+            let rows = client.scanner_get_list(id, batch_size)?;
+            if rows.is_empty() {
+                return Ok(());
+            }
+            on_page(client, &rows)?;
+        }
+    })();
+    let closed = client.scanner_close(id);
+    result.and(closed)
 }
 
-fn get_unix_time() -> i64 {
+pub(crate) fn get_unix_time() -> i64 {
     let now = std::time::SystemTime::now();
     now.duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as i64
 }
 
 #[cfg(test)]
+#[allow(noop_method_call, clippy::useless_conversion, clippy::partialeq_to_none, clippy::get_first, clippy::len_zero)]
 mod tests {
     use super::*;
     use crate::{
-        models::orders::{Orderline, OrderState},
-        repository::{hbase_connection::MockHbaseClient, hbase_utils::{create_mutation_from_order, order_to_trowresult, _to_tcell}},
+        models::{orders::{Orderline, OrderState}, schema::ColumnFamilySpec},
+        repository::{fake_hbase::{FakeHbase, FakeServer}, hbase_connection::MockHbaseClient, hbase_utils::{create_mutation_from_order, order_to_trowresult, _to_tcell}},
     };
     use hbase_thrift::{
-        hbase::{BatchMutation, Text, TScan, TRowResult, TCell},
+        hbase::{BatchMutation, ColumnDescriptor, Text},
         Attributes,
     };
     use mockall::predicate::eq;
@@ -93,9 +343,9 @@ mod tests {
         let userid = "id";
         let mut mock_con = MockHbaseClient::new();
         mock_con.expect_get_row()
-            .with(eq(userid.clone()))
+            .with(eq("orders"), eq(userid.clone()))
             .times(1)
-            .returning(|x| {
+            .returning(|_, x| {
                 Ok(vec![order_to_trowresult(
                     Order {
                         o_id: x.clone().to_owned(),
@@ -110,7 +360,7 @@ mod tests {
                     }
                 )])
             });
-        let res = get_order_row(userid.into(), mock_con);
+        let res = get_order_row(userid.into(), &Tables::default(), mock_con);
         assert!(res.is_ok());
     }
 
@@ -119,9 +369,9 @@ mod tests {
         let userid = "id";
         let mut mock_con = MockHbaseClient::new();
         mock_con.expect_get_row()
-            .with(eq(userid.clone()))
+            .with(eq("orders"), eq(userid.clone()))
             .times(1)
-            .returning(|x| {
+            .returning(|_, x| {
                 Ok(vec![order_to_trowresult(
                     Order {
                         o_id: x.clone().to_owned(),
@@ -136,7 +386,7 @@ mod tests {
                     }
                 )])
             });
-        let res = get_order_row(userid.into(), mock_con).unwrap();
+        let res = get_order_row(userid.into(), &Tables::default(), mock_con).unwrap();
         assert_eq!(res.o_id, userid);
     }
     #[test]
@@ -144,9 +394,9 @@ mod tests {
         let userid = "id";
         let mut mock_con = MockHbaseClient::new();
         mock_con.expect_get_row()
-            .with(eq(userid.clone()))
+            .with(eq("orders"), eq(userid.clone()))
             .times(1)
-            .returning(|x| {
+            .returning(|_, x| {
                 let mut columns: std::collections::BTreeMap<hbase_thrift::hbase::Text, hbase_thrift::hbase::TCell> = std::collections::BTreeMap::new();
                 columns.insert("ids:c_id".as_bytes().to_vec(), _to_tcell("cust_id"));
                 columns.insert("BADCOLUMNFAMILYNAME:r_id".as_bytes().to_vec(), _to_tcell("rest_id"));
@@ -155,10 +405,71 @@ mod tests {
                 let res = hbase_thrift::hbase::TRowResult { row: Some(x.as_bytes().to_vec()), columns: Some(columns), sorted_columns: None };
                 Ok(vec![res])
             });
-        let res = get_order_row(userid.into(), mock_con);
+        let res = get_order_row(userid.into(), &Tables::default(), mock_con);
         assert!(res.is_err());
         let result_error = res.err().unwrap();
-        assert_err!(result_error, OrderServiceError::OrderBuildFailed());
+        assert_err!(result_error, OrderServiceError::OrderBuildFailed(_));
+    }
+
+    #[test]
+    fn test_get_order_row_bad_trow_result_names_issues() {
+        let userid = "id";
+        let mut mock_con = MockHbaseClient::new();
+        mock_con.expect_get_row()
+            .with(eq("orders"), eq(userid))
+            .times(1)
+            .returning(|_, x| {
+                let mut columns: std::collections::BTreeMap<hbase_thrift::hbase::Text, hbase_thrift::hbase::TCell> = std::collections::BTreeMap::new();
+                columns.insert("ids:c_id".as_bytes().to_vec(), _to_tcell("cust_id"));
+                columns.insert("BADCOLUMNFAMILYNAME:r_id".as_bytes().to_vec(), _to_tcell("rest_id"));
+                columns.insert("addr:postal".as_bytes().to_vec(), _to_tcell("notanumber"));
+                let res = hbase_thrift::hbase::TRowResult { row: Some(x.as_bytes().to_vec()), columns: Some(columns), sorted_columns: None };
+                Ok(vec![res])
+            });
+        let issues = match get_order_row(userid, &Tables::default(), mock_con) {
+            Err(OrderServiceError::OrderBuildFailed(issues)) => issues,
+            other => panic!("expected OrderBuildFailed but got {:?}", other),
+        };
+        assert!(issues.contains(&RowIssue::MissingField("r_id")));
+        assert!(issues.contains(&RowIssue::MissingField("state")));
+        assert!(issues.contains(&RowIssue::UnknownColumn("BADCOLUMNFAMILYNAME:r_id".into())));
+        assert!(issues.iter().any(|i| matches!(i, RowIssue::InvalidValue { column, .. } if column == "addr:postal")));
+    }
+
+    #[test]
+    fn test_get_order_row_unknown_column_strict_is_err() {
+        let userid = "id";
+        let mut mock_con = MockHbaseClient::new();
+        mock_con.expect_get_row()
+            .times(1)
+            .returning(|_, x| {
+                let mut row = order_to_trowresult(Order::new(vec![], "c".into(), "r".into(), "cid".into(), "rid".into(), 2860));
+                row.row = Some(x.as_bytes().to_vec());
+                row.columns.as_mut().unwrap().insert("info:extra".as_bytes().to_vec(), _to_tcell("value"));
+                Ok(vec![row])
+            });
+        let res = get_order_row_with_mode(userid, DecodeMode::Strict, &Tables::default(), mock_con);
+        assert_err!(res, Err(OrderServiceError::OrderBuildFailed(_)));
+    }
+
+    #[test]
+    fn test_get_order_row_unknown_column_lenient_warns() {
+        let userid = "id";
+        let mut mock_con = MockHbaseClient::new();
+        mock_con.expect_get_row()
+            .times(1)
+            .returning(|_, x| {
+                let mut row = order_to_trowresult(Order::new(vec![], "c".into(), "r".into(), "cid".into(), "rid".into(), 2860));
+                row.row = Some(x.as_bytes().to_vec());
+                row.columns.as_mut().unwrap().insert("info:extra".as_bytes().to_vec(), _to_tcell("value"));
+                row.columns.as_mut().unwrap().insert("ol:0".as_bytes().to_vec(), _to_tcell("bad"));
+                Ok(vec![row])
+            });
+        let (order, warnings) = get_order_row_with_mode(userid, DecodeMode::Lenient, &Tables::default(), mock_con).unwrap();
+        assert_eq!(order.o_id, userid);
+        assert!(order.orderlines.is_empty());
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings.contains(&RowIssue::UnknownColumn("info:extra".into())));
     }
 
     #[test]
@@ -166,96 +477,100 @@ mod tests {
         let userid = "id";
         let mut mock_con = MockHbaseClient::new();
         mock_con.expect_get_row()
-            .with(eq(userid.clone()))
+            .with(eq("orders"), eq(userid.clone()))
             .times(1)
-            .returning(move|_x| {
+            .returning(move|_, _x| {
                 Err(OrderServiceError::DBError(thrift::Error::User("Error".into())))
             });
-        let res = get_order_row(userid.into(), mock_con);
+        let res = get_order_row(userid.into(), &Tables::default(), mock_con);
         assert!(res.is_err());
         let result_error = res.err().unwrap();
         assert_err!(result_error, OrderServiceError::DBError(_));
     }
 
-    #[test]
-    fn test_get_orders_from_user_scanner_get_fail() {
-        let userid = "id";
-        let mut mock_con = MockHbaseClient::new();
+    fn expect_index_put(mock_con: &mut MockHbaseClient, order: &Order) {
+        let exp = create_customer_index_mutation(&order.c_id, &order.o_id, ordertime_millis(&order.ordertime).unwrap());
+        mock_con
+            .expect_put()
+            .withf(move |tblname, row_batches, _timestamp, attributes| {
+                tblname.eq("orders_by_customer") && attributes.is_none() && row_batches.eq(&vec![exp.clone()])
+            })
+            .times(1)
+            .returning(|_tblname, _batch, _tmstmp, _attr| Ok(()));
+    }
+
+    fn index_entry(c_id: &str, o_id: &str, ordertime: i64) -> hbase_thrift::hbase::TRowResult {
+        let batch = create_customer_index_mutation(c_id, o_id, ordertime);
+        let mut columns = BTreeMap::new();
+        columns.insert("o:o_id".as_bytes().to_vec(), _to_tcell(o_id));
+        hbase_thrift::hbase::TRowResult { row: batch.row, columns: Some(columns), sorted_columns: None }
+    }
+
+    fn expect_index_scan(mock_con: &mut MockHbaseClient, userid: &'static str, result: Result<Vec<hbase_thrift::hbase::TRowResult>, ()>) {
         mock_con.expect_scanner_open_with_scan()
-            .withf(move |x,y,z| {
-                std::str::from_utf8(x).unwrap() == "orders"
-                && y.eq(&create_scan(
-                    vec!["info:o_id".into(), "info:o_time".into(), "info:state".into(), "ids:r_id".into(), "ids:c_id".into()],
-                    "ids", "c_id", &userid.clone()
-                    ))
+            .withf(move |x, y, z| {
+                std::str::from_utf8(x).unwrap() == "orders_by_customer"
+                && y.eq(&create_prefix_scan(vec!["o:o_id".into()], &customer_index_prefix(userid)))
                 && z.eq(&BTreeMap::default())
             })
             .times(1)
-            .returning(|_x, _y, _z| {
-                Ok(55)
-            });
+            .returning(|_x, _y, _z| Ok(55));
         mock_con.expect_scanner_get_list()
-            .withf(|x, y| {
-                x == &55 && y == &15
-            })
+            .withf(|x, y| x == &55 && y == &15)
             .times(1)
-            .returning(move|_x, _y| {
-                Err(OrderServiceError::DBError(thrift::Error::User("()".into())))
-            });
-        let res = get_orders_info_by_user(userid.into(), mock_con);
+            .returning(move |_x, _y| result.clone().map_err(|_| OrderServiceError::DBError(thrift::Error::User("()".into()))));
+        mock_con.expect_scanner_close().with(eq(55)).times(1).returning(|_| Ok(()));
+    }
+
+    #[test]
+    fn test_get_orders_from_user_scanner_get_fail() {
+        let mut mock_con = MockHbaseClient::new();
+        expect_index_scan(&mut mock_con, "id", Err(()));
+        mock_con.expect_get_rows_with_columns().never();
+        let res = get_orders_info_by_user("id".into(), DecodeMode::Strict, &Tables::default(), mock_con);
         assert!(res.is_err());
     }
 
     #[test]
     fn test_get_orders_from_user_scanner_open_fail() {
-        let userid = "id";
         let mut mock_con = MockHbaseClient::new();
         mock_con.expect_scanner_open_with_scan()
-            .withf(move |x,y,z| {
-                std::str::from_utf8(x).unwrap() == "orders"
-                && y.eq(&create_scan(
-                    vec!["info:o_id".into(), "info:o_time".into(), "info:state".into(), "ids:r_id".into(), "ids:c_id".into()],
-                    "ids", "c_id", &userid.clone()
-                    ))
-                && z.eq(&BTreeMap::default())
-            })
             .times(1)
             .returning(|_x, _y, _z| {
                 Err(OrderServiceError::DBError(thrift::Error::User("()".into())))
             });
         mock_con.expect_scanner_get_list().never();
-        let res = get_orders_info_by_user(userid.into(), mock_con);
+        mock_con.expect_scanner_close().never();
+        let res = get_orders_info_by_user("id".into(), DecodeMode::Strict, &Tables::default(), mock_con);
         assert!(res.is_err());
     }
 
+    #[test]
+    fn test_get_orders_from_user_empty_index() {
+        let mut mock_con = MockHbaseClient::new();
+        expect_index_scan(&mut mock_con, "id", Ok(vec![]));
+        mock_con.expect_get_rows_with_columns().never();
+        let res = get_orders_info_by_user("id".into(), DecodeMode::Strict, &Tables::default(), mock_con).unwrap().0;
+        assert!(res.is_empty());
+    }
+
     #[test]
     fn test_get_orders_from_user_on_content() {
         let userid = "id";
         let input_order = Order::new(vec![], "cust_addr".into(), "rest_addr".into(), userid.clone().into(), "r_addr".into(), 2860);
         let exp_order = input_order.clone();
         let mut mock_con = MockHbaseClient::new();
-        mock_con.expect_scanner_open_with_scan()
-            .withf(move |x,y,z| {
-                std::str::from_utf8(x).unwrap() == "orders"
-                && y.eq(&create_scan(
-                    vec!["info:o_id".into(), "info:o_time".into(), "info:state".into(), "ids:r_id".into(), "ids:c_id".into()],
-                    "ids", "c_id", &userid.clone()
-                    ))
-                && z.eq(&BTreeMap::default())
-            })
+        expect_index_scan(&mut mock_con, userid, Ok(vec![index_entry(userid, &input_order.o_id, 1)]));
+        let exp_id = input_order.o_id.clone();
+        mock_con.expect_get_rows_with_columns()
+            .withf(move |table, rows, _cols| table == "orders" && rows == &vec![exp_id.as_bytes().to_vec()])
             .times(1)
-            .returning(|_x, _y, _z| {
-                Ok(55)
+            .returning(move |_x, _y, _z| {
+                let mut row = order_to_trowresult(input_order.clone());
+                row.row = Some(input_order.o_id.as_bytes().to_vec());
+                Ok(vec![row])
             });
-        mock_con.expect_scanner_get_list()
-            .withf(|x, y| {
-                x == &55 && y == &15
-            })
-            .times(1)
-            .returning(move|_x, _y| {
-                Ok(vec![order_to_trowresult(input_order.clone())])
-            });
-        let res = get_orders_info_by_user(userid.into(), mock_con).unwrap();
+        let res = get_orders_info_by_user(userid.into(), DecodeMode::Strict, &Tables::default(), mock_con).unwrap().0;
         assert!(res.len() == 1);
         let oinfo = &res[0];
         assert_eq!(oinfo.o_id, exp_order.o_id);
@@ -265,33 +580,47 @@ mod tests {
     }
 
     #[test]
-    fn test_get_orders_from_user_is_ok() {
+    fn test_get_orders_from_user_keeps_index_order_and_skips_dangling() {
         let userid = "id";
-        let exp_order = Order::new(vec![], "cust_addr".into(), "rest_addr".into(), userid.clone().into(), "r_addr".into(), 2860);
         let mut mock_con = MockHbaseClient::new();
-        mock_con.expect_scanner_open_with_scan()
-            .withf(move |x,y,z| {
-                std::str::from_utf8(x).unwrap() == "orders"
-                && y.eq(&create_scan(
-                    vec!["info:o_id".into(), "info:o_time".into(), "info:state".into(), "ids:r_id".into(), "ids:c_id".into()],
-                    "ids", "c_id", &userid.clone()
-                    ))
-                && z.eq(&BTreeMap::default())
-            })
-            .times(1)
-            .returning(|_x, _y, _z| {
-                Ok(55)
-            });
-        mock_con.expect_scanner_get_list()
-            .withf(|x, y| {
-                x == &55 && y == &15
-            })
+        expect_index_scan(&mut mock_con, userid, Ok(vec![
+            index_entry(userid, "newest", 3),
+            index_entry(userid, "deleted", 2),
+            index_entry(userid, "oldest", 1),
+        ]));
+        mock_con.expect_get_rows_with_columns()
             .times(1)
-            .returning(move|_x, _y| {
-                Ok(vec![order_to_trowresult(exp_order.clone())])
+            .returning(move |_x, _y, _z| {
+                Ok(["oldest", "newest"].iter().map(|id| {
+                    let mut row = order_to_trowresult(Order::new(vec![], "c".into(), "r".into(), "id".into(), "rid".into(), 2860));
+                    row.row = Some(id.as_bytes().to_vec());
+                    row
+                }).collect())
             });
-        let res = get_orders_info_by_user(userid.into(), mock_con);
-        assert!(res.is_ok());
+        let res = get_orders_info_by_user(userid.into(), DecodeMode::Strict, &Tables::default(), mock_con).unwrap().0;
+        let ids: Vec<_> = res.iter().map(|o| o.o_id.as_str()).collect();
+        assert_eq!(ids, vec!["newest", "oldest"]);
+    }
+
+    #[test]
+    fn test_get_orders_from_user_counts_rows_that_do_not_decode() {
+        let userid = "id";
+        let rows = || {
+            let mut extra = order_to_trowresult(Order::new(vec![], "c".into(), "r".into(), "id".into(), "rid".into(), 2860));
+            extra.row = Some(b"extra".to_vec());
+            extra.columns.as_mut().unwrap().insert(b"info:extra".to_vec(), _to_tcell("value"));
+            let mut broken = order_to_trowresult(Order::new(vec![], "c".into(), "r".into(), "id".into(), "rid".into(), 2860));
+            broken.row = Some(b"broken".to_vec());
+            broken.columns.as_mut().unwrap().remove(b"info:state".as_slice());
+            vec![extra, broken]
+        };
+        for (mode, expected) in [(DecodeMode::Strict, 0), (DecodeMode::Lenient, 1)] {
+            let mut mock_con = MockHbaseClient::new();
+            expect_index_scan(&mut mock_con, userid, Ok(vec![index_entry(userid, "extra", 2), index_entry(userid, "broken", 1)]));
+            mock_con.expect_get_rows_with_columns().times(1).returning(move |_, _, _| Ok(rows()));
+            let (orders, skipped) = get_orders_info_by_user(userid.into(), mode, &Tables::default(), mock_con).unwrap();
+            assert_eq!((orders.len(), skipped), (expected, 2 - expected));
+        }
     }
 
     #[test]
@@ -320,7 +649,8 @@ mod tests {
             )
             .times(1)
             .returning(move |_tblname, _batch, _tmstmp, _attr| Ok(()));
-        let res = add_order(&order, mock_con);
+        expect_index_put(&mut mock_con, &order);
+        let res = add_order(&order, &Tables::default(), mock_con);
         assert_eq!(res.unwrap(), rkey);
     }
 
@@ -363,10 +693,155 @@ mod tests {
             )
             .times(1)
             .returning(move |_tblname, _batch, _tmstmp, _attr| Ok(()));
-        let res = add_order(&order, mock_con);
+        expect_index_put(&mut mock_con, &order);
+        let res = add_order(&order, &Tables::default(), mock_con);
         assert_eq!(res.unwrap(), rkey);
     }
 
+    #[test]
+    fn test_namespaced_tables_are_used_for_reads_and_writes() {
+        let tables = Tables::new(Some("staging"), "orders", "orders_by_customer", "schema_migrations", "order_sagas", "service_leases").unwrap();
+        let order = Order::new(vec![], "addr".into(), "addr2".into(), "custid".into(), "restid".into(), 2860);
+        let mut mock_con = MockHbaseClient::new();
+        mock_con.expect_put()
+            .withf(|tblname, _, _, _| tblname == "staging:orders" || tblname == "staging:orders_by_customer")
+            .times(2)
+            .returning(|_, _, _, _| Ok(()));
+        assert!(add_order(&order, &tables, mock_con).is_ok());
+
+        let mut mock_con = MockHbaseClient::new();
+        mock_con.expect_get_row()
+            .with(eq("staging:orders"), eq("id"))
+            .times(1)
+            .returning(|_, _| Ok(vec![]));
+        assert_err!(get_order_row("id", &tables, mock_con), Err(OrderServiceError::RowNotFound(_)));
+    }
+
+    #[test]
+    fn test_add_order_index_write_fails() {
+        let order = Order::new(vec![], "addr".into(), "addr2".into(), "custid".into(), "restid".into(), 2860);
+        let mut mock_con = MockHbaseClient::new();
+        mock_con.expect_put()
+            .withf(|tblname, _, _, _| tblname == "orders")
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+        mock_con.expect_put()
+            .withf(|tblname, _, _, _| tblname == "orders_by_customer")
+            .times(1)
+            .returning(|_, _, _, _| Err(OrderServiceError::DBError(thrift::Error::User("down".into()))));
+        let res = add_order(&order, &Tables::default(), mock_con);
+        assert_err!(res, Err(OrderServiceError::DBError(_)));
+    }
+
+    #[test]
+    fn test_order_table_spec_uses_family_settings() {
+        let ol = ColumnFamilySpec { compression: "SNAPPY".into(), ..ColumnFamilySpec::new("ol") };
+        let tables = Tables::default().with_family(ol.clone()).with_order_regions(4);
+        let spec = order_table_spec(&tables);
+        assert_eq!(spec.families[3], ol);
+        assert_eq!(spec.families[0], ColumnFamilySpec::new("info"));
+        assert_eq!(spec.split_keys, salt_split_keys(4));
+        assert!(customer_index_spec(&tables).split_keys.is_empty());
+    }
+
+    #[test]
+    fn test_describe_order_table_missing() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_table_exists().with(eq("orders")).times(1).returning(|_| Ok(false));
+        mock.expect_get_column_descriptors().times(0);
+
+        let schema = describe_order_table(&Tables::default(), mock).unwrap();
+        assert!(!schema.exists);
+        assert!(schema.column_families.is_empty());
+    }
+
+    #[test]
+    fn test_describe_order_table_strips_family_suffix() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_table_exists().returning(|_| Ok(true));
+        mock.expect_get_column_descriptors()
+            .with(eq("orders"))
+            .returning(|_| Ok(descriptors(&["info", "ids"])));
+
+        let schema = describe_order_table(&Tables::default(), mock).unwrap();
+        assert!(schema.exists);
+        let names: Vec<_> = schema.column_families.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["ids", "info"]);
+        assert_eq!(schema.column_families[0].max_versions, Some(3));
+    }
+
+    #[test]
+    fn test_ensure_order_table_creates_when_missing() {
+        let mut mock = MockHbaseClient::new();
+        let mut exists = false;
+        mock.expect_table_exists().times(2).returning(move |_| {
+            let r = exists;
+            exists = true;
+            Ok(r)
+        });
+        mock.expect_create_table()
+            .withf(|spec| spec.name == "orders" && spec.families.iter().map(|f| f.name.as_str()).eq(["info", "ids", "addr", "ol"]) && spec.split_keys.len() == 15)
+            .times(1)
+            .returning(|_| Ok(()));
+        mock.expect_get_column_descriptors().returning(|_| Ok(descriptors(&ORDER_FAMILIES)));
+
+        let schema = ensure_order_table(&Tables::default(), mock).unwrap();
+        assert_eq!(schema.column_families.len(), 4);
+    }
+
+    #[test]
+    fn test_ensure_order_table_existing_is_not_recreated() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_table_exists().returning(|_| Ok(true));
+        mock.expect_create_table().times(0);
+        mock.expect_get_column_descriptors().returning(|_| Ok(descriptors(&ORDER_FAMILIES)));
+
+        assert!(ensure_order_table(&Tables::default(), mock).unwrap().exists);
+    }
+
+    #[test]
+    fn test_order_table_drift_in_sync() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_table_exists().returning(|_| Ok(true));
+        mock.expect_get_column_descriptors().returning(|_| Ok(descriptors(&ORDER_FAMILIES)));
+
+        let drift = order_table_drift(&Tables::default(), mock).unwrap();
+        assert!(drift.in_sync);
+    }
+
+    #[test]
+    fn test_order_table_drift_reports_missing_and_unexpected() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_table_exists().returning(|_| Ok(true));
+        mock.expect_get_column_descriptors().returning(|_| Ok(descriptors(&["info", "ids", "legacy"])));
+
+        let drift = order_table_drift(&Tables::default(), mock).unwrap();
+        assert!(!drift.in_sync);
+        assert_eq!(drift.missing_families, vec!["addr", "ol"]);
+        assert_eq!(drift.unexpected_families, vec!["legacy"]);
+    }
+
+    #[test]
+    fn test_order_table_drift_missing_table() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_table_exists().returning(|_| Ok(false));
+
+        let drift = order_table_drift(&Tables::default(), mock).unwrap();
+        assert!(!drift.table_exists);
+        assert!(!drift.in_sync);
+        assert_eq!(drift.missing_families.len(), 4);
+    }
+
+    fn descriptors(families: &[&str]) -> BTreeMap<Text, ColumnDescriptor> {
+        families.iter()
+            .map(|f| {
+                let name: Text = format!("{}:", f).into_bytes();
+                let descriptor = ColumnDescriptor { name: Some(name.clone()), max_versions: Some(3), ..Default::default() };
+                (name, descriptor)
+            })
+            .collect()
+    }
+
     #[test]
     fn test_get_tables_single() {
         let exp = "orders";
@@ -417,4 +892,89 @@ mod tests {
         let res = res.unwrap();
         assert!(res.len() == 0);
     }
+
+    #[test]
+    fn test_order_scan_filter() {
+        assert_eq!(OrderScanFilter::default().filter_string(), None);
+        let filter = OrderScanFilter { r_id: Some("r1".into()), c_id: Some("o'brien".into()), state: None, from: Some(10), until: Some(20) };
+        assert_eq!(
+            filter.filter_string().unwrap(),
+            "SingleColumnValueFilter('ids', 'r_id', =, 'binary:r1', true, true) AND SingleColumnValueFilter('ids', 'c_id', =, 'binary:o''brien', true, true)"
+        );
+        let pending = OrderScanFilter { state: Some(OrderState::Pending), ..Default::default() };
+        assert_eq!(pending.filter_string().unwrap(), "SingleColumnValueFilter('info', 'state', =, 'binary:Pending', true, true)");
+        assert!(!filter.in_range(9));
+        assert!(filter.in_range(10));
+        assert!(!filter.in_range(20));
+    }
+
+    /// A fake HBase per Thrift API with the order tables, so the tests below run against both clients.
+    fn fake_servers(tables: &Tables) -> Vec<FakeServer> {
+        FakeHbase::each_api_with_tables(&[order_table_spec(tables), customer_index_spec(tables)])
+    }
+
+    #[test]
+    fn test_add_and_read_orders_on_each_api() {
+        let tables = Tables::default();
+        for server in fake_servers(&tables) {
+            let order = Order::new(
+                vec![Orderline { item_num: 1, price: 100 }, Orderline { item_num: 2, price: 250 }],
+                "Lyngvej 2, 2800 Lyngby".into(), "Rest 1".into(), "cust".into(), "rest".into(), 2800,
+            );
+            let o_id = add_order(&order, &tables, server.connect()).unwrap();
+            assert_eq!(get_order_row(&o_id, &tables, server.connect()).unwrap(), order, "{:?}", server.api);
+            let infos = get_orders_info_by_user("cust".into(), DecodeMode::Strict, &tables, server.connect()).unwrap().0;
+            assert_eq!(infos.len(), 1);
+            assert_eq!((infos[0].o_id.as_str(), infos[0].r_id.as_str()), (o_id.as_str(), "rest"));
+            assert!(get_orders_info_by_user("other".into(), DecodeMode::Strict, &tables, server.connect()).unwrap().0.is_empty());
+            assert_err!(get_order_row("nope", &tables, server.connect()), Err(OrderServiceError::RowNotFound(_)));
+        }
+    }
+
+    #[test]
+    fn test_describe_order_table_on_each_api() {
+        let tables = Tables::default();
+        for server in fake_servers(&tables) {
+            let schema = describe_order_table(&tables, server.connect()).unwrap();
+            assert!(schema.exists);
+            let mut families: Vec<&str> = schema.column_families.iter().map(|f| f.name.as_str()).collect();
+            families.sort();
+            assert_eq!(families, ["addr", "ids", "info", "ol"], "{:?}", server.api);
+            let info = schema.column_families.iter().find(|f| f.name == "info").unwrap();
+            assert_eq!((info.compression.as_deref(), info.bloom_filter_type.as_deref(), info.max_versions), (Some("NONE"), Some("NONE"), Some(3)));
+            assert!(order_table_drift(&tables, server.connect()).unwrap().in_sync);
+        }
+    }
+
+    #[test]
+    fn test_move_order_state_on_each_api() {
+        let tables = Tables::default();
+        for server in fake_servers(&tables) {
+            let order = Order::new(vec![], "Lyngvej 2, 2800 Lyngby".into(), "Rest 1".into(), "cust".into(), "rest".into(), 2800);
+            let o_id = add_order(&order, &tables, server.connect()).unwrap();
+            let mut client = server.connect();
+            assert!(!move_order_state(&mut client, &tables, &o_id, &OrderState::Accepted, &OrderState::Rejected).unwrap());
+            assert!(move_order_state(&mut client, &tables, &o_id, &OrderState::Pending, &OrderState::Accepted).unwrap());
+            set_order_state(&mut client, &tables, &o_id, &OrderState::Rejected).unwrap();
+            assert_eq!(get_order_row(&o_id, &tables, server.connect()).unwrap().state, OrderState::Rejected, "{:?}", server.api);
+        }
+    }
+
+    #[test]
+    fn test_scan_orders_filters_on_each_api() {
+        let tables = Tables::default();
+        for server in fake_servers(&tables) {
+            let mut ids = vec![];
+            for (r_id, state) in [("r1", OrderState::Pending), ("r1", OrderState::Accepted), ("r2", OrderState::Pending)] {
+                let mut order = Order::new(vec![], "Lyngvej 2, 2800 Lyngby".into(), "Rest 1".into(), "cust".into(), r_id.into(), 2800);
+                order.o_id = format!("{}-{}", r_id, state);
+                order.state = state;
+                ids.push(add_order(&order, &tables, server.connect()).unwrap());
+            }
+            let filter = OrderScanFilter { r_id: Some("r1".into()), state: Some(OrderState::Pending), ..Default::default() };
+            let mut found = vec![];
+            let skipped = scan_orders(&mut server.connect(), &tables, &filter, 2, |scanned| { found.push(scanned.order.o_id); Ok(()) }).unwrap();
+            assert_eq!((found, skipped), (vec![ids[0].clone()], 0), "{:?}", server.api);
+        }
+    }
 }
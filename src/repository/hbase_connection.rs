@@ -1,13 +1,14 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, io::{Read, Write}, net::{TcpStream, ToSocketAddrs}, str::FromStr, time::Duration};
 
 use thrift::{
-    protocol::{TBinaryInputProtocol, TBinaryOutputProtocol},
-    transport::{TBufferedReadTransport, TBufferedWriteTransport, TIoChannel, TTcpChannel, WriteHalf, ReadHalf},
+    protocol::{TBinaryInputProtocol, TBinaryOutputProtocol, TCompactInputProtocol, TCompactOutputProtocol, TInputProtocol, TOutputProtocol},
+    transport::{TBufferedReadTransport, TBufferedWriteTransport, TFramedReadTransport, TFramedWriteTransport},
 };
 
-use hbase_thrift::{hbase::{HbaseSyncClient, Text, THbaseSyncClient, BatchMutation, ColumnDescriptor, TRowResult, ScannerID, TScan}, THbaseSyncClientExt, Attributes};
+use hbase_thrift::{hbase::{HbaseSyncClient, Text, THbaseSyncClient, BatchMutation, ColumnDescriptor, Mutation, TRowResult, ScannerID, TScan}, THbaseSyncClientExt, Attributes};
 
-use crate::models::errors::OrderServiceError;
+use crate::models::{errors::OrderServiceError, schema::TableSpec};
+use super::{hbase_thrift2::Thrift2Connection, thrift_http::THttpChannel};
 
 #[cfg_attr(test, mockall::automock)]
 pub trait HbaseClient {
@@ -18,27 +19,180 @@ pub trait HbaseClient {
         row_batches: Vec<BatchMutation>,
         timestamp: Option<i64>,
         attributes: Option<Attributes>,
-    ) -> thrift::Result<()>;
-    fn create_table(&mut self, table_name: &str, column_families: Vec<String>) -> Result<(), OrderServiceError>;
-    fn get_row(&mut self, row_id: &str) -> Result<Vec<TRowResult>, OrderServiceError>;
+    ) -> Result<(), OrderServiceError>;
+    fn create_table(&mut self, spec: &TableSpec) -> Result<(), OrderServiceError>;
+    fn table_exists(&mut self, table_name: &str) -> Result<bool, OrderServiceError>;
+    fn get_column_descriptors(&mut self, table_name: &str) -> Result<BTreeMap<Text, ColumnDescriptor>, OrderServiceError>;
+    fn get_row(&mut self, table_name: &str, row_id: &str) -> Result<Vec<TRowResult>, OrderServiceError>;
+    fn get_rows_with_columns(&mut self, table_name: &str, rows: Vec<Text>, columns: Vec<Text>) -> Result<Vec<TRowResult>, OrderServiceError>;
     fn scanner_open_with_scan(&mut self, table_name: Text, scan: TScan, attributes: BTreeMap<Text, Text>) -> Result<ScannerID, OrderServiceError>;
     fn scanner_get_list(&mut self, id: ScannerID, nb_rows: i32) -> Result<Vec<TRowResult>, OrderServiceError>;
+    fn scanner_close(&mut self, id: ScannerID) -> Result<(), OrderServiceError>;
+    /// Applies `mutation` to `row` only if `column` holds `value`, or does not exist for an empty `value`.
+    /// Returns whether it was applied.
+    fn check_and_put(&mut self, table_name: &str, row: &str, column: &str, value: &[u8], mutation: Mutation) -> Result<bool, OrderServiceError>;
+    /// Replaces the underlying connection, e.g. after a transport error left it in an unknown state.
+    fn reconnect(&mut self) -> Result<(), OrderServiceError>;
+}
+
+impl<H: HbaseClient + ?Sized> HbaseClient for Box<H> {
+    fn get_table_names(&mut self) -> Result<Vec<Text>, OrderServiceError> {
+        (**self).get_table_names()
+    }
+    fn put(
+        &mut self,
+        table_name: &str,
+        row_batches: Vec<BatchMutation>,
+        timestamp: Option<i64>,
+        attributes: Option<Attributes>,
+    ) -> Result<(), OrderServiceError> {
+        (**self).put(table_name, row_batches, timestamp, attributes)
+    }
+    fn create_table(&mut self, spec: &TableSpec) -> Result<(), OrderServiceError> {
+        (**self).create_table(spec)
+    }
+    fn table_exists(&mut self, table_name: &str) -> Result<bool, OrderServiceError> {
+        (**self).table_exists(table_name)
+    }
+    fn get_column_descriptors(&mut self, table_name: &str) -> Result<BTreeMap<Text, ColumnDescriptor>, OrderServiceError> {
+        (**self).get_column_descriptors(table_name)
+    }
+    fn get_row(&mut self, table_name: &str, row_id: &str) -> Result<Vec<TRowResult>, OrderServiceError> {
+        (**self).get_row(table_name, row_id)
+    }
+    fn get_rows_with_columns(&mut self, table_name: &str, rows: Vec<Text>, columns: Vec<Text>) -> Result<Vec<TRowResult>, OrderServiceError> {
+        (**self).get_rows_with_columns(table_name, rows, columns)
+    }
+    fn scanner_open_with_scan(&mut self, table_name: Text, scan: TScan, attributes: BTreeMap<Text, Text>) -> Result<ScannerID, OrderServiceError> {
+        (**self).scanner_open_with_scan(table_name, scan, attributes)
+    }
+    fn scanner_get_list(&mut self, id: ScannerID, nb_rows: i32) -> Result<Vec<TRowResult>, OrderServiceError> {
+        (**self).scanner_get_list(id, nb_rows)
+    }
+    fn scanner_close(&mut self, id: ScannerID) -> Result<(), OrderServiceError> {
+        (**self).scanner_close(id)
+    }
+    fn check_and_put(&mut self, table_name: &str, row: &str, column: &str, value: &[u8], mutation: Mutation) -> Result<bool, OrderServiceError> {
+        (**self).check_and_put(table_name, row, column, value, mutation)
+    }
+    fn reconnect(&mut self) -> Result<(), OrderServiceError> {
+        (**self).reconnect()
+    }
+}
+
+pub(super) type InputProtocol = Box<dyn TInputProtocol + Send>;
+pub(super) type OutputProtocol = Box<dyn TOutputProtocol + Send>;
+
+/// Used for connecting, and for every read and write on the socket, unless set in the `ThriftConfig`.
+pub const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Transport of the HBase Thrift server: `buffered` (default), `framed` (`-framed`) or `http` (`-http`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThriftTransport {
+    #[default]
+    Buffered,
+    Framed,
+    Http,
+}
+
+/// Protocol of the HBase Thrift server: `binary` (default) or `compact` (`-compact`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThriftProtocol {
+    #[default]
+    Binary,
+    Compact,
+}
+
+/// API of the HBase Thrift server: `thrift1` (default, `hbase thrift`) or `thrift2` (`hbase thrift2`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThriftApi {
+    #[default]
+    Thrift1,
+    Thrift2,
+}
+
+/// How to talk to the HBase Thrift server. Must match the flags the server was started with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThriftConfig {
+    pub api: ThriftApi,
+    pub transport: ThriftTransport,
+    pub protocol: ThriftProtocol,
+    /// Connect timeout, and read/write timeout on the socket.
+    pub timeout: Duration,
+}
+
+impl Default for ThriftConfig {
+    fn default() -> Self {
+        Self { api: ThriftApi::default(), transport: ThriftTransport::default(), protocol: ThriftProtocol::default(), timeout: DEFAULT_CALL_TIMEOUT }
+    }
+}
+
+impl FromStr for ThriftApi {
+    type Err = ();
+    fn from_str(input: &str) -> Result<ThriftApi, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "1" | "thrift1" => Ok(ThriftApi::Thrift1),
+            "2" | "thrift2" => Ok(ThriftApi::Thrift2),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for ThriftTransport {
+    type Err = ();
+    fn from_str(input: &str) -> Result<ThriftTransport, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "buffered" => Ok(ThriftTransport::Buffered),
+            "framed" => Ok(ThriftTransport::Framed),
+            "http" => Ok(ThriftTransport::Http),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for ThriftProtocol {
+    type Err = ();
+    fn from_str(input: &str) -> Result<ThriftProtocol, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "binary" => Ok(ThriftProtocol::Binary),
+            "compact" => Ok(ThriftProtocol::Compact),
+            _ => Err(()),
+        }
+    }
 }
 
 pub struct HbaseConnection {
-    connection: HbaseSyncClient<TBinaryInputProtocol<TBufferedReadTransport<ReadHalf<TTcpChannel>>>, TBinaryOutputProtocol<TBufferedWriteTransport<WriteHalf<TTcpChannel>>>>,
+    connection: HbaseSyncClient<InputProtocol, OutputProtocol>,
+    url: String,
+    config: ThriftConfig,
 }
 
 impl HbaseConnection {
     pub fn connect(url: &str) -> Result<Self, OrderServiceError> {
-        let (i_prot, o_prot) = get_protocols(&url)?;
+        Self::connect_with(url, ThriftConfig::default())
+    }
+
+    /// Connects to `url`. A call that waits on the socket for longer than the timeout fails with a transport error.
+    pub fn connect_with(url: &str, config: ThriftConfig) -> Result<Self, OrderServiceError> {
+        let (i_prot, o_prot) = get_protocols(url, config)?;
         Ok(Self{
-            connection: HbaseSyncClient::new(i_prot, o_prot)
+            connection: HbaseSyncClient::new(i_prot, o_prot),
+            url: url.to_owned(),
+            config,
         })
-    }    
+    }
+}
+
+/// Connects with the client for the API in `config`.
+pub fn connect_client(url: &str, config: ThriftConfig) -> Result<Box<dyn HbaseClient + Send>, OrderServiceError> {
+    Ok(match config.api {
+        ThriftApi::Thrift1 => Box::new(HbaseConnection::connect_with(url, config)?),
+        ThriftApi::Thrift2 => Box::new(Thrift2Connection::connect_with(url, config)?),
+    })
 }
 
 impl HbaseClient for HbaseConnection {
+    #[tracing::instrument(name = "hbase.get_table_names", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "getTableNames"), err)]
     fn get_table_names(&mut self) -> Result<Vec<Text>, OrderServiceError> {
         match self.connection.get_table_names() {
             Ok(r) => Ok(r),
@@ -46,63 +200,433 @@ impl HbaseClient for HbaseConnection {
         }
     }
 
+    #[tracing::instrument(name = "hbase.put", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "mutateRows", db.hbase.table = table_name), err)]
     fn put(
         &mut self,
         table_name: &str,
         row_batches: Vec<BatchMutation>,
         timestamp: Option<i64>,
         attributes: Option<Attributes>,
-    ) -> thrift::Result<()> {
-        self.connection.put(&table_name, row_batches, timestamp, attributes)
-    } 
-    fn create_table(&mut self, table_name: &str, column_families: Vec<String>) -> Result<(), OrderServiceError> {
-        match self.connection.table_exists(table_name) {
+    ) -> Result<(), OrderServiceError> {
+        match self.connection.put(table_name, row_batches, timestamp, attributes) {
+            Ok(r) => Ok(r),
+            Err(e) => Err(OrderServiceError::DBError(e)),
+        }
+    }
+    #[tracing::instrument(name = "hbase.create_table", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "createTable", db.hbase.table = spec.name.as_str()), err)]
+    fn create_table(&mut self, spec: &TableSpec) -> Result<(), OrderServiceError> {
+        match self.connection.table_exists(&spec.name) {
             Ok(r) => if r {return Ok(())},
             Err(e) => return Err(OrderServiceError::from(e)),
         };
-        let colfams: Vec<ColumnDescriptor> = column_families.iter().map(|elem| {
+        if !spec.split_keys.is_empty() {
+            // Thrift1 createTable has no split keys argument.
+            return Err(OrderServiceError::InvalidConfig(format!(
+                "the Thrift1 API cannot create {} pre-split into {} regions. Use HBASE_THRIFT_API=thrift2, set ORDER_TABLE_REGIONS=1, or create the table with `order_service table-spec`",
+                spec.name,
+                spec.split_keys.len() + 1
+            )));
+        }
+        let colfams: Vec<ColumnDescriptor> = spec.families.iter().map(|family| {
             ColumnDescriptor {
-                name: Some(elem.to_owned().into()),
-                compression: Some("NONE".into()),
-                time_to_live: Some(0x7fffffff),
-                max_versions: Some(3),
-                bloom_filter_type: Some("NONE".into()),
+                name: Some(family.name.clone().into()),
+                compression: Some(family.compression.clone()),
+                time_to_live: Some(family.time_to_live),
+                max_versions: Some(family.max_versions),
+                bloom_filter_type: Some(family.bloom_filter_type.clone()),
+                in_memory: Some(family.in_memory),
+                block_cache_enabled: Some(true),
                 ..Default::default()
             }
         }).collect();
-        match self.connection.create_table(table_name.into(), colfams) {
+        match self.connection.create_table(spec.name.as_str().into(), colfams) {
             Ok(_) => Ok(()),
             Err(e) => Err(OrderServiceError::DBError(e)),
         }
     }
-    fn get_row(&mut self, row_id: &str) -> Result<Vec<TRowResult>, OrderServiceError> {
-        match self.connection.get_row("orders".into(), row_id.into(), BTreeMap::default()) {
+    #[tracing::instrument(name = "hbase.table_exists", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "getTableNames", db.hbase.table = table_name), err)]
+    fn table_exists(&mut self, table_name: &str) -> Result<bool, OrderServiceError> {
+        match self.connection.table_exists(table_name) {
+            Ok(r) => Ok(r),
+            Err(e) => Err(OrderServiceError::DBError(e)),
+        }
+    }
+    #[tracing::instrument(name = "hbase.get_column_descriptors", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "getColumnDescriptors", db.hbase.table = table_name), err)]
+    fn get_column_descriptors(&mut self, table_name: &str) -> Result<BTreeMap<Text, ColumnDescriptor>, OrderServiceError> {
+        match self.connection.get_column_descriptors(table_name.into()) {
+            Ok(r) => Ok(r),
+            Err(e) => Err(OrderServiceError::DBError(e)),
+        }
+    }
+    #[tracing::instrument(name = "hbase.get_row", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "getRow", db.hbase.table = table_name), err)]
+    fn get_row(&mut self, table_name: &str, row_id: &str) -> Result<Vec<TRowResult>, OrderServiceError> {
+        match self.connection.get_row(table_name.into(), row_id.into(), BTreeMap::default()) {
+            Ok(r) => Ok(r),
+            Err(e) => Err(OrderServiceError::DBError(e)),
+        }
+    }
+    #[tracing::instrument(name = "hbase.get_rows_with_columns", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "getRowsWithColumns", db.hbase.table = table_name, db.hbase.rows = rows.len()), err)]
+    fn get_rows_with_columns(&mut self, table_name: &str, rows: Vec<Text>, columns: Vec<Text>) -> Result<Vec<TRowResult>, OrderServiceError> {
+        match self.connection.get_rows_with_columns(table_name.into(), rows, columns, BTreeMap::default()) {
             Ok(r) => Ok(r),
             Err(e) => Err(OrderServiceError::DBError(e)),
         }
     }
+    #[tracing::instrument(name = "hbase.scanner_open_with_scan", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "scannerOpenWithScan", db.hbase.table = %String::from_utf8_lossy(&table_name)), err)]
     fn scanner_open_with_scan(&mut self, table_name: Text, scan: TScan, attributes: BTreeMap<Text, Text>) -> Result<ScannerID, OrderServiceError> {
         match self.connection.scanner_open_with_scan(table_name, scan, attributes) {
             Ok(r) => Ok(r),
             Err(e) => Err(OrderServiceError::DBError(e)),
         }
     }
+    #[tracing::instrument(name = "hbase.scanner_get_list", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "scannerGetList"), err)]
     fn scanner_get_list(&mut self,id:ScannerID,nb_rows:i32) -> Result<Vec<TRowResult>, OrderServiceError> {
         match self.connection.scanner_get_list(id, nb_rows) {
             Ok(r) => Ok(r),
             Err(e) => Err(OrderServiceError::DBError(e)),
         }
     }
-    
+    #[tracing::instrument(name = "hbase.scanner_close", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "scannerClose"), err)]
+    fn scanner_close(&mut self, id: ScannerID) -> Result<(), OrderServiceError> {
+        match self.connection.scanner_close(id) {
+            Ok(r) => Ok(r),
+            Err(e) => Err(OrderServiceError::DBError(e)),
+        }
+    }
+    #[tracing::instrument(name = "hbase.check_and_put", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "checkAndPut", db.hbase.table = table_name), err)]
+    fn check_and_put(&mut self, table_name: &str, row: &str, column: &str, value: &[u8], mutation: Mutation) -> Result<bool, OrderServiceError> {
+        match self.connection.check_and_put(table_name.into(), row.into(), column.into(), value.to_vec(), mutation, BTreeMap::default()) {
+            Ok(r) => Ok(r),
+            Err(e) => Err(OrderServiceError::DBError(e)),
+        }
+    }
+    #[tracing::instrument(name = "hbase.reconnect", skip_all, fields(otel.kind = "client", db.system = "hbase"), err)]
+    fn reconnect(&mut self) -> Result<(), OrderServiceError> {
+        let (i_prot, o_prot) = get_protocols(&self.url, self.config)?;
+        self.connection = HbaseSyncClient::new(i_prot, o_prot);
+        Ok(())
+    }
+}
+
+pub(super) fn get_protocols(url: &str, config: ThriftConfig) -> Result<(InputProtocol, OutputProtocol), thrift::Error> {
+    let (i_chan, o_chan): (Box<dyn Read + Send>, Box<dyn Write + Send>) = match config.transport {
+        ThriftTransport::Http => {
+            let channel = THttpChannel::connect(url, config.timeout)?;
+            (Box::new(channel.clone()), Box::new(channel))
+        }
+        ThriftTransport::Buffered | ThriftTransport::Framed => {
+            let stream = connect_stream(url, config.timeout)?;
+            stream.set_read_timeout(Some(config.timeout))?;
+            stream.set_write_timeout(Some(config.timeout))?;
+            stream.set_nodelay(true)?;
+            (Box::new(stream.try_clone()?), Box::new(stream))
+        }
+    };
+    Ok(protocols(i_chan, o_chan, config.transport, config.protocol))
+}
+
+/// Wraps both halves of a channel in the transport and protocol of the server.
+pub(super) fn protocols(i_chan: Box<dyn Read + Send>, o_chan: Box<dyn Write + Send>, transport: ThriftTransport, protocol: ThriftProtocol) -> (InputProtocol, OutputProtocol) {
+    let (i_tran, o_tran): (Box<dyn Read + Send>, Box<dyn Write + Send>) = match transport {
+        ThriftTransport::Buffered => (Box::new(TBufferedReadTransport::new(i_chan)), Box::new(TBufferedWriteTransport::new(o_chan))),
+        ThriftTransport::Framed => (Box::new(TFramedReadTransport::new(i_chan)), Box::new(TFramedWriteTransport::new(o_chan))),
+        // The HTTP channel already buffers a whole message.
+        ThriftTransport::Http => (i_chan, o_chan),
+    };
+    match protocol {
+        ThriftProtocol::Binary => (Box::new(TBinaryInputProtocol::new(i_tran, true)), Box::new(TBinaryOutputProtocol::new(o_tran, true))),
+        ThriftProtocol::Compact => (Box::new(TCompactInputProtocol::new(i_tran)), Box::new(TCompactOutputProtocol::new(o_tran))),
+    }
+}
+
+/// Tries every address `url` resolves to, each for at most `timeout`.
+pub(super) fn connect_stream(url: &str, timeout: Duration) -> std::io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in url.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("'{}' did not resolve to any address", url))))
 }
 
-fn get_protocols(url: &str) -> Result<(TBinaryInputProtocol<TBufferedReadTransport<ReadHalf<TTcpChannel>>>, TBinaryOutputProtocol<TBufferedWriteTransport<WriteHalf<TTcpChannel>>>), thrift::Error> {
-    let mut channel = TTcpChannel::new();
-    channel.open(url)?;
-    let (i_chan, o_chan) = channel.split()?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::{BufRead, BufReader, Cursor}, net::TcpListener, sync::{Arc, Mutex}, thread, time::Instant};
+    use crate::models::{orders::{DecodeMode, Order, Orderline}, tables::Tables};
+    use crate::repository::{fake_hbase::FakeHbase, hbase, migrations};
+    use hbase_thrift::hbase::Mutation;
+    use thrift::protocol::{TFieldIdentifier, TListIdentifier, TMessageIdentifier, TMessageType, TStructIdentifier, TType};
+
+    fn config(transport: ThriftTransport, protocol: ThriftProtocol) -> ThriftConfig {
+        ThriftConfig { api: ThriftApi::Thrift1, transport, protocol, timeout: Duration::from_millis(500) }
+    }
+
+    /// Reads a `getTableNames` call and answers it with the single table `orders`.
+    fn answer_get_table_names(i_prot: &mut dyn TInputProtocol, o_prot: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+        let call = i_prot.read_message_begin()?;
+        assert_eq!(call.name, "getTableNames");
+        i_prot.read_struct_begin()?;
+        assert_eq!(i_prot.read_field_begin()?.field_type, TType::Stop);
+        i_prot.read_struct_end()?;
+        i_prot.read_message_end()?;
+
+        o_prot.write_message_begin(&TMessageIdentifier::new("getTableNames", TMessageType::Reply, call.sequence_number))?;
+        o_prot.write_struct_begin(&TStructIdentifier::new("getTableNames_result"))?;
+        o_prot.write_field_begin(&TFieldIdentifier::new("success", TType::List, 0))?;
+        o_prot.write_list_begin(&TListIdentifier::new(TType::String, 1))?;
+        o_prot.write_bytes(b"orders")?;
+        o_prot.write_list_end()?;
+        o_prot.write_field_end()?;
+        o_prot.write_field_stop()?;
+        o_prot.write_struct_end()?;
+        o_prot.write_message_end()?;
+        o_prot.flush()
+    }
+
+    /// Serves `calls` calls over one socket connection, framed or buffered.
+    fn serve_socket(transport: ThriftTransport, protocol: ThriftProtocol, calls: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let (mut i_prot, mut o_prot) = protocols(Box::new(stream.try_clone().unwrap()), Box::new(stream), transport, protocol);
+            for _ in 0..calls {
+                answer_get_table_names(&mut *i_prot, &mut *o_prot).unwrap();
+            }
+        });
+        url
+    }
+
+    /// Serves `calls` HTTP requests over one kept-alive connection, with chunked response bodies.
+    fn serve_http(protocol: ThriftProtocol, calls: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            for _ in 0..calls {
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                assert_eq!(request_line, "POST / HTTP/1.1\r\n");
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end().to_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(v) = line.strip_prefix("content-length:") {
+                        length = v.trim().parse().unwrap();
+                    }
+                    if let Some(v) = line.strip_prefix("content-type:") {
+                        assert_eq!(v.trim(), "application/x-thrift");
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let reply = Arc::new(Mutex::new(Vec::new()));
+                let (mut i_prot, mut o_prot) = protocols(Box::new(Cursor::new(body)), Box::new(SharedBuf(Arc::clone(&reply))), ThriftTransport::Http, protocol);
+                answer_get_table_names(&mut *i_prot, &mut *o_prot).unwrap();
+                let reply = reply.lock().unwrap();
+                let (first, rest) = reply.split_at(reply.len() / 2);
+                let mut response = b"HTTP/1.1 200 OK\r\nContent-Type: application/x-thrift\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+                for chunk in [first, rest] {
+                    response.extend(format!("{:x}\r\n", chunk.len()).as_bytes());
+                    response.extend(chunk);
+                    response.extend(b"\r\n");
+                }
+                response.extend(b"0\r\n\r\n");
+                writer.write_all(&response).unwrap();
+            }
+        });
+        url
+    }
 
-    let i_prot = TBinaryInputProtocol::new(TBufferedReadTransport::new(i_chan), true);
-    let o_prot = TBinaryOutputProtocol::new(TBufferedWriteTransport::new(o_chan), true);
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
 
-    Ok((i_prot, o_prot))
-}
\ No newline at end of file
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_transports_and_protocols_round_trip() {
+        for transport in [ThriftTransport::Buffered, ThriftTransport::Framed, ThriftTransport::Http] {
+            for protocol in [ThriftProtocol::Binary, ThriftProtocol::Compact] {
+                let url = match transport {
+                    ThriftTransport::Http => serve_http(protocol, 2),
+                    _ => serve_socket(transport, protocol, 2),
+                };
+                let mut con = HbaseConnection::connect_with(&url, config(transport, protocol)).unwrap();
+                for _ in 0..2 {
+                    let tables = con.get_table_names();
+                    assert_eq!(tables.unwrap(), vec![b"orders".to_vec()], "{:?} {:?}", transport, protocol);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_mismatched_transport_fails() {
+        // A framed server does not understand a buffered client.
+        let url = serve_socket(ThriftTransport::Framed, ThriftProtocol::Binary, 1);
+        let mut con = HbaseConnection::connect_with(&url, config(ThriftTransport::Buffered, ThriftProtocol::Binary)).unwrap();
+        assert!(con.get_table_names().is_err());
+    }
+
+    #[test]
+    fn test_parse_api_transport_and_protocol() {
+        assert_eq!(ThriftApi::from_str("2"), Ok(ThriftApi::Thrift2));
+        assert_eq!(ThriftApi::from_str("Thrift1"), Ok(ThriftApi::Thrift1));
+        assert_eq!(ThriftApi::from_str("rest"), Err(()));
+        assert_eq!(ThriftTransport::from_str("Framed"), Ok(ThriftTransport::Framed));
+        assert_eq!(ThriftTransport::from_str("http"), Ok(ThriftTransport::Http));
+        assert_eq!(ThriftTransport::from_str("nonblocking"), Err(()));
+        assert_eq!(ThriftProtocol::from_str("COMPACT"), Ok(ThriftProtocol::Compact));
+        assert_eq!(ThriftProtocol::from_str("json"), Err(()));
+    }
+
+    #[test]
+    fn test_connect_fails_on_unresolvable_address() {
+        assert!(HbaseConnection::connect_with("not an address", config(ThriftTransport::Buffered, ThriftProtocol::Binary)).is_err());
+    }
+
+    #[test]
+    fn test_call_times_out_when_server_does_not_answer() {
+        // Accepts the connection but never answers.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = listener.local_addr().unwrap().to_string();
+        let mut con = HbaseConnection::connect_with(&url, ThriftConfig { timeout: Duration::from_millis(200), ..Default::default() }).unwrap();
+        let start = Instant::now();
+        let res = con.get_table_names();
+        assert!(matches!(res, Err(OrderServiceError::DBError(thrift::Error::Transport(_)))), "{:?}", res);
+        assert!(start.elapsed() < Duration::from_secs(2));
+        drop(listener);
+    }
+
+    fn put_cell(row: &str, column: &str, value: &str) -> BatchMutation {
+        BatchMutation {
+            row: Some(row.into()),
+            mutations: Some(vec![Mutation { is_delete: Some(false), column: Some(column.into()), value: Some(value.into()), write_to_w_a_l: Some(true) }]),
+        }
+    }
+
+    fn filter_scan(filter: &str) -> TScan {
+        TScan {
+            start_row: None,
+            stop_row: None,
+            timestamp: None,
+            columns: Some(vec!["info:state".into()]),
+            caching: None,
+            filter_string: Some(filter.into()),
+            batch_size: None,
+            sort_columns: None,
+            reversed: None,
+            cache_blocks: None,
+        }
+    }
+
+    #[test]
+    fn test_orders_round_trip_through_fake_hbase() {
+        for transport in [ThriftTransport::Buffered, ThriftTransport::Framed] {
+            for protocol in [ThriftProtocol::Binary, ThriftProtocol::Compact] {
+                let fake = FakeHbase::new();
+                let url = fake.serve_thrift1(transport, protocol);
+                let connect = || HbaseConnection::connect_with(&url, config(transport, protocol)).unwrap();
+                let tables = Tables::default().with_order_regions(1);
+                hbase::create_order_table(&tables, connect()).unwrap();
+                hbase::create_customer_index_table(&tables, connect()).unwrap();
+                hbase::create_order_table(&tables, connect()).unwrap();
+
+                let order = Order::new(
+                    vec![Orderline { item_num: 1, price: 100 }, Orderline { item_num: 2, price: 250 }],
+                    "Lyngvej 2, 2800 Lyngby".into(), "Rest 1".into(), "cust".into(), "rest".into(), 2800,
+                );
+                let o_id = hbase::add_order(&order, &tables, connect()).unwrap();
+                assert_eq!(hbase::get_order_row(&o_id, &tables, connect()).unwrap(), order, "{:?} {:?}", transport, protocol);
+                let infos = hbase::get_orders_info_by_user("cust".into(), DecodeMode::Strict, &tables, connect()).unwrap().0;
+                assert_eq!(infos.iter().map(|i| i.o_id.as_str()).collect::<Vec<_>>(), [o_id.as_str()]);
+                assert!(hbase::get_orders_info_by_user("other".into(), DecodeMode::Strict, &tables, connect()).unwrap().0.is_empty());
+                assert_eq!(fake.open_scanners(), 0);
+                assert!(matches!(hbase::get_order_row("nope", &tables, connect()), Err(OrderServiceError::RowNotFound(_))));
+            }
+        }
+    }
+
+    #[test]
+    fn test_schema_and_migrations_against_fake_hbase() {
+        let fake = FakeHbase::new();
+        let url = fake.serve_thrift1(ThriftTransport::Buffered, ThriftProtocol::Binary);
+        let tables = Tables::default().with_order_regions(1);
+        let schema = hbase::ensure_order_table(&tables, HbaseConnection::connect(&url).unwrap()).unwrap();
+        assert!(schema.exists);
+        assert!(hbase::order_table_drift(&tables, HbaseConnection::connect(&url).unwrap()).unwrap().in_sync);
+
+        hbase::create_customer_index_table(&tables, HbaseConnection::connect(&url).unwrap()).unwrap();
+        let order = Order::new(vec![], "Lyngvej 2, 2800 Lyngby".into(), "r".into(), "cust".into(), "rest".into(), 2800);
+        hbase::add_order(&order, &tables, HbaseConnection::connect(&url).unwrap()).unwrap();
+        let all = migrations::migrations(&tables);
+        let mut con = HbaseConnection::connect(&url).unwrap();
+        assert_eq!(migrations::run_pending(&mut con, &tables, &all, 2).unwrap().len(), all.len());
+        assert!(migrations::pending(&mut con, &tables, &all).unwrap().is_empty());
+        assert_eq!(fake.open_scanners(), 0);
+    }
+
+    #[test]
+    fn test_create_pre_split_table_fails_but_existing_one_is_kept() {
+        let fake = FakeHbase::new();
+        let url = fake.serve_thrift1(ThriftTransport::Buffered, ThriftProtocol::Binary);
+        let split = Tables::default().with_order_regions(4);
+        let err = hbase::create_order_table(&split, HbaseConnection::connect(&url).unwrap()).unwrap_err();
+        assert!(matches!(err, OrderServiceError::InvalidConfig(_)), "{:?}", err);
+        assert!(!HbaseConnection::connect(&url).unwrap().table_exists(&split.orders).unwrap());
+
+        hbase::create_order_table(&Tables::default().with_order_regions(1), HbaseConnection::connect(&url).unwrap()).unwrap();
+        hbase::create_order_table(&split, HbaseConnection::connect(&url).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_scan_with_single_column_value_filter() {
+        let fake = FakeHbase::new();
+        let url = fake.serve_thrift1(ThriftTransport::Buffered, ThriftProtocol::Binary);
+        let tables = Tables::default().with_order_regions(1);
+        hbase::create_order_table(&tables, HbaseConnection::connect(&url).unwrap()).unwrap();
+        let mut con = HbaseConnection::connect(&url).unwrap();
+        let rows = vec![put_cell("a", "info:state", "Pending"), put_cell("b", "info:state", "Rejected"), put_cell("c", "info:state", "Pending"), put_cell("d", "info:o_time", "1")];
+        con.put(&tables.orders, rows, None, None).unwrap();
+
+        let id = con.scanner_open_with_scan(tables.orders.as_str().into(), filter_scan("SingleColumnValueFilter('info', 'state', =, 'binary:Pending', true, true)"), BTreeMap::new()).unwrap();
+        let found: Vec<Text> = con.scanner_get_list(id, 10).unwrap().into_iter().filter_map(|r| r.row).collect();
+        con.scanner_close(id).unwrap();
+        assert_eq!(found, [b"a".to_vec(), b"c".to_vec()]);
+
+        let err = con.scanner_open_with_scan(tables.orders.as_str().into(), filter_scan("PrefixFilter('a')"), BTreeMap::new()).unwrap_err();
+        assert!(format!("{:?}", err).contains("only SingleColumnValueFilter is supported"), "{:?}", err);
+        assert!(con.scanner_get_list(id, 10).is_err());
+    }
+
+    #[test]
+    fn test_tables_are_shared_between_apis() {
+        let fake = FakeHbase::new();
+        let thrift1 = fake.serve_thrift1(ThriftTransport::Framed, ThriftProtocol::Compact);
+        let thrift2 = fake.serve_thrift2();
+        let tables = Tables::default().with_order_regions(1);
+        hbase::create_order_table(&tables, HbaseConnection::connect_with(&thrift1, config(ThriftTransport::Framed, ThriftProtocol::Compact)).unwrap()).unwrap();
+        hbase::create_customer_index_table(&tables, Thrift2Connection::connect_with(&thrift2, ThriftConfig::default()).unwrap()).unwrap();
+
+        let order = Order::new(vec![Orderline { item_num: 7, price: 70 }], "Lyngvej 2, 2800 Lyngby".into(), "r".into(), "cust".into(), "rest".into(), 2800);
+        let o_id = hbase::add_order(&order, &tables, Thrift2Connection::connect_with(&thrift2, ThriftConfig::default()).unwrap()).unwrap();
+        let con = HbaseConnection::connect_with(&thrift1, config(ThriftTransport::Framed, ThriftProtocol::Compact)).unwrap();
+        assert_eq!(hbase::get_order_row(&o_id, &tables, con).unwrap(), order);
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::BTreeMap;
+
+use hbase_thrift::{hbase::{BatchMutation, ColumnDescriptor, Mutation, ScannerID, TCell, TRowResult, Text}, Attributes};
+use thrift::protocol::{
+    verify_expected_message_type, verify_expected_sequence_number, verify_expected_service_call, TInputProtocol,
+    TMessageIdentifier, TMessageType, TOutputProtocol, TStructIdentifier, TType,
+};
+
+use crate::models::{errors::OrderServiceError, schema::TableSpec};
+use super::{
+    hbase_connection::{get_protocols, HbaseClient, InputProtocol, OutputProtocol, ThriftConfig},
+    thrift2_types::{
+        enum_name, enum_value, read_fields, read_struct_list, write_bool_field, write_bytes_field,
+        write_bytes_list_field, write_i32_field, write_struct_field, write_struct_list_field, Bytes, TColumn,
+        TColumnFamilyDescriptor, TColumnValue, TDelete, TGet, TPut, TResult, TScan, TServiceError, TTableDescriptor,
+        TTableName, TTimeRange, ThriftStruct, BLOOM_FILTER_TYPES, COMPRESSION_ALGORITHMS, DELETE_COLUMNS,
+    },
+};
+
+/// An `HbaseClient` over the Thrift2 API (`THBaseService`, `hbase thrift2`).
+/// Takes and returns the same Thrift1 types as `HbaseConnection`, so the repository code works with both.
+/// Unlike Thrift1, tables are created with their split keys.
+pub struct Thrift2Connection {
+    i_prot: InputProtocol,
+    o_prot: OutputProtocol,
+    sequence_number: i32,
+    url: String,
+    config: ThriftConfig,
+}
+
+impl Thrift2Connection {
+    pub fn connect_with(url: &str, config: ThriftConfig) -> Result<Self, OrderServiceError> {
+        let (i_prot, o_prot) = get_protocols(url, config)?;
+        Ok(Self { i_prot, o_prot, sequence_number: 0, url: url.to_owned(), config })
+    }
+
+    /// Sends `name` with the argument fields written by `args`, and reads the reply.
+    /// `success` reads field 0 of the result, which is absent for void methods.
+    /// `TIOError` and `TIllegalArgument` become `thrift::Error::User`.
+    fn call<T>(
+        &mut self,
+        name: &str,
+        args: impl FnOnce(&mut dyn TOutputProtocol) -> thrift::Result<()>,
+        success: impl FnOnce(&mut dyn TInputProtocol) -> thrift::Result<T>,
+    ) -> thrift::Result<Option<T>> {
+        self.sequence_number += 1;
+        let o_prot = &mut *self.o_prot;
+        o_prot.write_message_begin(&TMessageIdentifier::new(name, TMessageType::Call, self.sequence_number))?;
+        o_prot.write_struct_begin(&TStructIdentifier::new(format!("{}_args", name)))?;
+        args(o_prot)?;
+        o_prot.write_field_stop()?;
+        o_prot.write_struct_end()?;
+        o_prot.write_message_end()?;
+        o_prot.flush()?;
+
+        let i_prot = &mut *self.i_prot;
+        let message = i_prot.read_message_begin()?;
+        verify_expected_sequence_number(self.sequence_number, message.sequence_number)?;
+        verify_expected_service_call(name, &message.name)?;
+        if message.message_type == TMessageType::Exception {
+            let error = thrift::Error::read_application_error_from_in_protocol(i_prot)?;
+            i_prot.read_message_end()?;
+            return Err(thrift::Error::Application(error));
+        }
+        verify_expected_message_type(TMessageType::Reply, message.message_type)?;
+        let mut success = Some(success);
+        let mut value = None;
+        let mut error = None;
+        read_fields(i_prot, |i, id, t| Ok(match (id, t) {
+            (0, _) => match success.take() {
+                Some(read) => { value = Some(read(i)?); true }
+                None => false,
+            },
+            (1, TType::Struct) => { error = Some(TServiceError { kind: "TIOError", ..TServiceError::read(i)? }); true }
+            (2, TType::Struct) => { error = Some(TServiceError { kind: "TIllegalArgument", ..TServiceError::read(i)? }); true }
+            _ => false,
+        }))?;
+        i_prot.read_message_end()?;
+        match error {
+            Some(error) => Err(thrift::Error::User(Box::new(error))),
+            None => Ok(value),
+        }
+    }
+
+    fn call_returning<T>(
+        &mut self,
+        name: &str,
+        args: impl FnOnce(&mut dyn TOutputProtocol) -> thrift::Result<()>,
+        success: impl FnOnce(&mut dyn TInputProtocol) -> thrift::Result<T>,
+    ) -> thrift::Result<T> {
+        self.call(name, args, success)?.ok_or_else(|| {
+            thrift::Error::Application(thrift::ApplicationError::new(
+                thrift::ApplicationErrorKind::MissingResult,
+                format!("{} returned no result", name),
+            ))
+        })
+    }
+
+    fn table_exists_thrift(&mut self, table_name: &str) -> thrift::Result<bool> {
+        let table = TTableName::parse(table_name);
+        self.call_returning("tableExists", |o| write_struct_field(o, 1, &table), |i| i.read_bool())
+    }
+}
+
+/// `family:qualifier`, or a whole family for `family` or `family:`.
+fn to_column(column: &[u8]) -> TColumn {
+    match column.iter().position(|b| *b == b':') {
+        Some(at) if at + 1 < column.len() => TColumn { family: column[..at].to_vec(), qualifier: Some(column[at + 1..].to_vec()), timestamp: None },
+        Some(at) => TColumn { family: column[..at].to_vec(), qualifier: None, timestamp: None },
+        None => TColumn { family: column.to_vec(), qualifier: None, timestamp: None },
+    }
+}
+
+/// A Thrift1 row, or `None` for the empty result Thrift2 returns for a missing row.
+fn to_row_result(result: TResult) -> Option<TRowResult> {
+    if result.column_values.is_empty() {
+        return None;
+    }
+    let mut columns = BTreeMap::new();
+    for value in result.column_values {
+        // Results are newest first, keep the first version of each column.
+        columns.entry([value.family.as_slice(), b":", value.qualifier.as_slice()].concat())
+            .or_insert(TCell { value: Some(value.value), timestamp: value.timestamp });
+    }
+    Some(TRowResult { row: result.row, columns: Some(columns), sorted_columns: None })
+}
+
+fn to_row_results(results: Vec<TResult>) -> Vec<TRowResult> {
+    results.into_iter().filter_map(to_row_result).collect()
+}
+
+/// Splits Thrift1 mutations into puts and deletes, which are separate calls in Thrift2.
+fn to_puts_and_deletes(row_batches: Vec<BatchMutation>, timestamp: Option<i64>, attributes: Option<Attributes>) -> (Vec<TPut>, Vec<TDelete>) {
+    let mut puts = vec![];
+    let mut deletes = vec![];
+    for batch in row_batches {
+        let row = batch.row.unwrap_or_default();
+        let (deleted, written): (Vec<_>, Vec<_>) = batch.mutations.unwrap_or_default().into_iter().partition(|m| m.is_delete == Some(true));
+        if !written.is_empty() {
+            let column_values = written.into_iter().map(|m| {
+                let column = to_column(&m.column.unwrap_or_default());
+                TColumnValue { family: column.family, qualifier: column.qualifier.unwrap_or_default(), value: m.value.unwrap_or_default(), timestamp: None }
+            }).collect();
+            puts.push(TPut { row: row.clone(), column_values, timestamp, attributes: attributes.clone() });
+        }
+        if !deleted.is_empty() {
+            let columns = deleted.into_iter().map(|m| to_column(&m.column.unwrap_or_default())).collect();
+            deletes.push(TDelete { row, columns: Some(columns), timestamp, delete_type: Some(DELETE_COLUMNS) });
+        }
+    }
+    (puts, deletes)
+}
+
+fn to_scan(scan: hbase_thrift::hbase::TScan) -> TScan {
+    TScan {
+        start_row: scan.start_row,
+        stop_row: scan.stop_row,
+        columns: scan.columns.map(|columns| columns.iter().map(|c| to_column(c)).collect()),
+        caching: scan.caching.filter(|c| *c > 0),
+        // Thrift1 reads the versions up to the timestamp.
+        time_range: scan.timestamp.map(|max_stamp| TTimeRange { min_stamp: 0, max_stamp }),
+        filter_string: scan.filter_string.filter(|f| !f.is_empty()),
+        batch_size: scan.batch_size.filter(|b| *b > 0),
+        reversed: scan.reversed,
+        cache_blocks: scan.cache_blocks,
+    }
+}
+
+fn to_family_descriptor(spec: &crate::models::schema::ColumnFamilySpec) -> Result<TColumnFamilyDescriptor, OrderServiceError> {
+    let compression = enum_value(&COMPRESSION_ALGORITHMS, &spec.compression)
+        .ok_or_else(|| OrderServiceError::InvalidConfig(format!("compression '{}' is not supported over Thrift2", spec.compression)))?;
+    let bloom = enum_value(&BLOOM_FILTER_TYPES, &spec.bloom_filter_type)
+        .ok_or_else(|| OrderServiceError::InvalidConfig(format!("bloom filter '{}' is not supported over Thrift2", spec.bloom_filter_type)))?;
+    Ok(TColumnFamilyDescriptor {
+        name: spec.name.as_bytes().to_vec(),
+        bloom_filter_type: Some(bloom),
+        compression_type: Some(compression),
+        max_versions: Some(spec.max_versions),
+        time_to_live: Some(spec.time_to_live),
+        block_cache_enabled: Some(true),
+        in_memory: Some(spec.in_memory),
+    })
+}
+
+fn to_column_descriptor(family: TColumnFamilyDescriptor) -> (Text, ColumnDescriptor) {
+    let name: Text = [family.name.as_slice(), b":"].concat();
+    let descriptor = ColumnDescriptor {
+        name: Some(name.clone()),
+        max_versions: family.max_versions,
+        compression: family.compression_type.map(|c| enum_name(&COMPRESSION_ALGORITHMS, c)),
+        in_memory: family.in_memory,
+        bloom_filter_type: family.bloom_filter_type.map(|b| enum_name(&BLOOM_FILTER_TYPES, b)),
+        bloom_filter_vector_size: None,
+        bloom_filter_nb_hashes: None,
+        block_cache_enabled: family.block_cache_enabled,
+        time_to_live: family.time_to_live,
+    };
+    (name, descriptor)
+}
+
+impl HbaseClient for Thrift2Connection {
+    #[tracing::instrument(name = "hbase.get_table_names", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "getTableNamesByPattern"), err)]
+    fn get_table_names(&mut self) -> Result<Vec<Text>, OrderServiceError> {
+        let names: Vec<TTableName> = self.call_returning(
+            "getTableNamesByPattern",
+            |o| write_bool_field(o, 2, false),
+            |i| read_struct_list(i),
+        )?;
+        Ok(names.iter().map(TTableName::to_name).collect())
+    }
+
+    #[tracing::instrument(name = "hbase.put", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "putMultiple", db.hbase.table = table_name), err)]
+    fn put(
+        &mut self,
+        table_name: &str,
+        row_batches: Vec<BatchMutation>,
+        timestamp: Option<i64>,
+        attributes: Option<Attributes>,
+    ) -> Result<(), OrderServiceError> {
+        let (puts, deletes) = to_puts_and_deletes(row_batches, timestamp, attributes);
+        let table = table_name.as_bytes();
+        if !puts.is_empty() {
+            self.call("putMultiple", |o| {
+                write_bytes_field(o, 1, table)?;
+                write_struct_list_field(o, 2, &puts)
+            }, |_| Ok(()))?;
+        }
+        if !deletes.is_empty() {
+            // Returns the deletes that were not applied.
+            let failed: Vec<TDelete> = self.call_returning("deleteMultiple", |o| {
+                write_bytes_field(o, 1, table)?;
+                write_struct_list_field(o, 2, &deletes)
+            }, |i| read_struct_list(i))?;
+            if !failed.is_empty() {
+                return Err(OrderServiceError::DBError(thrift::Error::User(Box::new(TServiceError {
+                    kind: "TIOError",
+                    message: Some(format!("{} deletes were not applied", failed.len())),
+                }))));
+            }
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "hbase.create_table", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "createTable", db.hbase.table = spec.name.as_str()), err)]
+    fn create_table(&mut self, spec: &TableSpec) -> Result<(), OrderServiceError> {
+        if self.table_exists_thrift(&spec.name)? {
+            return Ok(());
+        }
+        let descriptor = TTableDescriptor {
+            table_name: TTableName::parse(&spec.name),
+            columns: Some(spec.families.iter().map(to_family_descriptor).collect::<Result<_, _>>()?),
+        };
+        let split_keys: Vec<Bytes> = spec.split_keys.iter().map(|k| k.as_bytes().to_vec()).collect();
+        self.call("createTable", |o| {
+            write_struct_field(o, 1, &descriptor)?;
+            write_bytes_list_field(o, 2, &split_keys)
+        }, |_| Ok(()))?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "hbase.table_exists", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "tableExists", db.hbase.table = table_name), err)]
+    fn table_exists(&mut self, table_name: &str) -> Result<bool, OrderServiceError> {
+        Ok(self.table_exists_thrift(table_name)?)
+    }
+
+    #[tracing::instrument(name = "hbase.get_column_descriptors", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "getTableDescriptor", db.hbase.table = table_name), err)]
+    fn get_column_descriptors(&mut self, table_name: &str) -> Result<BTreeMap<Text, ColumnDescriptor>, OrderServiceError> {
+        let table = TTableName::parse(table_name);
+        let descriptor = self.call_returning("getTableDescriptor", |o| write_struct_field(o, 1, &table), TTableDescriptor::read)?;
+        Ok(descriptor.columns.unwrap_or_default().into_iter().map(to_column_descriptor).collect())
+    }
+
+    #[tracing::instrument(name = "hbase.get_row", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "get", db.hbase.table = table_name), err)]
+    fn get_row(&mut self, table_name: &str, row_id: &str) -> Result<Vec<TRowResult>, OrderServiceError> {
+        let get = TGet { row: row_id.as_bytes().to_vec(), columns: None, max_versions: None };
+        let result = self.call_returning("get", |o| {
+            write_bytes_field(o, 1, table_name.as_bytes())?;
+            write_struct_field(o, 2, &get)
+        }, TResult::read)?;
+        Ok(to_row_result(result).into_iter().collect())
+    }
+
+    #[tracing::instrument(name = "hbase.get_rows_with_columns", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "getMultiple", db.hbase.table = table_name, db.hbase.rows = rows.len()), err)]
+    fn get_rows_with_columns(&mut self, table_name: &str, rows: Vec<Text>, columns: Vec<Text>) -> Result<Vec<TRowResult>, OrderServiceError> {
+        let columns: Vec<TColumn> = columns.iter().map(|c| to_column(c)).collect();
+        let gets: Vec<TGet> = rows.into_iter()
+            .map(|row| TGet { row, columns: Some(columns.clone()).filter(|c| !c.is_empty()), max_versions: None })
+            .collect();
+        let results = self.call_returning("getMultiple", |o| {
+            write_bytes_field(o, 1, table_name.as_bytes())?;
+            write_struct_list_field(o, 2, &gets)
+        }, |i| read_struct_list(i))?;
+        Ok(to_row_results(results))
+    }
+
+    #[tracing::instrument(name = "hbase.scanner_open_with_scan", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "openScanner", db.hbase.table = %String::from_utf8_lossy(&table_name)), err)]
+    fn scanner_open_with_scan(&mut self, table_name: Text, scan: hbase_thrift::hbase::TScan, attributes: BTreeMap<Text, Text>) -> Result<ScannerID, OrderServiceError> {
+        if !attributes.is_empty() {
+            tracing::warn!("scan attributes are not sent over Thrift2");
+        }
+        let scan = to_scan(scan);
+        Ok(self.call_returning("openScanner", |o| {
+            write_bytes_field(o, 1, &table_name)?;
+            write_struct_field(o, 2, &scan)
+        }, |i| i.read_i32())?)
+    }
+
+    #[tracing::instrument(name = "hbase.scanner_get_list", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "getScannerRows"), err)]
+    fn scanner_get_list(&mut self, id: ScannerID, nb_rows: i32) -> Result<Vec<TRowResult>, OrderServiceError> {
+        let results = self.call_returning("getScannerRows", |o| {
+            write_i32_field(o, 1, id)?;
+            write_i32_field(o, 2, nb_rows)
+        }, |i| read_struct_list(i))?;
+        Ok(to_row_results(results))
+    }
+
+    #[tracing::instrument(name = "hbase.scanner_close", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "closeScanner"), err)]
+    fn scanner_close(&mut self, id: ScannerID) -> Result<(), OrderServiceError> {
+        self.call("closeScanner", |o| write_i32_field(o, 1, id), |_| Ok(()))?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "hbase.check_and_put", skip_all, fields(otel.kind = "client", db.system = "hbase", db.operation = "checkAndPut", db.hbase.table = table_name), err)]
+    fn check_and_put(&mut self, table_name: &str, row: &str, column: &str, value: &[u8], mutation: Mutation) -> Result<bool, OrderServiceError> {
+        let checked = to_column(column.as_bytes());
+        let written = to_column(&mutation.column.unwrap_or_default());
+        let put = TPut {
+            row: row.as_bytes().to_vec(),
+            column_values: vec![TColumnValue { family: written.family, qualifier: written.qualifier.unwrap_or_default(), value: mutation.value.unwrap_or_default(), timestamp: None }],
+            timestamp: None,
+            attributes: None,
+        };
+        Ok(self.call_returning("checkAndPut", |o| {
+            write_bytes_field(o, 1, table_name.as_bytes())?;
+            write_bytes_field(o, 2, row.as_bytes())?;
+            write_bytes_field(o, 3, &checked.family)?;
+            write_bytes_field(o, 4, &checked.qualifier.unwrap_or_default())?;
+            // No value checks that the column does not exist.
+            if !value.is_empty() {
+                write_bytes_field(o, 5, value)?;
+            }
+            write_struct_field(o, 6, &put)
+        }, |i| i.read_bool())?)
+    }
+
+    #[tracing::instrument(name = "hbase.reconnect", skip_all, fields(otel.kind = "client", db.system = "hbase"), err)]
+    fn reconnect(&mut self) -> Result<(), OrderServiceError> {
+        let (i_prot, o_prot) = get_protocols(&self.url, self.config)?;
+        self.i_prot = i_prot;
+        self.o_prot = o_prot;
+        self.sequence_number = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{schema::salt_split_keys, tables::Tables};
+    use crate::repository::{hbase, resilience::is_transient, fake_hbase::FakeHbase};
+
+    fn connect(url: &str) -> Thrift2Connection {
+        Thrift2Connection::connect_with(url, ThriftConfig::default()).unwrap()
+    }
+
+    fn fake_with_tables(tables: &Tables) -> (FakeHbase, String) {
+        let fake = FakeHbase::new();
+        let url = fake.serve_thrift2();
+        hbase::create_order_table(tables, connect(&url)).unwrap();
+        hbase::create_customer_index_table(tables, connect(&url)).unwrap();
+        (fake, url)
+    }
+
+    #[test]
+    fn test_create_table_pre_splits_and_is_idempotent() {
+        let tables = Tables::default();
+        let (fake, url) = fake_with_tables(&tables);
+        let expected: Vec<Bytes> = salt_split_keys(tables.order_regions).into_iter().map(String::into_bytes).collect();
+        assert!(!expected.is_empty());
+        assert_eq!(fake.split_keys(&tables.orders), expected);
+        hbase::create_order_table(&tables, connect(&url)).unwrap();
+
+        let mut names: Vec<String> = hbase::get_tables(connect(&url)).unwrap().into_iter().map(|t| t.table_name).collect();
+        names.sort();
+        let mut expected_names = vec![tables.orders.clone(), tables.customer_index.clone()];
+        expected_names.sort();
+        assert_eq!(names, expected_names);
+    }
+
+    #[test]
+    fn test_missing_table_is_not_transient() {
+        let url = FakeHbase::new().serve_thrift2();
+        let err = connect(&url).get_row("missing", "row").unwrap_err();
+        assert!(!is_transient(&err));
+        assert!(err.to_string().contains("TableNotFoundException"));
+    }
+
+    #[test]
+    fn test_delete_mutations() {
+        let tables = Tables::default();
+        let (_, url) = fake_with_tables(&tables);
+        let mut client = connect(&url);
+        let write = |column: &str, value: Option<&str>| BatchMutation {
+            row: Some(b"row".to_vec()),
+            mutations: Some(vec![hbase_thrift::hbase::Mutation {
+                is_delete: Some(value.is_none()),
+                column: Some(column.as_bytes().to_vec()),
+                value: value.map(|v| v.as_bytes().to_vec()),
+                write_to_w_a_l: Some(true),
+            }]),
+        };
+        client.put(&tables.orders, vec![write("info:a", Some("1")), write("info:b", Some("2"))], Some(1), None).unwrap();
+        client.put(&tables.orders, vec![write("info:a", None)], Some(2), None).unwrap();
+        let rows = client.get_row(&tables.orders, "row").unwrap();
+        let columns: Vec<&Text> = rows[0].columns.as_ref().unwrap().keys().collect();
+        assert_eq!(columns, [&b"info:b".to_vec()]);
+    }
+
+    #[test]
+    fn test_check_and_put() {
+        let tables = Tables::default();
+        let (_, url) = fake_with_tables(&tables);
+        let mut client = connect(&url);
+        let state = |value: &str| hbase_thrift::hbase::Mutation {
+            is_delete: Some(false),
+            column: Some(b"info:state".to_vec()),
+            value: Some(value.as_bytes().to_vec()),
+            write_to_w_a_l: Some(true),
+        };
+        assert!(client.check_and_put(&tables.orders, "row", "info:state", b"", state("Pending")).unwrap());
+        assert!(!client.check_and_put(&tables.orders, "row", "info:state", b"", state("Accepted")).unwrap());
+        assert!(!client.check_and_put(&tables.orders, "row", "info:state", b"Accepted", state("Rejected")).unwrap());
+        assert!(client.check_and_put(&tables.orders, "row", "info:state", b"Pending", state("Rejected")).unwrap());
+        let rows = client.get_row(&tables.orders, "row").unwrap();
+        assert_eq!(rows[0].columns.as_ref().unwrap()[&b"info:state".to_vec()].value.as_deref(), Some(&b"Rejected"[..]));
+    }
+
+    #[test]
+    fn test_to_column() {
+        assert_eq!(to_column(b"info:o_time"), TColumn { family: b"info".to_vec(), qualifier: Some(b"o_time".to_vec()), timestamp: None });
+        assert_eq!(to_column(b"info:"), TColumn { family: b"info".to_vec(), qualifier: None, timestamp: None });
+        assert_eq!(to_column(b"info"), TColumn { family: b"info".to_vec(), qualifier: None, timestamp: None });
+    }
+}
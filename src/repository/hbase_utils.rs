@@ -1,10 +1,6 @@
 use hbase_thrift::{hbase::{BatchMutation, TScan}, MutationBuilder, BatchMutationBuilder};
 
-use rand::prelude::*;
-use rand_seeder::{Seeder};
-use rand_pcg::Pcg64;
-
-use crate::models::{orders::{Order, Orderline, OrderBuilder}};
+use crate::models::{orders::{Order, Orderline, OrderBuilder}, errors::RowIssue};
 
 pub(crate) fn create_mutation_from_order(order: &Order) -> (BatchMutation, String) {
     //let id_mut = create_cell_mutation("info", "o_id", order.o_id.to_string());
@@ -26,7 +22,7 @@ pub(crate) fn create_mutation_from_order(order: &Order) -> (BatchMutation, Strin
 }
 
 
-fn create_cell_mutation(column_family: impl Into<String>, column: impl Into<String>,  value: impl Into<Vec<u8>>) -> MutationBuilder {
+pub(crate) fn create_cell_mutation(column_family: impl Into<String>, column: impl Into<String>,  value: impl Into<Vec<u8>>) -> MutationBuilder {
     let mut mutation = MutationBuilder::default();
     mutation.column(column_family, column);
     mutation.value(value);
@@ -43,18 +39,29 @@ pub fn create_order_builder_from_hbase_row(
     };
     order_builder.o_id = get_value(hbase_row.row.clone());
     for (col, cell) in cols.iter() {
-        let col = col.clone();
-        let cell = cell.clone().value;
-        let (column, value) = match get_column_and_value(&col, cell) {
+        let column = match get_column(col) {
+            Some(v) => v,
+            None => {
+                order_builder.issues.push(RowIssue::UnknownColumn(String::from_utf8_lossy(col).into_owned()));
+                continue;
+            }
+        };
+        let value = match get_value(cell.value.clone()) {
             Some(v) => v,
-            None => continue,
+            None => {
+                order_builder.issues.push(RowIssue::InvalidValue {
+                    column: format!("{}:{}", column.0, column.1),
+                    value: String::from_utf8_lossy(cell.value.as_deref().unwrap_or_default()).into_owned(),
+                    reason: "value is missing or not valid UTF-8".into(),
+                });
+                continue;
+            }
         };
         set_order_field(column, value, &mut order_builder);
     }
     order_builder
 }
 
-
 // fn get_column(col: &Vec<u8>) -> Option<(String, String)> {
 //     let column: String = match std::str::from_utf8(col) {
 //         Ok(colname) => colname.to_string(),
@@ -83,44 +90,88 @@ fn get_value(cell: Option<Vec<u8>>) -> Option<String> {
     })
 }
 
-fn get_column_and_value(col: &Vec<u8>, cell: Option<Vec<u8>>) -> Option<((String, String),String)> {
-    Some((get_column(&col)?, get_value(cell)?))
-}
-
 fn set_order_field(field: (String, String), val: String, order_builder: &mut OrderBuilder) {
     let col: (&str, &str) = (&field.0, &field.1);
     match col {
-        ("info", "o_id") => order_builder.o_id = Some(val.clone()),
-        ("info", "o_time") => order_builder.ordertime = Some(val.clone()),
-        ("info", "state") => order_builder.state = Some(val.clone()),
-        ("ids", "c_id") => order_builder.c_id = Some(val.clone()),
-        ("ids", "r_id") => order_builder.r_id = Some(val.clone()),
-        ("addr", "c_addr") => order_builder.cust_addr = Some(val.clone()),
-        ("addr", "r_addr") => order_builder.rest_addr = Some(val.clone()),
-        ("addr", "postal") => order_builder.postal_code = val.parse::<u32>().ok(),
+        ("info", "o_id") => order_builder.o_id = Some(val),
+        ("info", "o_time") => order_builder.ordertime = Some(val),
+        ("info", "state") => order_builder.state = Some(val),
+        ("ids", "c_id") => order_builder.c_id = Some(val),
+        ("ids", "r_id") => order_builder.r_id = Some(val),
+        ("addr", "c_addr") => order_builder.cust_addr = Some(val),
+        ("addr", "r_addr") => order_builder.rest_addr = Some(val),
+        ("addr", "postal") => match val.parse::<u32>() {
+            Ok(v) => order_builder.postal_code = Some(v),
+            Err(e) => order_builder.issues.push(invalid_value(&field, val, e.to_string())),
+        },
         ("ol", _) => {
-            let result = <Orderline as std::str::FromStr>::from_str(&val.clone());
+            let result = <Orderline as std::str::FromStr>::from_str(&val);
             match result {
                 Ok(ol) => order_builder.orderlines.push(ol),
-                Err(_) => println!("Badly formatted Orderline."),
+                Err(e) => order_builder.issues.push(invalid_value(&field, val, e.to_string())),
             }
         }
-        (_, _) => println!("Unknown column type"),
+        (_, _) => order_builder.issues.push(RowIssue::UnknownColumn(format!("{}:{}", field.0, field.1))),
     }
 }
 
-pub fn build_single_column_filter(colfam: &str, col: &str, operator: &str, value: &str) -> String {
-    format!("SingleColumnValueFilter('{colfam}', '{col}', {operator}, 'binaryprefix:{value}')")
+fn invalid_value(field: &(String, String), value: String, reason: String) -> RowIssue {
+    RowIssue::InvalidValue { column: format!("{}:{}", field.0, field.1), value, reason }
 }
 
-pub fn create_scan(columns_to_fetch: Vec<Vec<u8>>, filter_colfam: &str, filter_col: &str, filter_val: &str) -> TScan {
+/// Row key of the customer index. The order time is reversed so a prefix scan on the customer
+/// returns the newest orders first, and the o_id keeps keys unique for orders placed in the same millisecond.
+pub(crate) fn customer_index_key(c_id: &str, ordertime_millis: i64, o_id: &str) -> String {
+    format!("{}{:019}|{}", customer_index_prefix(c_id), i64::MAX - ordertime_millis, o_id)
+}
+
+/// Row key prefix of a customer's index entries. The c_id is preceded by its length in bytes, so the prefix of
+/// customer `a` does not match the entries of customer `a|b`.
+pub(crate) fn customer_index_prefix(c_id: &str) -> String {
+    format!("{}:{}|", c_id.len(), c_id)
+}
+
+pub(crate) fn create_customer_index_mutation(c_id: &str, o_id: &str, ordertime_millis: i64) -> BatchMutation {
+    let mutation = create_cell_mutation("o", "o_id", o_id);
+    <BatchMutationBuilder>::default().row(customer_index_key(c_id, ordertime_millis, o_id)).mutations(vec![mutation]).build()
+}
+
+/// Milliseconds since the epoch of an RFC 3339 order time.
+pub(crate) fn ordertime_millis(ordertime: &str) -> Option<i64> {
+    chrono::DateTime::parse_from_rfc3339(ordertime).ok().map(|t| t.timestamp_millis())
+}
+
+/// Scan over every row whose key starts with `prefix`, fetching `columns_to_fetch`.
+pub fn create_prefix_scan(columns_to_fetch: Vec<Vec<u8>>, prefix: &str) -> TScan {
+    let start_row = prefix.as_bytes().to_vec();
+    let mut stop_row = start_row.clone();
+    if let Some(last) = stop_row.last_mut() {
+        *last += 1;
+    }
+    // Not built from `TScan::default()`, which sets a zero timestamp and an empty filter string.
     TScan {
         columns: Some(columns_to_fetch),
-        filter_string: Some(build_single_column_filter(filter_colfam, filter_col, "=", &filter_val).into()),
+        filter_string: None,
+        start_row: Some(start_row),
+        stop_row: Some(stop_row),
+        timestamp: None,
+        caching: None,
+        batch_size: Some(0),
+        sort_columns: Some(false),
+        reversed: Some(false),
+        cache_blocks: Some(false),
+    }
+}
+
+/// Scan over the whole table, fetching `columns_to_fetch` and `caching` rows per round trip.
+pub fn create_full_scan(columns_to_fetch: Vec<Vec<u8>>, caching: i32) -> TScan {
+    TScan {
+        columns: Some(columns_to_fetch),
+        filter_string: None,
         start_row: None,
         stop_row: None,
         timestamp: None,
-        caching: None,
+        caching: Some(caching),
         batch_size: Some(0),
         sort_columns: Some(false),
         reversed: Some(false),
@@ -129,6 +180,7 @@ pub fn create_scan(columns_to_fetch: Vec<Vec<u8>>, filter_colfam: &str, filter_c
 }
 
 // Only for testing purposes 
+#[cfg(test)]
 pub(crate) fn order_to_trowresult(order: Order) -> hbase_thrift::hbase::TRowResult {
     let mut columns: std::collections::BTreeMap<hbase_thrift::hbase::Text, hbase_thrift::hbase::TCell> = std::collections::BTreeMap::new();
     columns.insert("info:o_time".as_bytes().to_vec(), _to_tcell(&order.ordertime));
@@ -144,11 +196,13 @@ pub(crate) fn order_to_trowresult(order: Order) -> hbase_thrift::hbase::TRowResu
     hbase_thrift::hbase::TRowResult { row: Some(order.o_id.as_bytes().to_vec()), columns: Some(columns), sorted_columns: None }
 }
 
+#[cfg(test)]
 pub(crate) fn _to_tcell(val: &str) -> hbase_thrift::hbase::TCell {
     hbase_thrift::hbase::TCell { value: Some(val.as_bytes().to_vec()), timestamp: Some(0) }
 }
 
 #[cfg(test)]
+#[allow(clippy::len_zero)]
 mod tests {
     use std::{str::FromStr};
 
@@ -259,32 +313,51 @@ mod tests {
     }
 
     #[test]
-    fn test_create_scan_with_cols() {
-        let cols = vec!["col1:col".into(), "col2".into()];
-        let colfam = "testcolfam";
-        let col = "testcol";
-        let val = "testval";
-        let scan = create_scan(cols.clone(), colfam, col, val);
+    fn test_create_prefix_scan_bounds() {
+        let cols: Vec<Vec<u8>> = vec!["o:o_id".into()];
+        let scan = create_prefix_scan(cols.clone(), "cust|");
         assert_eq!(scan.columns.unwrap(), cols);
-        assert_eq!(scan.filter_string.unwrap(), Into::<Vec<u8>>::into(build_single_column_filter(colfam, col, "=", val)));
+        assert_eq!(scan.start_row.unwrap(), b"cust|".to_vec());
+        assert_eq!(scan.stop_row.unwrap(), b"cust}".to_vec());
+        assert!(scan.filter_string.is_none());
     }
 
     #[test]
-    fn test_create_scan_no_cols() {
-        let cols = vec![];
-        let colfam = "testcolfam";
-        let col = "testcol";
-        let val = "testval";
-        let scan = create_scan(cols.clone(), colfam, col, val);
-        assert_eq!(scan.columns.unwrap(), cols);
-        assert_eq!(scan.filter_string.unwrap(), Into::<Vec<u8>>::into(build_single_column_filter(colfam, col, "=", val)));
+    fn test_create_full_scan_is_unbounded_and_unfiltered() {
+        let scan = create_full_scan(vec!["o:o_id".into()], 50);
+        assert_eq!(scan.caching, Some(50));
+        assert!(scan.start_row.is_none() && scan.stop_row.is_none());
+        assert!(scan.filter_string.is_none() && scan.timestamp.is_none());
+    }
+
+    #[test]
+    fn test_customer_index_key_sorts_newest_first() {
+        let older = customer_index_key("cust", 1_000, "b");
+        let newer = customer_index_key("cust", 2_000, "a");
+        assert!(newer < older);
+        assert!(older.starts_with(&customer_index_prefix("cust")));
+        assert!(older.ends_with("|b"));
+    }
+
+    #[test]
+    fn test_customer_index_prefix_does_not_match_a_longer_c_id() {
+        assert!(!customer_index_key("a|b", 1_000, "o1").starts_with(&customer_index_prefix("a")));
+        assert!(customer_index_key("a|b", 1_000, "o1").starts_with(&customer_index_prefix("a|b")));
+    }
+
+    #[test]
+    fn test_create_customer_index_mutation() {
+        let batch = create_customer_index_mutation("cust", "oid", 1_000);
+        assert_eq!(batch.row.unwrap(), customer_index_key("cust", 1_000, "oid").into_bytes());
+        let mutation = &batch.mutations.unwrap()[0];
+        assert_eq!(mutation.column, Some(b"o:o_id".to_vec()));
+        assert_eq!(mutation.value, Some(b"oid".to_vec()));
     }
 
     #[test]
-    fn test_build_single_column_filter() {
-        let exp = "SingleColumnValueFilter('test', 'test', =, 'binaryprefix:test')";
-        let actual = build_single_column_filter("test", "test", "=", "test");
-        assert_eq!(actual, exp.to_string());
+    fn test_ordertime_millis() {
+        assert_eq!(ordertime_millis("1970-01-01T00:00:01.500+00:00"), Some(1_500));
+        assert_eq!(ordertime_millis("time"), None);
     }
 
     #[test]
@@ -0,0 +1,105 @@
+//! Leases in HBase, so a background job runs on one replica at a time.
+//!
+//! A lease is a row keyed by the job name, with its holder and expiry as JSON in `l:lease`. It is taken and renewed
+//! with `checkAndPut` against the value that was read, so when two replicas race for it only one of them wins.
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{errors::OrderServiceError, schema::TableSpec, tables::Tables};
+use super::{hbase_connection::HbaseClient, hbase_utils::create_cell_mutation};
+
+pub const LEASE_FAMILY: &str = "l";
+const LEASE_COLUMN: &str = "l:lease";
+
+pub fn lease_table_spec(tables: &Tables) -> TableSpec {
+    TableSpec { name: tables.leases.clone(), families: vec![tables.family(LEASE_FAMILY)], split_keys: vec![] }
+}
+
+/// Who holds a lease, and until when in Unix millis.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lease {
+    pub holder: String,
+    pub expires_at: i64,
+}
+
+/// The current lease on `name`, with the raw cell to check against. `None` when it was never taken.
+fn read_lease<H: HbaseClient>(client: &mut H, tables: &Tables, name: &str) -> Result<Option<(Lease, Vec<u8>)>, OrderServiceError> {
+    let rows = client.get_row(&tables.leases, name)?;
+    let Some(raw) = rows.into_iter().next().and_then(|row| row.columns?.remove(LEASE_COLUMN.as_bytes())?.value) else { return Ok(None) };
+    Ok(Some((serde_json::from_slice(&raw)?, raw)))
+}
+
+/// Takes the lease on `name` for `holder` until `expires_at`, or renews it when `holder` already has it.
+/// Returns whether `holder` has the lease. Fails while another holder's lease has not expired at `now`.
+pub fn try_acquire<H: HbaseClient>(client: &mut H, tables: &Tables, name: &str, holder: &str, now: i64, expires_at: i64) -> Result<bool, OrderServiceError> {
+    let current = read_lease(client, tables, name)?;
+    if let Some((lease, _)) = &current {
+        if lease.holder != holder && lease.expires_at > now {
+            return Ok(false);
+        }
+    }
+    let lease = Lease { holder: holder.to_owned(), expires_at };
+    let mutation = create_cell_mutation(LEASE_FAMILY, "lease", serde_json::to_vec(&lease)?).build();
+    let expected = current.map(|(_, raw)| raw).unwrap_or_default();
+    client.check_and_put(&tables.leases, name, LEASE_COLUMN, &expected, mutation)
+}
+
+/// Gives up the lease on `name` if `holder` has it, so another replica can take it without waiting for it to expire.
+pub fn release<H: HbaseClient>(client: &mut H, tables: &Tables, name: &str, holder: &str) -> Result<(), OrderServiceError> {
+    let Some((lease, raw)) = read_lease(client, tables, name)? else { return Ok(()) };
+    if lease.holder == holder {
+        let released = Lease { expires_at: 0, ..lease };
+        client.check_and_put(&tables.leases, name, LEASE_COLUMN, &raw, create_cell_mutation(LEASE_FAMILY, "lease", serde_json::to_vec(&released)?).build())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::fake_hbase::FakeHbase;
+
+    /// A client per Thrift API, each to a fake HBase with the lease table.
+    fn clients() -> Vec<Box<dyn HbaseClient + Send>> {
+        FakeHbase::each_api_with_tables(&[lease_table_spec(&Tables::default())]).iter().map(|server| server.connect()).collect()
+    }
+
+    #[test]
+    fn test_lease_is_held_by_one_holder_until_it_expires() {
+        for mut client in clients() {
+            let tables = Tables::default();
+            assert!(try_acquire(&mut client, &tables, "job", "a", 0, 100).unwrap());
+            assert!(!try_acquire(&mut client, &tables, "job", "b", 50, 150).unwrap());
+            assert!(try_acquire(&mut client, &tables, "job", "a", 50, 150).unwrap());
+            assert!(!try_acquire(&mut client, &tables, "job", "b", 149, 249).unwrap());
+            assert!(try_acquire(&mut client, &tables, "job", "b", 150, 250).unwrap());
+            assert!(!try_acquire(&mut client, &tables, "job", "a", 200, 300).unwrap());
+            assert!(try_acquire(&mut client, &tables, "other", "a", 200, 300).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_released_lease_can_be_taken_at_once() {
+        for mut client in clients() {
+            let tables = Tables::default();
+            assert!(try_acquire(&mut client, &tables, "job", "a", 0, 100).unwrap());
+            release(&mut client, &tables, "job", "b").unwrap();
+            assert!(!try_acquire(&mut client, &tables, "job", "b", 10, 110).unwrap());
+            release(&mut client, &tables, "job", "a").unwrap();
+            assert!(try_acquire(&mut client, &tables, "job", "b", 10, 110).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_check_fails_when_the_lease_changed_since_it_was_read() {
+        for mut client in clients() {
+            let tables = Tables::default();
+            assert!(try_acquire(&mut client, &tables, "job", "a", 0, 100).unwrap());
+            let (_, raw) = read_lease(&mut client, &tables, "job").unwrap().unwrap();
+            assert!(try_acquire(&mut client, &tables, "job", "a", 10, 110).unwrap());
+            let stolen = create_cell_mutation(LEASE_FAMILY, "lease", b"{}".to_vec()).build();
+            assert!(!client.check_and_put(&tables.leases, "job", LEASE_COLUMN, &raw, stolen.clone()).unwrap());
+            assert!(!client.check_and_put(&tables.leases, "job", LEASE_COLUMN, b"", stolen).unwrap());
+        }
+    }
+}
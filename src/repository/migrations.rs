@@ -0,0 +1,610 @@
+//! Versioned schema migrations for the order tables.
+//!
+//! Migrations run in ascending version order and each one is recorded in the migrations table
+//! (`schema_migrations` by default) once it has completed, so a run only applies what is still pending. Every step must be
+//! idempotent: a run that dies halfway is retried from the start of the unfinished migration.
+//!
+//! [`run_pending_with_lease`] holds the `schema-migrations` lease while it runs, so replicas that start together do
+//! not apply the same migration at once.
+
+use std::{collections::BTreeSet, time::Duration};
+
+use hbase_thrift::{hbase::{BatchMutation, TRowResult}, BatchMutationBuilder, MutationBuilder};
+use serde::Serialize;
+
+use crate::models::{errors::OrderServiceError, schema::TableSpec, tables::Tables};
+use crate::repository::hbase::{customer_index_spec, get_unix_time, order_table_spec, scan_pages, scan_rows};
+use crate::repository::hbase_connection::HbaseClient;
+use crate::repository::hbase_utils::{create_cell_mutation, create_customer_index_mutation, create_full_scan, ordertime_millis};
+use crate::repository::leases::{self, lease_table_spec};
+use crate::repository::sagas::saga_table_spec;
+
+pub const MIGRATIONS_FAMILY: &str = "m";
+pub const DEFAULT_BATCH_SIZE: i32 = 500;
+pub const MIGRATIONS_LEASE: &str = "schema-migrations";
+
+/// Rewrites a single row of a backfill. Returns `None` when the row needs no change.
+pub type RowRewrite = fn(&TRowResult) -> Option<BatchMutation>;
+
+pub enum MigrationStep {
+    /// Creates the table unless it already exists.
+    CreateTable(TableSpec),
+    /// Scans `table` in batches, fetching `columns`, and writes what `rewrite` returns to `target`.
+    Backfill { table: String, columns: &'static [&'static str], target: String, rewrite: RowRewrite },
+}
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub step: MigrationStep,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MigrationReport {
+    pub version: u32,
+    pub name: String,
+    pub rows_scanned: usize,
+    pub rows_rewritten: usize,
+}
+
+/// Who runs the migrations, and how long the lease is held for.
+pub struct MigrationLease {
+    pub holder: String,
+    /// How long the lease lasts without being renewed. It is renewed after every write.
+    pub duration: Duration,
+    /// How often a replica waiting for the lease checks again.
+    pub retry: Duration,
+}
+
+impl MigrationLease {
+    pub fn new(holder: String) -> Self {
+        Self { holder, duration: Duration::from_secs(300), retry: Duration::from_secs(2) }
+    }
+
+    /// Takes or renews the lease. Returns whether `holder` has it.
+    fn acquire<H: HbaseClient>(&self, client: &mut H, tables: &Tables) -> Result<bool, OrderServiceError> {
+        let now = get_unix_time();
+        leases::try_acquire(client, tables, MIGRATIONS_LEASE, &self.holder, now, now + self.duration.as_millis() as i64)
+    }
+}
+
+/// All migrations known to this build, in the order they must run. Never renumber or remove entries.
+pub fn migrations(tables: &Tables) -> Vec<Migration> {
+    vec![
+        Migration {
+            version: 1,
+            name: "create_orders_table",
+            step: MigrationStep::CreateTable(order_table_spec(tables)),
+        },
+        Migration {
+            version: 2,
+            name: "backfill_addr_postal",
+            step: MigrationStep::Backfill {
+                table: tables.orders.clone(),
+                columns: &["addr:c_addr", "addr:postal"],
+                target: tables.orders.clone(),
+                rewrite: backfill_postal_from_address,
+            },
+        },
+        Migration {
+            version: 3,
+            name: "create_customer_index_table",
+            step: MigrationStep::CreateTable(customer_index_spec(tables)),
+        },
+        Migration {
+            version: 4,
+            name: "backfill_customer_index",
+            step: MigrationStep::Backfill {
+                table: tables.orders.clone(),
+                columns: &["ids:c_id", "info:o_time"],
+                target: tables.customer_index.clone(),
+                rewrite: customer_index_entry,
+            },
+        },
+        Migration {
+            version: 5,
+            name: "create_sagas_table",
+            step: MigrationStep::CreateTable(saga_table_spec(tables)),
+        },
+        Migration {
+            version: 6,
+            name: "create_leases_table",
+            step: MigrationStep::CreateTable(lease_table_spec(tables)),
+        },
+        Migration {
+            version: 7,
+            name: "clear_customer_index",
+            step: MigrationStep::Backfill {
+                table: tables.customer_index.clone(),
+                columns: &["o:o_id"],
+                target: tables.customer_index.clone(),
+                rewrite: delete_index_entry,
+            },
+        },
+        Migration {
+            version: 8,
+            name: "rebuild_customer_index",
+            step: MigrationStep::Backfill {
+                table: tables.orders.clone(),
+                columns: &["ids:c_id", "info:o_time"],
+                target: tables.customer_index.clone(),
+                rewrite: customer_index_entry,
+            },
+        },
+    ]
+}
+
+/// The migrations that together build the customer index from `orders`.
+const CUSTOMER_INDEX_MIGRATIONS: [u32; 2] = [3, 4];
+
+pub fn migrations_table_spec(tables: &Tables) -> TableSpec {
+    TableSpec { name: tables.migrations.clone(), families: vec![tables.family(MIGRATIONS_FAMILY)], split_keys: vec![] }
+}
+
+/// Versions recorded as applied in the metadata table. A missing metadata table means nothing has run.
+pub fn applied_versions<H: HbaseClient>(client: &mut H, tables: &Tables) -> Result<BTreeSet<u32>, OrderServiceError> {
+    if !client.table_exists(&tables.migrations)? {
+        return Ok(BTreeSet::new());
+    }
+    let mut versions = BTreeSet::new();
+    scan_in_batches(client, &tables.migrations, &["m:name"], DEFAULT_BATCH_SIZE, |row| {
+        let key = String::from_utf8_lossy(&row.row.clone().unwrap_or_default()).into_owned();
+        match key.parse::<u32>() {
+            Ok(v) => { versions.insert(v); }
+            Err(_) => tracing::warn!(row = key.as_str(), "ignoring malformed row in migrations table"),
+        }
+        Ok(())
+    })?;
+    Ok(versions)
+}
+
+/// The migrations of `all` that have not been applied yet.
+pub fn pending<'a, H: HbaseClient>(client: &mut H, tables: &Tables, all: &'a [Migration]) -> Result<Vec<&'a Migration>, OrderServiceError> {
+    let applied = applied_versions(client, tables)?;
+    if let Some(unknown) = applied.iter().find(|v| !all.iter().any(|m| m.version == **v)) {
+        return Err(OrderServiceError::MigrationFailed {
+            version: *unknown,
+            reason: "applied in the database but unknown to this build".into(),
+        });
+    }
+    Ok(all.iter().filter(|m| !applied.contains(&m.version)).collect())
+}
+
+/// Applies every pending migration in version order and records each one as it completes.
+pub fn run_pending<H: HbaseClient>(client: &mut H, tables: &Tables, all: &[Migration], batch_size: i32) -> Result<Vec<MigrationReport>, OrderServiceError> {
+    run_pending_with(client, tables, all, batch_size, &mut |_| Ok(()))
+}
+
+/// Like [`run_pending`], while holding the `schema-migrations` lease. Waits while another replica holds it, and then
+/// applies whatever that replica left pending. Fails with [`OrderServiceError::LeaseLost`] when the lease ran out
+/// and was taken over mid-run.
+pub fn run_pending_with_lease<H: HbaseClient>(
+    client: &mut H,
+    tables: &Tables,
+    all: &[Migration],
+    batch_size: i32,
+    lease: &MigrationLease,
+) -> Result<Vec<MigrationReport>, OrderServiceError> {
+    ensure_table(client, &lease_table_spec(tables))?;
+    while !lease.acquire(client, tables)? {
+        tracing::info!(holder = lease.holder.as_str(), "another replica is running the migrations, waiting for it");
+        std::thread::sleep(lease.retry);
+    }
+    let result = run_pending_with(client, tables, all, batch_size, &mut |client| match lease.acquire(client, tables)? {
+        true => Ok(()),
+        false => Err(OrderServiceError::LeaseLost(MIGRATIONS_LEASE.into())),
+    });
+    if let Err(e) = leases::release(client, tables, MIGRATIONS_LEASE, &lease.holder) {
+        tracing::warn!(error = %e, "cannot release the migrations lease, it runs out on its own");
+    }
+    result
+}
+
+/// Runs the pending migrations, calling `on_write` after every write.
+fn run_pending_with<H: HbaseClient>(
+    client: &mut H,
+    tables: &Tables,
+    all: &[Migration],
+    batch_size: i32,
+    on_write: &mut dyn FnMut(&mut H) -> Result<(), OrderServiceError>,
+) -> Result<Vec<MigrationReport>, OrderServiceError> {
+    ensure_table(client, &migrations_table_spec(tables))?;
+    let mut todo = pending(client, tables, all)?;
+    todo.sort_by_key(|m| m.version);
+    let mut reports = Vec::new();
+    for migration in todo {
+        tracing::info!(version = migration.version, name = migration.name, "applying migration");
+        let report = apply(client, migration, batch_size, on_write).map_err(|e| OrderServiceError::MigrationFailed {
+            version: migration.version,
+            reason: e.to_string(),
+        })?;
+        record_applied(client, &tables.migrations, migration)?;
+        on_write(client)?;
+        tracing::info!(version = migration.version, rows_scanned = report.rows_scanned, rows_rewritten = report.rows_rewritten, "migration applied");
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+/// Applies one migration, calling `on_write` after every batch a backfill writes.
+fn apply<H: HbaseClient>(
+    client: &mut H,
+    migration: &Migration,
+    batch_size: i32,
+    on_write: &mut dyn FnMut(&mut H) -> Result<(), OrderServiceError>,
+) -> Result<MigrationReport, OrderServiceError> {
+    let mut report = MigrationReport { version: migration.version, name: migration.name.to_owned(), rows_scanned: 0, rows_rewritten: 0 };
+    match &migration.step {
+        MigrationStep::CreateTable(spec) => ensure_table(client, spec)?,
+        MigrationStep::Backfill { table, columns, target, rewrite } => {
+            let mut pending_writes = Vec::new();
+            let scan = create_full_scan(columns.iter().map(|c| c.as_bytes().to_vec()).collect(), batch_size);
+            scan_pages(client, table, scan, batch_size, |client, rows| {
+                for row in rows {
+                    report.rows_scanned += 1;
+                    pending_writes.extend(rewrite(row));
+                    if pending_writes.len() >= batch_size as usize {
+                        report.rows_rewritten += write_batch(client, target, std::mem::take(&mut pending_writes), on_write)?;
+                    }
+                }
+                Ok(())
+            })?;
+            if !pending_writes.is_empty() {
+                report.rows_rewritten += write_batch(client, target, pending_writes, on_write)?;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Repopulates the customer index from `orders`, regardless of what the metadata table says.
+/// Index keys are derived from the order, so rows that are already indexed are rewritten unchanged.
+pub fn rebuild_customer_index<H: HbaseClient>(client: &mut H, tables: &Tables, batch_size: i32) -> Result<MigrationReport, OrderServiceError> {
+    let all = migrations(tables);
+    let mut report = None;
+    for migration in all.iter().filter(|m| CUSTOMER_INDEX_MIGRATIONS.contains(&m.version)) {
+        report = Some(apply(client, migration, batch_size, &mut |_| Ok(()))?);
+    }
+    Ok(report.expect("customer index migrations are defined"))
+}
+
+/// Puts one batch of a backfill. Returns how many rows it wrote.
+fn write_batch<H: HbaseClient>(
+    client: &mut H,
+    target: &str,
+    batch: Vec<BatchMutation>,
+    on_write: &mut dyn FnMut(&mut H) -> Result<(), OrderServiceError>,
+) -> Result<usize, OrderServiceError> {
+    let rows = batch.len();
+    client.put(target, batch, Some(get_unix_time()), None)?;
+    on_write(client)?;
+    Ok(rows)
+}
+
+/// Creates `spec` unless it exists. Another replica creating it at the same time is not an error.
+fn ensure_table<H: HbaseClient>(client: &mut H, spec: &TableSpec) -> Result<(), OrderServiceError> {
+    if client.table_exists(&spec.name)? {
+        return Ok(());
+    }
+    match client.create_table(spec) {
+        Err(_) if client.table_exists(&spec.name)? => Ok(()),
+        res => res,
+    }
+}
+
+fn record_applied<H: HbaseClient>(client: &mut H, migrations_table: &str, migration: &Migration) -> Result<(), OrderServiceError> {
+    let mutations = vec![
+        create_cell_mutation(MIGRATIONS_FAMILY, "name", migration.name),
+        create_cell_mutation(MIGRATIONS_FAMILY, "applied_at", get_unix_time().to_string()),
+    ];
+    let batch = <BatchMutationBuilder>::default().row(version_key(migration.version)).mutations(mutations).build();
+    client.put(migrations_table, vec![batch], None, None)?;
+    Ok(())
+}
+
+/// Zero padded so the metadata rows sort by version.
+fn version_key(version: u32) -> String {
+    format!("{:010}", version)
+}
+
+/// Scans the whole of `table`, `batch_size` rows at a time, and always closes the scanner.
+fn scan_in_batches<H: HbaseClient>(
+    client: &mut H,
+    table: &str,
+    columns: &[&str],
+    batch_size: i32,
+    mut on_row: impl FnMut(&TRowResult) -> Result<(), OrderServiceError>,
+) -> Result<(), OrderServiceError> {
+    let scan = create_full_scan(columns.iter().map(|c| c.as_bytes().to_vec()).collect(), batch_size);
+    scan_rows(client, table, scan, batch_size, |row| on_row(row))
+}
+
+/// Rows written before `addr:postal` existed only have the postal code inside the customer
+/// address, e.g. `Lyngvej 2, 2800 Lyngby`. Takes the first 4 or 5 digit number after the comma.
+fn backfill_postal_from_address(row: &TRowResult) -> Option<BatchMutation> {
+    let columns = row.columns.as_ref()?;
+    if columns.contains_key(b"addr:postal".as_slice()) {
+        return None;
+    }
+    let address = columns.get(b"addr:c_addr".as_slice())?.value.as_ref()?;
+    let address = std::str::from_utf8(address).ok()?;
+    let (_, locality) = address.split_once(',')?;
+    let postal = locality
+        .split_whitespace()
+        .find(|t| (4..=5).contains(&t.len()) && t.chars().all(|c| c.is_ascii_digit()))?;
+    let mutation = create_cell_mutation("addr", "postal", postal);
+    Some(<BatchMutationBuilder>::default().row(row.row.clone()?).mutations(vec![mutation]).build())
+}
+
+/// Deletes an index entry. Entries written before the c_id in the key was length prefixed can match the prefix of
+/// another customer, so the index is cleared and built again from `orders`.
+fn delete_index_entry(row: &TRowResult) -> Option<BatchMutation> {
+    let mut mutation = MutationBuilder::default();
+    mutation.column("o", "o_id").is_delete(true);
+    Some(<BatchMutationBuilder>::default().row(row.row.clone()?).mutations(vec![mutation]).build())
+}
+
+/// Index entry for an order row. Uses the write time of `info:o_time` for rows whose order time is not RFC 3339.
+fn customer_index_entry(row: &TRowResult) -> Option<BatchMutation> {
+    let columns = row.columns.as_ref()?;
+    let c_id = std::str::from_utf8(columns.get(b"ids:c_id".as_slice())?.value.as_ref()?).ok()?;
+    let o_id = std::str::from_utf8(row.row.as_ref()?).ok()?;
+    let o_time = columns.get(b"info:o_time".as_slice())?;
+    let ordertime = o_time.value.as_ref()
+        .and_then(|v| std::str::from_utf8(v).ok())
+        .and_then(ordertime_millis)
+        .or(o_time.timestamp)?;
+    Some(create_customer_index_mutation(c_id, o_id, ordertime))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::orders::{DecodeMode, Order};
+    use crate::repository::{
+        fake_hbase::FakeHbase,
+        hbase,
+        hbase_connection::MockHbaseClient,
+        hbase_utils::_to_tcell,
+    };
+    use mockall::{predicate::eq, Sequence};
+
+    const ORDER_TABLE: &str = "orders";
+    const CUSTOMER_INDEX_TABLE: &str = "orders_by_customer";
+    const MIGRATIONS_TABLE: &str = "schema_migrations";
+
+    fn row(key: &str, cols: &[(&str, &str)]) -> TRowResult {
+        TRowResult {
+            row: Some(key.as_bytes().to_vec()),
+            columns: Some(cols.iter().map(|(c, v)| (c.as_bytes().to_vec(), _to_tcell(v))).collect()),
+            sorted_columns: None,
+        }
+    }
+
+    fn expect_scan(mock: &mut MockHbaseClient, table: &'static str, pages: Vec<Vec<TRowResult>>) {
+        mock.expect_scanner_open_with_scan()
+            .withf(move |t, _, _| t == table.as_bytes())
+            .times(1)
+            .returning(|_, _, _| Ok(7));
+        let mut pages = pages.into_iter();
+        mock.expect_scanner_get_list()
+            .with(eq(7), mockall::predicate::always())
+            .returning(move |_, _| Ok(pages.next().unwrap_or_default()));
+        mock.expect_scanner_close().with(eq(7)).times(1).returning(|_| Ok(()));
+    }
+
+    #[test]
+    fn test_backfill_postal_from_address() {
+        let r = row("o1", &[("addr:c_addr", "Lyngvej 2, 2800 Lyngby")]);
+        let batch = backfill_postal_from_address(&r).unwrap();
+        assert_eq!(batch.row, Some(b"o1".to_vec()));
+        let mutation = &batch.mutations.unwrap()[0];
+        assert_eq!(mutation.column, Some(b"addr:postal".to_vec()));
+        assert_eq!(mutation.value, Some(b"2800".to_vec()));
+    }
+
+    #[test]
+    fn test_backfill_postal_skips_rows_with_postal_or_without_code() {
+        assert!(backfill_postal_from_address(&row("o1", &[("addr:c_addr", "Lyngvej 2, 2800 Lyngby"), ("addr:postal", "2800")])).is_none());
+        assert!(backfill_postal_from_address(&row("o2", &[("addr:c_addr", "Lyngvej 2")])).is_none());
+        assert!(backfill_postal_from_address(&row("o3", &[("addr:c_addr", "Lyngvej 2, Lyngby")])).is_none());
+    }
+
+    #[test]
+    fn test_applied_versions_without_metadata_table() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_table_exists().with(eq(MIGRATIONS_TABLE)).returning(|_| Ok(false));
+        mock.expect_scanner_open_with_scan().times(0);
+        assert!(applied_versions(&mut mock, &Tables::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_pending_skips_applied_versions() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_table_exists().returning(|_| Ok(true));
+        expect_scan(&mut mock, MIGRATIONS_TABLE, vec![vec![row("0000000001", &[("m:name", "create_orders_table")])]]);
+        let all = migrations(&Tables::default());
+        let pending = pending(&mut mock, &Tables::default(), &all).unwrap();
+        assert_eq!(pending.iter().map(|m| m.version).collect::<Vec<_>>(), vec![2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_pending_fails_on_version_unknown_to_build() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_table_exists().returning(|_| Ok(true));
+        expect_scan(&mut mock, MIGRATIONS_TABLE, vec![vec![row("0000000099", &[("m:name", "from_the_future")])]]);
+        let all = migrations(&Tables::default());
+        assert!(matches!(pending(&mut mock, &Tables::default(), &all), Err(OrderServiceError::MigrationFailed { version: 99, .. })));
+    }
+
+    #[test]
+    fn test_run_pending_nothing_to_do() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_table_exists().returning(|_| Ok(true));
+        expect_scan(&mut mock, MIGRATIONS_TABLE, vec![(1..=8).map(|v| row(&version_key(v), &[])).collect()]);
+        mock.expect_put().times(0);
+        mock.expect_create_table().times(0);
+        assert!(run_pending(&mut mock, &Tables::default(), &migrations(&Tables::default()), 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_run_pending_applies_in_order_and_records() {
+        let mut mock = MockHbaseClient::new();
+        let mut seq = Sequence::new();
+        mock.expect_table_exists().with(eq(MIGRATIONS_TABLE)).times(2).returning(|_| Ok(false));
+        mock.expect_create_table()
+            .withf(|spec| spec.name == MIGRATIONS_TABLE && spec.families.len() == 1 && spec.families[0].name == "m")
+            .times(1).in_sequence(&mut seq).returning(|_| Ok(()));
+        mock.expect_table_exists().with(eq(ORDER_TABLE)).returning(|_| Ok(false));
+        mock.expect_create_table()
+            .withf(|spec| spec.name == ORDER_TABLE)
+            .times(1).in_sequence(&mut seq).returning(|_| Ok(()));
+        mock.expect_put()
+            .withf(|t, b, _, _| t == MIGRATIONS_TABLE && b[0].row == Some(b"0000000001".to_vec()))
+            .times(1).in_sequence(&mut seq).returning(|_, _, _, _| Ok(()));
+        expect_scan(&mut mock, ORDER_TABLE, vec![
+            vec![row("o1", &[("addr:c_addr", "Lyngvej 2, 2800 Lyngby")]), row("o2", &[("addr:c_addr", "a, 1000 b"), ("addr:postal", "1000")])],
+            vec![row("o3", &[("addr:c_addr", "Vej 1, 8000 Aarhus")])],
+        ]);
+        mock.expect_put()
+            .withf(|t, b, _, _| t == ORDER_TABLE && b.len() == 2)
+            .times(1).in_sequence(&mut seq).returning(|_, _, _, _| Ok(()));
+        mock.expect_put()
+            .withf(|t, b, _, _| t == MIGRATIONS_TABLE && b[0].row == Some(b"0000000002".to_vec()))
+            .times(1).in_sequence(&mut seq).returning(|_, _, _, _| Ok(()));
+
+        let reports = run_pending(&mut mock, &Tables::default(), &migrations(&Tables::default())[..2], 10).unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[1], MigrationReport { version: 2, name: "backfill_addr_postal".into(), rows_scanned: 3, rows_rewritten: 2 });
+    }
+
+    #[test]
+    fn test_backfill_puts_each_batch_during_the_scan() {
+        let mut mock = MockHbaseClient::new();
+        let mut seq = Sequence::new();
+        let page = |key: &str| vec![row(key, &[("addr:c_addr", "Lyngvej 2, 2800 Lyngby")])];
+        mock.expect_scanner_open_with_scan().times(1).in_sequence(&mut seq).returning(|_, _, _| Ok(7));
+        mock.expect_scanner_get_list().times(1).in_sequence(&mut seq).returning(move |_, _| Ok(page("o1")));
+        mock.expect_put().withf(|t, b, _, _| t == ORDER_TABLE && b.len() == 1).times(1).in_sequence(&mut seq).returning(|_, _, _, _| Ok(()));
+        mock.expect_scanner_get_list().times(1).in_sequence(&mut seq).returning(move |_, _| Ok(page("o2")));
+        mock.expect_put().withf(|t, b, _, _| t == ORDER_TABLE && b.len() == 1).times(1).in_sequence(&mut seq).returning(|_, _, _, _| Ok(()));
+        mock.expect_scanner_get_list().times(1).in_sequence(&mut seq).returning(|_, _| Ok(vec![]));
+        mock.expect_scanner_close().times(1).in_sequence(&mut seq).returning(|_| Ok(()));
+
+        let mut writes = 0;
+        let report = apply(&mut mock, &migrations(&Tables::default())[1], 1, &mut |_| { writes += 1; Ok(()) }).unwrap();
+        assert_eq!((report.rows_scanned, report.rows_rewritten, writes), (2, 2, 2));
+    }
+
+    #[test]
+    fn test_run_pending_with_lease_waits_for_the_other_replica() {
+        let tables = Tables::default().with_order_regions(1);
+        for server in FakeHbase::each_api_with_tables(&[lease_table_spec(&tables)]) {
+            let mut client = server.connect();
+            let all = migrations(&tables);
+            let now = get_unix_time();
+            assert!(leases::try_acquire(&mut client, &tables, MIGRATIONS_LEASE, "other", now, now + 300).unwrap());
+
+            let lease = MigrationLease { holder: "me".into(), duration: Duration::from_secs(60), retry: Duration::from_millis(50) };
+            let reports = run_pending_with_lease(&mut client, &tables, &all, 10, &lease).unwrap();
+            assert!(get_unix_time() >= now + 300);
+            assert_eq!(reports.len(), all.len());
+            // Released, so the next replica neither waits nor migrates again.
+            assert!(run_pending_with_lease(&mut client, &tables, &all, 10, &MigrationLease { holder: "next".into(), ..lease }).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_migrations_run_once_on_each_api() {
+        let tables = Tables::default().with_order_regions(1);
+        for server in FakeHbase::each_api_with_tables(&[hbase::order_table_spec(&tables), hbase::customer_index_spec(&tables)]) {
+            let order = Order::new(vec![], "Lyngvej 2, 2800 Lyngby".into(), "r".into(), "cust".into(), "rest".into(), 2800);
+            hbase::add_order(&order, &tables, server.connect()).unwrap();
+
+            let all = migrations(&tables);
+            let mut client = server.connect();
+            assert_eq!(run_pending(&mut client, &tables, &all, 2).unwrap().len(), all.len(), "{:?}", server.api);
+            assert!(pending(&mut client, &tables, &all).unwrap().is_empty());
+            assert!(run_pending(&mut client, &tables, &all, 2).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_run_pending_failed_step_is_not_recorded() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_table_exists().with(eq(MIGRATIONS_TABLE)).returning(|_| Ok(true));
+        expect_scan(&mut mock, MIGRATIONS_TABLE, vec![vec![row("0000000001", &[])]]);
+        mock.expect_scanner_open_with_scan()
+            .returning(|_, _, _| Err(OrderServiceError::DBError(thrift::Error::from("down"))));
+        mock.expect_put().times(0);
+
+        let res = run_pending(&mut mock, &Tables::default(), &migrations(&Tables::default())[..2], 10);
+        assert!(matches!(res, Err(OrderServiceError::MigrationFailed { version: 2, .. })));
+    }
+
+    #[test]
+    fn test_scanner_is_closed_when_row_handling_fails() {
+        let mut mock = MockHbaseClient::new();
+        expect_scan(&mut mock, ORDER_TABLE, vec![vec![row("o1", &[])]]);
+        let res = scan_in_batches(&mut mock, ORDER_TABLE, &[], 10, |_| Err(OrderServiceError::RowNotFound("o1".into())));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_customer_index_entry() {
+        let r = row("o1", &[("ids:c_id", "cust"), ("info:o_time", "1970-01-01T00:00:01+00:00")]);
+        assert_eq!(customer_index_entry(&r), Some(create_customer_index_mutation("cust", "o1", 1_000)));
+    }
+
+    #[test]
+    fn test_customer_index_entry_falls_back_to_cell_timestamp() {
+        let mut r = row("o1", &[("ids:c_id", "cust"), ("info:o_time", "not a time")]);
+        r.columns.as_mut().unwrap().get_mut(b"info:o_time".as_slice()).unwrap().timestamp = Some(42);
+        assert_eq!(customer_index_entry(&r), Some(create_customer_index_mutation("cust", "o1", 42)));
+        assert!(customer_index_entry(&row("o2", &[("info:o_time", "1970-01-01T00:00:01+00:00")])).is_none());
+    }
+
+    #[test]
+    fn test_customer_index_is_rebuilt_with_length_prefixed_keys() {
+        let tables = Tables::default();
+        for server in FakeHbase::each_api_with_tables(&[hbase::order_table_spec(&tables), hbase::customer_index_spec(&tables)]) {
+            let connect = || server.connect();
+            let a = Order::new(vec![], "CustAddr".into(), "RestAddr".into(), "a".into(), "rest".into(), 2800);
+            let a_b = Order::new(vec![], "CustAddr".into(), "RestAddr".into(), "a|b".into(), "rest".into(), 2800);
+            hbase::add_order(&a, &tables, connect()).unwrap();
+            hbase::add_order(&a_b, &tables, connect()).unwrap();
+            // An entry of `a|b` as it was keyed before the length prefix.
+            let legacy = format!("a|b|{:019}|{}", 0, a_b.o_id);
+            let entry = <BatchMutationBuilder>::default().row(legacy.clone()).mutations(vec![create_cell_mutation("o", "o_id", a_b.o_id.as_str())]).build();
+            connect().put(&tables.customer_index, vec![entry], None, None).unwrap();
+
+            let all = migrations(&tables);
+            for migration in all.iter().filter(|m| [7, 8].contains(&m.version)) {
+                apply(&mut connect(), migration, 1, &mut |_| Ok(())).unwrap();
+            }
+            assert!(connect().get_row(&tables.customer_index, &legacy).unwrap().is_empty());
+            let history = |c_id: &str| hbase::get_orders_info_by_user(c_id.into(), DecodeMode::Strict, &tables, connect()).unwrap().0.into_iter().map(|i| i.o_id).collect::<Vec<_>>();
+            assert_eq!(history("a"), [a.o_id.as_str()]);
+            assert_eq!(history("a|b"), [a_b.o_id.as_str()]);
+        }
+    }
+
+    #[test]
+    fn test_rebuild_customer_index_writes_to_index_table() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_table_exists().with(eq(CUSTOMER_INDEX_TABLE)).returning(|_| Ok(true));
+        mock.expect_create_table().times(0);
+        expect_scan(&mut mock, ORDER_TABLE, vec![vec![
+            row("o1", &[("ids:c_id", "cust"), ("info:o_time", "1970-01-01T00:00:01+00:00")]),
+            row("o2", &[("ids:c_id", "cust"), ("info:o_time", "1970-01-01T00:00:02+00:00")]),
+        ]]);
+        mock.expect_put()
+            .withf(|t, b, _, _| t == CUSTOMER_INDEX_TABLE && b.len() == 2)
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+        mock.expect_put().withf(|t, _, _, _| t == MIGRATIONS_TABLE).times(0);
+
+        let report = rebuild_customer_index(&mut mock, &Tables::default(), 10).unwrap();
+        assert_eq!((report.rows_scanned, report.rows_rewritten), (2, 2));
+    }
+}
@@ -1,3 +1,16 @@
+#[cfg(feature = "embedded")]
+pub mod embedded;
+pub mod failover;
 pub mod hbase;
 pub mod hbase_connection;
-mod hbase_utils;
\ No newline at end of file
+pub mod hbase_thrift2;
+pub mod leases;
+pub mod migrations;
+pub mod order_repository;
+pub mod resilience;
+pub mod sagas;
+#[cfg(any(test, feature = "test-support"))]
+pub mod fake_hbase;
+mod hbase_utils;
+mod thrift2_types;
+mod thrift_http;
\ No newline at end of file
@@ -0,0 +1,152 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+
+use crate::{
+    consumers::saga::{self, SagaConfig},
+    models::{
+        errors::{OrderServiceError, RowIssue},
+        orders::{DecodeMode, Order, OrderInfo},
+        tables::{TableName, Tables},
+    },
+    producers::event_publisher::EventPublisher,
+};
+use super::{failover::FailoverClient, hbase, hbase_connection::HbaseClient, resilience::ResilientClient};
+
+/// The order queries the API needs, independent of where orders are stored.
+pub trait OrderRepository {
+    /// Stores the order and its customer index entry, and returns the order id.
+    fn add_order(&self, order: &Order) -> Result<String, OrderServiceError>;
+    /// The order and, in lenient mode, the issues found while decoding it.
+    fn get_order(&self, o_id: &str, mode: DecodeMode) -> Result<(Order, Vec<RowIssue>), OrderServiceError>;
+    /// The newest orders of a customer, newest first, and the number of their rows that did not decode in `mode`.
+    fn get_orders_by_customer(&self, c_id: &str, mode: DecodeMode) -> Result<(Vec<OrderInfo>, usize), OrderServiceError>;
+    fn get_tables(&self) -> Result<Vec<TableName>, OrderServiceError>;
+    /// Creates the order table and the customer index when they are missing.
+    fn create_tables(&self) -> Result<(), OrderServiceError>;
+    /// Whether the store keeps sagas, so [`OrderRepository::start_saga`] can run. Only the HBase store does.
+    fn supports_sagas(&self) -> bool {
+        false
+    }
+    /// Stores a new saga for the stored `order` and sends its first command, see [`saga::start_saga`].
+    fn start_saga(&self, _order: &Order, _config: &SagaConfig, _request_id: &str, _publisher: &mut dyn EventPublisher) -> Result<(), OrderServiceError> {
+        Err(OrderServiceError::InvalidConfig("the saga needs the hbase order store".into()))
+    }
+}
+
+/// Where orders are stored: `hbase` (default) or `embedded`, an on-disk store for local development and tests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OrderStore {
+    #[default]
+    Hbase,
+    Embedded,
+}
+
+impl FromStr for OrderStore {
+    type Err = ();
+    fn from_str(input: &str) -> Result<OrderStore, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "hbase" => Ok(OrderStore::Hbase),
+            "embedded" => Ok(OrderStore::Embedded),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Orders in HBase, through the Thrift endpoints in `hosts`. Every order call takes its own connection, sagas are
+/// started on the connections shared by the process.
+pub struct HbaseRepository {
+    hosts: String,
+    tables: Tables,
+}
+
+impl HbaseRepository {
+    pub fn new(hosts: impl Into<String>, tables: Tables) -> Self {
+        Self { hosts: hosts.into(), tables }
+    }
+}
+
+impl OrderRepository for HbaseRepository {
+    fn add_order(&self, order: &Order) -> Result<String, OrderServiceError> {
+        hbase::add_order(order, &self.tables, ResilientClient::connect(&self.hosts)?)
+    }
+
+    fn get_order(&self, o_id: &str, mode: DecodeMode) -> Result<(Order, Vec<RowIssue>), OrderServiceError> {
+        hbase::get_order_row_with_mode(o_id, mode, &self.tables, ResilientClient::connect(&self.hosts)?)
+    }
+
+    fn get_orders_by_customer(&self, c_id: &str, mode: DecodeMode) -> Result<(Vec<OrderInfo>, usize), OrderServiceError> {
+        hbase::get_orders_info_by_user(c_id.to_owned(), mode, &self.tables, ResilientClient::connect(&self.hosts)?)
+    }
+
+    fn get_tables(&self) -> Result<Vec<TableName>, OrderServiceError> {
+        hbase::get_tables(ResilientClient::connect(&self.hosts)?)
+    }
+
+    fn create_tables(&self) -> Result<(), OrderServiceError> {
+        hbase::create_order_table(&self.tables, ResilientClient::connect(&self.hosts)?)?;
+        hbase::create_customer_index_table(&self.tables, ResilientClient::connect(&self.hosts)?)
+    }
+
+    fn supports_sagas(&self) -> bool {
+        true
+    }
+
+    fn start_saga(&self, order: &Order, config: &SagaConfig, request_id: &str, publisher: &mut dyn EventPublisher) -> Result<(), OrderServiceError> {
+        with_shared_client(&self.hosts, |client| saga::start_saga(client, &self.tables, config, order, request_id, publisher).map(|_| ()))
+    }
+}
+
+type SharedClient = ResilientClient<FailoverClient<Box<dyn HbaseClient + Send>>>;
+
+static IDLE_CLIENTS: OnceLock<Mutex<HashMap<String, Vec<SharedClient>>>> = OnceLock::new();
+
+/// Runs `f` on an idle connection to `hosts`, or a new one when all are in use, and keeps the connection for the
+/// next call. A connection that broke reconnects on its next call.
+fn with_shared_client<T>(hosts: &str, f: impl FnOnce(&mut SharedClient) -> Result<T, OrderServiceError>) -> Result<T, OrderServiceError> {
+    let idle = IDLE_CLIENTS.get_or_init(Default::default);
+    let taken = idle.lock().unwrap_or_else(|e| e.into_inner()).get_mut(hosts).and_then(Vec::pop);
+    let mut client = match taken {
+        Some(client) => client,
+        None => ResilientClient::connect(hosts)?,
+    };
+    let result = f(&mut client);
+    idle.lock().unwrap_or_else(|e| e.into_inner()).entry(hosts.to_owned()).or_default().push(client);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::orders::Orderline,
+        producers::event_publisher::InMemoryPublisher,
+        repository::{fake_hbase::FakeHbase, sagas::{load_saga, saga_table_spec}},
+    };
+
+    #[test]
+    fn test_parse_order_store() {
+        assert_eq!(OrderStore::from_str("HBase"), Ok(OrderStore::Hbase));
+        assert_eq!(OrderStore::from_str("embedded"), Ok(OrderStore::Embedded));
+        assert_eq!(OrderStore::from_str("sqlite"), Err(()));
+    }
+
+    #[test]
+    fn test_sagas_start_on_a_shared_connection() {
+        let tables = Tables::default();
+        let url = FakeHbase::serve_with_tables(&[hbase::order_table_spec(&tables), hbase::customer_index_spec(&tables), saga_table_spec(&tables)]);
+        let repository = HbaseRepository::new(url.clone(), tables.clone());
+        assert!(repository.supports_sagas());
+
+        let recorder = InMemoryPublisher::new();
+        for item_num in [1, 2] {
+            let order = Order::new(vec![Orderline { item_num, price: 100 }], "CustAddr".into(), "RestAddr".into(), "custid".into(), "restid".into(), 2860);
+            repository.add_order(&order).unwrap();
+            repository.start_saga(&order, &SagaConfig::default(), "req-1", &mut recorder.clone()).unwrap();
+            assert!(with_shared_client(&url, |client| load_saga(client, &tables, &order.o_id)).unwrap().is_some());
+        }
+        assert_eq!(IDLE_CLIENTS.get().unwrap().lock().unwrap()[&url].len(), 1);
+    }
+}
@@ -0,0 +1,599 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use hbase_thrift::{hbase::{BatchMutation, ColumnDescriptor, Mutation, ScannerID, TRowResult, TScan, Text}, Attributes};
+use opentelemetry::{global, metrics::{Counter, Histogram, Meter}, KeyValue};
+use rand::Rng;
+
+use crate::{
+    api::utils::env::{get_resilience_policy, get_thrift_config},
+    models::{errors::OrderServiceError, schema::TableSpec},
+    repository::{failover::FailoverClient, hbase_connection::{HbaseClient, ThriftConfig, DEFAULT_CALL_TIMEOUT}},
+};
+
+/// How calls to HBase are bounded, retried and cut off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResiliencePolicy {
+    /// Connect timeout, and read/write timeout on the socket for a single attempt.
+    pub call_timeout: Duration,
+    /// Total time a call may spend on attempts and backoff before giving up.
+    pub deadline: Duration,
+    /// Attempts per call, including the first one.
+    pub max_attempts: u32,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Consecutive failed attempts that open the circuit breaker.
+    pub failure_threshold: u32,
+    /// How long the breaker stays open before a single trial call is let through.
+    pub open_duration: Duration,
+}
+
+impl Default for ResiliencePolicy {
+    fn default() -> Self {
+        Self {
+            call_timeout: DEFAULT_CALL_TIMEOUT,
+            deadline: Duration::from_secs(15),
+            max_attempts: 3,
+            base_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { trial_in_flight: bool },
+}
+
+impl BreakerState {
+    fn name(&self) -> &'static str {
+        match self {
+            BreakerState::Closed { .. } => "closed",
+            BreakerState::Open { .. } => "open",
+            BreakerState::HalfOpen { .. } => "half_open",
+        }
+    }
+}
+
+struct HbaseMetrics {
+    calls: Counter<u64>,
+    retries: Counter<u64>,
+    reconnects: Counter<u64>,
+    breaker_transitions: Counter<u64>,
+    attempt_duration: Histogram<f64>,
+}
+
+impl HbaseMetrics {
+    fn new(meter: &Meter) -> Self {
+        Self {
+            calls: meter.u64_counter("hbase.client.calls")
+                .with_description("HBase calls by operation and outcome: success, error, transient_error, deadline_exceeded or rejected")
+                .build(),
+            retries: meter.u64_counter("hbase.client.retries")
+                .with_description("HBase attempts retried after a transient error")
+                .build(),
+            reconnects: meter.u64_counter("hbase.client.reconnects")
+                .with_description("Reconnects to HBase after a transient error, by outcome")
+                .build(),
+            breaker_transitions: meter.u64_counter("hbase.client.circuit_breaker.transitions")
+                .with_description("Circuit breaker state changes, by the state entered")
+                .build(),
+            attempt_duration: meter.f64_histogram("hbase.client.attempt.duration")
+                .with_description("Duration of single HBase attempts")
+                .with_unit("s")
+                .build(),
+        }
+    }
+}
+
+/// Retry, deadline and circuit breaker state shared by every HBase client in the process.
+pub struct Resilience {
+    policy: ResiliencePolicy,
+    state: Mutex<BreakerState>,
+    metrics: HbaseMetrics,
+}
+
+static SHARED: OnceLock<Arc<Resilience>> = OnceLock::new();
+
+impl Resilience {
+    pub fn new(policy: ResiliencePolicy, meter: &Meter) -> Self {
+        Self {
+            policy,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+            metrics: HbaseMetrics::new(meter),
+        }
+    }
+
+    /// The process wide instance, configured from the environment on first use.
+    pub fn shared() -> Arc<Resilience> {
+        Arc::clone(SHARED.get_or_init(|| Arc::new(Resilience::new(get_resilience_policy(), &global::meter("order_service")))))
+    }
+
+    pub fn policy(&self) -> &ResiliencePolicy {
+        &self.policy
+    }
+
+    /// Runs `attempt` until it succeeds, fails with a non-transient error, or the policy gives up.
+    /// Transient errors are only retried when the operation is `retryable`.
+    fn execute<T>(&self, operation: &'static str, retryable: bool, mut attempt: impl FnMut() -> Result<T, OrderServiceError>) -> Result<T, OrderServiceError> {
+        let start = Instant::now();
+        let mut attempts = 0;
+        loop {
+            if !self.acquire() {
+                self.record_call(operation, "rejected");
+                return Err(OrderServiceError::CircuitOpen);
+            }
+            attempts += 1;
+            let attempt_start = Instant::now();
+            let result = attempt();
+            self.metrics.attempt_duration.record(attempt_start.elapsed().as_secs_f64(), &[KeyValue::new("operation", operation)]);
+            let error = match result {
+                Ok(value) => {
+                    self.on_success();
+                    self.record_call(operation, "success");
+                    return Ok(value);
+                }
+                Err(e) if !is_transient(&e) => {
+                    // HBase answered, so the connection is healthy even though the call failed.
+                    self.on_success();
+                    self.record_call(operation, "error");
+                    return Err(e);
+                }
+                Err(e) => e,
+            };
+            // Once the breaker opens there is no point in retrying.
+            let open = self.on_failure();
+            if open || !retryable || attempts >= self.policy.max_attempts {
+                self.record_call(operation, "transient_error");
+                return Err(error);
+            }
+            let backoff = self.backoff(attempts);
+            if start.elapsed() + backoff >= self.policy.deadline {
+                self.record_call(operation, "deadline_exceeded");
+                return Err(error);
+            }
+            tracing::warn!(operation, attempt = attempts, backoff_ms = backoff.as_millis() as u64, error = %error, "transient HBase error, retrying");
+            self.metrics.retries.add(1, &[KeyValue::new("operation", operation)]);
+            thread::sleep(backoff);
+        }
+    }
+
+    /// Exponential backoff for the given attempt, with the upper half jittered.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self.policy.base_backoff.saturating_mul(1 << (attempt - 1).min(16));
+        let capped = exp.min(self.policy.max_backoff);
+        let half = capped / 2;
+        half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+
+    fn acquire(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } if Instant::now() >= until => {
+                self.transition(&mut state, BreakerState::HalfOpen { trial_in_flight: true });
+                true
+            }
+            BreakerState::Open { .. } => false,
+            BreakerState::HalfOpen { trial_in_flight: true } => false,
+            BreakerState::HalfOpen { trial_in_flight: false } => {
+                *state = BreakerState::HalfOpen { trial_in_flight: true };
+                true
+            }
+        }
+    }
+
+    fn on_success(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        self.transition(&mut state, BreakerState::Closed { failures: 0 });
+    }
+
+    /// Returns true when the breaker is open afterwards.
+    fn on_failure(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let open = BreakerState::Open { until: Instant::now() + self.policy.open_duration };
+        match *state {
+            BreakerState::Closed { failures } if failures + 1 < self.policy.failure_threshold => {
+                *state = BreakerState::Closed { failures: failures + 1 };
+            }
+            // A call that started before the breaker opened keeps it open.
+            BreakerState::Open { .. } => {}
+            _ => self.transition(&mut state, open),
+        }
+        matches!(*state, BreakerState::Open { .. })
+    }
+
+    fn transition(&self, state: &mut BreakerState, next: BreakerState) {
+        if state.name() != next.name() {
+            tracing::warn!(from = state.name(), to = next.name(), "HBase circuit breaker changed state");
+            self.metrics.breaker_transitions.add(1, &[KeyValue::new("state", next.name())]);
+        }
+        *state = next;
+    }
+
+    fn record_call(&self, operation: &'static str, outcome: &'static str) {
+        self.metrics.calls.add(1, &[KeyValue::new("operation", operation), KeyValue::new("outcome", outcome)]);
+    }
+
+    fn record_reconnect(&self, result: &Result<(), OrderServiceError>) {
+        let outcome = if result.is_ok() { "success" } else { "error" };
+        self.metrics.reconnects.add(1, &[KeyValue::new("outcome", outcome)]);
+    }
+}
+
+/// Transport and protocol errors leave the connection in an unknown state and may pass on retry.
+/// Errors HBase itself returns, such as a missing table, will not.
+pub(crate) fn is_transient(error: &OrderServiceError) -> bool {
+    matches!(error, OrderServiceError::DBError(thrift::Error::Transport(_) | thrift::Error::Protocol(_)))
+}
+
+/// An `HbaseClient` that applies the shared `Resilience` policy to every call of the wrapped client.
+pub struct ResilientClient<H: HbaseClient> {
+    inner: H,
+    resilience: Arc<Resilience>,
+    broken: bool,
+}
+
+impl ResilientClient<FailoverClient<Box<dyn HbaseClient + Send>>> {
+    /// Connects to one of the comma separated Thrift endpoints in `hosts`.
+    pub fn connect(hosts: &str) -> Result<Self, OrderServiceError> {
+        Self::connect_with(hosts, Resilience::shared())
+    }
+
+    pub fn connect_with(hosts: &str, resilience: Arc<Resilience>) -> Result<Self, OrderServiceError> {
+        let config = ThriftConfig { timeout: resilience.policy.call_timeout, ..get_thrift_config()? };
+        let inner = resilience.execute("connect", true, || FailoverClient::connect(hosts, config))?;
+        Ok(Self::new(inner, resilience))
+    }
+}
+
+impl<H: HbaseClient> ResilientClient<H> {
+    pub fn new(inner: H, resilience: Arc<Resilience>) -> Self {
+        Self { inner, resilience, broken: false }
+    }
+
+    fn call<T>(&mut self, operation: &'static str, retryable: bool, mut f: impl FnMut(&mut H) -> Result<T, OrderServiceError>) -> Result<T, OrderServiceError> {
+        let resilience = Arc::clone(&self.resilience);
+        let inner = &mut self.inner;
+        let broken = &mut self.broken;
+        resilience.execute(operation, retryable, || {
+            if *broken {
+                let reconnected = inner.reconnect();
+                resilience.record_reconnect(&reconnected);
+                reconnected?;
+                *broken = false;
+            }
+            let result = f(inner);
+            if result.as_ref().is_err_and(is_transient) {
+                *broken = true;
+            }
+            result
+        })
+    }
+}
+
+impl<H: HbaseClient> HbaseClient for ResilientClient<H> {
+    fn get_table_names(&mut self) -> Result<Vec<Text>, OrderServiceError> {
+        self.call("get_table_names", true, |c| c.get_table_names())
+    }
+    /// Only retried with an explicit timestamp, so a replayed mutation overwrites the same cell versions.
+    fn put(
+        &mut self,
+        table_name: &str,
+        row_batches: Vec<BatchMutation>,
+        timestamp: Option<i64>,
+        attributes: Option<Attributes>,
+    ) -> Result<(), OrderServiceError> {
+        self.call("put", timestamp.is_some(), |c| c.put(table_name, row_batches.clone(), timestamp, attributes.clone()))
+    }
+    fn create_table(&mut self, spec: &TableSpec) -> Result<(), OrderServiceError> {
+        self.call("create_table", false, |c| c.create_table(spec))
+    }
+    fn table_exists(&mut self, table_name: &str) -> Result<bool, OrderServiceError> {
+        self.call("table_exists", true, |c| c.table_exists(table_name))
+    }
+    fn get_column_descriptors(&mut self, table_name: &str) -> Result<BTreeMap<Text, ColumnDescriptor>, OrderServiceError> {
+        self.call("get_column_descriptors", true, |c| c.get_column_descriptors(table_name))
+    }
+    fn get_row(&mut self, table_name: &str, row_id: &str) -> Result<Vec<TRowResult>, OrderServiceError> {
+        self.call("get_row", true, |c| c.get_row(table_name, row_id))
+    }
+    fn get_rows_with_columns(&mut self, table_name: &str, rows: Vec<Text>, columns: Vec<Text>) -> Result<Vec<TRowResult>, OrderServiceError> {
+        self.call("get_rows_with_columns", true, |c| c.get_rows_with_columns(table_name, rows.clone(), columns.clone()))
+    }
+    fn scanner_open_with_scan(&mut self, table_name: Text, scan: TScan, attributes: BTreeMap<Text, Text>) -> Result<ScannerID, OrderServiceError> {
+        self.call("scanner_open_with_scan", true, |c| c.scanner_open_with_scan(table_name.clone(), scan.clone(), attributes.clone()))
+    }
+    /// Scanners live on the server connection and move forward on every call, so this is never retried.
+    fn scanner_get_list(&mut self, id: ScannerID, nb_rows: i32) -> Result<Vec<TRowResult>, OrderServiceError> {
+        self.call("scanner_get_list", false, |c| c.scanner_get_list(id, nb_rows))
+    }
+    fn scanner_close(&mut self, id: ScannerID) -> Result<(), OrderServiceError> {
+        self.call("scanner_close", false, |c| c.scanner_close(id))
+    }
+    /// Not retried, a lost reply would turn an applied mutation into a failed check.
+    fn check_and_put(&mut self, table_name: &str, row: &str, column: &str, value: &[u8], mutation: Mutation) -> Result<bool, OrderServiceError> {
+        self.call("check_and_put", false, |c| c.check_and_put(table_name, row, column, value, mutation.clone()))
+    }
+    fn reconnect(&mut self) -> Result<(), OrderServiceError> {
+        let reconnected = self.inner.reconnect();
+        self.resilience.record_reconnect(&reconnected);
+        if reconnected.is_ok() {
+            self.broken = false;
+        }
+        reconnected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::hbase_connection::MockHbaseClient;
+    use mockall::Sequence;
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry_sdk::{
+        metrics::{data::{ResourceMetrics, Sum}, reader::MetricReader, InstrumentKind, ManualReader, MetricResult, Pipeline, SdkMeterProvider, Temporality},
+        error::OTelSdkResult,
+        Resource,
+    };
+    use std::sync::Weak;
+    use thrift::{TransportError, TransportErrorKind};
+
+    fn policy() -> ResiliencePolicy {
+        ResiliencePolicy {
+            call_timeout: Duration::from_millis(100),
+            deadline: Duration::from_secs(5),
+            max_attempts: 3,
+            base_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(60),
+        }
+    }
+
+    fn resilience(policy: ResiliencePolicy) -> Arc<Resilience> {
+        Arc::new(Resilience::new(policy, &global::meter("test")))
+    }
+
+    fn transport_error() -> OrderServiceError {
+        OrderServiceError::DBError(thrift::Error::Transport(TransportError::new(TransportErrorKind::TimedOut, "timed out")))
+    }
+
+    fn hbase_error() -> OrderServiceError {
+        OrderServiceError::DBError(thrift::Error::User("TableNotFoundException".into()))
+    }
+
+    #[test]
+    fn test_read_is_retried_after_reconnect() {
+        let mut mock = MockHbaseClient::new();
+        let mut seq = Sequence::new();
+        mock.expect_get_row().times(1).in_sequence(&mut seq).returning(|_, _| Err(transport_error()));
+        mock.expect_reconnect().times(1).in_sequence(&mut seq).returning(|| Ok(()));
+        mock.expect_get_row().times(1).in_sequence(&mut seq).returning(|_, _| Ok(vec![]));
+        let mut client = ResilientClient::new(mock, resilience(policy()));
+
+        assert!(client.get_row("orders", "row").is_ok());
+    }
+
+    #[test]
+    fn test_read_gives_up_after_max_attempts() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_table_exists().times(3).returning(|_| Err(transport_error()));
+        mock.expect_reconnect().times(2).returning(|| Ok(()));
+        let mut client = ResilientClient::new(mock, resilience(policy()));
+
+        let res = client.table_exists("orders");
+        assert!(matches!(res, Err(OrderServiceError::DBError(thrift::Error::Transport(_)))));
+    }
+
+    #[test]
+    fn test_failed_reconnect_counts_as_attempt() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_get_table_names().times(1).returning(|| Err(transport_error()));
+        mock.expect_reconnect().times(2).returning(|| Err(transport_error()));
+        let mut client = ResilientClient::new(mock, resilience(policy()));
+
+        assert!(client.get_table_names().is_err());
+    }
+
+    #[test]
+    fn test_non_transient_error_is_not_retried() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_get_row().times(1).returning(|_, _| Err(hbase_error()));
+        let mut client = ResilientClient::new(mock, resilience(policy()));
+
+        assert!(matches!(client.get_row("orders", "row"), Err(OrderServiceError::DBError(thrift::Error::User(_)))));
+    }
+
+    #[test]
+    fn test_put_without_timestamp_is_not_retried() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_put().times(1).returning(|_, _, _, _| Err(transport_error()));
+        let mut client = ResilientClient::new(mock, resilience(policy()));
+
+        assert!(client.put("orders", vec![], None, None).is_err());
+    }
+
+    #[test]
+    fn test_put_with_timestamp_is_retried_with_same_timestamp() {
+        let mut mock = MockHbaseClient::new();
+        let mut seq = Sequence::new();
+        mock.expect_put().times(1).in_sequence(&mut seq).returning(|_, _, _, _| Err(transport_error()));
+        mock.expect_reconnect().times(1).in_sequence(&mut seq).returning(|| Ok(()));
+        mock.expect_put()
+            .withf(|_, _, timestamp, _| *timestamp == Some(42))
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _, _, _| Ok(()));
+        let mut client = ResilientClient::new(mock, resilience(policy()));
+
+        assert!(client.put("orders", vec![], Some(42), None).is_ok());
+    }
+
+    #[test]
+    fn test_scanner_get_list_is_not_retried_but_next_call_reconnects() {
+        let mut mock = MockHbaseClient::new();
+        let mut seq = Sequence::new();
+        mock.expect_scanner_get_list().times(1).in_sequence(&mut seq).returning(|_, _| Err(transport_error()));
+        mock.expect_reconnect().times(1).in_sequence(&mut seq).returning(|| Ok(()));
+        mock.expect_get_table_names().times(1).in_sequence(&mut seq).returning(|| Ok(vec![]));
+        let mut client = ResilientClient::new(mock, resilience(policy()));
+
+        assert!(client.scanner_get_list(1, 10).is_err());
+        assert!(client.get_table_names().is_ok());
+    }
+
+    #[test]
+    fn test_retries_stop_at_deadline() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_get_row().times(1).returning(|_, _| Err(transport_error()));
+        let policy = ResiliencePolicy {
+            deadline: Duration::from_millis(50),
+            base_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(1),
+            ..policy()
+        };
+        let mut client = ResilientClient::new(mock, resilience(policy));
+
+        let start = Instant::now();
+        assert!(client.get_row("orders", "row").is_err());
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_breaker_opens_and_rejects_calls() {
+        let mut mock = MockHbaseClient::new();
+        mock.expect_get_row().times(2).returning(|_, _| Err(transport_error()));
+        mock.expect_reconnect().times(1).returning(|| Ok(()));
+        let policy = ResiliencePolicy { failure_threshold: 2, ..policy() };
+        let mut client = ResilientClient::new(mock, resilience(policy));
+
+        // The second failure opens the breaker, which stops the retries and rejects the next call.
+        assert!(matches!(client.get_row("orders", "row"), Err(OrderServiceError::DBError(_))));
+        assert!(matches!(client.get_row("orders", "row"), Err(OrderServiceError::CircuitOpen)));
+    }
+
+    #[test]
+    fn test_breaker_is_shared_between_clients() {
+        let shared = resilience(ResiliencePolicy { failure_threshold: 1, max_attempts: 1, ..policy() });
+        let mut failing = MockHbaseClient::new();
+        failing.expect_get_row().times(1).returning(|_, _| Err(transport_error()));
+        let mut idle = MockHbaseClient::new();
+        idle.expect_get_row().never();
+
+        assert!(ResilientClient::new(failing, Arc::clone(&shared)).get_row("orders", "row").is_err());
+        assert!(matches!(ResilientClient::new(idle, shared).get_row("orders", "row"), Err(OrderServiceError::CircuitOpen)));
+    }
+
+    #[test]
+    fn test_half_open_success_closes_breaker() {
+        let policy = ResiliencePolicy { failure_threshold: 1, max_attempts: 1, open_duration: Duration::from_millis(20), ..policy() };
+        let shared = resilience(policy);
+        let mut mock = MockHbaseClient::new();
+        let mut seq = Sequence::new();
+        mock.expect_get_row().times(1).in_sequence(&mut seq).returning(|_, _| Err(transport_error()));
+        mock.expect_reconnect().times(1).in_sequence(&mut seq).returning(|| Ok(()));
+        mock.expect_get_row().times(2).in_sequence(&mut seq).returning(|_, _| Ok(vec![]));
+        let mut client = ResilientClient::new(mock, Arc::clone(&shared));
+
+        assert!(client.get_row("orders", "row").is_err());
+        assert!(matches!(client.get_row("orders", "row"), Err(OrderServiceError::CircuitOpen)));
+        thread::sleep(Duration::from_millis(30));
+        assert!(client.get_row("orders", "row").is_ok());
+        assert!(client.get_row("orders", "row").is_ok());
+        assert_eq!(*shared.state.lock().unwrap(), BreakerState::Closed { failures: 0 });
+    }
+
+    #[test]
+    fn test_half_open_failure_reopens_breaker() {
+        let policy = ResiliencePolicy { failure_threshold: 1, max_attempts: 1, open_duration: Duration::from_millis(20), ..policy() };
+        let shared = resilience(policy);
+        let mut mock = MockHbaseClient::new();
+        mock.expect_get_row().times(1).returning(|_, _| Err(transport_error()));
+        mock.expect_reconnect().times(1).returning(|| Err(transport_error()));
+        let mut client = ResilientClient::new(mock, Arc::clone(&shared));
+
+        assert!(client.get_row("orders", "row").is_err());
+        thread::sleep(Duration::from_millis(30));
+        assert!(matches!(client.get_row("orders", "row"), Err(OrderServiceError::DBError(_))));
+        assert!(matches!(client.get_row("orders", "row"), Err(OrderServiceError::CircuitOpen)));
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let r = resilience(ResiliencePolicy { base_backoff: Duration::from_millis(100), max_backoff: Duration::from_millis(300), ..policy() });
+        let first = r.backoff(1);
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+        let second = r.backoff(2);
+        assert!(second >= Duration::from_millis(100) && second <= Duration::from_millis(200));
+        let capped = r.backoff(10);
+        assert!(capped >= Duration::from_millis(150) && capped <= Duration::from_millis(300));
+    }
+
+    /// Lets the test keep a handle on the reader the meter provider owns.
+    #[derive(Debug, Clone)]
+    struct SharedReader(Arc<ManualReader>);
+
+    impl MetricReader for SharedReader {
+        fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+            self.0.register_pipeline(pipeline)
+        }
+        fn collect(&self, rm: &mut ResourceMetrics) -> MetricResult<()> {
+            self.0.collect(rm)
+        }
+        fn force_flush(&self) -> OTelSdkResult {
+            self.0.force_flush()
+        }
+        fn shutdown(&self) -> OTelSdkResult {
+            self.0.shutdown()
+        }
+        fn temporality(&self, kind: InstrumentKind) -> Temporality {
+            self.0.temporality(kind)
+        }
+    }
+
+    fn counter_value(rm: &ResourceMetrics, name: &str, attributes: &[KeyValue]) -> u64 {
+        rm.scope_metrics.iter()
+            .flat_map(|s| s.metrics.iter())
+            .filter(|m| m.name == name)
+            .filter_map(|m| m.data.as_any().downcast_ref::<Sum<u64>>())
+            .flat_map(|sum| sum.data_points.iter())
+            .filter(|p| attributes.iter().all(|a| p.attributes.contains(a)))
+            .map(|p| p.value)
+            .sum()
+    }
+
+    #[test]
+    fn test_decisions_are_recorded_as_metrics() {
+        let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+        let provider = SdkMeterProvider::builder().with_reader(reader.clone()).build();
+        let shared = Arc::new(Resilience::new(ResiliencePolicy { failure_threshold: 2, ..policy() }, &provider.meter("test")));
+        let mut mock = MockHbaseClient::new();
+        mock.expect_get_row().times(2).returning(|_, _| Err(transport_error()));
+        mock.expect_reconnect().times(1).returning(|| Ok(()));
+        let mut client = ResilientClient::new(mock, shared);
+
+        assert!(client.get_row("orders", "row").is_err());
+
+        let mut rm = ResourceMetrics { resource: Resource::builder_empty().build(), scope_metrics: vec![] };
+        reader.collect(&mut rm).unwrap();
+        let get_row = KeyValue::new("operation", "get_row");
+        assert_eq!(counter_value(&rm, "hbase.client.retries", std::slice::from_ref(&get_row)), 1);
+        assert_eq!(counter_value(&rm, "hbase.client.reconnects", &[KeyValue::new("outcome", "success")]), 1);
+        assert_eq!(counter_value(&rm, "hbase.client.circuit_breaker.transitions", &[KeyValue::new("state", "open")]), 1);
+        assert_eq!(counter_value(&rm, "hbase.client.calls", &[get_row.clone(), KeyValue::new("outcome", "transient_error")]), 1);
+
+        assert!(matches!(client.get_row("orders", "row"), Err(OrderServiceError::CircuitOpen)));
+        let mut rm = ResourceMetrics { resource: Resource::builder_empty().build(), scope_metrics: vec![] };
+        reader.collect(&mut rm).unwrap();
+        assert_eq!(counter_value(&rm, "hbase.client.calls", &[get_row, KeyValue::new("outcome", "rejected")]), 1);
+    }
+}
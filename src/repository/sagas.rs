@@ -0,0 +1,133 @@
+//! Saga state in HBase, one row per order keyed by o_id.
+//!
+//! The saga is stored as JSON in `s:saga`, written with `checkAndPut` against the value that was read, so when a
+//! reply and the timeout sweep race on the same saga only one of them moves it on. Its status and deadline are
+//! then copied to their own cells, so the timeout sweep can have HBase filter the running sagas. The JSON is what
+//! counts: a saga whose copies were not written yet is still checked by the sweep.
+
+use hbase_thrift::{hbase::TScan, BatchMutationBuilder};
+
+use crate::models::{errors::OrderServiceError, saga::{Saga, SagaStatus}, schema::TableSpec, tables::Tables};
+use super::{hbase::{get_unix_time, scan_rows}, hbase_connection::HbaseClient, hbase_utils::{create_cell_mutation, create_full_scan}};
+
+pub const SAGA_FAMILY: &str = "s";
+const SAGA_COLUMN: &str = "s:saga";
+
+pub fn saga_table_spec(tables: &Tables) -> TableSpec {
+    TableSpec { name: tables.sagas.clone(), families: vec![tables.family(SAGA_FAMILY)], split_keys: vec![] }
+}
+
+/// Stores `saga` if `s:saga` still holds `expected`, the raw value it was read from, or is missing for an empty
+/// `expected`. Returns whether it was stored.
+pub fn save_saga<H: HbaseClient>(client: &mut H, tables: &Tables, saga: &Saga, expected: &[u8]) -> Result<bool, OrderServiceError> {
+    let mutation = create_cell_mutation(SAGA_FAMILY, "saga", serde_json::to_string(saga)?).build();
+    if !client.check_and_put(&tables.sagas, saga.o_id(), SAGA_COLUMN, expected, mutation)? {
+        return Ok(false);
+    }
+    let mutations = vec![
+        create_cell_mutation(SAGA_FAMILY, "status", saga.status.to_string()),
+        create_cell_mutation(SAGA_FAMILY, "deadline", saga.deadline.to_string()),
+    ];
+    let batch = <BatchMutationBuilder>::default().row(saga.o_id()).mutations(mutations).build();
+    client.put(&tables.sagas, vec![batch], Some(get_unix_time()), None)?;
+    Ok(true)
+}
+
+/// The saga of an order, `None` when the order has none.
+pub fn load_saga<H: HbaseClient>(client: &mut H, tables: &Tables, o_id: &str) -> Result<Option<Saga>, OrderServiceError> {
+    Ok(read_saga(client, tables, o_id)?.map(|(saga, _)| saga))
+}
+
+/// The saga of an order, with the raw cell to save it against. `None` when the order has none.
+pub fn read_saga<H: HbaseClient>(client: &mut H, tables: &Tables, o_id: &str) -> Result<Option<(Saga, Vec<u8>)>, OrderServiceError> {
+    let rows = client.get_row(&tables.sagas, o_id)?;
+    let Some(raw) = rows.into_iter().next().and_then(|row| row.columns?.remove(SAGA_COLUMN.as_bytes())?.value) else { return Ok(None) };
+    Ok(Some((serde_json::from_slice(&raw)?, raw)))
+}
+
+/// The running sagas whose current step timed out at or before `now`, with their raw cells, read `batch_size`
+/// rows at a time. Rows that do not decode are logged and skipped.
+pub fn expired_sagas<H: HbaseClient>(client: &mut H, tables: &Tables, now: i64, batch_size: i32) -> Result<Vec<(Saga, Vec<u8>)>, OrderServiceError> {
+    let filter = format!("SingleColumnValueFilter('{}', 'status', =, 'binary:{}', false, true)", SAGA_FAMILY, SagaStatus::Running);
+    let scan = TScan { filter_string: Some(filter.into_bytes()), ..create_full_scan(vec![SAGA_COLUMN.as_bytes().to_vec(), b"s:status".to_vec()], batch_size) };
+    let mut expired = vec![];
+    scan_rows(client, &tables.sagas, scan, batch_size, |row| {
+        let value = row.columns.as_ref().and_then(|c| c.get(SAGA_COLUMN.as_bytes())).and_then(|c| c.value.as_deref()).unwrap_or_default();
+        match serde_json::from_slice::<Saga>(value) {
+            Ok(saga) if saga.status == SagaStatus::Running && saga.deadline <= now => expired.push((saga, value.to_vec())),
+            Ok(_) => {}
+            Err(e) => tracing::warn!(error = %e, row = %String::from_utf8_lossy(row.row.as_deref().unwrap_or_default()), "skipping saga row that does not decode"),
+        }
+        Ok(())
+    })?;
+    Ok(expired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{orders::Order, saga::StepTimeouts},
+        repository::fake_hbase::FakeHbase,
+    };
+
+    /// A client per Thrift API, each to a fake HBase with the saga table.
+    fn clients() -> Vec<Box<dyn HbaseClient + Send>> {
+        FakeHbase::each_api_with_tables(&[saga_table_spec(&Tables::default())]).iter().map(|server| server.connect()).collect()
+    }
+
+    fn saga(o_id: &str, now: i64) -> Saga {
+        let mut order = Order::new(vec![], "CustAddr".into(), "RestAddr".into(), "custid".into(), "restid".into(), 2860);
+        order.o_id = o_id.into();
+        Saga::start(&order, now, &StepTimeouts::default()).0
+    }
+
+    #[test]
+    fn test_saga_round_trip() {
+        for mut client in clients() {
+            let tables = Tables::default();
+            assert_eq!(load_saga(&mut client, &tables, "a").unwrap(), None);
+            let saga = saga("a", 1000);
+            assert!(save_saga(&mut client, &tables, &saga, b"").unwrap());
+            assert_eq!(load_saga(&mut client, &tables, "a").unwrap(), Some(saga));
+        }
+    }
+
+    #[test]
+    fn test_saga_is_saved_only_against_the_value_read() {
+        for mut client in clients() {
+            let tables = Tables::default();
+            let mut saga = saga("a", 0);
+            assert!(save_saga(&mut client, &tables, &saga, b"").unwrap());
+            assert!(!save_saga(&mut client, &tables, &saga, b"").unwrap());
+            let (_, read) = read_saga(&mut client, &tables, "a").unwrap().unwrap();
+
+            let mut timed_out = saga.clone();
+            timed_out.on_timeout(i64::MAX).unwrap();
+            assert!(save_saga(&mut client, &tables, &timed_out, &read).unwrap());
+            saga.updated_at = 1;
+            assert!(!save_saga(&mut client, &tables, &saga, &read).unwrap());
+            assert_eq!(load_saga(&mut client, &tables, "a").unwrap(), Some(timed_out));
+        }
+    }
+
+    #[test]
+    fn test_expired_sagas_are_running_and_past_their_deadline() {
+        for mut client in clients() {
+            let tables = Tables::default();
+            let expired = saga("a", 0);
+            let waiting = saga("b", 1_000_000);
+            let mut done = saga("c", 0);
+            done.on_timeout(i64::MAX).unwrap();
+            for saga in [&expired, &waiting, &done] {
+                assert!(save_saga(&mut client, &tables, saga, b"").unwrap());
+            }
+            // Stored without the status and deadline copies, as after a crash in between.
+            let uncopied = saga("d", 0);
+            let mutation = create_cell_mutation(SAGA_FAMILY, "saga", serde_json::to_string(&uncopied).unwrap()).build();
+            assert!(client.check_and_put(&tables.sagas, "d", SAGA_COLUMN, b"", mutation).unwrap());
+            let found = expired_sagas(&mut client, &tables, expired.deadline, 2).unwrap();
+            assert_eq!(found.into_iter().map(|(saga, _)| saga).collect::<Vec<_>>(), [expired, uncopied]);
+        }
+    }
+}
@@ -0,0 +1,592 @@
+//! The subset of the HBase Thrift2 IDL (`hbase-thrift/src/main/resources/org/apache/hadoop/hbase/thrift2/hbase.thrift`)
+//! the service uses, encoded by hand since the `hbase-thrift` crate only ships Thrift1.
+//! Field ids follow the IDL. Unknown fields are skipped when reading, so newer servers stay compatible.
+
+use std::collections::BTreeMap;
+
+use thrift::protocol::{
+    TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TOutputProtocol, TStructIdentifier, TType,
+};
+
+pub type Bytes = Vec<u8>;
+
+pub trait ThriftStruct: Sized {
+    fn write(&self, o: &mut dyn TOutputProtocol) -> thrift::Result<()>;
+    fn read(i: &mut dyn TInputProtocol) -> thrift::Result<Self>;
+}
+
+pub fn write_field_begin(o: &mut dyn TOutputProtocol, id: i16, field_type: TType) -> thrift::Result<()> {
+    o.write_field_begin(&TFieldIdentifier::new::<Option<String>, String, i16>(None, field_type, id))
+}
+
+pub fn write_bytes_field(o: &mut dyn TOutputProtocol, id: i16, value: &[u8]) -> thrift::Result<()> {
+    write_field_begin(o, id, TType::String)?;
+    o.write_bytes(value)?;
+    o.write_field_end()
+}
+
+pub fn write_string_field(o: &mut dyn TOutputProtocol, id: i16, value: &str) -> thrift::Result<()> {
+    write_field_begin(o, id, TType::String)?;
+    o.write_string(value)?;
+    o.write_field_end()
+}
+
+pub fn write_i32_field(o: &mut dyn TOutputProtocol, id: i16, value: i32) -> thrift::Result<()> {
+    write_field_begin(o, id, TType::I32)?;
+    o.write_i32(value)?;
+    o.write_field_end()
+}
+
+pub fn write_i64_field(o: &mut dyn TOutputProtocol, id: i16, value: i64) -> thrift::Result<()> {
+    write_field_begin(o, id, TType::I64)?;
+    o.write_i64(value)?;
+    o.write_field_end()
+}
+
+pub fn write_bool_field(o: &mut dyn TOutputProtocol, id: i16, value: bool) -> thrift::Result<()> {
+    write_field_begin(o, id, TType::Bool)?;
+    o.write_bool(value)?;
+    o.write_field_end()
+}
+
+pub fn write_struct_field(o: &mut dyn TOutputProtocol, id: i16, value: &impl ThriftStruct) -> thrift::Result<()> {
+    write_field_begin(o, id, TType::Struct)?;
+    value.write(o)?;
+    o.write_field_end()
+}
+
+pub fn write_struct_list_field<T: ThriftStruct>(o: &mut dyn TOutputProtocol, id: i16, values: &[T]) -> thrift::Result<()> {
+    write_field_begin(o, id, TType::List)?;
+    write_struct_list(o, values)?;
+    o.write_field_end()
+}
+
+pub fn write_struct_list<T: ThriftStruct>(o: &mut dyn TOutputProtocol, values: &[T]) -> thrift::Result<()> {
+    o.write_list_begin(&TListIdentifier::new(TType::Struct, values.len() as i32))?;
+    for value in values {
+        value.write(o)?;
+    }
+    o.write_list_end()
+}
+
+pub fn write_bytes_list_field(o: &mut dyn TOutputProtocol, id: i16, values: &[Bytes]) -> thrift::Result<()> {
+    write_field_begin(o, id, TType::List)?;
+    o.write_list_begin(&TListIdentifier::new(TType::String, values.len() as i32))?;
+    for value in values {
+        o.write_bytes(value)?;
+    }
+    o.write_list_end()?;
+    o.write_field_end()
+}
+
+pub fn write_bytes_map_field(o: &mut dyn TOutputProtocol, id: i16, values: &BTreeMap<Bytes, Bytes>) -> thrift::Result<()> {
+    write_field_begin(o, id, TType::Map)?;
+    o.write_map_begin(&TMapIdentifier::new(TType::String, TType::String, values.len() as i32))?;
+    for (k, v) in values {
+        o.write_bytes(k)?;
+        o.write_bytes(v)?;
+    }
+    o.write_map_end()?;
+    o.write_field_end()
+}
+
+fn write_struct_begin(o: &mut dyn TOutputProtocol, name: &str) -> thrift::Result<()> {
+    o.write_struct_begin(&TStructIdentifier::new(name))
+}
+
+fn write_struct_end(o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+    o.write_field_stop()?;
+    o.write_struct_end()
+}
+
+pub fn read_struct_list<T: ThriftStruct>(i: &mut dyn TInputProtocol) -> thrift::Result<Vec<T>> {
+    let list = i.read_list_begin()?;
+    let values = (0..list.size).map(|_| T::read(i)).collect::<thrift::Result<Vec<T>>>()?;
+    i.read_list_end()?;
+    Ok(values)
+}
+
+/// Only the fake HBase reads split keys.
+#[cfg(any(test, feature = "test-support"))]
+pub fn read_bytes_list(i: &mut dyn TInputProtocol) -> thrift::Result<Vec<Bytes>> {
+    let list = i.read_list_begin()?;
+    let values = (0..list.size).map(|_| i.read_bytes()).collect::<thrift::Result<Vec<Bytes>>>()?;
+    i.read_list_end()?;
+    Ok(values)
+}
+
+/// Reads the fields of a struct, handing each `(id, type)` to `on_field`, which returns false for fields it skips.
+pub fn read_fields(i: &mut dyn TInputProtocol, mut on_field: impl FnMut(&mut dyn TInputProtocol, i16, TType) -> thrift::Result<bool>) -> thrift::Result<()> {
+    i.read_struct_begin()?;
+    loop {
+        let field = i.read_field_begin()?;
+        if field.field_type == TType::Stop {
+            break;
+        }
+        if !on_field(i, field.id.unwrap_or_default(), field.field_type)? {
+            i.skip(field.field_type)?;
+        }
+        i.read_field_end()?;
+    }
+    i.read_struct_end()
+}
+
+fn missing(structure: &str, field: &str) -> thrift::Error {
+    thrift::Error::Protocol(thrift::ProtocolError::new(
+        thrift::ProtocolErrorKind::InvalidData,
+        format!("{} is missing the required field {}", structure, field),
+    ))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TTimeRange {
+    pub min_stamp: i64,
+    pub max_stamp: i64,
+}
+
+impl ThriftStruct for TTimeRange {
+    fn write(&self, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+        write_struct_begin(o, "TTimeRange")?;
+        write_i64_field(o, 1, self.min_stamp)?;
+        write_i64_field(o, 2, self.max_stamp)?;
+        write_struct_end(o)
+    }
+    fn read(i: &mut dyn TInputProtocol) -> thrift::Result<Self> {
+        let mut range = TTimeRange::default();
+        read_fields(i, |i, id, t| Ok(match (id, t) {
+            (1, TType::I64) => { range.min_stamp = i.read_i64()?; true }
+            (2, TType::I64) => { range.max_stamp = i.read_i64()?; true }
+            _ => false,
+        }))?;
+        Ok(range)
+    }
+}
+
+/// A family, or a single column when the qualifier is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TColumn {
+    pub family: Bytes,
+    pub qualifier: Option<Bytes>,
+    pub timestamp: Option<i64>,
+}
+
+impl ThriftStruct for TColumn {
+    fn write(&self, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+        write_struct_begin(o, "TColumn")?;
+        write_bytes_field(o, 1, &self.family)?;
+        if let Some(qualifier) = &self.qualifier {
+            write_bytes_field(o, 2, qualifier)?;
+        }
+        if let Some(timestamp) = self.timestamp {
+            write_i64_field(o, 3, timestamp)?;
+        }
+        write_struct_end(o)
+    }
+    fn read(i: &mut dyn TInputProtocol) -> thrift::Result<Self> {
+        let mut column = TColumn::default();
+        read_fields(i, |i, id, t| Ok(match (id, t) {
+            (1, TType::String) => { column.family = i.read_bytes()?; true }
+            (2, TType::String) => { column.qualifier = Some(i.read_bytes()?); true }
+            (3, TType::I64) => { column.timestamp = Some(i.read_i64()?); true }
+            _ => false,
+        }))?;
+        Ok(column)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TColumnValue {
+    pub family: Bytes,
+    pub qualifier: Bytes,
+    pub value: Bytes,
+    pub timestamp: Option<i64>,
+}
+
+impl ThriftStruct for TColumnValue {
+    fn write(&self, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+        write_struct_begin(o, "TColumnValue")?;
+        write_bytes_field(o, 1, &self.family)?;
+        write_bytes_field(o, 2, &self.qualifier)?;
+        write_bytes_field(o, 3, &self.value)?;
+        if let Some(timestamp) = self.timestamp {
+            write_i64_field(o, 4, timestamp)?;
+        }
+        write_struct_end(o)
+    }
+    fn read(i: &mut dyn TInputProtocol) -> thrift::Result<Self> {
+        let mut value = TColumnValue::default();
+        read_fields(i, |i, id, t| Ok(match (id, t) {
+            (1, TType::String) => { value.family = i.read_bytes()?; true }
+            (2, TType::String) => { value.qualifier = i.read_bytes()?; true }
+            (3, TType::String) => { value.value = i.read_bytes()?; true }
+            (4, TType::I64) => { value.timestamp = Some(i.read_i64()?); true }
+            _ => false,
+        }))?;
+        Ok(value)
+    }
+}
+
+/// A row, or an empty result without `row` when the row does not exist.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TResult {
+    pub row: Option<Bytes>,
+    pub column_values: Vec<TColumnValue>,
+}
+
+impl ThriftStruct for TResult {
+    fn write(&self, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+        write_struct_begin(o, "TResult")?;
+        if let Some(row) = &self.row {
+            write_bytes_field(o, 1, row)?;
+        }
+        write_struct_list_field(o, 2, &self.column_values)?;
+        write_struct_end(o)
+    }
+    fn read(i: &mut dyn TInputProtocol) -> thrift::Result<Self> {
+        let mut result = TResult::default();
+        read_fields(i, |i, id, t| Ok(match (id, t) {
+            (1, TType::String) => { result.row = Some(i.read_bytes()?); true }
+            (2, TType::List) => { result.column_values = read_struct_list(i)?; true }
+            _ => false,
+        }))?;
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TGet {
+    pub row: Bytes,
+    pub columns: Option<Vec<TColumn>>,
+    pub max_versions: Option<i32>,
+}
+
+impl ThriftStruct for TGet {
+    fn write(&self, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+        write_struct_begin(o, "TGet")?;
+        write_bytes_field(o, 1, &self.row)?;
+        if let Some(columns) = &self.columns {
+            write_struct_list_field(o, 2, columns)?;
+        }
+        if let Some(max_versions) = self.max_versions {
+            write_i32_field(o, 5, max_versions)?;
+        }
+        write_struct_end(o)
+    }
+    fn read(i: &mut dyn TInputProtocol) -> thrift::Result<Self> {
+        let mut get = TGet::default();
+        let mut has_row = false;
+        read_fields(i, |i, id, t| Ok(match (id, t) {
+            (1, TType::String) => { get.row = i.read_bytes()?; has_row = true; true }
+            (2, TType::List) => { get.columns = Some(read_struct_list(i)?); true }
+            (5, TType::I32) => { get.max_versions = Some(i.read_i32()?); true }
+            _ => false,
+        }))?;
+        if !has_row {
+            return Err(missing("TGet", "row"));
+        }
+        Ok(get)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TPut {
+    pub row: Bytes,
+    pub column_values: Vec<TColumnValue>,
+    /// Applies to the column values without their own timestamp.
+    pub timestamp: Option<i64>,
+    pub attributes: Option<BTreeMap<Bytes, Bytes>>,
+}
+
+impl ThriftStruct for TPut {
+    fn write(&self, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+        write_struct_begin(o, "TPut")?;
+        write_bytes_field(o, 1, &self.row)?;
+        write_struct_list_field(o, 2, &self.column_values)?;
+        if let Some(timestamp) = self.timestamp {
+            write_i64_field(o, 3, timestamp)?;
+        }
+        if let Some(attributes) = &self.attributes {
+            write_bytes_map_field(o, 5, attributes)?;
+        }
+        write_struct_end(o)
+    }
+    fn read(i: &mut dyn TInputProtocol) -> thrift::Result<Self> {
+        let mut put = TPut::default();
+        read_fields(i, |i, id, t| Ok(match (id, t) {
+            (1, TType::String) => { put.row = i.read_bytes()?; true }
+            (2, TType::List) => { put.column_values = read_struct_list(i)?; true }
+            (3, TType::I64) => { put.timestamp = Some(i.read_i64()?); true }
+            _ => false,
+        }))?;
+        Ok(put)
+    }
+}
+
+/// `TDeleteType.DELETE_COLUMNS`: all versions of a column, like a Thrift1 delete mutation.
+pub const DELETE_COLUMNS: i32 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TDelete {
+    pub row: Bytes,
+    pub columns: Option<Vec<TColumn>>,
+    pub timestamp: Option<i64>,
+    pub delete_type: Option<i32>,
+}
+
+impl ThriftStruct for TDelete {
+    fn write(&self, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+        write_struct_begin(o, "TDelete")?;
+        write_bytes_field(o, 1, &self.row)?;
+        if let Some(columns) = &self.columns {
+            write_struct_list_field(o, 2, columns)?;
+        }
+        if let Some(timestamp) = self.timestamp {
+            write_i64_field(o, 3, timestamp)?;
+        }
+        if let Some(delete_type) = self.delete_type {
+            write_i32_field(o, 4, delete_type)?;
+        }
+        write_struct_end(o)
+    }
+    fn read(i: &mut dyn TInputProtocol) -> thrift::Result<Self> {
+        let mut delete = TDelete::default();
+        read_fields(i, |i, id, t| Ok(match (id, t) {
+            (1, TType::String) => { delete.row = i.read_bytes()?; true }
+            (2, TType::List) => { delete.columns = Some(read_struct_list(i)?); true }
+            (3, TType::I64) => { delete.timestamp = Some(i.read_i64()?); true }
+            (4, TType::I32) => { delete.delete_type = Some(i.read_i32()?); true }
+            _ => false,
+        }))?;
+        Ok(delete)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TScan {
+    pub start_row: Option<Bytes>,
+    pub stop_row: Option<Bytes>,
+    pub columns: Option<Vec<TColumn>>,
+    pub caching: Option<i32>,
+    pub time_range: Option<TTimeRange>,
+    pub filter_string: Option<Bytes>,
+    pub batch_size: Option<i32>,
+    pub reversed: Option<bool>,
+    pub cache_blocks: Option<bool>,
+}
+
+impl ThriftStruct for TScan {
+    fn write(&self, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+        write_struct_begin(o, "TScan")?;
+        if let Some(start_row) = &self.start_row {
+            write_bytes_field(o, 1, start_row)?;
+        }
+        if let Some(stop_row) = &self.stop_row {
+            write_bytes_field(o, 2, stop_row)?;
+        }
+        if let Some(columns) = &self.columns {
+            write_struct_list_field(o, 3, columns)?;
+        }
+        if let Some(caching) = self.caching {
+            write_i32_field(o, 4, caching)?;
+        }
+        if let Some(time_range) = &self.time_range {
+            write_struct_field(o, 6, time_range)?;
+        }
+        if let Some(filter_string) = &self.filter_string {
+            write_bytes_field(o, 7, filter_string)?;
+        }
+        if let Some(batch_size) = self.batch_size {
+            write_i32_field(o, 8, batch_size)?;
+        }
+        if let Some(reversed) = self.reversed {
+            write_bool_field(o, 11, reversed)?;
+        }
+        if let Some(cache_blocks) = self.cache_blocks {
+            write_bool_field(o, 12, cache_blocks)?;
+        }
+        write_struct_end(o)
+    }
+    fn read(i: &mut dyn TInputProtocol) -> thrift::Result<Self> {
+        let mut scan = TScan::default();
+        read_fields(i, |i, id, t| Ok(match (id, t) {
+            (1, TType::String) => { scan.start_row = Some(i.read_bytes()?); true }
+            (2, TType::String) => { scan.stop_row = Some(i.read_bytes()?); true }
+            (3, TType::List) => { scan.columns = Some(read_struct_list(i)?); true }
+            (4, TType::I32) => { scan.caching = Some(i.read_i32()?); true }
+            (6, TType::Struct) => { scan.time_range = Some(TTimeRange::read(i)?); true }
+            (7, TType::String) => { scan.filter_string = Some(i.read_bytes()?); true }
+            (8, TType::I32) => { scan.batch_size = Some(i.read_i32()?); true }
+            (11, TType::Bool) => { scan.reversed = Some(i.read_bool()?); true }
+            (12, TType::Bool) => { scan.cache_blocks = Some(i.read_bool()?); true }
+            _ => false,
+        }))?;
+        Ok(scan)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TTableName {
+    pub ns: Option<Bytes>,
+    pub qualifier: Bytes,
+}
+
+impl TTableName {
+    /// Splits `namespace:table`. A name without namespace is in the default namespace.
+    pub fn parse(name: &str) -> Self {
+        match name.split_once(':') {
+            Some((ns, qualifier)) => TTableName { ns: Some(ns.as_bytes().to_vec()), qualifier: qualifier.as_bytes().to_vec() },
+            None => TTableName { ns: None, qualifier: name.as_bytes().to_vec() },
+        }
+    }
+
+    /// The Thrift1 style name, `namespace:table` outside the default namespace.
+    pub fn to_name(&self) -> Bytes {
+        match self.ns.as_deref() {
+            Some(ns) if !ns.is_empty() && ns != b"default" => [ns, b":", &self.qualifier].concat(),
+            _ => self.qualifier.clone(),
+        }
+    }
+}
+
+impl ThriftStruct for TTableName {
+    fn write(&self, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+        write_struct_begin(o, "TTableName")?;
+        if let Some(ns) = &self.ns {
+            write_bytes_field(o, 1, ns)?;
+        }
+        write_bytes_field(o, 2, &self.qualifier)?;
+        write_struct_end(o)
+    }
+    fn read(i: &mut dyn TInputProtocol) -> thrift::Result<Self> {
+        let mut name = TTableName::default();
+        read_fields(i, |i, id, t| Ok(match (id, t) {
+            (1, TType::String) => { name.ns = Some(i.read_bytes()?); true }
+            (2, TType::String) => { name.qualifier = i.read_bytes()?; true }
+            _ => false,
+        }))?;
+        Ok(name)
+    }
+}
+
+/// Names of `TCompressionAlgorithm`, by value.
+pub const COMPRESSION_ALGORITHMS: [&str; 7] = ["LZO", "GZ", "NONE", "SNAPPY", "LZ4", "BZIP2", "ZSTD"];
+/// Names of `TBloomFilterType`, by value.
+pub const BLOOM_FILTER_TYPES: [&str; 4] = ["NONE", "ROW", "ROWCOL", "ROWPREFIX_FIXED_LENGTH"];
+
+pub fn enum_value(names: &[&str], name: &str) -> Option<i32> {
+    names.iter().position(|n| n.eq_ignore_ascii_case(name)).map(|v| v as i32)
+}
+
+pub fn enum_name(names: &[&str], value: i32) -> String {
+    usize::try_from(value).ok().and_then(|v| names.get(v)).map(|n| n.to_string()).unwrap_or_else(|| value.to_string())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TColumnFamilyDescriptor {
+    pub name: Bytes,
+    pub bloom_filter_type: Option<i32>,
+    pub compression_type: Option<i32>,
+    pub max_versions: Option<i32>,
+    pub time_to_live: Option<i32>,
+    pub block_cache_enabled: Option<bool>,
+    pub in_memory: Option<bool>,
+}
+
+impl ThriftStruct for TColumnFamilyDescriptor {
+    fn write(&self, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+        write_struct_begin(o, "TColumnFamilyDescriptor")?;
+        write_bytes_field(o, 1, &self.name)?;
+        if let Some(bloom_filter_type) = self.bloom_filter_type {
+            write_i32_field(o, 5, bloom_filter_type)?;
+        }
+        if let Some(compression_type) = self.compression_type {
+            write_i32_field(o, 6, compression_type)?;
+        }
+        if let Some(max_versions) = self.max_versions {
+            write_i32_field(o, 10, max_versions)?;
+        }
+        if let Some(time_to_live) = self.time_to_live {
+            write_i32_field(o, 13, time_to_live)?;
+        }
+        if let Some(block_cache_enabled) = self.block_cache_enabled {
+            write_bool_field(o, 14, block_cache_enabled)?;
+        }
+        if let Some(in_memory) = self.in_memory {
+            write_bool_field(o, 20, in_memory)?;
+        }
+        write_struct_end(o)
+    }
+    fn read(i: &mut dyn TInputProtocol) -> thrift::Result<Self> {
+        let mut family = TColumnFamilyDescriptor::default();
+        read_fields(i, |i, id, t| Ok(match (id, t) {
+            (1, TType::String) => { family.name = i.read_bytes()?; true }
+            (5, TType::I32) => { family.bloom_filter_type = Some(i.read_i32()?); true }
+            (6, TType::I32) => { family.compression_type = Some(i.read_i32()?); true }
+            (10, TType::I32) => { family.max_versions = Some(i.read_i32()?); true }
+            (13, TType::I32) => { family.time_to_live = Some(i.read_i32()?); true }
+            (14, TType::Bool) => { family.block_cache_enabled = Some(i.read_bool()?); true }
+            (20, TType::Bool) => { family.in_memory = Some(i.read_bool()?); true }
+            _ => false,
+        }))?;
+        Ok(family)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TTableDescriptor {
+    pub table_name: TTableName,
+    pub columns: Option<Vec<TColumnFamilyDescriptor>>,
+}
+
+impl ThriftStruct for TTableDescriptor {
+    fn write(&self, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+        write_struct_begin(o, "TTableDescriptor")?;
+        write_struct_field(o, 1, &self.table_name)?;
+        if let Some(columns) = &self.columns {
+            write_struct_list_field(o, 2, columns)?;
+        }
+        write_struct_end(o)
+    }
+    fn read(i: &mut dyn TInputProtocol) -> thrift::Result<Self> {
+        let mut descriptor = TTableDescriptor::default();
+        read_fields(i, |i, id, t| Ok(match (id, t) {
+            (1, TType::Struct) => { descriptor.table_name = TTableName::read(i)?; true }
+            (2, TType::List) => { descriptor.columns = Some(read_struct_list(i)?); true }
+            _ => false,
+        }))?;
+        Ok(descriptor)
+    }
+}
+
+/// `TIOError` and `TIllegalArgument`, which both carry an optional message in field 1.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TServiceError {
+    pub kind: &'static str,
+    pub message: Option<String>,
+}
+
+impl ThriftStruct for TServiceError {
+    fn write(&self, o: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+        write_struct_begin(o, self.kind)?;
+        if let Some(message) = &self.message {
+            write_string_field(o, 1, message)?;
+        }
+        write_struct_end(o)
+    }
+    fn read(i: &mut dyn TInputProtocol) -> thrift::Result<Self> {
+        let mut error = TServiceError::default();
+        read_fields(i, |i, id, t| Ok(match (id, t) {
+            (1, TType::String) => { error.message = Some(i.read_string()?); true }
+            _ => false,
+        }))?;
+        Ok(error)
+    }
+}
+
+impl std::fmt::Display for TServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message.as_deref().unwrap_or("no message"))
+    }
+}
+
+impl std::error::Error for TServiceError {}
@@ -0,0 +1,229 @@
+use std::{
+    io::{self, BufRead, BufReader, Cursor, Read, Write},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::hbase_connection::connect_stream;
+
+/// Thrift over HTTP, as served by `hbase thrift start -http`. Every Thrift message is sent as the body
+/// of a `POST /`, and the reply is the response body. The connection is kept alive between messages.
+///
+/// Both protocol halves share the channel: writes are buffered until the output protocol flushes
+/// at the end of a message, which sends the request and buffers the response for reading.
+#[derive(Clone)]
+pub(crate) struct THttpChannel(Arc<Mutex<HttpState>>);
+
+struct HttpState {
+    url: String,
+    timeout: Duration,
+    stream: Option<BufReader<TcpStream>>,
+    request: Vec<u8>,
+    response: Cursor<Vec<u8>>,
+}
+
+/// Where a round trip failed. A kept-alive connection the server already closed fails before
+/// any response arrives, and only then is it safe to send the request again.
+enum RoundTripError {
+    BeforeResponse(io::Error),
+    AfterResponse(io::Error),
+}
+
+impl THttpChannel {
+    /// Connects to `url` (`host:port`), so an unreachable server is noticed before the first call.
+    pub(crate) fn connect(url: &str, timeout: Duration) -> io::Result<Self> {
+        let stream = open(url, timeout)?;
+        Ok(Self(Arc::new(Mutex::new(HttpState {
+            url: url.to_owned(),
+            timeout,
+            stream: Some(stream),
+            request: Vec::new(),
+            response: Cursor::new(Vec::new()),
+        }))))
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, HttpState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Read for THttpChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.state().response.read(buf)
+    }
+}
+
+impl Write for THttpChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.state().request.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut state = self.state();
+        if state.request.is_empty() {
+            return Ok(());
+        }
+        let reused = state.stream.is_some();
+        let body = match state.round_trip() {
+            Ok(body) => body,
+            Err(RoundTripError::BeforeResponse(_)) if reused => {
+                state.stream = None;
+                state.round_trip().map_err(RoundTripError::into_inner)?
+            }
+            Err(e) => {
+                state.stream = None;
+                return Err(e.into_inner());
+            }
+        };
+        state.request.clear();
+        state.response = Cursor::new(body);
+        Ok(())
+    }
+}
+
+impl RoundTripError {
+    fn into_inner(self) -> io::Error {
+        match self {
+            RoundTripError::BeforeResponse(e) | RoundTripError::AfterResponse(e) => e,
+        }
+    }
+}
+
+impl HttpState {
+    fn round_trip(&mut self) -> Result<Vec<u8>, RoundTripError> {
+        if self.stream.is_none() {
+            self.stream = Some(open(&self.url, self.timeout).map_err(RoundTripError::BeforeResponse)?);
+        }
+        let url = self.url.as_str();
+        let request = self.request.as_slice();
+        let Some(stream) = self.stream.as_mut() else { unreachable!() };
+
+        let head = format!(
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/x-thrift\r\nAccept: application/x-thrift\r\nContent-Length: {}\r\n\r\n",
+            url,
+            request.len()
+        );
+        let sent = stream.get_mut().write_all(head.as_bytes())
+            .and_then(|_| stream.get_mut().write_all(request))
+            .and_then(|_| stream.get_mut().flush());
+        sent.map_err(RoundTripError::BeforeResponse)?;
+
+        let mut status_line = String::new();
+        match stream.read_line(&mut status_line) {
+            Ok(0) => return Err(RoundTripError::BeforeResponse(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before response"))),
+            Ok(_) => {}
+            Err(e) => return Err(RoundTripError::BeforeResponse(e)),
+        }
+        let (body, close) = read_response(stream, &status_line).map_err(RoundTripError::AfterResponse)?;
+        if close {
+            self.stream = None;
+        }
+        Ok(body)
+    }
+}
+
+fn open(url: &str, timeout: Duration) -> io::Result<BufReader<TcpStream>> {
+    let stream = connect_stream(url, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.set_nodelay(true)?;
+    Ok(BufReader::new(stream))
+}
+
+/// Reads headers and body after the status line. Returns the body and whether the server closes the connection.
+fn read_response(stream: &mut impl BufRead, status_line: &str) -> io::Result<(Vec<u8>, bool)> {
+    let status = status_line.split_whitespace().nth(1).and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("malformed HTTP status line '{}'", status_line.trim_end())))?;
+    let mut content_length = None;
+    let mut chunked = false;
+    let mut close = status_line.starts_with("HTTP/1.0");
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed in HTTP headers"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else { continue };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.parse::<usize>().ok(),
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            "connection" => close = value.eq_ignore_ascii_case("close"),
+            _ => {}
+        }
+    }
+    let body = if chunked {
+        read_chunked(stream)?
+    } else if let Some(length) = content_length {
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body)?;
+        body
+    } else {
+        close = true;
+        let mut body = Vec::new();
+        stream.read_to_end(&mut body)?;
+        body
+    };
+    if status != 200 {
+        let text = String::from_utf8_lossy(&body[..body.len().min(200)]).into_owned();
+        return Err(io::Error::other(format!("HBase Thrift HTTP server answered {}: {}", status, text)));
+    }
+    Ok((body, close))
+}
+
+fn read_chunked(stream: &mut impl BufRead) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let mut size_line = String::new();
+        stream.read_line(&mut size_line)?;
+        let size = size_line.trim().split(';').next().and_then(|s| usize::from_str_radix(s.trim(), 16).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("malformed chunk size '{}'", size_line.trim())))?;
+        if size == 0 {
+            // Trailers, up to the empty line.
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                    return Ok(body);
+                }
+            }
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        stream.read_exact(&mut body[start..])?;
+        let mut crlf = [0; 2];
+        stream.read_exact(&mut crlf)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_response_with_content_length() {
+        let mut stream = Cursor::new(b"Content-Length: 3\r\nContent-Type: application/x-thrift\r\n\r\nabcrest".to_vec());
+        let (body, close) = read_response(&mut stream, "HTTP/1.1 200 OK\r\n").unwrap();
+        assert_eq!(body, b"abc");
+        assert!(!close);
+    }
+
+    #[test]
+    fn test_read_response_chunked() {
+        let mut stream = Cursor::new(b"Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n3\r\nabc\r\n2;ext=1\r\nde\r\n0\r\n\r\n".to_vec());
+        let (body, close) = read_response(&mut stream, "HTTP/1.1 200 OK\r\n").unwrap();
+        assert_eq!(body, b"abcde");
+        assert!(close);
+    }
+
+    #[test]
+    fn test_read_response_error_status() {
+        let mut stream = Cursor::new(b"Content-Length: 9\r\n\r\nForbidden".to_vec());
+        let err = read_response(&mut stream, "HTTP/1.1 403 Forbidden\r\n").unwrap_err();
+        assert!(err.to_string().contains("403"));
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::OnceLock;
+
+use opentelemetry::{global, propagation::Injector, trace::{TraceError, TracerProvider as _}};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    metrics::{MetricResult, PeriodicReader, SdkMeterProvider},
+    propagation::TraceContextPropagator,
+    trace::SdkTracerProvider,
+    Resource,
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Registry};
+
+use crate::{api::utils::env::{get_otlp_endpoint, get_service_name}, producers::event_publisher::EventHeaders};
+
+static TRACER_PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+static METER_PROVIDER: OnceLock<SdkMeterProvider> = OnceLock::new();
+
+/// Installs the global JSON log subscriber. The level is read from `RUST_LOG` and defaults to `info`.
+/// When an OTLP endpoint is configured, spans and metrics are also exported there.
+pub fn init() {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    if let Some(endpoint) = get_otlp_endpoint() {
+        match meter_provider(&endpoint, &get_service_name()) {
+            Ok(provider) => {
+                global::set_meter_provider(provider.clone());
+                let _ = METER_PROVIDER.set(provider);
+            }
+            Err(e) => eprintln!("Could not set up OTLP metric export to {}: {}", endpoint, e),
+        }
+    }
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let otel_layer = get_otlp_endpoint().and_then(|endpoint| match tracer_provider(&endpoint, &get_service_name()) {
+        Ok(provider) => {
+            let tracer = provider.tracer("order_service");
+            let _ = TRACER_PROVIDER.set(provider);
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        Err(e) => {
+            eprintln!("Could not set up OTLP trace export to {}: {}", endpoint, e);
+            None
+        }
+    });
+    let _ = Registry::default()
+        .with(filter)
+        .with(otel_layer)
+        .with(fmt::layer().json().with_current_span(true).with_span_list(false))
+        .try_init();
+}
+
+/// Flushes and stops the trace and metric exporters, if they were started.
+pub fn shutdown() {
+    if let Some(provider) = TRACER_PROVIDER.get() {
+        let _ = provider.shutdown();
+    }
+    if let Some(provider) = METER_PROVIDER.get() {
+        let _ = provider.shutdown();
+    }
+}
+
+/// Builds a tracer provider exporting spans over OTLP/HTTP (protobuf) to `{endpoint}/v1/traces`.
+pub fn tracer_provider(endpoint: &str, service_name: &str) -> Result<SdkTracerProvider, TraceError> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name.to_owned()).build())
+        .build())
+}
+
+/// Builds a meter provider exporting metrics over OTLP/HTTP (protobuf) to `{endpoint}/v1/metrics` every 60 seconds.
+pub fn meter_provider(endpoint: &str, service_name: &str) -> MetricResult<SdkMeterProvider> {
+    let exporter = MetricExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/metrics", endpoint.trim_end_matches('/')))
+        .build()?;
+    Ok(SdkMeterProvider::builder()
+        .with_reader(PeriodicReader::builder(exporter).build())
+        .with_resource(Resource::builder().with_service_name(service_name.to_owned()).build())
+        .build())
+}
+
+/// Adds the W3C `traceparent` (and `tracestate`) of the current span to the event headers.
+pub(crate) fn inject_trace_context(headers: &mut EventHeaders) {
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut HeaderInjector(headers)));
+}
+
+struct HeaderInjector<'a>(&'a mut EventHeaders);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.retain(|(k, _)| k != key);
+        self.0.push((key.to_owned(), value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::{Read, Write}, net::TcpListener, sync::mpsc, time::Duration};
+
+    #[test]
+    fn test_inject_trace_context_adds_traceparent() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = SdkTracerProvider::builder().build();
+        let subscriber = Registry::default().with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let mut headers = EventHeaders::new();
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("publish").entered();
+            inject_trace_context(&mut headers);
+        });
+        let (_, traceparent) = headers.iter().find(|(k, _)| k == "traceparent").unwrap();
+        let parts: Vec<&str> = traceparent.split('-').collect();
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts[0], "00");
+        assert_eq!(parts[1].len(), 32);
+        assert_eq!(parts[2].len(), 16);
+    }
+
+    #[test]
+    fn test_inject_trace_context_without_span_adds_nothing() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let mut headers = EventHeaders::new();
+        inject_trace_context(&mut headers);
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn test_spans_are_exported_to_collector() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0u8; 4096];
+            let n = stream.read(&mut buf).unwrap();
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n");
+            tx.send(String::from_utf8_lossy(&buf[..n]).into_owned()).unwrap();
+        });
+
+        let provider = tracer_provider(&endpoint, "order_service_test").unwrap();
+        let subscriber = Registry::default().with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("hbase.get_row").entered();
+        });
+        let _ = provider.force_flush();
+
+        let request = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(request.starts_with("POST /v1/traces"));
+        assert!(request.to_lowercase().contains("content-type: application/x-protobuf"));
+        let _ = provider.shutdown();
+    }
+}
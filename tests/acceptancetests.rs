@@ -1,10 +1,13 @@
+#![allow(unused_imports)]
 extern crate order_service;
 
 use actix_web::web::Json;
 use cucumber::{given, then, when, World, Parameter};
-use order_service::{repository::hbase_connection::HbaseConnection, api::{utils::env::get_env_var, workers}, models::orders::{Orderline, CreateOrder}};
+use order_service::{repository::hbase_connection::HbaseConnection, api::{utils::env::get_env_var, workers}, models::{orders::{Orderline, CreateOrder}, tables::Tables}};
 use order_service::models::errors::OrderServiceError;
 use order_service::models::orders::Order;
+use order_service::producers::producer_connection::KafkaProdConnection;
+use order_service::repository::order_repository::HbaseRepository;
 
 #[derive(World, Debug, Default, Clone)]
 pub struct State {
@@ -43,8 +46,9 @@ fn when_(s: &mut State) {
 
     let res = workers::create_order(
         Json(order_to_create.clone()), 
-        &hbip, 
-        &kafip
+        &HbaseRepository::new(&hbip, Tables::default()), 
+        &mut KafkaProdConnection::connect(kafip).unwrap(),
+        "acceptance-test"
     ).unwrap();
     s.output = Some(res);
 }
@@ -54,7 +58,7 @@ fn then_(s: &mut State) {
     let (hbip, _) = s.input.clone().unwrap();
     let expected = s.expected.clone().unwrap();
     let order = s.output.clone().unwrap();
-    let res = workers::get_row(&order.o_id, &hbip).unwrap();
+    let res = workers::get_row(&order.o_id, &HbaseRepository::new(&hbip, Tables::default())).unwrap();
     assert_eq!(res.c_id, expected.c_id);
     assert_eq!(res.r_id, expected.r_id);
     assert_eq!(res.cust_addr, expected.cust_addr);
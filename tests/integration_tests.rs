@@ -1,58 +1,29 @@
+/// The HBase flows against the fake HBase of the `test-support` feature, which needs no containers.
 #[cfg(test)]
 mod integration_tests {
     extern crate order_service;
-    use std::collections::HashMap;
 
     use actix_web::web::Json;
-    use testcontainers::{core::WaitFor, images::generic::GenericImage, *};
-
-    use kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
 
     use order_service::{
-        api::{workers::{self, create_table}, utils::env::get_env_var},
-        models::orders::{CreateOrder, Orderline, Order},
-        repository::{hbase, hbase_connection::HbaseConnection},
+        api::workers,
+        models::{orders::{CreateOrder, Orderline, Order}, tables::Tables},
+        producers::event_publisher::InMemoryPublisher,
+        repository::{
+            fake_hbase::FakeHbase,
+            hbase,
+            hbase_connection::HbaseConnection,
+            order_repository::HbaseRepository,
+        },
     };
 
-    macro_rules! start_hbase_container_and_create_table {
-        ($docker: expr) => {{
-            let wait_for = WaitFor::message_on_stdout("server.Server: Started");
-            let image = GenericImage::new("harisekhon/hbase", "2.0")
-                .with_exposed_port(9090)
-                .with_wait_for(wait_for.clone());
-            let hbase = $docker.run(image);
-
-            let mut ip = String::from("127.0.0.1");
-            let port = hbase.get_host_port_ipv4(9090).to_string();
-            ip.push(':');
-            ip.push_str(&port);
-            println!("Started container at IP: {:?}", ip);
-            std::thread::sleep(std::time::Duration::from_secs(5)); // no clue why this makes it work
-            let res = create_table(&ip);
-            match res {
-                Ok(_) => Ok((hbase, ip)),
-                Err(e) => {
-                    println!("Error!: {:?}", e.to_string());
-                    Err(e)
-                }
-            }
-        }};
+    fn tables() -> Tables {
+        Tables::default()
     }
 
-    macro_rules! start_kafka_container_and_create_topic {
-        ($docker: expr) => {{
-            let img = images::kafka::Kafka::default();
-            let runnableimg =
-                RunnableImage::from(img).with_env_var(("KAFKA_CREATE_TOPICS", "OrderCreated"));
-            let kafka_node = $docker.run(runnableimg);
-
-            let ip = format!(
-                "127.0.0.1:{}",
-                kafka_node.get_host_port_ipv4(images::kafka::KAFKA_PORT)
-            );
-            std::thread::sleep(std::time::Duration::from_secs(5));
-            (kafka_node, ip)
-        }};
+    /// Serves a fake HBase with the order tables over Thrift1, and returns its address.
+    fn start_hbase_and_create_table() -> String {
+        FakeHbase::serve_with_tables(&[hbase::order_table_spec(&tables()), hbase::customer_index_spec(&tables())])
     }
 
     #[test]
@@ -77,14 +48,15 @@ mod integration_tests {
             postal_code: 2860,
             orderlines: vec![ol1.clone(), ol2.clone(), ol3.clone()],
         };
-        let hbip = get_env_var("HBASE_TEST_IP").unwrap();
+        let hbip = start_hbase_and_create_table();
         let hbase_con = HbaseConnection::connect(&hbip).unwrap();
         let o_id = hbase::add_order(
             &Order::from(Json(order_to_create.clone())), 
+            &tables(),
             hbase_con
         ).unwrap();
         
-        let res = workers::get_row(&o_id, &hbip).unwrap();
+        let res = workers::get_row(&o_id, &HbaseRepository::new(&hbip, tables())).unwrap();
         assert_eq!(res.c_id, order_to_create.c_id);
         assert_eq!(res.r_id, order_to_create.r_id);
         assert_eq!(res.cust_addr, order_to_create.cust_addr);
@@ -121,13 +93,14 @@ mod integration_tests {
             postal_code: 2860,
             orderlines: vec![ol1.clone(), ol2.clone(), ol3.clone()],
         };
-        let hbip = get_env_var("HBASE_TEST_IP").unwrap();
-        let kafip = get_env_var("KAFKA_TEST_IP").unwrap();
+        let hbip = start_hbase_and_create_table();
+        let events = InMemoryPublisher::new();
         //Act
         let res = workers::create_order(
             Json(order_to_create.clone()), 
-            &hbip, 
-            &kafip
+            &HbaseRepository::new(&hbip, tables()), 
+            &mut events.clone(),
+            "integration-test"
         );
 
         //Assert
@@ -144,6 +117,166 @@ mod integration_tests {
         assert_eq!(res.orderlines[0].price, ol1.price);
         assert_eq!(res.orderlines[1].price, ol2.price);
         assert_eq!(res.orderlines[2].price, ol3.price);
+        let published = events.events_for("OrderCreated");
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].header("X-Request-Id"), Some("integration-test"));
+    }
+
+    #[test]
+    fn component_test_get_order_by_user() {
+        let ip = start_hbase_and_create_table();
+        let cust_id = "CustomerId";
+        let order_to_create1 = CreateOrder {
+            c_id: cust_id.into(),
+            r_id: "RestaurantId".into(),
+            cust_addr: "CustomerAddress".into(),
+            rest_addr: "RestaurantAddress".into(),
+            postal_code: 2860,
+            orderlines: vec![],
+        };
+        let order_to_create2 = CreateOrder {
+            c_id: cust_id.into(),
+            r_id: "otherrest".into(),
+            cust_addr: "CustomerAddress".into(),
+            rest_addr: "otheraddresss".into(),
+            postal_code: 2860,
+            orderlines: vec![Orderline {
+                item_num: 1,
+                price: 5,
+            }],
+        };
+        let order_to_create3 = CreateOrder {
+            c_id: cust_id.into(),
+            r_id: "otherrest".into(),
+            cust_addr: "CustomerAddress".into(),
+            rest_addr: "otheraddresss".into(),
+            postal_code: 2860,
+            orderlines: vec![Orderline {
+                item_num: 1,
+                price: 5,
+            }],
+        };
+        let _x = workers::create_order(Json(order_to_create1.clone()), &HbaseRepository::new(&ip, tables()), &mut InMemoryPublisher::new(), "integration-test").unwrap();
+        let _y = workers::create_order(Json(order_to_create2.clone()), &HbaseRepository::new(&ip, tables()), &mut InMemoryPublisher::new(), "integration-test").unwrap();
+        let _z = workers::create_order(Json(order_to_create3.clone()), &HbaseRepository::new(&ip, tables()), &mut InMemoryPublisher::new(), "integration-test").unwrap();
+        let res = workers::get_orders_info_by_user(cust_id, &HbaseRepository::new(&ip, tables())).unwrap();
+        assert_eq!(res.len(), 3);
+    }
+
+    #[test]
+    fn component_create_order_empty() {
+        let ip = start_hbase_and_create_table();
+
+        let order_to_create = CreateOrder {
+            c_id: "CustomerId".into(),
+            r_id: "RestaurantId".into(),
+            cust_addr: "CustomerAddress".into(),
+            rest_addr: "RestaurantAddress".into(),
+            postal_code: 2860,
+            orderlines: vec![],
+        };
+        let o = workers::create_order(Json(order_to_create.clone()), &HbaseRepository::new(&ip, tables()), &mut InMemoryPublisher::new(), "integration-test").unwrap();
+        let res = workers::get_row(&o.o_id, &HbaseRepository::new(&ip, tables())).unwrap();
+        assert_eq!(res.c_id, order_to_create.c_id);
+        assert_eq!(res.r_id, order_to_create.r_id);
+        assert_eq!(res.cust_addr, order_to_create.cust_addr);
+        assert_eq!(res.rest_addr, order_to_create.rest_addr);
+        assert_eq!(res.orderlines.len(), order_to_create.orderlines.len());
+    }
+
+    #[test]
+    fn component_create_order() {
+        let ip = start_hbase_and_create_table();
+
+        let ol1 = Orderline {
+            item_num: 10,
+            price: 5,
+        };
+        let ol2 = Orderline {
+            item_num: 16,
+            price: 32,
+        };
+        let ol3 = Orderline {
+            item_num: 20,
+            price: 64,
+        };
+        let order_to_create = CreateOrder {
+            c_id: "CustomerId".into(),
+            r_id: "RestaurantId".into(),
+            cust_addr: "CustomerAddress".into(),
+            rest_addr: "RestaurantAddress".into(),
+            postal_code: 2860,
+            orderlines: vec![ol1.clone(), ol2.clone(), ol3.clone()],
+        };
+        let o = workers::create_order(Json(order_to_create.clone()), &HbaseRepository::new(&ip, tables()), &mut InMemoryPublisher::new(), "integration-test").unwrap();
+        let res = workers::get_row(&o.o_id, &HbaseRepository::new(&ip, tables())).unwrap();
+        assert_eq!(res.c_id, order_to_create.c_id);
+        assert_eq!(res.r_id, order_to_create.r_id);
+        assert_eq!(res.cust_addr, order_to_create.cust_addr);
+        assert_eq!(res.rest_addr, order_to_create.rest_addr);
+        assert_eq!(res.orderlines.len(), order_to_create.orderlines.len());
+        for ol in res.orderlines.iter() {
+            assert!(
+                ol.item_num == ol1.item_num
+                    || ol.item_num == ol2.item_num
+                    || ol.item_num == ol3.item_num
+            );
+            assert!(ol.price == ol1.price || ol.price == ol2.price || ol.price == ol3.price);
+        }
+    }
+
+    #[test]
+    fn component_test_get_tables() {
+        let ip = start_hbase_and_create_table();
+        let names: Vec<String> = workers::get_tables(&HbaseRepository::new(&ip, tables())).unwrap().into_iter().map(|t| t.table_name).collect();
+        assert!(names.contains(&tables().orders));
+        assert!(names.contains(&tables().customer_index));
+    }
+}
+
+/// The flows against a real HBase in a container. They need Docker, so they only run with `cargo test -- --ignored`.
+#[cfg(test)]
+mod container_tests {
+    extern crate order_service;
+
+    use actix_web::web::Json;
+    use testcontainers::{core::WaitFor, images::generic::GenericImage, *};
+
+    use order_service::{
+        api::workers::{self, create_table},
+        models::{orders::{CreateOrder, Orderline}, tables::Tables},
+        producers::event_publisher::InMemoryPublisher,
+        repository::order_repository::HbaseRepository,
+    };
+
+    /// Single region, since the Thrift1 API cannot pre-split the order table.
+    fn tables() -> Tables {
+        Tables::default().with_order_regions(1)
+    }
+
+    macro_rules! start_hbase_container_and_create_table {
+        ($docker: expr) => {{
+            let wait_for = WaitFor::message_on_stdout("server.Server: Started");
+            let image = GenericImage::new("harisekhon/hbase", "2.0")
+                .with_exposed_port(9090)
+                .with_wait_for(wait_for.clone());
+            let hbase = $docker.run(image);
+
+            let mut ip = String::from("127.0.0.1");
+            let port = hbase.get_host_port_ipv4(9090).to_string();
+            ip.push(':');
+            ip.push_str(&port);
+            println!("Started container at IP: {:?}", ip);
+            std::thread::sleep(std::time::Duration::from_secs(5)); // no clue why this makes it work
+            let res = create_table(&ip, &tables());
+            match res {
+                Ok(_) => Ok((hbase, ip)),
+                Err(e) => {
+                    println!("Error!: {:?}", e.to_string());
+                    Err(e)
+                }
+            }
+        }};
     }
 
     #[test]
@@ -151,10 +284,9 @@ mod integration_tests {
     fn component_test_get_order_by_user() {
         let docker = clients::Cli::docker();
         let (_hbase, ip) = start_hbase_container_and_create_table!(docker).unwrap();
-        // let (kafka, kaf_ip) = start_kafka_container_and_create_topic!(docker);
         let cust_id = "CustomerId";
         let order_to_create1 = CreateOrder {
-            c_id: cust_id.clone().into(),
+            c_id: cust_id.into(),
             r_id: "RestaurantId".into(),
             cust_addr: "CustomerAddress".into(),
             rest_addr: "RestaurantAddress".into(),
@@ -162,7 +294,7 @@ mod integration_tests {
             orderlines: vec![],
         };
         let order_to_create2 = CreateOrder {
-            c_id: cust_id.clone().into(),
+            c_id: cust_id.into(),
             r_id: "otherrest".into(),
             cust_addr: "CustomerAddress".into(),
             rest_addr: "otheraddresss".into(),
@@ -173,7 +305,7 @@ mod integration_tests {
             }],
         };
         let order_to_create3 = CreateOrder {
-            c_id: cust_id.clone().into(),
+            c_id: cust_id.into(),
             r_id: "otherrest".into(),
             cust_addr: "CustomerAddress".into(),
             rest_addr: "otheraddresss".into(),
@@ -183,23 +315,19 @@ mod integration_tests {
                 price: 5,
             }],
         };
-        let x = workers::create_order(Json(order_to_create1.clone()), &ip, "localhost:9092").unwrap();
-        std::thread::sleep(std::time::Duration::from_secs(5));
-        let y = workers::create_order(Json(order_to_create2.clone()), &ip, "localhost:9092").unwrap();
-        std::thread::sleep(std::time::Duration::from_secs(5));
-        let z = workers::create_order(Json(order_to_create3.clone()), &ip, "localhost:9092").unwrap();
-        std::thread::sleep(std::time::Duration::from_secs(5));
-        let res = workers::get_orders_info_by_user(cust_id, &ip).unwrap();
-        println!("{}", res.len());
-        assert!(res.len() == 3);
+        let repository = HbaseRepository::new(&ip, tables());
+        let _x = workers::create_order(Json(order_to_create1.clone()), &repository, &mut InMemoryPublisher::new(), "integration-test").unwrap();
+        let _y = workers::create_order(Json(order_to_create2.clone()), &repository, &mut InMemoryPublisher::new(), "integration-test").unwrap();
+        let _z = workers::create_order(Json(order_to_create3.clone()), &repository, &mut InMemoryPublisher::new(), "integration-test").unwrap();
+        let res = workers::get_orders_info_by_user(cust_id, &repository).unwrap();
+        assert_eq!(res.len(), 3);
     }
 
     #[test]
     #[ignore = "This test is expensive, and does not work when test is run in docker container. Use 'cargo test -- --ignored' to run ignored tests."]
     fn component_create_order_empty() {
         let docker = clients::Cli::docker();
-        let (hbase, ip) = start_hbase_container_and_create_table!(docker).unwrap();
-        // let (kafka,  kaf_ip) = start_kafka_container_and_create_topic!(docker);
+        let (_hbase, ip) = start_hbase_container_and_create_table!(docker).unwrap();
 
         let order_to_create = CreateOrder {
             c_id: "CustomerId".into(),
@@ -209,8 +337,9 @@ mod integration_tests {
             postal_code: 2860,
             orderlines: vec![],
         };
-        let o = workers::create_order(Json(order_to_create.clone()), &ip, "localhost:9092").unwrap();
-        let res = workers::get_row(&o.o_id, &ip).unwrap();
+        let repository = HbaseRepository::new(&ip, tables());
+        let o = workers::create_order(Json(order_to_create.clone()), &repository, &mut InMemoryPublisher::new(), "integration-test").unwrap();
+        let res = workers::get_row(&o.o_id, &repository).unwrap();
         assert_eq!(res.c_id, order_to_create.c_id);
         assert_eq!(res.r_id, order_to_create.r_id);
         assert_eq!(res.cust_addr, order_to_create.cust_addr);
@@ -222,8 +351,7 @@ mod integration_tests {
     #[ignore = "This test is expensive, and does not work when test is run in docker container. Use 'cargo test -- --ignored' to run ignored tests."]
     fn component_create_order() {
         let docker = clients::Cli::docker();
-        let (hbase, ip) = start_hbase_container_and_create_table!(docker).unwrap();
-        // let (kafka,  kaf_ip) = start_kafka_container_and_create_topic!(docker);
+        let (_hbase, ip) = start_hbase_container_and_create_table!(docker).unwrap();
 
         let ol1 = Orderline {
             item_num: 10,
@@ -245,8 +373,9 @@ mod integration_tests {
             postal_code: 2860,
             orderlines: vec![ol1.clone(), ol2.clone(), ol3.clone()],
         };
-        let o = workers::create_order(Json(order_to_create.clone()), &ip, "localhost:9092").unwrap();
-        let res = workers::get_row(&o.o_id, &ip).unwrap();
+        let repository = HbaseRepository::new(&ip, tables());
+        let o = workers::create_order(Json(order_to_create.clone()), &repository, &mut InMemoryPublisher::new(), "integration-test").unwrap();
+        let res = workers::get_row(&o.o_id, &repository).unwrap();
         assert_eq!(res.c_id, order_to_create.c_id);
         assert_eq!(res.r_id, order_to_create.r_id);
         assert_eq!(res.cust_addr, order_to_create.cust_addr);
@@ -266,16 +395,84 @@ mod integration_tests {
     #[ignore = "This test is expensive, and does not work when test is run in docker container. Use 'cargo test -- --ignored' to run ignored tests."]
     fn component_test_get_tables() {
         let docker = clients::Cli::docker();
-        let (hbase, ip) = start_hbase_container_and_create_table!(docker).unwrap();
-        let res = match workers::get_tables(&ip) {
+        let (_hbase, ip) = start_hbase_container_and_create_table!(docker).unwrap();
+        let res = match workers::get_tables(&HbaseRepository::new(&ip, tables())) {
             Ok(r) => r,
             Err(e) => {
                 println!("Error!: {:?}", e.to_string());
                 panic!("Booooo")
             }
         };
-        for table in res {
-            assert_eq!(table.table_name, "orders");
+        let names: Vec<String> = res.into_iter().map(|t| t.table_name).collect();
+        assert!(names.contains(&tables().orders));
+        assert!(names.contains(&tables().customer_index));
+    }
+}
+
+/// The same flows against the embedded store, which needs no containers.
+#[cfg(all(test, feature = "embedded"))]
+mod embedded_integration_tests {
+    extern crate order_service;
+
+    use actix_web::web::Json;
+
+    use order_service::{
+        api::workers,
+        models::{orders::{CreateOrder, Order, Orderline}, tables::Tables},
+        producers::event_publisher::InMemoryPublisher,
+        repository::{embedded::EmbeddedRepository, order_repository::OrderRepository},
+    };
+
+    fn create_order(c_id: &str, orderlines: Vec<Orderline>) -> CreateOrder {
+        CreateOrder {
+            c_id: c_id.into(),
+            r_id: "RestaurantId".into(),
+            cust_addr: "CustomerAddress".into(),
+            rest_addr: "RestaurantAddress".into(),
+            postal_code: 2860,
+            orderlines,
         }
     }
+
+    #[test]
+    fn embedded_test_add_and_get_order() {
+        let repo = EmbeddedRepository::temporary(Tables::default()).unwrap();
+        let order = Order::from(Json(create_order("CustomerId", vec![Orderline { item_num: 10, price: 5 }, Orderline { item_num: 16, price: 32 }])));
+        let o_id = repo.add_order(&order).unwrap();
+        let res = workers::get_row(&o_id, &repo).unwrap();
+        assert_eq!(res, order);
+    }
+
+    #[test]
+    fn embedded_test_get_order_by_user() {
+        let repo = EmbeddedRepository::temporary(Tables::default()).unwrap();
+        for orderlines in [vec![], vec![Orderline { item_num: 1, price: 5 }], vec![Orderline { item_num: 2, price: 7 }]] {
+            repo.add_order(&Order::from(Json(create_order("CustomerId", orderlines)))).unwrap();
+        }
+        repo.add_order(&Order::from(Json(create_order("OtherCustomer", vec![])))).unwrap();
+        let res = workers::get_orders_info_by_user("CustomerId", &repo).unwrap();
+        assert_eq!(res.len(), 3);
+        assert!(res.iter().all(|info| info.c_id == "CustomerId"));
+    }
+
+    #[test]
+    fn embedded_test_get_tables() {
+        let tables = Tables::default();
+        let repo = EmbeddedRepository::temporary(tables.clone()).unwrap();
+        repo.create_tables().unwrap();
+        let names: Vec<String> = workers::get_tables(&repo).unwrap().into_iter().map(|t| t.table_name).collect();
+        assert!(names.contains(&tables.orders));
+        assert!(names.contains(&tables.customer_index));
+    }
+
+    #[test]
+    fn embedded_test_create_order_publishes_event() {
+        let repo = EmbeddedRepository::temporary(Tables::default()).unwrap();
+        let events = InMemoryPublisher::new();
+        let order = workers::create_order(Json(create_order("CustomerId", vec![])), &repo, &mut events.clone(), "integration-test").unwrap();
+        let published = events.events_for("OrderCreated");
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].json, order.to_json_string().unwrap());
+        assert_eq!(published[0].header("X-Request-Id"), Some("integration-test"));
+    }
 }